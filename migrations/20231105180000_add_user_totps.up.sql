@@ -0,0 +1,6 @@
+CREATE TABLE "user_totps" (
+    "user_id" uuid PRIMARY KEY REFERENCES "users"("id") ON DELETE CASCADE,
+    "secret" varchar(128) NOT NULL,
+    "enabled" boolean NOT NULL DEFAULT false,
+    "recovery_codes" text[] NOT NULL DEFAULT '{}'
+);
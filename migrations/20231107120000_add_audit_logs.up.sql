@@ -0,0 +1,11 @@
+CREATE TABLE "audit_logs" (
+    "id" uuid PRIMARY KEY,
+    "created_at" timestamptz(3) NOT NULL DEFAULT current_timestamp,
+    "actor" uuid,
+    "action" varchar(64) NOT NULL,
+    "target" uuid,
+    "metadata" jsonb NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX "audit_logs_created_at_idx" ON "audit_logs"("created_at");
+CREATE INDEX "audit_logs_actor_idx" ON "audit_logs"("actor");
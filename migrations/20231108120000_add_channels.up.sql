@@ -0,0 +1,11 @@
+CREATE TABLE "channels" (
+    "id" uuid PRIMARY KEY,
+    "created_at" timestamptz(3) NOT NULL DEFAULT current_timestamp,
+    "updated_at" timestamptz(3) NOT NULL DEFAULT current_timestamp,
+    "user_id" uuid NOT NULL REFERENCES "users"("id") ON DELETE CASCADE,
+    "name" varchar(64) NOT NULL,
+    "description" varchar(1024),
+    "topic" varchar(256)
+);
+
+CREATE INDEX "channels_user_id_idx" ON "channels"("user_id");
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS "channel_permissions";
+
+DROP TYPE IF EXISTS "channelpermission";
@@ -0,0 +1,10 @@
+CREATE TYPE "channelpermission" AS ENUM('ADMIN', 'INTERACT', 'READ');
+
+CREATE TABLE "channel_permissions" (
+    "channel_id" uuid NOT NULL REFERENCES "channels"("id") ON DELETE CASCADE,
+    "user_id" uuid NOT NULL REFERENCES "users"("id") ON DELETE CASCADE,
+    "permission" "channelpermission" NOT NULL,
+    PRIMARY KEY ("channel_id", "user_id")
+);
+
+CREATE INDEX "channel_permissions_user_id_idx" ON "channel_permissions"("user_id");
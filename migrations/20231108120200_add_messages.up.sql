@@ -0,0 +1,12 @@
+CREATE TABLE "messages" (
+    "id" uuid PRIMARY KEY,
+    "created_at" timestamptz(3) NOT NULL DEFAULT current_timestamp,
+    "updated_at" timestamptz(3) NOT NULL DEFAULT current_timestamp,
+    "channel_id" uuid NOT NULL REFERENCES "channels"("id") ON DELETE CASCADE,
+    "user_id" uuid NOT NULL,
+    "seq" bigint NOT NULL,
+    "content" text,
+    "image" uuid
+);
+
+CREATE UNIQUE INDEX "messages_channel_id_seq_idx" ON "messages"("channel_id", "seq");
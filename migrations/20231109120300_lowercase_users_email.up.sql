@@ -0,0 +1,3 @@
+-- Fails if two accounts only differ by the case of their emails, those must be
+-- merged or renamed by hand before migrating
+UPDATE "users" SET "email" = lower("email") WHERE "email" <> lower("email");
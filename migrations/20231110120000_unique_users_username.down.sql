@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS "users_username_lower_key";
+CREATE INDEX "users_username_lower_idx" ON "users"(lower("username"));
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS "users_username_lower_idx";
+CREATE UNIQUE INDEX "users_username_lower_key" ON "users"(lower("username"));
@@ -0,0 +1,1528 @@
+use crate::{
+    attachment::{
+        handlers::AttachmentHandlers, repository::StorageRepository,
+        thumbnail::DEFAULT_THUMBNAIL_SIZE, validation::AttachmentLimits,
+    },
+    audit::{handlers::AuditHandlers, repository::AuditRepository},
+    auth::{
+        handlers::AuthHandlers, password_policy::PasswordPolicy, repository::AuthRepository,
+        totp::TotpManager,
+    },
+    channel::{
+        handlers::ChannelHandlers, models::CHANNEL_MAX_INVITES, repository::ChannelRepository,
+    },
+    event::repository::EventRepository,
+    gateway::{
+        handlers::{ws_upgrader, GatewayLimits},
+        limiter::ConnectionLimiter,
+        sse::{sse_handler, EventStreamOptions},
+        tail::{events_tail_upgrader, EventsTailLimits},
+    },
+    handlers,
+    http::{
+        concurrency_limit, deprecated_route, json_payload_too_large, method_not_allowed,
+        request_timeout, route_not_found, security_headers, AppData, SecurityHeaders,
+        TrustedProxies,
+    },
+    mail::repository::Mailer,
+    message::{
+        filter::{ContentFilter, NoopContentFilter},
+        handlers::MessageHandlers,
+        models::{message_body_limit, MESSAGE_CONTENT_MAX_LEN, MESSAGE_MAX_TTL},
+        repository::MessageRepository,
+    },
+    setup::JsonPanicHandler,
+    stats::handlers::StatsHandlers,
+    user::repository::UserRepository,
+};
+use axum::{extract::DefaultBodyLimit, middleware, routing, Extension, Router};
+use chrono::{DateTime, Utc};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+use tower_http::{
+    catch_panic::CatchPanicLayer, compression::CompressionLayer, limit::RequestBodyLimitLayer,
+    normalize_path::NormalizePath,
+};
+
+/// The versions of the API, each one served under its own path prefix so a
+/// new version can be added along with the previous ones.
+///
+/// A new version is only introduced for breaking changes, the ones that may
+/// make a client written against the current version fail: removing or
+/// renaming a route, a field, an event or an enum variant, changing the type
+/// or the meaning of a field, making an optional field required or rejecting
+/// values that were accepted before. Adding routes, optional request fields,
+/// response fields, events and error codes is not a breaking change, and
+/// clients must ignore what they do not know.
+///
+/// The timestamps are sent as RFC3339 strings. The exceptions are the `exp`
+/// and `iat` claims of the tokens, which are unix seconds as the JWT spec
+/// requires, and the `connected_at` of the gateway connections, which was
+/// released as unix seconds in [`ApiVersion::V1`] and is only going to be
+/// aligned in the next version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    /// The path prefix the routes of the version are nested under.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::V1 => "/v1",
+        }
+    }
+}
+
+/// The settings of the app that do not depend on the repositories.
+#[derive(Debug, Clone)]
+pub struct AppOptions {
+    /// The path all the routes are nested under, such as `/api/v1`, or an
+    /// empty string to serve them from the root
+    pub base_path: String,
+    /// Whether the routes of [`ApiVersion::V1`] are also served without the
+    /// version prefix, as deprecated aliases
+    pub unversioned_routes: bool,
+    /// The date the unversioned aliases are going to be removed on, sent in
+    /// the `Sunset` header of their responses
+    pub unversioned_sunset: Option<DateTime<Utc>>,
+    pub request_timeout: Duration,
+    /// The amount of http requests processed at once, the exceeding ones are
+    /// rejected. Does not apply to the gateway connections and event streams
+    pub max_concurrent_requests: usize,
+    pub http_compression: bool,
+    pub trusted_proxies: TrustedProxies,
+    pub security_headers: SecurityHeaders,
+    pub max_conns_per_ip: usize,
+    pub max_conns: usize,
+    pub gateway_limits: GatewayLimits,
+    pub events_tail_limits: EventsTailLimits,
+    pub event_stream_options: EventStreamOptions,
+    pub require_email_verification: bool,
+    /// The email of the user that is granted the admin role, see
+    /// [`AuthHandlers::with_bootstrap_admin_email`]
+    pub bootstrap_admin_email: Option<String>,
+    pub password_policy: PasswordPolicy,
+    pub attachment_limits: AttachmentLimits,
+    pub thumbnail_size: u32,
+    pub message_max_len: usize,
+    pub message_edit_window: Option<Duration>,
+    pub message_edit_window_bypass: bool,
+    /// The furthest in the future a message can be set to expire
+    pub message_max_ttl: Duration,
+    /// See [`MessageHandlers::with_verbose_channel_mismatch`]
+    pub message_verbose_channel_mismatch: bool,
+    /// The maximum amount of active invites of a channel
+    pub channel_max_invites: usize,
+    /// See [`ChannelHandlers::with_system_messages`]
+    pub channel_system_messages: bool,
+}
+
+impl Default for AppOptions {
+    fn default() -> Self {
+        Self {
+            base_path: String::new(),
+            unversioned_routes: true,
+            unversioned_sunset: None,
+            request_timeout: Duration::from_secs(30),
+            max_concurrent_requests: 1024,
+            http_compression: true,
+            trusted_proxies: TrustedProxies::default(),
+            security_headers: SecurityHeaders::default(),
+            max_conns_per_ip: 16,
+            max_conns: 10000,
+            gateway_limits: GatewayLimits::default(),
+            events_tail_limits: EventsTailLimits::default(),
+            event_stream_options: EventStreamOptions::default(),
+            require_email_verification: false,
+            bootstrap_admin_email: None,
+            password_policy: PasswordPolicy::default(),
+            attachment_limits: AttachmentLimits::default(),
+            thumbnail_size: DEFAULT_THUMBNAIL_SIZE,
+            message_max_len: MESSAGE_CONTENT_MAX_LEN,
+            message_edit_window: None,
+            message_edit_window_bypass: false,
+            message_max_ttl: MESSAGE_MAX_TTL,
+            message_verbose_channel_mismatch: false,
+            channel_max_invites: CHANNEL_MAX_INVITES,
+            channel_system_messages: false,
+        }
+    }
+}
+
+/// The repositories the app is built from.
+pub struct AppRepositories<A, U, C, M, E, L, S, Ml> {
+    pub auth_repo: A,
+    pub user_repo: U,
+    pub channel_repo: C,
+    pub message_repo: M,
+    pub event_repo: E,
+    pub audit_repo: L,
+    pub storage_repo: S,
+    pub mailer: Ml,
+    pub totp: TotpManager,
+}
+
+/// Wires the handlers on top of the repositories and mounts them on the
+/// route table, so the app can be built without binding a socket.
+pub struct AppBuilder<A, U, C, M, E, L, S, Ml> {
+    repos: AppRepositories<A, U, C, M, E, L, S, Ml>,
+    options: AppOptions,
+    content_filter: Box<dyn ContentFilter>,
+}
+
+impl<A, U, C, M, E, L, S, Ml> AppBuilder<A, U, C, M, E, L, S, Ml>
+where
+    A: AuthRepository + Clone + 'static,
+    U: UserRepository + Clone + 'static,
+    C: ChannelRepository + Clone + 'static,
+    M: MessageRepository + Clone + 'static,
+    E: EventRepository + Clone + 'static,
+    L: AuditRepository + 'static,
+    S: StorageRepository + 'static,
+    Ml: Mailer + Clone + 'static,
+{
+    pub fn new(repos: AppRepositories<A, U, C, M, E, L, S, Ml>) -> Self {
+        Self {
+            repos,
+            options: AppOptions::default(),
+            content_filter: Box::new(NoopContentFilter),
+        }
+    }
+
+    pub fn with_options(mut self, options: AppOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// See [`MessageHandlers::with_content_filter`]
+    pub fn with_content_filter(mut self, filter: Box<dyn ContentFilter>) -> Self {
+        self.content_filter = filter;
+        self
+    }
+
+    pub fn build(self) -> Router {
+        let AppRepositories {
+            auth_repo,
+            user_repo,
+            channel_repo,
+            message_repo,
+            event_repo,
+            audit_repo,
+            storage_repo,
+            mailer,
+            totp,
+        } = self.repos;
+        let options = self.options;
+        let app = routes::<A, U, C, M, E, L, S, Ml>(&options);
+
+        let audit_handlers = AuditHandlers::new(audit_repo.clone(), user_repo.clone());
+        let mut auth_handlers = AuthHandlers::new(
+            auth_repo.clone(),
+            user_repo.clone(),
+            event_repo.clone(),
+            mailer,
+            audit_repo.clone(),
+            totp,
+        )
+        .with_require_email_verification(options.require_email_verification)
+        .with_password_policy(options.password_policy);
+        if let Some(email) = options.bootstrap_admin_email {
+            auth_handlers = auth_handlers.with_bootstrap_admin_email(email);
+        }
+        let mut message_handlers = MessageHandlers::new(
+            message_repo.clone(),
+            channel_repo.clone(),
+            user_repo.clone(),
+            event_repo.clone(),
+            audit_repo.clone(),
+        )
+        .with_edit_window_bypass(options.message_edit_window_bypass)
+        .with_verbose_channel_mismatch(options.message_verbose_channel_mismatch)
+        .with_max_content_len(options.message_max_len)
+        .with_max_ttl(options.message_max_ttl)
+        .with_content_filter(self.content_filter);
+        if let Some(window) = options.message_edit_window {
+            message_handlers = message_handlers.with_edit_window(window);
+        }
+        let channel_handlers = ChannelHandlers::new(
+            channel_repo.clone(),
+            message_repo.clone(),
+            event_repo.clone(),
+            audit_repo,
+        )
+        .with_max_invites(options.channel_max_invites)
+        .with_system_messages(options.channel_system_messages);
+        let limiter =
+            ConnectionLimiter::new(options.max_conns_per_ip).with_max_total(options.max_conns);
+        let stats_handlers = StatsHandlers::new(
+            user_repo.clone(),
+            channel_repo.clone(),
+            message_repo.clone(),
+            limiter.clone(),
+        );
+        let attachment_handlers = AttachmentHandlers::new(storage_repo)
+            .with_limits(options.attachment_limits)
+            .with_thumbnail_size(options.thumbnail_size);
+
+        let app = app
+            .layer(AppData::extension(attachment_handlers))
+            .layer(AppData::extension(audit_handlers))
+            .layer(AppData::extension(auth_handlers))
+            .layer(AppData::extension(message_handlers))
+            .layer(AppData::extension(channel_handlers))
+            .layer(AppData::extension(stats_handlers))
+            .layer(AppData::extension(event_repo))
+            .layer(AppData::extension(channel_repo))
+            .layer(AppData::extension(message_repo))
+            .layer(AppData::extension(user_repo))
+            .layer(Extension(auth_repo))
+            .layer(AppData::extension(limiter))
+            .layer(AppData::extension(options.gateway_limits))
+            .layer(AppData::extension(options.events_tail_limits))
+            .layer(AppData::extension(options.event_stream_options))
+            .layer(AppData::extension(options.trusted_proxies))
+            .layer(CatchPanicLayer::custom(JsonPanicHandler));
+
+        let versioned = Router::new().nest(ApiVersion::V1.prefix(), app.clone());
+        let app = if options.unversioned_routes {
+            versioned.merge(app.layer(middleware::from_fn_with_state(
+                options.unversioned_sunset,
+                deprecated_route,
+            )))
+        } else {
+            versioned
+        };
+
+        let app = match normalize_base_path(&options.base_path) {
+            Some(base_path) => Router::new().nest(&base_path, app),
+            None => app,
+        }
+        .fallback(route_not_found);
+
+        // Layers added with `Router::layer` only run after the route was
+        // matched, so the path must be normalized by a service wrapping the
+        // whole router for the trailing slashes to be trimmed before routing
+        Router::new()
+            .fallback_service(NormalizePath::trim_trailing_slash(app))
+            .layer(middleware::map_response(method_not_allowed))
+            .layer(middleware::from_fn_with_state(
+                options.security_headers,
+                security_headers,
+            ))
+    }
+}
+
+/// Turns `base_path` into the `/prefix` form expected by [`Router::nest`],
+/// returning `None` if the routes must be served from the root.
+fn normalize_base_path(base_path: &str) -> Option<String> {
+    let base_path = base_path.trim().trim_matches('/');
+    (!base_path.is_empty()).then(|| format!("/{base_path}"))
+}
+
+/// Builds the route table of the app. The repositories and handlers the
+/// routes rely on must be added as request extensions on top of it, as done
+/// by [`AppBuilder`].
+pub fn routes<A, U, C, M, E, L, S, Ml>(options: &AppOptions) -> Router
+where
+    A: AuthRepository + Clone + 'static,
+    U: UserRepository + Clone + 'static,
+    C: ChannelRepository + 'static,
+    M: MessageRepository + Clone + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+    S: StorageRepository + 'static,
+    Ml: Mailer + Clone + 'static,
+{
+    // The attachments need a larger body limit than the json routes, so it is
+    // scoped to their own router. The limit is enforced while the body is
+    // streamed, so it is never buffered past it
+    let message_body_limit = message_body_limit(options.message_max_len);
+
+    let attachment_routes = Router::new()
+        .route("/", routing::post(handlers::post_attachments::<S, A>))
+        .route(
+            "/:attachment_id",
+            routing::get(handlers::get_attachment_id::<S, A>),
+        )
+        .route(
+            "/:attachment_id",
+            routing::delete(handlers::delete_attachment_id::<S, A>),
+        )
+        .route(
+            "/:attachment_id/thumbnail",
+            routing::get(handlers::get_attachment_id_thumbnail::<S, A>),
+        )
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(
+            options.attachment_limits.max_size,
+        ))
+        .layer(middleware::map_response(json_payload_too_large));
+
+    let mut app = Router::new()
+        .route(
+            "/auth/signin",
+            routing::post(handlers::post_auth_signin::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/signup",
+            routing::post(handlers::post_auth_signup::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/self",
+            routing::get(handlers::get_auth_self::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/validate",
+            routing::get(handlers::get_auth_validate::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/self/connections",
+            routing::get(handlers::get_auth_self_connections::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/self/invalidate",
+            routing::post(handlers::post_auth_self_invalidate::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/self/revoke-all",
+            routing::post(handlers::post_auth_self_revoke_all::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/username-availability/:username",
+            routing::get(handlers::get_auth_username_availability::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/verify-email",
+            routing::post(handlers::post_auth_verify_email::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/self/verify-email",
+            routing::post(handlers::post_auth_self_verify_email::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/forgot-password",
+            routing::post(handlers::post_auth_forgot_password::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/reset-password",
+            routing::post(handlers::post_auth_reset_password::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/2fa/enroll",
+            routing::post(handlers::post_auth_2fa_enroll::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/2fa/verify",
+            routing::post(handlers::post_auth_2fa_verify::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/auth/2fa/challenge",
+            routing::post(handlers::post_auth_2fa_challenge::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/admin/audit",
+            routing::get(handlers::get_admin_audit::<L, U, A>),
+        )
+        .route(
+            "/admin/connections/:connection_id",
+            routing::delete(handlers::delete_admin_connection_id::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/admin/users",
+            routing::get(handlers::get_admin_users::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/admin/users/by-username/:username",
+            routing::get(handlers::get_admin_users_username::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/admin/users/:user_id/role",
+            routing::put(handlers::put_admin_users_id_role::<A, U, E, Ml, L>),
+        )
+        .route(
+            "/channel/:channel_id",
+            routing::get(handlers::get_channel_id::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/permission/self",
+            routing::get(handlers::get_channel_id_permission_self::<C, M, A, E, L>),
+        )
+        .route(
+            "/channels/self",
+            routing::get(handlers::get_channels_self::<C, M, A, E, L>),
+        )
+        .route(
+            "/channels/self/read-all",
+            routing::post(handlers::post_channels_self_read_all::<M, C, A, U, E, L>),
+        )
+        .route(
+            "/channels/batch",
+            routing::post(handlers::post_channels_batch::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel",
+            routing::post(handlers::post_channel::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/permission",
+            routing::put(handlers::put_channel_id_permission::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/notifications",
+            routing::get(handlers::get_channel_id_notifications::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/notifications",
+            routing::put(handlers::put_channel_id_notifications::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/invites",
+            routing::post(handlers::post_channel_id_invites::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/invites",
+            routing::get(handlers::get_channel_id_invites::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/invites/:invite_id",
+            routing::delete(handlers::delete_channel_id_invite_id::<C, M, A, E, L>),
+        )
+        .route(
+            "/invites/:token",
+            routing::get(handlers::get_invite_token::<C, M, A, E, L>),
+        )
+        .route(
+            "/invites/:token/accept",
+            routing::post(handlers::post_invite_token_accept::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id",
+            routing::put(handlers::put_channel_id::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id",
+            routing::patch(handlers::put_channel_id::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id",
+            routing::delete(handlers::delete_channel_id::<C, M, A, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/message/:message_id",
+            routing::get(handlers::get_channel_id_message_id::<M, C, A, U, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/messages",
+            routing::get(handlers::get_channel_id_messages::<M, C, A, U, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/export",
+            routing::get(handlers::get_channel_id_export::<M, C, A, U, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/import",
+            routing::post(handlers::post_channel_id_import::<M, C, A, U, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/messages/around",
+            routing::get(handlers::get_channel_id_messages_around::<M, C, A, U, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/message",
+            routing::post(handlers::post_channel_id_message::<M, C, A, U, E, L>)
+                .layer(RequestBodyLimitLayer::new(message_body_limit))
+                .layer(middleware::map_response(json_payload_too_large)),
+        )
+        .route(
+            "/channel/:channel_id/message/:message_id",
+            routing::put(handlers::put_channel_id_message_id::<M, C, A, U, E, L>)
+                .layer(RequestBodyLimitLayer::new(message_body_limit))
+                .layer(middleware::map_response(json_payload_too_large)),
+        )
+        .route(
+            "/channel/:channel_id/message/:message_id",
+            routing::patch(handlers::put_channel_id_message_id::<M, C, A, U, E, L>)
+                .layer(RequestBodyLimitLayer::new(message_body_limit))
+                .layer(middleware::map_response(json_payload_too_large)),
+        )
+        .route(
+            "/channel/:channel_id/message/:message_id",
+            routing::delete(handlers::delete_channel_id_message_id::<M, C, A, U, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/message/:message_id/read",
+            routing::post(handlers::post_channel_id_message_id_read::<M, C, A, U, E, L>),
+        )
+        .route(
+            "/channel/:channel_id/message/:message_id/receipts",
+            routing::get(handlers::get_channel_id_message_id_receipts::<M, C, A, U, E, L>),
+        )
+        .route("/stats", routing::get(handlers::get_stats::<U, C, M, A>))
+        .route(
+            "/channel/:channel_id/typing",
+            routing::post(handlers::post_channel_id_typing::<M, C, A, U, E, L>)
+                .get(handlers::get_channel_id_typing::<M, C, A, U, E, L>),
+        )
+        .nest("/attachments", attachment_routes);
+
+    if options.http_compression {
+        app = app.layer(CompressionLayer::new());
+    }
+
+    app = app
+        .layer(middleware::from_fn_with_state(
+            options.request_timeout,
+            request_timeout,
+        ))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(Semaphore::new(options.max_concurrent_requests)),
+            concurrency_limit,
+        ))
+        // The gateway connections and the event streams are long-lived and
+        // must not be compressed nor hold a request permit, so the routes
+        // must be added after the timeout, compression and concurrency layers
+        .route("/gateway", routing::get(ws_upgrader::<E, A, C, M>))
+        .route("/events", routing::get(sse_handler::<E, A, C>))
+        .route(
+            "/admin/events/tail",
+            routing::get(events_tail_upgrader::<E, A, U>),
+        );
+
+    app
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppBuilder, AppOptions, AppRepositories};
+    use crate::{
+        attachment::memory_repository::InMemoryStorageRepository,
+        audit::memory_repository::InMemoryAuditRepository,
+        auth::{jwt_repository::JwtAuthRepository, totp::TotpManager},
+        cache::memory_repository::InMemoryCacheRepository,
+        channel::memory_repository::InMemoryChannelRepository,
+        event::{memory_repository::InMemoryEventRepository, repository::EventRepository},
+        gateway::handlers::GatewayLimits,
+        http::SecurityHeaders,
+        mail::log_repository::LogMailer,
+        message::memory_repository::InMemoryMessageRepository,
+        user::memory_repository::InMemoryUserRepository,
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Method, Request, StatusCode},
+        Router,
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+    use serde_json::{json, Value};
+    use std::{
+        net::SocketAddr,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{
+        connect_async,
+        tungstenite::{client::IntoClientRequest, Message as WsMessage},
+    };
+    use tower::ServiceExt;
+
+    const JWT_KEY: &str = "dGVzdGluZy1qd3Qta2V5LW9mLXRoZS1tZXNzYWdpbmctYXBw";
+
+    type EventConn = <InMemoryEventRepository as EventRepository>::Connection;
+
+    /// Returns the app along with an event receiver, that must be kept alive
+    /// so the published events are accepted.
+    async fn app(options: AppOptions) -> (Router, EventConn) {
+        let event_repo = InMemoryEventRepository::new();
+        let conn = event_repo.get_conn().await.unwrap();
+
+        let app = AppBuilder::new(AppRepositories {
+            auth_repo: JwtAuthRepository::new(
+                Algorithm::HS512,
+                EncodingKey::from_base64_secret(JWT_KEY).unwrap(),
+                DecodingKey::from_base64_secret(JWT_KEY).unwrap(),
+                3600,
+                InMemoryCacheRepository::new(),
+            ),
+            user_repo: InMemoryUserRepository::new(4),
+            channel_repo: InMemoryChannelRepository::new(),
+            message_repo: InMemoryMessageRepository::new(),
+            event_repo,
+            audit_repo: InMemoryAuditRepository::new(),
+            storage_repo: InMemoryStorageRepository::new(),
+            mailer: LogMailer::new(),
+            totp: TotpManager::new(JWT_KEY.as_bytes(), "messaging-app".into()),
+        })
+        .with_options(options)
+        .build();
+
+        (app, conn)
+    }
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let req = match body {
+            Some(body) => req
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => req.body(Body::empty()),
+        }
+        .unwrap();
+
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let buf = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&buf).unwrap_or(Value::Null))
+    }
+
+    /// Signs up an user named after the local part of `email` and signs it in,
+    /// returning its id and auth token.
+    async fn signup_and_login(app: &Router, email: &str) -> (String, String) {
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/auth/signup",
+            None,
+            Some(json!({
+                "email": email,
+                "username": email.split('@').next().unwrap(),
+                "password": "tr0ub4dor&3",
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let user_id = body["data"]["id"].as_str().unwrap().to_owned();
+
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/auth/signin",
+            None,
+            Some(json!({ "email": email, "password": "tr0ub4dor&3" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let token = body["data"]["auth_token"].as_str().unwrap().to_owned();
+
+        (user_id, token)
+    }
+
+    /// Enrolls the user of `token` in two-factor authentication, returning the
+    /// generator of its codes, along with the time and code it was verified at.
+    async fn enable_2fa(app: &Router, token: &str) -> (totp_rs::TOTP, u64, String) {
+        let (status, body) = send(app, Method::POST, "/auth/2fa/enroll", Some(token), None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let url = body["data"]["otpauth_url"].as_str().unwrap();
+        let totp = totp_rs::TOTP::from_url(url).unwrap();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let code = totp.generate(now);
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/auth/2fa/verify",
+            Some(token),
+            Some(json!({ "code": code })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        (totp, now, code)
+    }
+
+    /// Creates a channel owned by the user of `token`, returning its id.
+    async fn create_channel(app: &Router, token: &str, name: &str) -> String {
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/channel",
+            Some(token),
+            Some(json!({ "name": name })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body["data"]["id"].as_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_message_flow() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/auth/signup",
+            None,
+            Some(json!({
+                "email": "user@example.com",
+                "username": "user",
+                "password": "Password",
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["error_code"], 40012);
+
+        let (user_id, token) = signup_and_login(&app, "user@example.com").await;
+
+        let (status, _) = send(&app, Method::GET, "/auth/self", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = send(&app, Method::GET, "/auth/self", Some(&token), None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(body["data"]["last_login_at"].is_string(), "{body}");
+
+        let (status, body) = send(&app, Method::GET, "/auth/validate", Some(&token), None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["valid"], true);
+        assert_eq!(body["data"]["user_id"], user_id.as_str());
+        assert!(body["data"]["exp"].is_u64());
+
+        let (status, _) = send(&app, Method::GET, "/auth/validate", Some("invalid"), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let channel_id = create_channel(&app, &token, "general").await;
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            &format!("/channel/{channel_id}/message"),
+            Some(&token),
+            Some(json!({ "content": "Hello" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let message_id = body["data"]["id"].as_str().unwrap().to_owned();
+        assert_eq!(body["data"]["user_id"], user_id.as_str());
+
+        let (status, body) = send(
+            &app,
+            Method::GET,
+            &format!("/channel/{channel_id}/messages"),
+            Some(&token),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let messages = body["data"].as_array().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["id"], message_id.as_str());
+        assert_eq!(messages[0]["content"], "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_base_path() {
+        let (app, _conn) = app(AppOptions {
+            base_path: "/api/v1/".into(),
+            ..Default::default()
+        })
+        .await;
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/api/v1/auth/signup",
+            None,
+            Some(json!({
+                "email": "user@example.com",
+                "username": "user",
+                "password": "tr0ub4dor&3",
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/api/v1/auth/signin/",
+            None,
+            Some(json!({ "email": "user@example.com", "password": "tr0ub4dor&3" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let token = body["data"]["auth_token"].as_str().unwrap().to_owned();
+
+        let (status, body) =
+            send(&app, Method::GET, "/api/v1/auth/self/", Some(&token), None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (status, _) = send(&app, Method::GET, "/auth/self", Some(&token), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&app, Method::GET, "/api/v1/gateway", Some(&token), None).await;
+        assert_ne!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_gateway_max_message_size() {
+        let (app, _conn) = app(AppOptions {
+            gateway_limits: GatewayLimits {
+                max_message_size: 1024,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        let (_, token) = signup_and_login(&app, "user@example.com").await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        let mut req = format!("ws://{addr}/v1/gateway")
+            .into_client_request()
+            .unwrap();
+        req.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Bearer {token}").parse().unwrap(),
+        );
+        let (mut ws, _) = connect_async(req).await.unwrap();
+
+        let ready = ws.next().await.unwrap().unwrap();
+        assert!(ready.to_text().unwrap().contains("READY"), "{ready}");
+
+        ws.send(WsMessage::Text("a".repeat(2048))).await.unwrap();
+
+        let error: Value =
+            serde_json::from_str(ws.next().await.unwrap().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(error["data"]["error_code"], 41302, "{error}");
+
+        match ws.next().await.unwrap().unwrap() {
+            WsMessage::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1009),
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gateway_typing() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        let (user_id, token) = signup_and_login(&app, "user@example.com").await;
+
+        let channel_id = create_channel(&app, &token, "general").await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        let mut req = format!("ws://{addr}/v1/gateway")
+            .into_client_request()
+            .unwrap();
+        req.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Bearer {token}").parse().unwrap(),
+        );
+        let (mut ws, _) = connect_async(req).await.unwrap();
+
+        let ready = ws.next().await.unwrap().unwrap();
+        assert!(ready.to_text().unwrap().contains("READY"), "{ready}");
+
+        let typing = |channel_id: &str| {
+            WsMessage::Text(
+                json!({ "type": "TYPING", "data": { "channel_id": channel_id } }).to_string(),
+            )
+        };
+        ws.send(typing(&channel_id)).await.unwrap();
+        let event: Value =
+            serde_json::from_str(ws.next().await.unwrap().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(event["type"], "USER_TYPING", "{event}");
+        assert_eq!(event["data"]["channel_id"], channel_id.as_str());
+        assert_eq!(event["data"]["user_id"], user_id.as_str());
+
+        ws.send(typing(&uuid::Uuid::new_v4().to_string()))
+            .await
+            .unwrap();
+        let error: Value =
+            serde_json::from_str(ws.next().await.unwrap().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(error["data"]["error_code"], 40303, "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_revoke_all_sessions() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        signup_and_login(&app, "user@example.com").await;
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/auth/signin",
+            None,
+            Some(json!({ "email": "user@example.com", "password": "tr0ub4dor&3" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let token = body["data"]["auth_token"].as_str().unwrap().to_owned();
+        let refresh_token = body["data"]["refresh_token"].as_str().unwrap().to_owned();
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/auth/self/revoke-all?keep_session=true",
+            Some(&token),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["reason"], "REQUESTED");
+        let new_token = body["data"]["auth_token"].as_str().unwrap().to_owned();
+        let new_refresh_token = body["data"]["refresh_token"].as_str().unwrap();
+        assert_ne!(new_refresh_token, refresh_token);
+
+        // The old session is signed out, while the kept one still works
+        let (status, _) = send(&app, Method::GET, "/auth/self", Some(&token), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = send(&app, Method::GET, "/auth/self", Some(&new_token), None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["email"], "user@example.com");
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/auth/self/revoke-all",
+            Some(&new_token),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(body["data"].get("auth_token").is_none());
+        assert!(body["data"].get("refresh_token").is_none());
+
+        let (status, _) = send(&app, Method::GET, "/auth/self", Some(&new_token), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_api_versions() {
+        let (strict, _strict_conn) = app(AppOptions {
+            unversioned_routes: false,
+            ..Default::default()
+        })
+        .await;
+
+        let sunset = "2027-01-01T00:00:00Z".parse().unwrap();
+        let (app, _conn) = app(AppOptions {
+            unversioned_sunset: Some(sunset),
+            ..Default::default()
+        })
+        .await;
+
+        let signup = |uri: &str, email: &str| {
+            Request::post(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({
+                        "email": email,
+                        "username": email.split('@').next().unwrap(),
+                        "password": "tr0ub4dor&3",
+                    })
+                    .to_string(),
+                ))
+                .unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(signup("/v1/auth/signup", "v1@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("deprecation").is_none());
+
+        let res = app
+            .clone()
+            .oneshot(signup("/auth/signup", "unversioned@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["deprecation"], "true");
+        assert_eq!(res.headers()["sunset"], "Fri, 01 Jan 2027 00:00:00 GMT");
+
+        let (status, _) = send(&strict, Method::GET, "/auth/self", None, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&strict, Method::GET, "/v1/auth/self", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_responses() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        for uri in ["/unknown", "/v1/unknown", "/v1/auth/unknown"] {
+            let (status, body) = send(&app, Method::GET, uri, None, None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+            assert_eq!(body["error_code"], 40405, "{uri}");
+        }
+
+        let req = Request::builder()
+            .method(Method::DELETE)
+            .uri("/v1/auth/signup")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert!(res.headers().contains_key(header::ALLOW));
+
+        let buf = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(body["error_code"], 40501);
+    }
+
+    #[tokio::test]
+    async fn test_security_headers() {
+        let (disabled, _disabled_conn) = app(AppOptions {
+            security_headers: SecurityHeaders {
+                enabled: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        let (app, _conn) = app(AppOptions {
+            security_headers: SecurityHeaders {
+                hsts_max_age: Some(Duration::from_secs(31536000)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+
+        for uri in ["/v1/auth/self", "/not-found"] {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            let headers = res.headers();
+
+            assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+            assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+            assert_eq!(headers[header::REFERRER_POLICY], "no-referrer");
+            assert_eq!(
+                headers[header::STRICT_TRANSPORT_SECURITY],
+                "max-age=31536000"
+            );
+        }
+
+        let req = Request::get("/v1/auth/self").body(Body::empty()).unwrap();
+        let res = disabled.oneshot(req).await.unwrap();
+        assert!(res.headers().get(header::X_FRAME_OPTIONS).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_admin_audit() {
+        let (app, _conn) = app(AppOptions {
+            bootstrap_admin_email: Some("admin@example.com".into()),
+            ..Default::default()
+        })
+        .await;
+
+        let (admin_id, admin_token) = signup_and_login(&app, "admin@example.com").await;
+        let (user_id, user_token) = signup_and_login(&app, "user@example.com").await;
+
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            "/auth/signin",
+            None,
+            Some(json!({ "email": "user@example.com", "password": "wrong-password" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // The logs are recorded in the background
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let (status, _) = send(&app, Method::GET, "/admin/audit", Some(&user_token), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Newest first
+        for (query, expected) in [
+            (
+                "".to_owned(),
+                vec![
+                    ("USER_SIGNIN_FAILED", &user_id),
+                    ("USER_SIGNIN", &user_id),
+                    ("USER_SIGNIN", &admin_id),
+                ],
+            ),
+            (
+                "?action=USER_SIGNIN".to_owned(),
+                vec![("USER_SIGNIN", &user_id), ("USER_SIGNIN", &admin_id)],
+            ),
+            (
+                format!("?actor={admin_id}"),
+                vec![("USER_SIGNIN", &admin_id)],
+            ),
+            (
+                "?limit=1&offset=1".to_owned(),
+                vec![("USER_SIGNIN", &user_id)],
+            ),
+            ("?offset=3".to_owned(), vec![]),
+            ("?after=2100-01-01T00:00:00Z".to_owned(), vec![]),
+        ] {
+            let (status, body) = send(
+                &app,
+                Method::GET,
+                &format!("/admin/audit{query}"),
+                Some(&admin_token),
+                None,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+
+            let got = body["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|log| {
+                    (
+                        log["action"].as_str().unwrap(),
+                        log["actor"].as_str().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let expected = expected
+                .into_iter()
+                .map(|(action, actor)| (action, actor.as_str()))
+                .collect::<Vec<_>>();
+            assert_eq!(got, expected, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_bootstrap_and_roles() {
+        let (app, _conn) = app(AppOptions {
+            bootstrap_admin_email: Some("Admin@example.com".into()),
+            ..Default::default()
+        })
+        .await;
+
+        let (admin_id, admin_token) = signup_and_login(&app, "admin@example.com").await;
+        let (user_id, user_token) = signup_and_login(&app, "user@example.com").await;
+        let (admin_token, user_token) = (admin_token.as_str(), user_token.as_str());
+
+        for (token, role) in [(admin_token, "ADMIN"), (user_token, "COMMON")] {
+            let (status, body) = send(&app, Method::GET, "/auth/self", Some(token), None).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(body["data"]["role"], role);
+        }
+
+        let (status, _) = send(
+            &app,
+            Method::PUT,
+            &format!("/admin/users/{admin_id}/role"),
+            Some(user_token),
+            Some(json!({ "role": "COMMON" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send(&app, Method::GET, "/admin/users", Some(user_token), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send(&app, Method::GET, "/stats", Some(user_token), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send(&app, Method::GET, "/stats", Some(admin_token), None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body["data"],
+            json!({ "users": 2, "channels": 0, "messages": 0, "connections": 0 })
+        );
+
+        // The repository counts are cached, so the new channel is not counted
+        create_channel(&app, user_token, "general").await;
+        let (status, body) = send(&app, Method::GET, "/stats", Some(admin_token), None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["channels"], 0);
+
+        for (query, total, email) in [
+            ("?role=COMMON", 1, "user@example.com"),
+            ("?email=ADMIN", 1, "admin@example.com"),
+            ("?limit=1", 2, "admin@example.com"),
+        ] {
+            let (status, body) = send(
+                &app,
+                Method::GET,
+                &format!("/admin/users{query}"),
+                Some(admin_token),
+                None,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(body["data"]["total"], total, "{query}");
+            assert_eq!(body["data"]["items"][0]["email"], email, "{query}");
+        }
+
+        let (status, _) = send(
+            &app,
+            Method::GET,
+            "/admin/users/by-username/user",
+            Some(user_token),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // The usernames are compared case-insensitively
+        let (status, body) = send(
+            &app,
+            Method::GET,
+            "/admin/users/by-username/USER",
+            Some(admin_token),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["id"], user_id.as_str());
+
+        let (status, body) = send(
+            &app,
+            Method::GET,
+            "/admin/users/by-username/nobody",
+            Some(admin_token),
+            None,
+        )
+        .await;
+        assert!(status.is_client_error(), "{body}");
+        assert_eq!(body["error_code"], 40402);
+
+        let (status, body) = send(
+            &app,
+            Method::PUT,
+            &format!("/admin/users/{user_id}/role"),
+            Some(admin_token),
+            Some(json!({ "role": "ADMIN" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["id"], user_id.as_str());
+        assert_eq!(body["data"]["role"], "ADMIN");
+    }
+
+    #[tokio::test]
+    async fn test_username_availability() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        let app = &app;
+        let available = |username: &'static str| async move {
+            let uri = format!("/auth/username-availability/{username}");
+            let (status, body) = send(app, Method::GET, &uri, None, None).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            body["data"]["available"].as_bool().unwrap()
+        };
+
+        assert!(available("alice").await);
+
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/auth/signup",
+            None,
+            Some(json!({ "email": "alice@example.com", "username": "Alice", "password": "tr0ub4dor&3" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        assert!(!available("alice").await);
+        assert!(available("bob").await);
+
+        let (status, body) = send(
+            app,
+            Method::POST,
+            "/auth/signup",
+            None,
+            Some(json!({ "email": "other@example.com", "username": "ALICE", "password": "tr0ub4dor&3" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT, "{body}");
+        assert_eq!(body["error_code"], 40905);
+    }
+
+    #[tokio::test]
+    async fn test_email_case_insensitive() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        let signup = |email: &'static str| {
+            send(
+                &app,
+                Method::POST,
+                "/auth/signup",
+                None,
+                Some(json!({ "email": email, "username": "user", "password": "tr0ub4dor&3" })),
+            )
+        };
+
+        let (status, body) = signup("User@Example.com").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["data"]["email"], "user@example.com");
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/auth/signup",
+            None,
+            Some(json!({ "email": "user@EXAMPLE.com", "username": "other", "password": "tr0ub4dor&3" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT, "{body}");
+        assert_eq!(body["error_code"], 40901);
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/auth/signin",
+            None,
+            Some(json!({ "email": "USER@example.com", "password": "tr0ub4dor&3" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_forgot_password_same_response() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        signup_and_login(&app, "user@example.com").await;
+
+        let mut responses = Vec::new();
+        for email in ["user@example.com", "nobody@example.com"] {
+            responses.push(
+                send(
+                    &app,
+                    Method::POST,
+                    "/auth/forgot-password",
+                    None,
+                    Some(json!({ "email": email })),
+                )
+                .await,
+            );
+        }
+        assert_eq!(responses[0].0, StatusCode::OK);
+        assert_eq!(responses[0], responses[1]);
+    }
+
+    #[tokio::test]
+    async fn test_2fa_challenge() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        let (_, token) = signup_and_login(&app, "user@example.com").await;
+
+        let signin = || {
+            send(
+                &app,
+                Method::POST,
+                "/auth/signin",
+                None,
+                Some(json!({ "email": "user@example.com", "password": "tr0ub4dor&3" })),
+            )
+        };
+        let challenge = |challenge_token: String, code: String| {
+            send(
+                &app,
+                Method::POST,
+                "/auth/2fa/challenge",
+                None,
+                Some(json!({ "challenge_token": challenge_token, "code": code })),
+            )
+        };
+
+        let (totp, now, code) = enable_2fa(&app, &token).await;
+
+        let (status, body) = signin().await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let challenge_token = body["data"]["challenge_token"].as_str().unwrap().to_owned();
+
+        // Neither a replayed code nor a wrong one consume the challenge
+        for code in [code, "abcdef".into()] {
+            let (status, body) = challenge(challenge_token.clone(), code).await;
+            assert_ne!(status, StatusCode::OK, "{body}");
+            assert_eq!(body["error_code"], 40108);
+        }
+
+        let code = totp.generate(now + 30);
+        let (status, body) = challenge(challenge_token.clone(), code.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(body["data"]["auth_token"].is_string());
+
+        let (status, body) = challenge(challenge_token, code).await;
+        assert_ne!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["error_code"], 40109);
+    }
+    #[tokio::test]
+    async fn test_2fa_challenge_lockout() {
+        let (app, _conn) = app(AppOptions::default()).await;
+
+        let (_, token) = signup_and_login(&app, "user@example.com").await;
+        enable_2fa(&app, &token).await;
+
+        let signin = || {
+            send(
+                &app,
+                Method::POST,
+                "/auth/signin",
+                None,
+                Some(json!({ "email": "user@example.com", "password": "tr0ub4dor&3" })),
+            )
+        };
+        let wrong_code = |challenge_token: String| {
+            send(
+                &app,
+                Method::POST,
+                "/auth/2fa/challenge",
+                None,
+                Some(json!({ "challenge_token": challenge_token, "code": "abcdef" })),
+            )
+        };
+
+        // Signing in again with the right password must not reset the
+        // attempts of the challenges, both count towards the lockout
+        for _ in 0..2 {
+            let (status, body) = signin().await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let challenge_token = body["data"]["challenge_token"].as_str().unwrap().to_owned();
+
+            let (_, body) = wrong_code(challenge_token).await;
+            assert_eq!(body["error_code"], 40108);
+        }
+
+        let (status, body) = signin().await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let challenge_token = body["data"]["challenge_token"].as_str().unwrap().to_owned();
+
+        let (status, body) = wrong_code(challenge_token).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        assert_eq!(body["error_code"], 42901);
+
+        let (status, body) = signin().await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        assert_eq!(body["error_code"], 42901);
+    }
+}
@@ -0,0 +1,230 @@
+use super::{
+    models::Attachment,
+    range::ByteRange,
+    repository::StorageRepository,
+    thumbnail::{self, DEFAULT_THUMBNAIL_SIZE},
+    validation::AttachmentLimits,
+};
+use crate::{
+    auth::models::UserAuthPayload,
+    errors::ApiError,
+    http::{DataResponse, NoContent},
+};
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttachmentIdPathParams {
+    pub attachment_id: Uuid,
+}
+
+pub struct AttachmentHandlers<S: StorageRepository> {
+    storage_repo: S,
+    limits: AttachmentLimits,
+    thumbnail_size: u32,
+}
+
+impl<S: StorageRepository> AttachmentHandlers<S> {
+    pub fn new(storage_repo: S) -> Self {
+        Self {
+            storage_repo,
+            limits: AttachmentLimits::default(),
+            thumbnail_size: DEFAULT_THUMBNAIL_SIZE,
+        }
+    }
+
+    #[inline]
+    pub fn with_limits(mut self, limits: AttachmentLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the maximum width and height of the generated thumbnails.
+    #[inline]
+    pub fn with_thumbnail_size(mut self, size: u32) -> Self {
+        self.thumbnail_size = size;
+        self
+    }
+
+    pub async fn handle_upload(
+        &self,
+        auth: UserAuthPayload,
+        content_type: Option<&str>,
+        data: Bytes,
+    ) -> Result<DataResponse<Attachment>, ApiError> {
+        let content_type = content_type.ok_or_else(|| {
+            ApiError::AttachmentInvalid("the 'Content-Type' header is required".into())
+        })?;
+
+        let image = self.limits.validate(content_type, &data)?;
+
+        let thumbnail_size = self.thumbnail_size;
+        let thumbnail_src = data.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            thumbnail::generate(&thumbnail_src, thumbnail_size).map_err(|e| e.to_string())
+        })
+        .await;
+
+        // The original is served when there is no thumbnail, so the upload
+        // must not fail because of it
+        let thumbnail = match res {
+            Ok(Ok(v)) => v,
+            Ok(Err(e)) => {
+                tracing::error!(error = e, "Failed to generate attachment thumbnail");
+                None
+            }
+            Err(e) => {
+                tracing::error!(error = e.to_string(), "Thumbnail generation task failed");
+                None
+            }
+        };
+
+        let attachment = Attachment {
+            id: Uuid::now_v7(),
+            user_id: auth.sub,
+            created_at: Utc::now(),
+            content_type: image.content_type.into(),
+            size: data.len() as u64,
+            width: image.width,
+            height: image.height,
+            thumbnail: thumbnail.as_ref().map(|(t, _)| t.clone()),
+        };
+
+        self.storage_repo.store(attachment.clone(), data).await?;
+        if let Some((_, thumbnail_data)) = thumbnail {
+            self.storage_repo
+                .store_thumbnail(attachment.id, thumbnail_data.into())
+                .await?;
+        }
+
+        Ok(attachment.into())
+    }
+
+    /// Streams the attachment file, or the part of it requested by the
+    /// `Range` header.
+    pub async fn handle_download(
+        &self,
+        path: AttachmentIdPathParams,
+        range: Option<&str>,
+    ) -> Result<Response, ApiError> {
+        let attachment = self
+            .storage_repo
+            .get_by_id(path.attachment_id)
+            .await?
+            .ok_or(ApiError::AttachmentNotFound)?;
+
+        let size = attachment.size;
+        let (status, range) = match ByteRange::parse(range, size) {
+            ByteRange::Full => (StatusCode::OK, 0..size),
+            ByteRange::Partial(range) => (StatusCode::PARTIAL_CONTENT, range),
+            ByteRange::Unsatisfiable => {
+                let mut res = ApiError::AttachmentRangeNotSatisfiable.into_response();
+                res.headers_mut().insert(
+                    header::CONTENT_RANGE,
+                    header_value(format!("bytes */{size}")),
+                );
+                return Ok(res);
+            }
+        };
+
+        let stream = self
+            .storage_repo
+            .get_stream(path.attachment_id, range.clone())
+            .await?
+            .ok_or(ApiError::AttachmentNotFound)?;
+
+        let mut res = Response::new(Body::from_stream(stream));
+        *res.status_mut() = status;
+
+        let headers = res.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            content_type_value(&attachment.content_type),
+        );
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(
+            header::CONTENT_LENGTH,
+            HeaderValue::from(range.end - range.start),
+        );
+        if status == StatusCode::PARTIAL_CONTENT {
+            headers.insert(
+                header::CONTENT_RANGE,
+                header_value(format!("bytes {}-{}/{size}", range.start, range.end - 1)),
+            );
+        }
+
+        Ok(res)
+    }
+
+    /// Serves the thumbnail of the attachment, or the original file if it
+    /// has no thumbnail.
+    pub async fn handle_download_thumbnail(
+        &self,
+        path: AttachmentIdPathParams,
+    ) -> Result<Response, ApiError> {
+        let attachment = self
+            .storage_repo
+            .get_by_id(path.attachment_id)
+            .await?
+            .ok_or(ApiError::AttachmentNotFound)?;
+
+        let Some(thumbnail) = attachment.thumbnail else {
+            return self.handle_download(path, None).await;
+        };
+
+        let data = self
+            .storage_repo
+            .get_thumbnail_data(path.attachment_id)
+            .await?
+            .ok_or(ApiError::AttachmentNotFound)?;
+
+        Ok((
+            [(
+                header::CONTENT_TYPE,
+                content_type_value(&thumbnail.content_type),
+            )],
+            data,
+        )
+            .into_response())
+    }
+
+    /// Deletes the attachment, the file itself is only removed once no other
+    /// attachment references it.
+    pub async fn handle_delete(
+        &self,
+        auth: UserAuthPayload,
+        path: AttachmentIdPathParams,
+    ) -> Result<NoContent, ApiError> {
+        let attachment = self
+            .storage_repo
+            .get_by_id(path.attachment_id)
+            .await?
+            .ok_or(ApiError::AttachmentNotFound)?;
+
+        if attachment.user_id != auth.sub {
+            return Err(ApiError::AttachmentDeleteDenied);
+        }
+
+        self.storage_repo.delete(path.attachment_id).await?;
+
+        Ok(NoContent)
+    }
+}
+
+#[inline]
+fn content_type_value(content_type: &str) -> HeaderValue {
+    HeaderValue::from_str(content_type)
+        .unwrap_or_else(|_| HeaderValue::from_static(mime::APPLICATION_OCTET_STREAM.as_ref()))
+}
+
+#[inline]
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::try_from(value).expect("Range headers are always valid header values")
+}
@@ -0,0 +1,182 @@
+use super::{
+    models::Attachment,
+    repository::{content_digest, ByteStream, StorageRepository},
+};
+use crate::errors::ApiError;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use std::{collections::HashMap, ops::Range, sync::Arc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// The size of the chunks the files are streamed in
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+struct StoredObject {
+    data: Bytes,
+    thumbnail: Option<Bytes>,
+    /// The amount of attachments that reference the object
+    refs: usize,
+}
+
+#[derive(Default)]
+struct Storage {
+    /// Maps the attachment ids to their metadata and the digest of the file
+    attachments: HashMap<Uuid, (Attachment, String)>,
+    objects: HashMap<String, StoredObject>,
+}
+
+impl Storage {
+    #[inline]
+    fn get_object(&self, id: Uuid) -> Option<&StoredObject> {
+        let (_, digest) = self.attachments.get(&id)?;
+        self.objects.get(digest)
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct InMemoryStorageRepository(Arc<Mutex<Storage>>);
+
+impl InMemoryStorageRepository {
+    #[inline]
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Storage::default())))
+    }
+
+    #[cfg(test)]
+    async fn object_count(&self) -> usize {
+        self.0.lock().await.objects.len()
+    }
+}
+
+#[async_trait]
+impl StorageRepository for InMemoryStorageRepository {
+    async fn store(&self, attachment: Attachment, data: Bytes) -> Result<(), ApiError> {
+        let digest = content_digest(data.clone()).await?;
+
+        let mut lock = self.0.lock().await;
+        lock.objects
+            .entry(digest.clone())
+            .or_insert_with(|| StoredObject {
+                data,
+                thumbnail: None,
+                refs: 0,
+            })
+            .refs += 1;
+        lock.attachments.insert(attachment.id, (attachment, digest));
+
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<Attachment>, ApiError> {
+        let lock = self.0.lock().await;
+        Ok(lock.attachments.get(&id).map(|(a, _)| a.clone()))
+    }
+
+    async fn get_stream(
+        &self,
+        id: Uuid,
+        range: Range<u64>,
+    ) -> Result<Option<ByteStream>, ApiError> {
+        let lock = self.0.lock().await;
+        let Some(object) = lock.get_object(id) else {
+            return Ok(None);
+        };
+
+        let len = object.data.len();
+        let start = (range.start as usize).min(len);
+        let end = (range.end as usize).clamp(start, len);
+        let data = object.data.slice(start..end);
+        drop(lock);
+
+        let chunks = (0..data.len())
+            .step_by(STREAM_CHUNK_SIZE)
+            .map(move |i| Ok(data.slice(i..(i + STREAM_CHUNK_SIZE).min(data.len()))));
+
+        Ok(Some(Box::pin(tokio_stream::iter(chunks))))
+    }
+
+    async fn store_thumbnail(&self, id: Uuid, data: Bytes) -> Result<(), ApiError> {
+        let mut lock = self.0.lock().await;
+        let digest = match lock.attachments.get(&id) {
+            Some((_, digest)) => digest.clone(),
+            None => return Err(ApiError::AttachmentNotFound),
+        };
+
+        if let Some(object) = lock.objects.get_mut(&digest) {
+            object.thumbnail = Some(data);
+        }
+
+        Ok(())
+    }
+
+    async fn get_thumbnail_data(&self, id: Uuid) -> Result<Option<Bytes>, ApiError> {
+        let lock = self.0.lock().await;
+        Ok(lock.get_object(id).and_then(|o| o.thumbnail.clone()))
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), ApiError> {
+        let mut lock = self.0.lock().await;
+        let (_, digest) = lock
+            .attachments
+            .remove(&id)
+            .ok_or(ApiError::AttachmentNotFound)?;
+
+        if let Some(object) = lock.objects.get_mut(&digest) {
+            object.refs -= 1;
+            if object.refs == 0 {
+                lock.objects.remove(&digest);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryStorageRepository;
+    use crate::attachment::{models::Attachment, repository::StorageRepository};
+    use axum::body::Bytes;
+    use chrono::Utc;
+    use tokio_stream::StreamExt;
+    use uuid::Uuid;
+
+    fn attachment(size: usize) -> Attachment {
+        Attachment {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            content_type: "image/png".into(),
+            size: size as u64,
+            width: 1,
+            height: 1,
+            thumbnail: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let repo = InMemoryStorageRepository::new();
+        let data = Bytes::from_static(b"same file");
+
+        let first = attachment(data.len());
+        let second = attachment(data.len());
+        repo.store(first.clone(), data.clone()).await.unwrap();
+        repo.store(second.clone(), data.clone()).await.unwrap();
+        assert_eq!(repo.object_count().await, 1);
+
+        repo.delete(first.id).await.unwrap();
+        assert!(repo.get_by_id(first.id).await.unwrap().is_none());
+        let mut stream = repo
+            .get_stream(second.id, 0..data.len() as u64)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), data);
+
+        repo.delete(second.id).await.unwrap();
+        assert_eq!(repo.object_count().await, 0);
+        assert!(repo.delete(second.id).await.is_err());
+    }
+}
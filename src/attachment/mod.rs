@@ -0,0 +1,7 @@
+pub mod handlers;
+pub mod memory_repository;
+pub mod models;
+pub mod range;
+pub mod repository;
+pub mod thumbnail;
+pub mod validation;
@@ -0,0 +1,43 @@
+use crate::http::ApiResponder;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub content_type: String,
+    /// The size of the file in bytes
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    /// The downscaled preview of the image, served at
+    /// `/attachments/:attachment_id/thumbnail`. Images that are already small
+    /// and animated ones have no thumbnail, so the original is served instead
+    #[serde(default)]
+    pub thumbnail: Option<AttachmentThumbnail>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttachmentThumbnail {
+    pub content_type: String,
+    /// The size of the thumbnail in bytes
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ApiResponder for Attachment {
+    #[inline]
+    fn unit() -> &'static str {
+        "attachment"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "An"
+    }
+}
@@ -0,0 +1,82 @@
+use std::ops::Range;
+
+/// The part of a file requested by a `Range` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The whole file, when no range or an unsupported one was requested
+    Full,
+    /// The requested bytes, with an exclusive end
+    Partial(Range<u64>),
+    /// The range starts past the end of the file
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parses a single `bytes` range of a file with `size` bytes. Malformed
+    /// and multipart ranges are ignored, as the header is allowed to be.
+    pub fn parse(header: Option<&str>, size: u64) -> Self {
+        let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+            return Self::Full;
+        };
+        if spec.contains(',') {
+            return Self::Full;
+        }
+        let Some((start, end)) = spec.split_once('-') else {
+            return Self::Full;
+        };
+        let (start, end) = (start.trim(), end.trim());
+
+        if start.is_empty() {
+            // The last `end` bytes of the file
+            return match end.parse::<u64>() {
+                Ok(0) => Self::Unsatisfiable,
+                Ok(_) if size == 0 => Self::Unsatisfiable,
+                Ok(n) => Self::Partial(size.saturating_sub(n)..size),
+                Err(_) => Self::Full,
+            };
+        }
+
+        let Ok(start) = start.parse::<u64>() else {
+            return Self::Full;
+        };
+        let end = if end.is_empty() {
+            size
+        } else {
+            match end.parse::<u64>() {
+                Ok(end) if end >= start => end.saturating_add(1).min(size),
+                _ => return Self::Full,
+            }
+        };
+
+        if start >= size {
+            return Self::Unsatisfiable;
+        }
+
+        Self::Partial(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteRange;
+
+    #[test]
+    fn test_parse() {
+        let parse = |h: &str| ByteRange::parse(Some(h), 1000);
+
+        assert_eq!(ByteRange::parse(None, 1000), ByteRange::Full);
+        assert_eq!(parse("bytes=0-499"), ByteRange::Partial(0..500));
+        assert_eq!(parse("bytes=500-"), ByteRange::Partial(500..1000));
+        assert_eq!(parse("bytes=-100"), ByteRange::Partial(900..1000));
+        assert_eq!(parse("bytes=900-5000"), ByteRange::Partial(900..1000));
+        assert_eq!(parse("bytes=-5000"), ByteRange::Partial(0..1000));
+
+        assert_eq!(parse("bytes=1000-"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0"), ByteRange::Unsatisfiable);
+
+        assert_eq!(parse("bytes=0-1,5-9"), ByteRange::Full);
+        assert_eq!(parse("bytes=9-1"), ByteRange::Full);
+        assert_eq!(parse("items=0-1"), ByteRange::Full);
+        assert_eq!(parse("bytes=a-b"), ByteRange::Full);
+    }
+}
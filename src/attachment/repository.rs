@@ -0,0 +1,45 @@
+use super::models::Attachment;
+use crate::errors::ApiError;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use sha2::{Digest, Sha256};
+use std::{io, ops::Range, pin::Pin};
+use tokio_stream::Stream;
+use uuid::Uuid;
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send>>;
+
+/// Stores the attachment files along with their metadata.
+///
+/// Files are content-addressed: identical uploads share a single stored
+/// object, keyed by their [`content_digest`], and each attachment id maps to
+/// one of those objects. Objects are reference-counted, so deleting an
+/// attachment only removes the file once no other attachment references it.
+#[async_trait]
+pub trait StorageRepository: Sync + Send {
+    async fn store(&self, attachment: Attachment, data: Bytes) -> Result<(), ApiError>;
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Option<Attachment>, ApiError>;
+
+    /// Streams the `range` of the file, which must be within its size.
+    async fn get_stream(&self, id: Uuid, range: Range<u64>)
+        -> Result<Option<ByteStream>, ApiError>;
+
+    /// Stores the thumbnail of an already stored attachment.
+    async fn store_thumbnail(&self, id: Uuid, data: Bytes) -> Result<(), ApiError>;
+
+    async fn get_thumbnail_data(&self, id: Uuid) -> Result<Option<Bytes>, ApiError>;
+
+    async fn delete(&self, id: Uuid) -> Result<(), ApiError>;
+}
+
+/// Computes the hex encoded sha256 digest of the file on a blocking thread,
+/// used as the storage key of the file.
+pub async fn content_digest(data: Bytes) -> Result<String, ApiError> {
+    tokio::task::spawn_blocking(move || format!("{:x}", Sha256::digest(&data)))
+        .await
+        .map_err(|e| {
+            tracing::error!(error = e.to_string(), "Attachment digest task failed");
+            ApiError::AttachmentStoreFailed
+        })
+}
@@ -0,0 +1,115 @@
+use super::models::AttachmentThumbnail;
+use image::{codecs::gif::GifDecoder, AnimationDecoder, ImageFormat, ImageReader, ImageResult};
+use std::io::Cursor;
+
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 320;
+
+/// Generates a downscaled copy of the image that fits in a `max_edge` square,
+/// keeping the aspect ratio. Returns `None` if the image is already small
+/// enough or is an animated gif, which can not be represented by a single
+/// frame.
+///
+/// The image is fully decoded, so this must run on a blocking thread and only
+/// after the image dimensions were validated.
+pub fn generate(data: &[u8], max_edge: u32) -> ImageResult<Option<(AttachmentThumbnail, Vec<u8>)>> {
+    let reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let Some(format) = reader.format() else {
+        return Ok(None);
+    };
+
+    if format == ImageFormat::Gif && is_animated(data)? {
+        return Ok(None);
+    }
+
+    let image = reader.decode()?;
+    if image.width() <= max_edge && image.height() <= max_edge {
+        return Ok(None);
+    }
+
+    let thumbnail = image.thumbnail(max_edge, max_edge);
+
+    // Photos are better compressed as jpeg, everything else keeps its
+    // transparency as png
+    let (format, thumbnail) = if format == ImageFormat::Jpeg {
+        (ImageFormat::Jpeg, thumbnail.into_rgb8().into())
+    } else {
+        (ImageFormat::Png, thumbnail)
+    };
+
+    let mut buf = Cursor::new(Vec::new());
+    thumbnail.write_to(&mut buf, format)?;
+    let buf = buf.into_inner();
+
+    Ok(Some((
+        AttachmentThumbnail {
+            content_type: format.to_mime_type().into(),
+            size: buf.len() as u64,
+            width: thumbnail.width(),
+            height: thumbnail.height(),
+        },
+        buf,
+    )))
+}
+
+fn is_animated(data: &[u8]) -> ImageResult<bool> {
+    let frames = GifDecoder::new(Cursor::new(data))?.into_frames();
+    Ok(frames.take(2).count() > 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate;
+    use image::{codecs::gif::GifEncoder, Delay, Frame, ImageFormat, RgbImage, RgbaImage};
+    use std::io::Cursor;
+
+    fn encode(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        RgbImage::new(width, height)
+            .write_to(&mut buf, format)
+            .unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn test_generate() {
+        let (thumbnail, data) = generate(&encode(1000, 500, ImageFormat::Png), 320)
+            .unwrap()
+            .unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (320, 160));
+        assert_eq!(thumbnail.content_type, "image/png");
+        assert_eq!(thumbnail.size, data.len() as u64);
+        assert_eq!(image::guess_format(&data).unwrap(), ImageFormat::Png);
+
+        let (thumbnail, _) = generate(&encode(400, 800, ImageFormat::Jpeg), 320)
+            .unwrap()
+            .unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (160, 320));
+        assert_eq!(thumbnail.content_type, "image/jpeg");
+
+        // Already small enough
+        assert!(generate(&encode(320, 100, ImageFormat::Png), 320)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_generate_animated_gif() {
+        let mut buf = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut buf);
+            let frames = (0..2).map(|_| {
+                Frame::from_parts(
+                    RgbaImage::new(640, 640),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(100, 1),
+                )
+            });
+            encoder.encode_frames(frames).unwrap();
+        }
+        assert!(generate(&buf, 320).unwrap().is_none());
+
+        let still = encode(640, 640, ImageFormat::Gif);
+        assert!(generate(&still, 320).unwrap().is_some());
+    }
+}
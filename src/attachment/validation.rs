@@ -0,0 +1,146 @@
+use crate::errors::ApiError;
+use image::ImageReader;
+use std::io::Cursor;
+
+pub const DEFAULT_ALLOWED_TYPES: &[&str] = &["image/png", "image/jpeg", "image/webp", "image/gif"];
+
+/// The restrictions applied to the uploaded attachments.
+#[derive(Debug, Clone)]
+pub struct AttachmentLimits {
+    pub allowed_types: Vec<String>,
+    /// The maximum size of the file in bytes
+    pub max_size: usize,
+    /// The maximum width and height of the image in pixels, that prevents
+    /// decompression bombs from being stored
+    pub max_dimension: u32,
+}
+
+impl Default for AttachmentLimits {
+    fn default() -> Self {
+        Self {
+            allowed_types: DEFAULT_ALLOWED_TYPES
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+            max_size: 8 * 1024 * 1024,
+            max_dimension: 8192,
+        }
+    }
+}
+
+/// The properties of an attachment that passed the validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedImage {
+    pub content_type: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AttachmentLimits {
+    /// Checks the declared content type against the allowlist and against the
+    /// one sniffed from the file, then the size and the dimensions of the
+    /// image. Only the image header is read, the image is never decoded.
+    pub fn validate(&self, declared_type: &str, data: &[u8]) -> Result<ValidatedImage, ApiError> {
+        if data.len() > self.max_size {
+            return Err(ApiError::AttachmentInvalid(format!(
+                "the file must be at most {} bytes long",
+                self.max_size
+            )));
+        }
+
+        let declared_type = declared_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        if !self.allowed_types.contains(&declared_type) {
+            return Err(ApiError::AttachmentInvalid(format!(
+                "the content type '{declared_type}' is not allowed"
+            )));
+        }
+
+        let reader = ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|_| ApiError::AttachmentInvalid("the file could not be read".into()))?;
+
+        let content_type = reader
+            .format()
+            .map(|f| f.to_mime_type())
+            .ok_or_else(|| ApiError::AttachmentInvalid("the file is not a known image".into()))?;
+
+        if content_type != declared_type {
+            return Err(ApiError::AttachmentInvalid(format!(
+                "the file is a '{content_type}' but was declared as '{declared_type}'"
+            )));
+        }
+
+        let (width, height) = reader
+            .into_dimensions()
+            .map_err(|_| ApiError::AttachmentInvalid("the image is malformed".into()))?;
+
+        if width > self.max_dimension || height > self.max_dimension {
+            return Err(ApiError::AttachmentInvalid(format!(
+                "the image must be at most {0}x{0} pixels",
+                self.max_dimension
+            )));
+        }
+
+        Ok(ValidatedImage {
+            content_type,
+            width,
+            height,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttachmentLimits, ValidatedImage};
+    use crate::errors::ApiError;
+    use image::{ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        RgbImage::new(width, height)
+            .write_to(&mut buf, ImageFormat::Png)
+            .unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn test_validate() {
+        let limits = AttachmentLimits {
+            max_dimension: 64,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            limits.validate("image/png; charset=binary", &png(32, 16)),
+            Ok(ValidatedImage {
+                content_type: "image/png",
+                width: 32,
+                height: 16,
+            }),
+        );
+
+        let invalid = |res| matches!(res, Err(ApiError::AttachmentInvalid(_)));
+
+        // Declared type does not match the sniffed one
+        assert!(invalid(limits.validate("image/jpeg", &png(32, 16))));
+        // Not in the allowlist
+        assert!(invalid(limits.validate("image/svg+xml", &png(32, 16))));
+        // Not an image
+        assert!(invalid(limits.validate("image/png", b"hello world")));
+        // Too many pixels
+        assert!(invalid(limits.validate("image/png", &png(65, 1))));
+
+        let limits = AttachmentLimits {
+            max_size: 16,
+            ..Default::default()
+        };
+        assert!(invalid(limits.validate("image/png", &png(1, 1))));
+    }
+}
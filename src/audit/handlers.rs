@@ -0,0 +1,79 @@
+use super::{
+    models::{AuditAction, AuditLog, AuditLogFilter},
+    repository::AuditRepository,
+};
+use crate::{
+    auth::models::UserAuthPayload,
+    errors::ApiError,
+    http::DataResponse,
+    user::{models::UserRole, repository::UserRepository},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[inline(always)]
+fn default_limit() -> u64 {
+    100
+}
+#[inline(always)]
+fn default_offset() -> u64 {
+    0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetManyQueryParams {
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+    #[serde(default = "default_offset")]
+    pub offset: u64,
+    pub actor: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+pub struct AuditHandlers<L: AuditRepository, U: UserRepository> {
+    audit_repo: L,
+    user_repo: U,
+}
+
+impl<L: AuditRepository, U: UserRepository> AuditHandlers<L, U> {
+    pub fn new(audit_repo: L, user_repo: U) -> Self {
+        Self {
+            audit_repo,
+            user_repo,
+        }
+    }
+
+    pub async fn handle_get_many(
+        &self,
+        auth: UserAuthPayload,
+        query: GetManyQueryParams,
+    ) -> Result<DataResponse<Vec<AuditLog>>, ApiError> {
+        let user = self
+            .user_repo
+            .get_by_id(auth.sub)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+
+        if user.role != UserRole::Admin {
+            return Err(ApiError::AdminPermissionRequired);
+        }
+
+        let filter = AuditLogFilter {
+            actor: query.actor,
+            action: query.action,
+            after: query.after,
+            before: query.before,
+        };
+
+        let logs = self
+            .audit_repo
+            .get_many(filter, query.offset, query.limit)
+            .await?;
+
+        Ok(logs.into())
+    }
+}
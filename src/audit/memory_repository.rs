@@ -0,0 +1,127 @@
+use super::{
+    models::{AuditLog, AuditLogCreateData, AuditLogFilter},
+    repository::AuditRepository,
+};
+use crate::errors::ApiError;
+use async_trait::async_trait;
+use chrono::Utc;
+use std::{collections::VecDeque, sync::Arc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// The default amount of logs kept in memory, see
+/// [`InMemoryAuditRepository::with_max_entries`].
+pub const DEFAULT_MAX_AUDIT_LOGS: usize = 10_000;
+
+#[derive(Clone)]
+pub struct InMemoryAuditRepository {
+    logs: Arc<Mutex<VecDeque<AuditLog>>>,
+    max_entries: usize,
+}
+
+impl Default for InMemoryAuditRepository {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryAuditRepository {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            logs: Arc::new(Mutex::new(VecDeque::new())),
+            max_entries: DEFAULT_MAX_AUDIT_LOGS,
+        }
+    }
+
+    /// Keeps only the latest `max_entries` logs, dropping the oldest ones as
+    /// new logs are created.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+}
+
+#[async_trait]
+impl AuditRepository for InMemoryAuditRepository {
+    async fn create(&self, data: AuditLogCreateData) -> Result<AuditLog, ApiError> {
+        let log = AuditLog {
+            id: Uuid::now_v7(),
+            created_at: Utc::now(),
+            actor: data.actor,
+            action: data.action,
+            target: data.target,
+            metadata: data.metadata,
+        };
+
+        let mut lock = self.logs.lock().await;
+        if lock.len() >= self.max_entries {
+            lock.pop_front();
+        }
+        lock.push_back(log.clone());
+        drop(lock);
+
+        Ok(log)
+    }
+
+    async fn get_many(
+        &self,
+        filter: AuditLogFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<AuditLog>, ApiError> {
+        let lock = self.logs.lock().await;
+
+        let logs = lock
+            .iter()
+            .rev()
+            .filter(|log| filter.matches(log))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect();
+
+        Ok(logs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryAuditRepository;
+    use crate::audit::{
+        models::{AuditAction, AuditLogCreateData, AuditLogFilter},
+        repository::AuditRepository,
+    };
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_max_entries() {
+        let repo = InMemoryAuditRepository::new().with_max_entries(3);
+
+        let mut targets = Vec::new();
+        for _ in 0..5 {
+            let target = Uuid::new_v4();
+            let data = AuditLogCreateData::new(
+                None,
+                AuditAction::UserSignin,
+                Some(target),
+                serde_json::Value::Null,
+            );
+            repo.create(data).await.unwrap();
+            targets.push(target);
+        }
+
+        let logs = repo
+            .get_many(AuditLogFilter::default(), 0, 100)
+            .await
+            .unwrap();
+        let got = logs
+            .iter()
+            .map(|log| log.target.unwrap())
+            .collect::<Vec<_>>();
+
+        // Only the latest logs are kept, newest first
+        assert_eq!(got, targets.into_iter().skip(2).rev().collect::<Vec<_>>());
+    }
+}
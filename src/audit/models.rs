@@ -0,0 +1,128 @@
+use crate::http::ApiResponder;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditAction {
+    UserSignin,
+    UserSigninFailed,
+    UserInvalidated,
+    UserPasswordReset,
+    UserRoleChanged,
+    ChannelPermissionChanged,
+    ChannelDeleted,
+    MessageDeleted,
+    ConnectionClosed,
+}
+
+impl AuditAction {
+    #[inline]
+    pub fn to_upper_enum(self) -> &'static str {
+        match self {
+            AuditAction::UserSignin => "USER_SIGNIN",
+            AuditAction::UserSigninFailed => "USER_SIGNIN_FAILED",
+            AuditAction::UserInvalidated => "USER_INVALIDATED",
+            AuditAction::UserPasswordReset => "USER_PASSWORD_RESET",
+            AuditAction::UserRoleChanged => "USER_ROLE_CHANGED",
+            AuditAction::ChannelPermissionChanged => "CHANNEL_PERMISSION_CHANGED",
+            AuditAction::ChannelDeleted => "CHANNEL_DELETED",
+            AuditAction::MessageDeleted => "MESSAGE_DELETED",
+            AuditAction::ConnectionClosed => "CONNECTION_CLOSED",
+        }
+    }
+}
+
+impl Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.to_upper_enum())
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "USER_SIGNIN" => Ok(AuditAction::UserSignin),
+            "USER_SIGNIN_FAILED" => Ok(AuditAction::UserSigninFailed),
+            "USER_INVALIDATED" => Ok(AuditAction::UserInvalidated),
+            "USER_PASSWORD_RESET" => Ok(AuditAction::UserPasswordReset),
+            "USER_ROLE_CHANGED" => Ok(AuditAction::UserRoleChanged),
+            "CHANNEL_PERMISSION_CHANGED" => Ok(AuditAction::ChannelPermissionChanged),
+            "CHANNEL_DELETED" => Ok(AuditAction::ChannelDeleted),
+            "MESSAGE_DELETED" => Ok(AuditAction::MessageDeleted),
+            "CONNECTION_CLOSED" => Ok(AuditAction::ConnectionClosed),
+            _ => Err(format!("invalid value {s:?} for enum AuditAction")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLog {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// The user that performed the action, if known
+    pub actor: Option<Uuid>,
+    pub action: AuditAction,
+    /// The id of the resource the action was performed on
+    pub target: Option<Uuid>,
+    pub metadata: serde_json::Value,
+}
+
+impl ApiResponder for AuditLog {
+    #[inline]
+    fn unit() -> &'static str {
+        "audit log"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "An"
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditLogCreateData {
+    pub actor: Option<Uuid>,
+    pub action: AuditAction,
+    pub target: Option<Uuid>,
+    pub metadata: serde_json::Value,
+}
+
+impl AuditLogCreateData {
+    #[inline]
+    pub fn new(
+        actor: Option<Uuid>,
+        action: AuditAction,
+        target: Option<Uuid>,
+        metadata: serde_json::Value,
+    ) -> Self {
+        Self {
+            actor,
+            action,
+            target,
+            metadata,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLogFilter {
+    pub actor: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl AuditLogFilter {
+    pub fn matches(&self, log: &AuditLog) -> bool {
+        self.actor.is_none_or(|v| log.actor == Some(v))
+            && self.action.is_none_or(|v| log.action == v)
+            && self.after.is_none_or(|v| log.created_at >= v)
+            && self.before.is_none_or(|v| log.created_at <= v)
+    }
+}
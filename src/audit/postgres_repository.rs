@@ -0,0 +1,126 @@
+use super::{
+    models::{AuditAction, AuditLog, AuditLogCreateData, AuditLogFilter},
+    repository::AuditRepository,
+};
+use crate::errors::ApiError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{types::Json, Pool, Postgres};
+use uuid::Uuid;
+
+type AuditLogRow = (
+    Uuid,
+    DateTime<Utc>,
+    Option<Uuid>,
+    String,
+    Option<Uuid>,
+    Json<serde_json::Value>,
+);
+
+fn from_row(row: AuditLogRow) -> Result<AuditLog, ApiError> {
+    let (id, created_at, actor, action, target, Json(metadata)) = row;
+
+    let action = action.parse::<AuditAction>().map_err(|e| {
+        tracing::error!(error = e, "PostgresAuditRepository decode error");
+        ApiError::SqlxError
+    })?;
+
+    Ok(AuditLog {
+        id,
+        created_at,
+        actor,
+        action,
+        target,
+        metadata,
+    })
+}
+
+#[derive(Clone)]
+pub struct PostgresAuditRepository {
+    pool: Pool<Postgres>,
+    read_pool: Pool<Postgres>,
+}
+
+impl PostgresAuditRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self {
+            read_pool: pool.clone(),
+            pool,
+        }
+    }
+
+    /// Serves the log listing from `read_pool`, usually a replica.
+    pub fn with_read_pool(mut self, read_pool: Pool<Postgres>) -> Self {
+        self.read_pool = read_pool;
+        self
+    }
+}
+
+#[async_trait]
+impl AuditRepository for PostgresAuditRepository {
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn create(&self, data: AuditLogCreateData) -> Result<AuditLog, ApiError> {
+        let row: AuditLogRow = sqlx::query_as(
+            r#"INSERT INTO "audit_logs"
+            ("id", "actor", "action", "target", "metadata")
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING "id", "created_at", "actor", "action", "target", "metadata""#,
+        )
+        .bind(Uuid::now_v7())
+        .bind(data.actor)
+        .bind(data.action.to_upper_enum())
+        .bind(data.target)
+        .bind(Json(data.metadata))
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                method = "create",
+                "PostgresAuditRepository sqlx error"
+            );
+
+            ApiError::from_sqlx(&e)
+        })?;
+
+        from_row(row)
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn get_many(
+        &self,
+        filter: AuditLogFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<AuditLog>, ApiError> {
+        let rows: Vec<AuditLogRow> = sqlx::query_as(
+            r#"SELECT "id", "created_at", "actor", "action", "target", "metadata"
+            FROM "audit_logs"
+            WHERE ($1::uuid IS NULL OR "actor" = $1)
+            AND ($2::varchar IS NULL OR "action" = $2)
+            AND ($3::timestamptz IS NULL OR "created_at" >= $3)
+            AND ($4::timestamptz IS NULL OR "created_at" <= $4)
+            ORDER BY "created_at" DESC
+            LIMIT $5 OFFSET $6"#,
+        )
+        .bind(filter.actor)
+        .bind(filter.action.map(|a| a.to_upper_enum()))
+        .bind(filter.after)
+        .bind(filter.before)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.read_pool)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                method = "get_many",
+                "PostgresAuditRepository sqlx error"
+            );
+
+            ApiError::from_sqlx(&e)
+        })?;
+
+        rows.into_iter().map(from_row).collect()
+    }
+}
@@ -0,0 +1,33 @@
+use super::models::{AuditLog, AuditLogCreateData, AuditLogFilter};
+use crate::errors::ApiError;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait AuditRepository: Sync + Send + Clone + 'static {
+    async fn create(&self, data: AuditLogCreateData) -> Result<AuditLog, ApiError>;
+
+    /// Returns the matching audit logs, newest first.
+    async fn get_many(
+        &self,
+        filter: AuditLogFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<AuditLog>, ApiError>;
+
+    /// Records the audit log in the background, so that a failure to write it
+    /// never fails the request that triggered it.
+    fn record(&self, data: AuditLogCreateData) {
+        let repo = self.clone();
+
+        tokio::spawn(async move {
+            let action = data.action;
+            if let Err(e) = repo.create(data).await {
+                tracing::error!(
+                    error = e.to_string(),
+                    action = action.to_string(),
+                    "Failed to record audit log"
+                );
+            }
+        });
+    }
+}
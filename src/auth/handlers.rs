@@ -1,17 +1,89 @@
 use super::{
-    models::{InvalidationReason, UserAuthPayload},
+    models::{GatewayConnectionPayload, InvalidationReason, UserAuthPayload},
+    password::needs_rehash,
+    password_policy::PasswordPolicy,
     repository::AuthRepository,
+    totp::{generate_recovery_codes, hash_recovery_code, TotpManager},
 };
 use crate::{
+    audit::{
+        models::{AuditAction, AuditLogCreateData},
+        repository::AuditRepository,
+    },
     errors::ApiError,
     event::{models::AppEvent, repository::EventRepository},
-    http::{ApiResponder, DataResponse},
+    http::{ApiResponder, DataResponse, NoContent},
+    mail::repository::Mailer,
     user::{
-        models::{User, UserCreateData, UserRole},
+        models::{normalize_email, User, UserCreateData, UserFilter, UserRole, UserTotp},
         repository::UserRepository,
     },
 };
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectionIdPathParams {
+    pub connection_id: Uuid,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserIdPathParams {
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsernamePathParams {
+    pub username: String,
+}
+
+/// The maximum amount of users returned in a page of
+/// [`AuthHandlers::handle_get_users`], larger limits are clamped to it.
+pub const USERS_PAGE_MAX_LEN: u64 = 100;
+
+#[inline(always)]
+fn default_limit() -> u64 {
+    USERS_PAGE_MAX_LEN
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetUsersQueryParams {
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+    #[serde(default)]
+    pub offset: u64,
+    pub role: Option<UserRole>,
+    pub email: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+}
+
+/// A page of users, along with the amount of users matching the filters.
+#[derive(Debug, Serialize)]
+pub struct UsersResponseBody {
+    pub items: Vec<User>,
+    pub total: u64,
+}
+
+impl ApiResponder for UsersResponseBody {
+    fn unit() -> &'static str {
+        "user list"
+    }
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetRoleRequestBody {
+    pub role: UserRole,
+}
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -21,9 +93,15 @@ pub struct SignInRequestBody {
 }
 
 #[derive(Debug, Serialize)]
-pub struct SignInResponseBody {
-    pub auth_token: String,
-    pub refresh_token: String,
+#[serde(untagged)]
+pub enum SignInResponseBody {
+    Authenticated {
+        auth_token: String,
+        refresh_token: String,
+    },
+    /// Returned when the user has two-factor authentication enabled, the
+    /// token must be sent along with a code to `/auth/2fa/challenge`
+    ChallengeRequired { challenge_token: String },
 }
 
 impl ApiResponder for SignInResponseBody {
@@ -49,32 +127,270 @@ impl ApiResponder for InvalidationResponseBody {
     }
 }
 
-pub struct AuthHandlers<A: AuthRepository, U: UserRepository, E: EventRepository> {
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RevokeAllQueryParams {
+    /// Whether new tokens must be returned, keeping the current device
+    /// signed in
+    #[serde(default)]
+    pub keep_session: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsernameAvailabilityResponseBody {
+    pub available: bool,
+}
+
+impl ApiResponder for UsernameAvailabilityResponseBody {
+    fn unit() -> &'static str {
+        "username availability"
+    }
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeAllResponseBody {
+    pub reason: InvalidationReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+impl ApiResponder for RevokeAllResponseBody {
+    fn unit() -> &'static str {
+        "session revocation response payload"
+    }
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+/// The result of a token validation, only the claims of the token are used so
+/// the user is not fetched.
+#[derive(Debug, Serialize)]
+pub struct TokenValidationResponseBody {
+    pub valid: bool,
+    pub user_id: Uuid,
+    /// The unix timestamp in seconds the token expires at
+    pub exp: u64,
+}
+
+impl ApiResponder for TokenValidationResponseBody {
+    fn unit() -> &'static str {
+        "token validation"
+    }
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerifyEmailRequestBody {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForgotPasswordRequestBody {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResetPasswordRequestBody {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TwoFactorVerifyRequestBody {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TwoFactorChallengeRequestBody {
+    pub challenge_token: String,
+    /// Either a totp code or one of the recovery codes
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorEnrollResponseBody {
+    pub secret: String,
+    pub otpauth_url: String,
+}
+
+impl ApiResponder for TwoFactorEnrollResponseBody {
+    fn unit() -> &'static str {
+        "two-factor enrollment payload"
+    }
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorRecoveryCodesResponseBody {
+    pub recovery_codes: Vec<String>,
+}
+
+impl ApiResponder for TwoFactorRecoveryCodesResponseBody {
+    fn unit() -> &'static str {
+        "two-factor recovery codes payload"
+    }
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionsResponseBody {
+    count: usize,
+    connections: Vec<GatewayConnectionPayload>,
+}
+
+impl ApiResponder for ConnectionsResponseBody {
+    #[inline]
+    fn unit() -> &'static str {
+        "list of gateway connections"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+pub struct AuthHandlers<A, U, E, M, L>
+where
+    A: AuthRepository,
+    U: UserRepository,
+    E: EventRepository,
+    M: Mailer,
+    L: AuditRepository,
+{
     auth_repo: A,
     user_repo: U,
     event_repo: E,
+    mailer: M,
+    audit_repo: L,
+    totp: TotpManager,
+    require_email_verification: bool,
+    bootstrap_admin_email: Option<String>,
+    password_policy: PasswordPolicy,
 }
 
-impl<A: AuthRepository, U: UserRepository, E: EventRepository> AuthHandlers<A, U, E> {
-    pub fn new(auth_repo: A, user_repo: U, event_repo: E) -> Self {
+impl<A, U, E, M, L> AuthHandlers<A, U, E, M, L>
+where
+    A: AuthRepository,
+    U: UserRepository,
+    E: EventRepository,
+    M: Mailer,
+    L: AuditRepository,
+{
+    pub fn new(
+        auth_repo: A,
+        user_repo: U,
+        event_repo: E,
+        mailer: M,
+        audit_repo: L,
+        totp: TotpManager,
+    ) -> Self {
         Self {
             auth_repo,
             user_repo,
             event_repo,
+            mailer,
+            audit_repo,
+            totp,
+            require_email_verification: false,
+            bootstrap_admin_email: None,
+            password_policy: PasswordPolicy::default(),
+        }
+    }
+
+    /// Rejects the signin of users that did not verify their email address.
+    pub fn with_require_email_verification(mut self, require: bool) -> Self {
+        self.require_email_verification = require;
+        self
+    }
+
+    /// Grants [`UserRole::Admin`] to the user that signs up with the email,
+    /// so the first admin can be created. The email should be removed once
+    /// the admin signed up, and email verification should be required for
+    /// signins, otherwise anyone could claim it first.
+    pub fn with_bootstrap_admin_email(mut self, email: String) -> Self {
+        self.bootstrap_admin_email = Some(email);
+        self
+    }
+
+    /// Sets the requirements of the passwords chosen on signup and on
+    /// password reset.
+    pub fn with_password_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.password_policy = policy;
+        self
+    }
+
+    /// Records the signin of the user, a failure is only logged since it must
+    /// not prevent the signin.
+    async fn touch_last_login(&self, user_id: Uuid) {
+        if let Err(e) = self.user_repo.touch_last_login(user_id).await {
+            tracing::warn!(
+                user_id = user_id.to_string(),
+                error = e.to_string(),
+                "Failed to update the last login of the user"
+            );
         }
     }
 
+    async fn send_email_verification(&self, user: &User) -> Result<(), ApiError> {
+        let token = self.auth_repo.create_email_verification(user.id).await?;
+
+        self.mailer
+            .send(
+                user.email.clone(),
+                "Verify your email address".into(),
+                format!(
+                    "Hello {}, use the following token to verify your email address: {token}",
+                    user.username
+                ),
+            )
+            .await
+    }
+
     pub async fn handle_signin(
         &self,
         body: SignInRequestBody,
     ) -> Result<DataResponse<SignInResponseBody>, ApiError> {
-        let user = self
-            .user_repo
-            .get_by_email(body.email)
-            .await?
-            .ok_or(ApiError::AuthFailed)?;
+        // The lockout must not be bypassed by changing the case of the email
+        let email = normalize_email(&body.email);
+        self.auth_repo.add_login_attempt(&email).await?;
 
-        let auth_token = self
+        let user = match self.user_repo.get_by_email(email.clone()).await? {
+            Some(v) => v,
+            None => {
+                self.audit_repo.record(AuditLogCreateData::new(
+                    None,
+                    AuditAction::UserSigninFailed,
+                    None,
+                    serde_json::json!({ "email": email }),
+                ));
+                self.auth_repo.login_unknown_user(body.password).await?;
+                return Err(ApiError::AuthFailed);
+            }
+        };
+
+        let user_id = user.id;
+        let email_verified = user.email_verified;
+        // Legacy hashes are replaced once the password is proven correct
+        let rehash_password = needs_rehash(&user.password).then(|| body.password.clone());
+
+        let auth_token = match self
             .auth_repo
             .login_user(
                 user.id,
@@ -83,11 +399,225 @@ impl<A: AuthRepository, U: UserRepository, E: EventRepository> AuthHandlers<A, U
                 user.password,
                 body.password,
             )
+            .await
+        {
+            Ok(v) => v,
+            Err(ApiError::AuthFailed) => {
+                self.audit_repo.record(AuditLogCreateData::new(
+                    Some(user_id),
+                    AuditAction::UserSigninFailed,
+                    Some(user_id),
+                    serde_json::json!({ "email": email }),
+                ));
+                return Err(ApiError::AuthFailed);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(password) = rehash_password {
+            // A failed migration must not prevent the signin, it is retried
+            // on the next one
+            if let Err(e) = self.user_repo.update_password(user_id, password).await {
+                tracing::warn!(
+                    user_id = user_id.to_string(),
+                    error = e.to_string(),
+                    "Failed to rehash legacy password"
+                );
+            }
+        }
+
+        if self.require_email_verification && !email_verified {
+            return Err(ApiError::EmailNotVerified);
+        }
+
+        // The attempts are only cleared once the user is fully authenticated,
+        // otherwise signing in again would reset the ones of the challenge
+        let totp = self.user_repo.get_totp(user.id).await?;
+        if totp.is_some_and(|t| t.enabled) {
+            let challenge_token = self.auth_repo.create_2fa_challenge(user.id).await?;
+
+            return Ok(SignInResponseBody::ChallengeRequired { challenge_token }.into());
+        }
+
+        self.auth_repo.clear_login_attempts(&email).await?;
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(user_id),
+            AuditAction::UserSignin,
+            Some(user_id),
+            serde_json::json!({ "two_factor": false }),
+        ));
+
+        let refresh_token = self.auth_repo.get_refresh_token(user.id).await?;
+        self.touch_last_login(user.id).await;
+
+        Ok(SignInResponseBody::Authenticated {
+            auth_token,
+            refresh_token,
+        }
+        .into())
+    }
+
+    pub async fn handle_reset_password(
+        &self,
+        body: ResetPasswordRequestBody,
+    ) -> Result<DataResponse<()>, ApiError> {
+        const REASON: InvalidationReason = InvalidationReason::PasswordChanged;
+
+        self.password_policy.validate(&body.password)?;
+        let user_id = self.auth_repo.consume_password_reset(body.token).await?;
+
+        let user = self
+            .user_repo
+            .update_password(user_id, body.password)
             .await?;
 
+        self.auth_repo.add_invalidation(user.id, REASON).await?;
+        self.auth_repo.clear_login_attempts(&user.email).await?;
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(user.id),
+            AuditAction::UserPasswordReset,
+            Some(user.id),
+            serde_json::Value::Null,
+        ));
+
+        self.event_repo
+            .publish(AppEvent::UserInvalidated(user.id, REASON))
+            .await?;
+
+        Ok(DataResponse {
+            data: (),
+            message: Some("Password reset".into()),
+            http_code: Some(StatusCode::OK),
+        })
+    }
+
+    pub async fn handle_2fa_enroll(
+        &self,
+        auth: UserAuthPayload,
+    ) -> Result<DataResponse<TwoFactorEnrollResponseBody>, ApiError> {
+        let totp = self.user_repo.get_totp(auth.sub).await?;
+        if totp.is_some_and(|t| t.enabled) {
+            return Err(ApiError::TwoFactorAlreadyEnabled);
+        }
+
+        let enrollment = self.totp.generate(auth.email)?;
+
+        self.user_repo
+            .set_totp(
+                auth.sub,
+                Some(UserTotp {
+                    secret: enrollment.encrypted_secret,
+                    enabled: false,
+                    recovery_codes: Vec::new(),
+                }),
+            )
+            .await?;
+
+        Ok(TwoFactorEnrollResponseBody {
+            secret: enrollment.secret,
+            otpauth_url: enrollment.otpauth_url,
+        }
+        .into())
+    }
+
+    pub async fn handle_2fa_verify(
+        &self,
+        auth: UserAuthPayload,
+        body: TwoFactorVerifyRequestBody,
+    ) -> Result<DataResponse<TwoFactorRecoveryCodesResponseBody>, ApiError> {
+        let mut totp = self
+            .user_repo
+            .get_totp(auth.sub)
+            .await?
+            .ok_or(ApiError::TwoFactorNotEnrolled)?;
+
+        if totp.enabled {
+            return Err(ApiError::TwoFactorAlreadyEnabled);
+        }
+        let step = self
+            .totp
+            .verify(&totp.secret, &body.code)?
+            .ok_or(ApiError::TwoFactorCodeInvalid)?;
+        if !self.user_repo.use_totp_step(auth.sub, step).await? {
+            return Err(ApiError::TwoFactorCodeInvalid);
+        }
+
+        let (recovery_codes, hashes) = generate_recovery_codes();
+        totp.enabled = true;
+        totp.recovery_codes = hashes;
+
+        self.user_repo.set_totp(auth.sub, Some(totp)).await?;
+
+        Ok(TwoFactorRecoveryCodesResponseBody { recovery_codes }.into())
+    }
+
+    pub async fn handle_2fa_challenge(
+        &self,
+        body: TwoFactorChallengeRequestBody,
+    ) -> Result<DataResponse<SignInResponseBody>, ApiError> {
+        // The challenge is only consumed once the code is accepted, so a typo
+        // does not send the user back to the signin
+        let user_id = self
+            .auth_repo
+            .get_2fa_challenge(&body.challenge_token)
+            .await?;
+
+        let user = self
+            .user_repo
+            .get_by_id(user_id)
+            .await?
+            .ok_or(ApiError::TwoFactorChallengeInvalid)?;
+
+        self.auth_repo.add_login_attempt(&user.email).await?;
+
+        let totp = self
+            .user_repo
+            .get_totp(user.id)
+            .await?
+            .filter(|t| t.enabled)
+            .ok_or(ApiError::TwoFactorNotEnrolled)?;
+
+        let accepted = match self.totp.verify(&totp.secret, &body.code)? {
+            Some(step) => self.user_repo.use_totp_step(user.id, step).await?,
+            None => {
+                let hash = hash_recovery_code(&body.code);
+                self.user_repo.use_recovery_code(user.id, &hash).await?
+            }
+        };
+        if !accepted {
+            self.audit_repo.record(AuditLogCreateData::new(
+                Some(user.id),
+                AuditAction::UserSigninFailed,
+                Some(user.id),
+                serde_json::json!({ "email": user.email, "two_factor": true }),
+            ));
+            return Err(ApiError::TwoFactorCodeInvalid);
+        }
+
+        let consumed = self
+            .auth_repo
+            .consume_2fa_challenge(body.challenge_token)
+            .await?;
+        if consumed != user.id {
+            return Err(ApiError::TwoFactorChallengeInvalid);
+        }
+
+        self.auth_repo.clear_login_attempts(&user.email).await?;
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(user.id),
+            AuditAction::UserSignin,
+            Some(user.id),
+            serde_json::json!({ "two_factor": true }),
+        ));
+
+        let auth_token = self
+            .auth_repo
+            .generate_token(user.id, user.username, user.email)
+            .await?;
         let refresh_token = self.auth_repo.get_refresh_token(user.id).await?;
+        self.touch_last_login(user.id).await;
 
-        Ok(SignInResponseBody {
+        Ok(SignInResponseBody::Authenticated {
             auth_token,
             refresh_token,
         }
@@ -98,11 +628,107 @@ impl<A: AuthRepository, U: UserRepository, E: EventRepository> AuthHandlers<A, U
         &self,
         body: UserCreateData,
     ) -> Result<DataResponse<User>, ApiError> {
-        let user = self.user_repo.create(UserRole::Common, body).await?;
+        self.password_policy.validate(&body.password)?;
+        if self
+            .user_repo
+            .get_by_username(&body.username)
+            .await?
+            .is_some()
+        {
+            return Err(ApiError::UsernameAlreadyTaken);
+        }
+
+        let is_bootstrap_admin = self
+            .bootstrap_admin_email
+            .as_ref()
+            .is_some_and(|email| email.eq_ignore_ascii_case(&body.email));
+        let role = if is_bootstrap_admin {
+            UserRole::Admin
+        } else {
+            UserRole::Common
+        };
+
+        let user = self.user_repo.create(role, body).await?;
+        if is_bootstrap_admin {
+            tracing::info!(user_id = user.id.to_string(), "Bootstrap admin signed up");
+        }
+
+        if let Err(e) = self.send_email_verification(&user).await {
+            tracing::error!(
+                error = e.to_string(),
+                user_id = user.id.to_string(),
+                "Failed to send email verification"
+            );
+        }
+
+        Ok(user.into())
+    }
+
+    /// Tells whether a user could sign up with the username, ignoring the
+    /// case.
+    pub async fn handle_username_availability(
+        &self,
+        path: UsernamePathParams,
+    ) -> Result<DataResponse<UsernameAvailabilityResponseBody>, ApiError> {
+        let taken = self.user_repo.get_by_username(&path.username).await?;
+
+        Ok(UsernameAvailabilityResponseBody {
+            available: taken.is_none(),
+        }
+        .into())
+    }
+
+    pub async fn handle_verify_email(
+        &self,
+        body: VerifyEmailRequestBody,
+    ) -> Result<DataResponse<User>, ApiError> {
+        let user_id = self
+            .auth_repo
+            .consume_email_verification(body.token)
+            .await?;
+
+        let user = self.user_repo.set_email_verified(user_id, true).await?;
 
         Ok(user.into())
     }
 
+    pub async fn handle_request_email_verification(
+        &self,
+        auth: UserAuthPayload,
+    ) -> Result<DataResponse<()>, ApiError> {
+        let user = self
+            .user_repo
+            .get_by_id(auth.sub)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+
+        if user.email_verified {
+            return Err(ApiError::EmailAlreadyVerified);
+        }
+
+        self.send_email_verification(&user).await?;
+
+        Ok(DataResponse {
+            data: (),
+            message: Some("Verification email sent".into()),
+            http_code: Some(StatusCode::OK),
+        })
+    }
+
+    /// Reports the token as valid, the [`crate::auth::http::AuthExtractor`]
+    /// already rejected it otherwise.
+    pub fn handle_validate(
+        &self,
+        auth: UserAuthPayload,
+    ) -> Result<DataResponse<TokenValidationResponseBody>, ApiError> {
+        Ok(TokenValidationResponseBody {
+            valid: true,
+            user_id: auth.sub,
+            exp: auth.exp,
+        }
+        .into())
+    }
+
     pub async fn handle_get_self(
         &self,
         auth: UserAuthPayload,
@@ -116,6 +742,146 @@ impl<A: AuthRepository, U: UserRepository, E: EventRepository> AuthHandlers<A, U
         Ok(user.into())
     }
 
+    pub async fn handle_get_connections(
+        &self,
+        auth: UserAuthPayload,
+    ) -> Result<DataResponse<ConnectionsResponseBody>, ApiError> {
+        let connections = self.auth_repo.get_connections(auth.sub).await?;
+
+        Ok(ConnectionsResponseBody {
+            count: connections.len(),
+            connections,
+        }
+        .into())
+    }
+
+    /// Closes a single gateway connection of any user, leaving their other
+    /// connections and tokens untouched. Only available to admins.
+    pub async fn handle_close_connection(
+        &self,
+        auth: UserAuthPayload,
+        path: ConnectionIdPathParams,
+    ) -> Result<NoContent, ApiError> {
+        let user = self
+            .user_repo
+            .get_by_id(auth.sub)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+
+        if user.role != UserRole::Admin {
+            return Err(ApiError::AdminPermissionRequired);
+        }
+
+        self.event_repo
+            .publish(AppEvent::ConnectionClosed {
+                connection_id: path.connection_id,
+            })
+            .await?;
+
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(auth.sub),
+            AuditAction::ConnectionClosed,
+            Some(path.connection_id),
+            serde_json::json!({}),
+        ));
+
+        Ok(NoContent)
+    }
+
+    /// Lists the users matching the filters. Only available to admins.
+    pub async fn handle_get_users(
+        &self,
+        auth: UserAuthPayload,
+        query: GetUsersQueryParams,
+    ) -> Result<DataResponse<UsersResponseBody>, ApiError> {
+        let actor = self
+            .user_repo
+            .get_by_id(auth.sub)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+
+        if actor.role != UserRole::Admin {
+            return Err(ApiError::AdminPermissionRequired);
+        }
+
+        let filter = UserFilter {
+            role: query.role,
+            email: query.email,
+            created_after: query.created_after,
+        };
+
+        let items = self
+            .user_repo
+            .list(&filter, query.offset, query.limit.min(USERS_PAGE_MAX_LEN))
+            .await?;
+        let total = self.user_repo.count(&filter).await?;
+
+        Ok(UsersResponseBody { items, total }.into())
+    }
+
+    /// Looks up a user by their username, ignoring the case. Only available to
+    /// admins.
+    pub async fn handle_get_user_by_username(
+        &self,
+        auth: UserAuthPayload,
+        path: UsernamePathParams,
+    ) -> Result<DataResponse<User>, ApiError> {
+        let actor = self
+            .user_repo
+            .get_by_id(auth.sub)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+
+        if actor.role != UserRole::Admin {
+            return Err(ApiError::AdminPermissionRequired);
+        }
+
+        let user = self
+            .user_repo
+            .get_by_username(&path.username)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+
+        Ok(user.into())
+    }
+
+    /// Promotes or demotes a user, invalidating their tokens so the new role
+    /// takes effect right away. Only available to admins.
+    pub async fn handle_set_role(
+        &self,
+        auth: UserAuthPayload,
+        path: UserIdPathParams,
+        body: SetRoleRequestBody,
+    ) -> Result<DataResponse<User>, ApiError> {
+        const REASON: InvalidationReason = InvalidationReason::RoleChanged;
+
+        let actor = self
+            .user_repo
+            .get_by_id(auth.sub)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+
+        if actor.role != UserRole::Admin {
+            return Err(ApiError::AdminPermissionRequired);
+        }
+
+        let user = self.user_repo.set_role(path.user_id, body.role).await?;
+
+        self.auth_repo.add_invalidation(user.id, REASON).await?;
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(auth.sub),
+            AuditAction::UserRoleChanged,
+            Some(user.id),
+            serde_json::json!({ "role": user.role }),
+        ));
+
+        self.event_repo
+            .publish(AppEvent::UserInvalidated(user.id, REASON))
+            .await?;
+
+        Ok(user.into())
+    }
+
     pub async fn handle_invalidate(
         &self,
         auth: UserAuthPayload,
@@ -125,6 +891,12 @@ impl<A: AuthRepository, U: UserRepository, E: EventRepository> AuthHandlers<A, U
         self.auth_repo
             .add_invalidation(auth.sub, DEFAULT_REASON)
             .await?;
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(auth.sub),
+            AuditAction::UserInvalidated,
+            Some(auth.sub),
+            serde_json::json!({ "reason": DEFAULT_REASON }),
+        ));
 
         self.event_repo
             .publish(AppEvent::UserInvalidated(auth.sub, DEFAULT_REASON))
@@ -135,4 +907,118 @@ impl<A: AuthRepository, U: UserRepository, E: EventRepository> AuthHandlers<A, U
         }
         .into())
     }
+
+    /// Signs the user out of every session: the refresh token is revoked and
+    /// all the tokens issued so far are invalidated, which also disconnects
+    /// the gateway connections. New tokens, issued after the invalidation, are
+    /// returned if the client asked to keep the current session.
+    pub async fn handle_revoke_all(
+        &self,
+        auth: UserAuthPayload,
+        query: RevokeAllQueryParams,
+    ) -> Result<DataResponse<RevokeAllResponseBody>, ApiError> {
+        const REASON: InvalidationReason = InvalidationReason::Requested;
+
+        self.auth_repo.add_invalidation(auth.sub, REASON).await?;
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(auth.sub),
+            AuditAction::UserInvalidated,
+            Some(auth.sub),
+            serde_json::json!({ "reason": REASON, "refresh_token_revoked": true }),
+        ));
+
+        self.event_repo
+            .publish(AppEvent::UserInvalidated(auth.sub, REASON))
+            .await?;
+
+        if !query.keep_session {
+            return Ok(RevokeAllResponseBody {
+                reason: REASON,
+                auth_token: None,
+                refresh_token: None,
+            }
+            .into());
+        }
+
+        let user = self
+            .user_repo
+            .get_by_id(auth.sub)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+        let auth_token = self
+            .auth_repo
+            .generate_token(user.id, user.username, user.email)
+            .await?;
+        let refresh_token = self.auth_repo.get_refresh_token(user.id).await?;
+
+        Ok(RevokeAllResponseBody {
+            reason: REASON,
+            auth_token: Some(auth_token),
+            refresh_token: Some(refresh_token),
+        }
+        .into())
+    }
+}
+
+impl<A, U, E, M, L> AuthHandlers<A, U, E, M, L>
+where
+    A: AuthRepository + Clone + 'static,
+    U: UserRepository + Clone + 'static,
+    E: EventRepository,
+    M: Mailer + Clone + 'static,
+    L: AuditRepository,
+{
+    /// Sends the password reset email in the background. The response, and
+    /// the time it takes, are the same whether the user exists or not.
+    pub async fn handle_forgot_password(
+        &self,
+        body: ForgotPasswordRequestBody,
+    ) -> Result<DataResponse<()>, ApiError> {
+        let auth_repo = self.auth_repo.clone();
+        let user_repo = self.user_repo.clone();
+        let mailer = self.mailer.clone();
+
+        tokio::spawn(async move {
+            let res = send_password_reset(&auth_repo, &user_repo, &mailer, body.email).await;
+            if let Err(e) = res {
+                tracing::error!(error = e.to_string(), "Failed to send password reset email");
+            }
+        });
+
+        Ok(DataResponse {
+            data: (),
+            message: Some("If the user exists, a password reset email was sent".into()),
+            http_code: Some(StatusCode::OK),
+        })
+    }
+}
+
+/// Sends a password reset token to the user with the email, if any.
+async fn send_password_reset<A, U, M>(
+    auth_repo: &A,
+    user_repo: &U,
+    mailer: &M,
+    email: String,
+) -> Result<(), ApiError>
+where
+    A: AuthRepository,
+    U: UserRepository,
+    M: Mailer,
+{
+    let Some(user) = user_repo.get_by_email(email).await? else {
+        return Ok(());
+    };
+
+    let token = auth_repo.create_password_reset(user.id).await?;
+
+    mailer
+        .send(
+            user.email,
+            "Reset your password".into(),
+            format!(
+                "Hello {}, use the following token to reset your password: {token}",
+                user.username
+            ),
+        )
+        .await
 }
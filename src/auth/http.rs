@@ -40,12 +40,8 @@ impl<T: AuthRepository + 'static, S: Send + Sync> FromRequestParts<S> for AuthEx
 
         let payload = repo.auth_user(token.to_string()).await?;
 
-        let invalidation = repo.is_invalidated(payload.sub).await?;
-        if let Some(invalidation) = invalidation {
-            if (invalidation.created_at.timestamp() as u64) + 10 > payload.iat {
-                return Err(ApiError::AuthUserInvalidated.into());
-            }
-        }
+        repo.check_invalidation(&payload).await?;
+        repo.touch_session(&payload).await?;
 
         Ok(Self(payload, PhantomData))
     }
@@ -119,7 +115,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_auth_extractor() {
-        const RANDOM_BASE64_STRING: &'static str =
+        const RANDOM_BASE64_STRING: &str =
             "YYX3sUuIw9wbAQOL3XOUkOwWE5JCx32VLae5t0mo7Zpqx17PT9UFl58Yj3QQetBn";
 
         let uuid = Uuid::new_v4();
@@ -132,7 +128,8 @@ mod tests {
             DecodingKey::from_base64_secret(RANDOM_BASE64_STRING).unwrap(),
             3,
             InMemoryCacheRepository::new(),
-        );
+        )
+        .with_leeway(1);
 
         let token = ar
             .generate_token(uuid, username.into(), email.into())
@@ -147,8 +144,11 @@ mod tests {
 
         mock_must_fail_req(ar.clone(), &token).await;
 
+        // The invalidation is gone by now, but it outlived the token, that is
+        // never accepted again
         tokio::time::sleep(Duration::from_secs(15)).await;
 
-        mock_must_success_req(ar.clone(), &token, uuid, email, username).await;
+        assert!(ar.is_invalidated(uuid).await.unwrap().is_none());
+        mock_must_fail_req(ar.clone(), &token).await;
     }
 }
@@ -1,5 +1,8 @@
 use super::{
-    models::{InvalidationReason, UserAuthPayload, UserInvalidationPayload},
+    models::{
+        GatewayConnectionPayload, InvalidationReason, UserAuthPayload, UserInvalidationPayload,
+    },
+    password::{dummy_hash, verify_password, PasswordHashError, HASH_OVERLOAD_RETRY_AFTER},
     repository::AuthRepository,
 };
 use crate::{cache::repository::CacheRepository, errors::ApiError};
@@ -8,9 +11,26 @@ use base64::{engine::general_purpose, Engine};
 use chrono::Utc;
 use jsonwebtoken::{errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use rand::Rng;
-use tokio::task::spawn_blocking;
 use uuid::Uuid;
 
+/// The extra seconds an invalidation is kept for, on top of the lifetime of
+/// the tokens it rejects.
+const INVALIDATION_TTL_MARGIN: u64 = 10;
+
+/// The default of [`JwtAuthRepository::with_invalidation_skew`].
+pub const DEFAULT_INVALIDATION_SKEW: u64 = 0;
+
+/// The default of [`JwtAuthRepository::with_max_refresh_token_len`].
+pub const DEFAULT_MAX_REFRESH_TOKEN_LEN: usize = 200;
+
+/// The prefixes of the cache keys that can be evicted before they expire,
+/// since losing them only signs the users out or hides their connections.
+/// The rest, like the invalidations and the login failures, must be kept.
+pub const EVICTABLE_CACHE_PREFIXES: &[&str] = &["connections/", "last_seen/"];
+
+/// The length of a base64 encoded refresh token.
+const RF_TOKEN_ENCODED_LEN: usize = 96;
+
 #[derive(Clone)]
 pub struct JwtAuthRepository<C: CacheRepository + Clone> {
     enc_key: EncodingKey,
@@ -19,6 +39,18 @@ pub struct JwtAuthRepository<C: CacheRepository + Clone> {
     algo: Algorithm,
 
     token_duration: u64,
+    issuer: Option<String>,
+    audience: Option<String>,
+
+    max_login_attempts: u32,
+    login_lockout_window: u64,
+
+    email_verification_ttl: u64,
+    password_reset_ttl: u64,
+
+    max_session_idle: u64,
+    invalidation_skew: u64,
+    max_refresh_token_len: usize,
 
     cache_repo: C,
 }
@@ -27,6 +59,10 @@ impl<C> JwtAuthRepository<C>
 where
     C: CacheRepository + Clone,
 {
+    /// Creates the repository issuing tokens valid for `token_duration`
+    /// seconds. The user invalidations are kept for as long as the tokens
+    /// issued before them are accepted, so the duration also bounds how long
+    /// each invalidation stays in the cache (see [`Self::invalidation_ttl`]).
     pub fn new(
         algo: Algorithm,
         enc_key: EncodingKey,
@@ -42,9 +78,138 @@ where
             validation,
             algo,
             token_duration,
+            issuer: None,
+            audience: None,
+            max_login_attempts: 5,
+            login_lockout_window: 900,
+            email_verification_ttl: 86400,
+            password_reset_ttl: 900,
+            max_session_idle: 0,
+            invalidation_skew: DEFAULT_INVALIDATION_SKEW,
+            max_refresh_token_len: DEFAULT_MAX_REFRESH_TOKEN_LEN,
             cache_repo,
         }
     }
+
+    /// Sets the amount of failed login attempts allowed for an email within
+    /// `window` seconds. Setting `max_attempts` to zero disables the lockout.
+    pub fn with_login_lockout(mut self, max_attempts: u32, window: u64) -> Self {
+        self.max_login_attempts = max_attempts;
+        self.login_lockout_window = window;
+        self
+    }
+
+    /// Sets the amount of seconds of clock skew tolerated when validating the
+    /// time based claims of the tokens.
+    pub fn with_leeway(mut self, leeway: u64) -> Self {
+        self.validation.leeway = leeway;
+        self
+    }
+
+    /// Toggles the validation of the token expiration.
+    pub fn with_validate_exp(mut self, validate_exp: bool) -> Self {
+        self.validation.validate_exp = validate_exp;
+        self
+    }
+
+    /// Sets the `iss` claim of the generated tokens and rejects the tokens
+    /// issued by anyone else.
+    pub fn with_issuer(mut self, issuer: String) -> Self {
+        self.validation.set_issuer(&[&issuer]);
+        self.validation.required_spec_claims.insert("iss".into());
+        self.issuer = Some(issuer);
+        self
+    }
+
+    /// Sets the `aud` claim of the generated tokens and rejects the tokens
+    /// meant for any other audience.
+    pub fn with_audience(mut self, audience: String) -> Self {
+        self.validation.set_audience(&[&audience]);
+        self.validation.required_spec_claims.insert("aud".into());
+        self.audience = Some(audience);
+        self
+    }
+
+    pub fn with_email_verification_ttl(mut self, ttl: u64) -> Self {
+        self.email_verification_ttl = ttl;
+        self
+    }
+
+    pub fn with_password_reset_ttl(mut self, ttl: u64) -> Self {
+        self.password_reset_ttl = ttl;
+        self
+    }
+
+    /// Sets the amount of seconds a user can stay without any authenticated
+    /// request before the tokens issued before the last activity are rejected.
+    /// Setting it to zero disables the idle timeout.
+    pub fn with_max_session_idle(mut self, max_idle: u64) -> Self {
+        self.max_session_idle = max_idle;
+        self
+    }
+
+    /// Also rejects the tokens issued up to `skew` seconds after an
+    /// invalidation, to account for the clock differences between the servers
+    /// invalidating and issuing the tokens. Only needed when their clocks are
+    /// not synchronized.
+    pub fn with_invalidation_skew(mut self, skew: u64) -> Self {
+        self.invalidation_skew = skew;
+        self
+    }
+
+    /// Sets the length above which the refresh tokens are rejected before
+    /// being decoded. Values shorter than a valid token are raised to its
+    /// length.
+    pub fn with_max_refresh_token_len(mut self, max_len: usize) -> Self {
+        self.max_refresh_token_len = max_len.max(RF_TOKEN_ENCODED_LEN);
+        self
+    }
+
+    /// The amount of seconds an invalidation must outlive the tokens it
+    /// rejects, the last of which is issued `invalidation_skew` seconds after
+    /// it: their duration, plus the leeway they are still accepted for after
+    /// expiring. Saturates instead of overflowing with huge durations.
+    fn invalidation_ttl(&self) -> u64 {
+        self.token_duration
+            .saturating_add(self.invalidation_skew)
+            .saturating_add(self.validation.leeway)
+            .saturating_add(INVALIDATION_TTL_MARGIN)
+    }
+
+    /// Creates a random single-use token that maps to the user id for `ttl`
+    /// seconds.
+    async fn create_user_token(
+        &self,
+        prefix: &str,
+        user_id: Uuid,
+        ttl: u64,
+    ) -> Result<String, ApiError> {
+        let mut buf: [u8; 32] = [0; 32];
+        rand::thread_rng().fill(&mut buf);
+        let token = general_purpose::URL_SAFE_NO_PAD.encode(buf);
+
+        self.cache_repo
+            .set_ttl(format!("{prefix}/{token}"), user_id.to_string(), ttl)
+            .await?;
+
+        Ok(token)
+    }
+
+    /// Returns the user id a token created by [`Self::create_user_token`] maps
+    /// to, deleting the token. Only one of the concurrent calls with the same
+    /// token gets the id.
+    async fn consume_user_token(
+        &self,
+        prefix: &str,
+        token: String,
+    ) -> Result<Option<Uuid>, ApiError> {
+        let user_id = self
+            .cache_repo
+            .get_delete(format!("{prefix}/{token}"))
+            .await?;
+
+        Ok(user_id.and_then(|v| Uuid::parse_str(&v).ok()))
+    }
 }
 
 #[async_trait]
@@ -71,19 +236,20 @@ where
         user_password: String,
         password: String,
     ) -> Result<String, ApiError> {
-        let b = spawn_blocking(move || bcrypt::verify(password, &user_password))
+        let b = verify_password(password, user_password)
             .await
-            .map_err(|e| {
-                tracing::error!(error = e.to_string(), "Failed to spawn blocking");
-                ApiError::AuthBcryptHashFailed
-            })?
-            .map_err(|e| {
-                tracing::error!(
-                    user_id = user_id.to_string(),
-                    error = e.to_string(),
-                    "Failed to compare user password hash"
-                );
-                ApiError::AuthBcryptHashFailed
+            .map_err(|e| match e {
+                PasswordHashError::Overloaded => ApiError::AuthOverloaded {
+                    retry_after: HASH_OVERLOAD_RETRY_AFTER,
+                },
+                e => {
+                    tracing::error!(
+                        user_id = user_id.to_string(),
+                        error = e.to_string(),
+                        "Failed to compare user password hash"
+                    );
+                    ApiError::AuthBcryptHashFailed
+                }
             })?;
 
         if !b {
@@ -93,6 +259,28 @@ where
         self.generate_token(user_id, username, user_email).await
     }
 
+    async fn login_unknown_user(&self, password: String) -> Result<String, ApiError> {
+        let res = match dummy_hash().await {
+            Ok(hash) => verify_password(password, hash.into()).await,
+            Err(e) => Err(e),
+        };
+
+        res.map_err(|e| match e {
+            PasswordHashError::Overloaded => ApiError::AuthOverloaded {
+                retry_after: HASH_OVERLOAD_RETRY_AFTER,
+            },
+            e => {
+                tracing::error!(
+                    error = e.to_string(),
+                    "Failed to compare dummy password hash"
+                );
+                ApiError::AuthBcryptHashFailed
+            }
+        })?;
+
+        Err(ApiError::AuthFailed)
+    }
+
     async fn get_refresh_token(&self, user_id: Uuid) -> Result<String, ApiError> {
         let key = format!("refresh_token/{user_id}");
 
@@ -109,8 +297,15 @@ where
         Ok(rt)
     }
 
+    async fn revoke_refresh_token(&self, user_id: Uuid) -> Result<(), ApiError> {
+        self.cache_repo
+            .delete(format!("refresh_token/{user_id}"))
+            .await
+    }
+
     async fn parse_refresh_token(&self, token: String) -> Result<Uuid, ApiError> {
-        extract_rf_token_id(&token).ok_or(ApiError::AuthRefreshTokenInvalid)
+        extract_rf_token_id(&token, self.max_refresh_token_len)
+            .ok_or(ApiError::AuthRefreshTokenInvalid)
     }
 
     async fn generate_token(
@@ -119,7 +314,11 @@ where
         username: String,
         email: String,
     ) -> Result<String, ApiError> {
-        let claims = UserAuthPayload::new(user_id, username, email, self.token_duration);
+        let claims = UserAuthPayload {
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            ..UserAuthPayload::new(user_id, username, email, self.token_duration)
+        };
 
         jsonwebtoken::encode(&Header::new(self.algo), &claims, &self.enc_key)
             .or(Err(ApiError::AuthTokenGenerationFailed))
@@ -142,25 +341,169 @@ where
         user_id: Uuid,
         reason: InvalidationReason,
     ) -> Result<(), ApiError> {
-        self.cache_repo
-            .delete(format!("refresh_token/{user_id}"))
-            .await?;
-
-        let now = Utc::now();
+        self.revoke_refresh_token(user_id).await?;
 
-        let value = UserInvalidationPayload {
-            created_at: now,
-            reason,
-        };
+        let value = UserInvalidationPayload::new(Utc::now(), reason);
 
         self.cache_repo
             .ser_set_ttl(
                 format!("user_invalidation/{user_id}"),
                 &value,
-                self.token_duration + 10,
+                self.invalidation_ttl(),
+            )
+            .await
+    }
+
+    async fn check_invalidation(&self, payload: &UserAuthPayload) -> Result<(), ApiError> {
+        let Some(invalidation) = self.is_invalidated(payload.sub).await? else {
+            return Ok(());
+        };
+
+        let invalidated_at = invalidation
+            .timestamp_nanos()
+            .saturating_add(self.invalidation_skew.saturating_mul(1_000_000_000));
+        if payload.issued_at_nanos() <= invalidated_at {
+            return Err(ApiError::AuthUserInvalidated);
+        }
+
+        Ok(())
+    }
+
+    async fn add_login_attempt(&self, email: &str) -> Result<(), ApiError> {
+        if self.max_login_attempts == 0 {
+            return Ok(());
+        }
+
+        // Incremented atomically before deciding, so each of the concurrent
+        // attempts sees its own count
+        let key = format!("login_attempts/{email}");
+        let count = self
+            .cache_repo
+            .incr_ttl(&key, self.login_lockout_window.max(1))
+            .await?;
+
+        if count > self.max_login_attempts.into() {
+            let ttl = self.cache_repo.ttl(&key).await?;
+
+            return Err(ApiError::AccountLocked {
+                retry_after: ttl.unwrap_or(self.login_lockout_window).max(1),
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn clear_login_attempts(&self, email: &str) -> Result<(), ApiError> {
+        self.cache_repo
+            .delete(format!("login_attempts/{email}"))
+            .await
+    }
+
+    async fn touch_session(&self, payload: &UserAuthPayload) -> Result<(), ApiError> {
+        if self.max_session_idle == 0 {
+            return Ok(());
+        }
+
+        let key = format!("last_seen/{}", payload.sub);
+        let now = Utc::now().timestamp().max(0) as u64;
+
+        let last_seen = self
+            .cache_repo
+            .get(&key)
+            .await?
+            .and_then(|v| v.parse::<u64>().ok());
+        let last_active = last_seen.unwrap_or(0).max(payload.iat);
+
+        if now.saturating_sub(last_active) > self.max_session_idle {
+            return Err(ApiError::SessionIdleExpired);
+        }
+
+        self.cache_repo
+            .set_ttl(key, now.to_string(), self.max_session_idle)
+            .await
+    }
+
+    async fn create_2fa_challenge(&self, user_id: Uuid) -> Result<String, ApiError> {
+        const CHALLENGE_TTL: u64 = 300;
+
+        self.create_user_token("2fa_challenge", user_id, CHALLENGE_TTL)
+            .await
+    }
+
+    async fn get_2fa_challenge(&self, token: &str) -> Result<Uuid, ApiError> {
+        let user_id = self
+            .cache_repo
+            .get(format!("2fa_challenge/{token}"))
+            .await?;
+
+        user_id
+            .and_then(|v| Uuid::parse_str(&v).ok())
+            .ok_or(ApiError::TwoFactorChallengeInvalid)
+    }
+
+    async fn consume_2fa_challenge(&self, token: String) -> Result<Uuid, ApiError> {
+        self.consume_user_token("2fa_challenge", token)
+            .await?
+            .ok_or(ApiError::TwoFactorChallengeInvalid)
+    }
+
+    async fn create_email_verification(&self, user_id: Uuid) -> Result<String, ApiError> {
+        self.create_user_token("email_verification", user_id, self.email_verification_ttl)
+            .await
+    }
+
+    async fn consume_email_verification(&self, token: String) -> Result<Uuid, ApiError> {
+        self.consume_user_token("email_verification", token)
+            .await?
+            .ok_or(ApiError::EmailVerificationTokenInvalid)
+    }
+
+    async fn create_password_reset(&self, user_id: Uuid) -> Result<String, ApiError> {
+        self.create_user_token("password_reset", user_id, self.password_reset_ttl)
+            .await
+    }
+
+    async fn consume_password_reset(&self, token: String) -> Result<Uuid, ApiError> {
+        self.consume_user_token("password_reset", token)
+            .await?
+            .ok_or(ApiError::PasswordResetTokenInvalid)
+    }
+
+    async fn set_connection(
+        &self,
+        user_id: Uuid,
+        conn: &GatewayConnectionPayload,
+    ) -> Result<(), ApiError> {
+        const CONNECTION_TTL: u64 = 60;
+
+        self.cache_repo
+            .ser_set_member_ttl(
+                format!("connections/{user_id}"),
+                conn.id.to_string(),
+                conn,
+                CONNECTION_TTL,
             )
             .await
     }
+
+    async fn remove_connection(&self, user_id: Uuid, conn_id: Uuid) -> Result<(), ApiError> {
+        self.cache_repo
+            .remove_member(format!("connections/{user_id}"), conn_id.to_string())
+            .await
+    }
+
+    async fn get_connections(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<GatewayConnectionPayload>, ApiError> {
+        let mut conns: Vec<GatewayConnectionPayload> = self
+            .cache_repo
+            .de_get_members(format!("connections/{user_id}"))
+            .await?;
+        conns.sort_by_key(|c| c.connected_at);
+
+        Ok(conns)
+    }
 }
 
 fn generate_rf_token(id: Uuid) -> String {
@@ -171,17 +514,17 @@ fn generate_rf_token(id: Uuid) -> String {
         *b = t_rng.gen();
     }
 
-    let id = id.as_bytes();
-    let mut i = 0;
-    for b in id {
-        buf[i] = *b;
-        i += 1;
-    }
+    buf[..16].copy_from_slice(id.as_bytes());
 
     general_purpose::STANDARD.encode(buf)
 }
 
-fn extract_rf_token_id(s: &str) -> Option<Uuid> {
+fn extract_rf_token_id(s: &str, max_len: usize) -> Option<Uuid> {
+    // Avoids decoding arbitrarily long inputs
+    if s.len() > max_len {
+        return None;
+    }
+
     let vec = match general_purpose::STANDARD.decode(s) {
         Ok(v) => v,
         Err(_) => return None,
@@ -202,17 +545,303 @@ fn extract_rf_token_id(s: &str) -> Option<Uuid> {
 
 #[cfg(test)]
 mod tests {
-    use super::{extract_rf_token_id, generate_rf_token};
+    use super::{
+        extract_rf_token_id, generate_rf_token, JwtAuthRepository, DEFAULT_MAX_REFRESH_TOKEN_LEN,
+        INVALIDATION_TTL_MARGIN, RF_TOKEN_ENCODED_LEN,
+    };
+    use crate::{
+        auth::{
+            models::{InvalidationReason, UserAuthPayload},
+            password::{dummy_hash, verify_password},
+            repository::AuthRepository,
+        },
+        cache::{memory_repository::InMemoryCacheRepository, repository::CacheRepository},
+        errors::ApiError,
+    };
+    use chrono::Utc;
+    use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
     use uuid::Uuid;
 
+    const RANDOM_BASE64_STRING: &str =
+        "YYX3sUuIw9wbAQOL3XOUkOwWE5JCx32VLae5t0mo7Zpqx17PT9UFl58Yj3QQetBn";
+
+    fn mock_repository() -> JwtAuthRepository<InMemoryCacheRepository> {
+        JwtAuthRepository::new(
+            Algorithm::HS512,
+            EncodingKey::from_base64_secret(RANDOM_BASE64_STRING).unwrap(),
+            DecodingKey::from_base64_secret(RANDOM_BASE64_STRING).unwrap(),
+            3600,
+            InMemoryCacheRepository::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_login_unknown_user() {
+        let ar = mock_repository();
+
+        let hash = dummy_hash().await.unwrap();
+        assert!(!verify_password("".into(), hash.into()).await.unwrap());
+        assert_eq!(
+            ar.login_unknown_user("izanrodrigues".into()).await,
+            Err(ApiError::AuthFailed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalidation_ttl() {
+        let ar = mock_repository().with_leeway(60);
+        assert_eq!(ar.invalidation_ttl(), 3600 + 60 + INVALIDATION_TTL_MARGIN);
+
+        let ar = JwtAuthRepository {
+            token_duration: u64::MAX,
+            ..ar
+        };
+        assert_eq!(ar.invalidation_ttl(), u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_refresh_token() {
+        let ar = mock_repository();
+        let user_id = Uuid::new_v4();
+
+        let token = ar.get_refresh_token(user_id).await.unwrap();
+        assert_eq!(ar.get_refresh_token(user_id).await.unwrap(), token);
+
+        ar.revoke_refresh_token(user_id).await.unwrap();
+        let new_token = ar.get_refresh_token(user_id).await.unwrap();
+        assert_ne!(new_token, token);
+        assert_eq!(ar.parse_refresh_token(new_token).await.unwrap(), user_id);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_token_consumption() {
+        let ar = mock_repository();
+        let user_id = Uuid::new_v4();
+
+        let token = ar.create_password_reset(user_id).await.unwrap();
+        let (first, second) = tokio::join!(
+            ar.consume_password_reset(token.clone()),
+            ar.consume_password_reset(token),
+        );
+        assert!(first.is_ok() ^ second.is_ok());
+        assert_eq!(first.or(second).unwrap(), user_id);
+    }
+
+    #[tokio::test]
+    async fn test_check_invalidation() {
+        let user_id = Uuid::new_v4();
+        // Tokens issued before the sub-second precision was added
+        let payload = |iat| UserAuthPayload {
+            iat,
+            iat_nanos: None,
+            ..UserAuthPayload::new(
+                user_id,
+                "izanrodrigues".into(),
+                "izan@example.com".into(),
+                3600,
+            )
+        };
+
+        for skew in [0, 5] {
+            let ar = mock_repository().with_invalidation_skew(skew);
+            ar.check_invalidation(&payload(0)).await.unwrap();
+
+            ar.add_invalidation(user_id, InvalidationReason::Requested)
+                .await
+                .unwrap();
+            let invalidated_at = ar
+                .is_invalidated(user_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .created_at;
+            let invalidated_at = invalidated_at.timestamp() as u64;
+
+            for iat in [invalidated_at - 1, invalidated_at, invalidated_at + skew] {
+                assert_eq!(
+                    ar.check_invalidation(&payload(iat)).await,
+                    Err(ApiError::AuthUserInvalidated),
+                    "skew {skew}, issued {}s after",
+                    iat as i64 - invalidated_at as i64,
+                );
+            }
+            ar.check_invalidation(&payload(invalidated_at + skew + 1))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signin_after_invalidation() {
+        let ar = mock_repository();
+        let user_id = Uuid::new_v4();
+        let token = || ar.generate_token(user_id, "user".into(), "user@example.com".into());
+
+        // Both tokens are most likely issued in the same second as the
+        // invalidation
+        let before = ar.auth_user(token().await.unwrap()).await.unwrap();
+        ar.add_invalidation(user_id, InvalidationReason::PasswordChanged)
+            .await
+            .unwrap();
+        let after = ar.auth_user(token().await.unwrap()).await.unwrap();
+
+        assert_eq!(
+            ar.check_invalidation(&before).await,
+            Err(ApiError::AuthUserInvalidated)
+        );
+        ar.check_invalidation(&after).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_idle() {
+        let ar = mock_repository().with_max_session_idle(60);
+        let user_id = Uuid::new_v4();
+        let now = Utc::now().timestamp() as u64;
+
+        let mut payload = UserAuthPayload::new(
+            user_id,
+            "izanrodrigues".into(),
+            "izan@example.com".into(),
+            3600,
+        );
+        ar.touch_session(&payload).await.unwrap();
+
+        payload.iat = now - 120;
+        ar.cache_repo
+            .delete(format!("last_seen/{user_id}"))
+            .await
+            .unwrap();
+        assert_eq!(
+            ar.touch_session(&payload).await,
+            Err(ApiError::SessionIdleExpired)
+        );
+
+        // Recent activity keeps the old token alive
+        ar.cache_repo
+            .set(format!("last_seen/{user_id}"), (now - 10).to_string())
+            .await
+            .unwrap();
+        ar.touch_session(&payload).await.unwrap();
+
+        let ar = mock_repository();
+        payload.iat = 0;
+        ar.touch_session(&payload).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_login_lockout() {
+        let ar = mock_repository().with_login_lockout(3, 60);
+        let email = "izanrodrigues999@gmail.com";
+
+        for _ in 0..3 {
+            ar.add_login_attempt(email).await.unwrap();
+        }
+
+        match ar.add_login_attempt(email).await {
+            Err(ApiError::AccountLocked { retry_after }) => assert!(retry_after <= 60),
+            v => panic!("Expected the account to be locked, got {v:?}"),
+        }
+
+        ar.clear_login_attempts(email).await.unwrap();
+        ar.add_login_attempt(email).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_login_attempts() {
+        let ar = mock_repository().with_login_lockout(20, 60);
+        let email = "izanrodrigues999@gmail.com";
+
+        let tasks = (0..50)
+            .map(|_| {
+                let ar = ar.clone();
+                tokio::spawn(async move { ar.add_login_attempt(email).await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut allowed = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(()) => allowed += 1,
+                Err(ApiError::AccountLocked { .. }) => {}
+                Err(e) => panic!("Unexpected error {e:?}"),
+            }
+        }
+
+        // Exactly the allowed attempts get through, however they interleave
+        assert_eq!(allowed, 20);
+    }
+
+    #[tokio::test]
+    async fn test_token_audience() {
+        let ar = mock_repository()
+            .with_issuer("messaging-app".into())
+            .with_audience("messaging-app".into());
+        let other = mock_repository()
+            .with_issuer("messaging-app".into())
+            .with_audience("other-app".into());
+        let user_id = Uuid::new_v4();
+
+        let token = ar
+            .generate_token(user_id, "izanrodrigues".into(), "izan@example.com".into())
+            .await
+            .unwrap();
+
+        let payload = ar.auth_user(token.clone()).await.unwrap();
+        assert_eq!(payload.sub, user_id);
+        assert_eq!(payload.aud.as_deref(), Some("messaging-app"));
+
+        assert_eq!(
+            other.auth_user(token).await.unwrap_err(),
+            ApiError::AuthTokenInvalid
+        );
+
+        let token = mock_repository()
+            .generate_token(user_id, "izanrodrigues".into(), "izan@example.com".into())
+            .await
+            .unwrap();
+        assert_eq!(
+            ar.auth_user(token).await.unwrap_err(),
+            ApiError::AuthTokenInvalid
+        );
+    }
+
     #[test]
     fn test_generate_token() {
         let uuid = Uuid::new_v4();
         let token = generate_rf_token(uuid);
 
-        match extract_rf_token_id(&token) {
+        match extract_rf_token_id(&token, DEFAULT_MAX_REFRESH_TOKEN_LEN) {
             Some(v) => assert_eq!(v, uuid),
             None => panic!("Failed to extract id from generated token"),
         }
     }
+
+    #[test]
+    fn test_extract_oversized_token() {
+        let token = generate_rf_token(Uuid::new_v4());
+        assert_eq!(token.len(), RF_TOKEN_ENCODED_LEN);
+
+        // A valid token padded with more base64 is rejected by its length
+        let oversized = token.repeat(1 << 14);
+        assert_eq!(
+            extract_rf_token_id(&oversized, DEFAULT_MAX_REFRESH_TOKEN_LEN),
+            None
+        );
+        assert_eq!(extract_rf_token_id(&token, RF_TOKEN_ENCODED_LEN - 1), None);
+        assert!(extract_rf_token_id(&token, RF_TOKEN_ENCODED_LEN).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_parse_oversized_refresh_token() {
+        let ar = mock_repository().with_max_refresh_token_len(0);
+
+        let token = generate_rf_token(Uuid::new_v4());
+        assert!(ar.parse_refresh_token(token).await.is_ok());
+        assert_eq!(
+            ar.parse_refresh_token("A".repeat(1 << 20))
+                .await
+                .unwrap_err(),
+            ApiError::AuthRefreshTokenInvalid
+        );
+    }
 }
@@ -2,4 +2,7 @@ pub mod handlers;
 pub mod http;
 pub mod jwt_repository;
 pub mod models;
+pub mod password;
+pub mod password_policy;
 pub mod repository;
+pub mod totp;
@@ -1,6 +1,7 @@
+use crate::errors::ApiError;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::fmt::Display;
+use std::{fmt::Display, net::IpAddr};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,21 +12,126 @@ pub struct UserAuthPayload {
     pub username: String,
     pub exp: u64,
     pub iat: u64,
+    /// The sub-second part of `iat`, which tells apart the tokens issued in
+    /// the same second as an invalidation. Missing in the older tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat_nanos: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
+/// Only stored in the cache and never sent to the clients, so it keeps the
+/// compact unix seconds timestamps.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct UserInvalidationPayload {
     #[serde(with = "chrono::serde::ts_seconds")]
     pub created_at: DateTime<Utc>,
+    /// The sub-second part of `created_at`, missing in the older entries
+    #[serde(default)]
+    pub created_at_nanos: u32,
     pub reason: InvalidationReason,
 }
 
+impl UserInvalidationPayload {
+    pub fn new(created_at: DateTime<Utc>, reason: InvalidationReason) -> Self {
+        Self {
+            created_at,
+            created_at_nanos: created_at.timestamp_subsec_nanos(),
+            reason,
+        }
+    }
+
+    /// The unix timestamp of the invalidation, in nanoseconds.
+    #[inline]
+    pub fn timestamp_nanos(&self) -> u64 {
+        unix_nanos(self.created_at.timestamp(), self.created_at_nanos)
+    }
+}
+
+#[inline]
+fn unix_nanos(secs: i64, nanos: u32) -> u64 {
+    (secs.max(0) as u64)
+        .saturating_mul(1_000_000_000)
+        .saturating_add(nanos.into())
+}
+
+/// An open gateway connection of a user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GatewayConnectionPayload {
+    pub id: Uuid,
+    pub addr: IpAddr,
+    /// Unix seconds rather than RFC3339 like the other timestamps of the API,
+    /// kept for compatibility with the clients of [`ApiVersion::V1`]
+    ///
+    /// [`ApiVersion::V1`]: crate::app::ApiVersion::V1
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub connected_at: DateTime<Utc>,
+    /// The client the user connected with, if it identified itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<GatewayClientInfo>,
+}
+
+impl GatewayConnectionPayload {
+    #[inline]
+    pub fn new(addr: IpAddr, client: Option<GatewayClientInfo>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            addr,
+            connected_at: Utc::now(),
+            client,
+        }
+    }
+}
+
+/// The maximum amount of characters of each field of [`GatewayClientInfo`].
+pub const GATEWAY_CLIENT_INFO_MAX_LEN: usize = 64;
+
+/// What a gateway client tells about itself, so the users can recognize their
+/// sessions, like `iOS app 1.2.3`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GatewayClientInfo {
+    pub client: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl GatewayClientInfo {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        let check = |name: &str, value: &str| {
+            if value.is_empty() || value.chars().count() > GATEWAY_CLIENT_INFO_MAX_LEN {
+                return Err(ApiError::ValidationFailed(format!(
+                    "the {name} must be between 1 and {GATEWAY_CLIENT_INFO_MAX_LEN} characters long"
+                )));
+            }
+            if value.chars().any(char::is_control) {
+                return Err(ApiError::ValidationFailed(format!(
+                    "the {name} must not contain control characters"
+                )));
+            }
+
+            Ok(())
+        };
+
+        check("client", &self.client)?;
+        if let Some(version) = &self.version {
+            check("client version", version)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum InvalidationReason {
     Requested,
     PasswordChanged,
+    RoleChanged,
     Deleted,
 }
 
@@ -34,6 +140,7 @@ impl Display for InvalidationReason {
         f.write_str(match self {
             InvalidationReason::Requested => "REQUESTED",
             InvalidationReason::PasswordChanged => "PASSWORD_CHANGED",
+            InvalidationReason::RoleChanged => "ROLE_CHANGED",
             InvalidationReason::Deleted => "DELETED",
         })
     }
@@ -41,7 +148,8 @@ impl Display for InvalidationReason {
 
 impl UserAuthPayload {
     pub fn new(user_id: Uuid, username: String, email: String, duration: u64) -> Self {
-        let now = Utc::now()
+        let now_time = Utc::now();
+        let now = now_time
             .timestamp()
             .try_into()
             .expect("Failed to convert an unix timestamp integer type");
@@ -52,6 +160,16 @@ impl UserAuthPayload {
             username,
             exp: now + duration,
             iat: now,
+            iat_nanos: Some(now_time.timestamp_subsec_nanos()),
+            iss: None,
+            aud: None,
         }
     }
+
+    /// The unix timestamp the token was issued at, in nanoseconds. The older
+    /// tokens are taken as issued at the start of their second.
+    #[inline]
+    pub fn issued_at_nanos(&self) -> u64 {
+        unix_nanos(self.iat as i64, self.iat_nanos.unwrap_or(0))
+    }
 }
@@ -0,0 +1,303 @@
+//! Password hashing with support for more than one algorithm.
+//!
+//! The stored hashes carry the prefix of the algorithm that produced them
+//! (`$argon2id$` for Argon2id PHC strings and `$2a$`, `$2b$` or `$2y$` for
+//! bcrypt), so the verification is dispatched by looking at the hash itself.
+//! New hashes are created with Argon2id when the `argon2` feature is enabled,
+//! while the legacy bcrypt hashes can still be verified and are migrated on the
+//! next successful signin (see [`needs_rehash`]).
+//!
+//! The hashing runs on the blocking pool, at most [`set_hash_concurrency`]
+//! of them at once, so a burst of signins can not take all of its threads.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+};
+use tokio::{sync::Semaphore, task::spawn_blocking};
+
+/// The prefix of the Argon2id PHC strings.
+pub const ARGON2ID_PREFIX: &str = "$argon2id$";
+
+const BCRYPT_PREFIXES: [&str; 3] = ["$2a$", "$2b$", "$2y$"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum PasswordHashError {
+    #[error("bcrypt error: {0}")]
+    Bcrypt(#[from] bcrypt::BcryptError),
+    #[cfg(feature = "argon2")]
+    #[error("argon2 error: {0}")]
+    Argon2(argon2::password_hash::Error),
+    #[error("the hash algorithm is not supported")]
+    UnsupportedAlgorithm,
+    #[error("failed to spawn blocking: {0}")]
+    Join(#[from] tokio::task::JoinError),
+    #[error("too many hashes are queued")]
+    Overloaded,
+}
+
+/// The seconds a client rejected by a full hashing queue should wait.
+pub const HASH_OVERLOAD_RETRY_AFTER: u64 = 1;
+
+/// The default amount of hashes waiting for a permit before new ones are
+/// rejected.
+pub const DEFAULT_HASH_MAX_QUEUE: usize = 256;
+
+static HASH_LIMITER: OnceLock<HashLimiter> = OnceLock::new();
+
+/// Limits the hashes running at once to `concurrency`, with at most
+/// `max_queue` of them waiting. The hashing is unbounded if it is never
+/// called. Meant to be set once on startup.
+pub fn set_hash_concurrency(concurrency: usize, max_queue: usize) {
+    _ = HASH_LIMITER.set(HashLimiter::new(concurrency, max_queue));
+}
+
+struct HashLimiter {
+    semaphore: Semaphore,
+    max_queue: usize,
+    queued: AtomicUsize,
+}
+
+/// Removes a waiter from the queue once it got its permit or gave up.
+struct QueueGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl HashLimiter {
+    fn new(concurrency: usize, max_queue: usize) -> Self {
+        Self {
+            semaphore: Semaphore::new(concurrency.max(1)),
+            max_queue,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    async fn acquire(&self) -> Result<tokio::sync::SemaphorePermit<'_>, PasswordHashError> {
+        if let Ok(permit) = self.semaphore.try_acquire() {
+            return Ok(permit);
+        }
+
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed);
+        let _guard = QueueGuard(&self.queued);
+        if queued >= self.max_queue {
+            return Err(PasswordHashError::Overloaded);
+        }
+
+        self.semaphore
+            .acquire()
+            .await
+            .map_err(|_| PasswordHashError::Overloaded)
+    }
+}
+
+/// Runs the hashing on the blocking pool once the limiter lets it.
+async fn spawn_limited<T, F>(f: F) -> Result<T, PasswordHashError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, PasswordHashError> + Send + 'static,
+{
+    let _permit = match HASH_LIMITER.get() {
+        Some(limiter) => Some(limiter.acquire().await?),
+        None => None,
+    };
+
+    spawn_blocking(f).await?
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHashAlgorithm {
+    Bcrypt,
+    Argon2id,
+}
+
+impl PasswordHashAlgorithm {
+    /// The algorithm used to create new hashes.
+    #[cfg(feature = "argon2")]
+    pub const DEFAULT: Self = Self::Argon2id;
+    /// The algorithm used to create new hashes.
+    #[cfg(not(feature = "argon2"))]
+    pub const DEFAULT: Self = Self::Bcrypt;
+
+    /// Detects the algorithm of a stored hash by its prefix.
+    pub fn of_hash(hash: &str) -> Option<Self> {
+        if hash.starts_with(ARGON2ID_PREFIX) {
+            Some(Self::Argon2id)
+        } else if BCRYPT_PREFIXES.iter().any(|p| hash.starts_with(p)) {
+            Some(Self::Bcrypt)
+        } else {
+            None
+        }
+    }
+}
+
+/// Hashes the password with [`PasswordHashAlgorithm::DEFAULT`]. The
+/// `bcrypt_cost` is only used when the default algorithm is bcrypt.
+pub async fn hash_password(
+    password: String,
+    bcrypt_cost: u32,
+) -> Result<String, PasswordHashError> {
+    spawn_limited(move || hash_password_blocking(&password, bcrypt_cost)).await
+}
+
+/// Verifies the password against a hash created by any of the supported
+/// algorithms.
+pub async fn verify_password(password: String, hash: String) -> Result<bool, PasswordHashError> {
+    spawn_limited(move || verify_password_blocking(&password, &hash)).await
+}
+
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+/// Creates the hash [`dummy_hash`] returns, with the algorithm and cost of the
+/// new user passwords. Meant to be called once on startup, the later calls
+/// keep the first hash.
+pub async fn init_dummy_hash(bcrypt_cost: u32) -> Result<&'static str, PasswordHashError> {
+    if let Some(hash) = DUMMY_HASH.get() {
+        return Ok(hash);
+    }
+
+    let hash = hash_password(uuid::Uuid::new_v4().to_string(), bcrypt_cost).await?;
+    Ok(DUMMY_HASH.get_or_init(|| hash))
+}
+
+/// A hash of a random password that no user password is compared against.
+/// Verifying against it when the user does not exist keeps the signin
+/// response time the same as for a wrong password. Created with
+/// [`bcrypt::DEFAULT_COST`] if [`init_dummy_hash`] was never called.
+pub async fn dummy_hash() -> Result<&'static str, PasswordHashError> {
+    init_dummy_hash(bcrypt::DEFAULT_COST).await
+}
+
+/// Whether the hash was not created by [`PasswordHashAlgorithm::DEFAULT`] and
+/// should be replaced once the plain password is known.
+#[inline]
+pub fn needs_rehash(hash: &str) -> bool {
+    PasswordHashAlgorithm::of_hash(hash) != Some(PasswordHashAlgorithm::DEFAULT)
+}
+
+#[cfg(feature = "argon2")]
+fn hash_password_blocking(password: &str, _bcrypt_cost: u32) -> Result<String, PasswordHashError> {
+    use argon2::{
+        password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+        Argon2,
+    };
+
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(PasswordHashError::Argon2)
+}
+
+#[cfg(not(feature = "argon2"))]
+fn hash_password_blocking(password: &str, bcrypt_cost: u32) -> Result<String, PasswordHashError> {
+    Ok(bcrypt::hash(password, bcrypt_cost)?)
+}
+
+fn verify_password_blocking(password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+    match PasswordHashAlgorithm::of_hash(hash) {
+        Some(PasswordHashAlgorithm::Bcrypt) => Ok(bcrypt::verify(password, hash)?),
+        #[cfg(feature = "argon2")]
+        Some(PasswordHashAlgorithm::Argon2id) => {
+            use argon2::{
+                password_hash::{Error, PasswordHash, PasswordVerifier},
+                Argon2,
+            };
+
+            let hash = PasswordHash::new(hash).map_err(PasswordHashError::Argon2)?;
+            match Argon2::default().verify_password(password.as_bytes(), &hash) {
+                Ok(()) => Ok(true),
+                Err(Error::Password) => Ok(false),
+                Err(e) => Err(PasswordHashError::Argon2(e)),
+            }
+        }
+        _ => Err(PasswordHashError::UnsupportedAlgorithm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        dummy_hash, hash_password, init_dummy_hash, needs_rehash, verify_password, HashLimiter,
+        PasswordHashAlgorithm, PasswordHashError,
+    };
+    use futures_util::FutureExt;
+
+    #[tokio::test]
+    async fn test_hash_and_verify() {
+        let hash = hash_password("izanrodrigues".into(), 4).await.unwrap();
+
+        assert_eq!(
+            PasswordHashAlgorithm::of_hash(&hash),
+            Some(PasswordHashAlgorithm::DEFAULT)
+        );
+        assert!(!needs_rehash(&hash));
+        assert!(verify_password("izanrodrigues".into(), hash.clone())
+            .await
+            .unwrap());
+        assert!(!verify_password("izan".into(), hash).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_legacy_bcrypt() {
+        let hash = bcrypt::hash("izanrodrigues", 4).unwrap();
+
+        assert_eq!(
+            PasswordHashAlgorithm::of_hash(&hash),
+            Some(PasswordHashAlgorithm::Bcrypt)
+        );
+        assert_eq!(needs_rehash(&hash), cfg!(feature = "argon2"));
+        assert!(verify_password("izanrodrigues".into(), hash).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_dummy_hash() {
+        init_dummy_hash(4).await.unwrap();
+        let hash = dummy_hash().await.unwrap();
+
+        assert!(!needs_rehash(hash));
+        assert!(!verify_password("".into(), hash.into()).await.unwrap());
+        // The first hash is kept
+        init_dummy_hash(5).await.unwrap();
+        assert_eq!(dummy_hash().await.unwrap(), hash);
+    }
+
+    #[tokio::test]
+    async fn test_verify_unknown_algorithm() {
+        assert!(verify_password("izanrodrigues".into(), "plain".into())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hash_limiter() {
+        let limiter = HashLimiter::new(1, 1);
+
+        let permit = limiter.acquire().await.unwrap();
+        let queued = limiter.acquire();
+        tokio::pin!(queued);
+        // Polls the second acquire so it waits in the queue
+        assert!(queued.as_mut().now_or_never().is_none());
+
+        assert!(matches!(
+            limiter.acquire().await,
+            Err(PasswordHashError::Overloaded)
+        ));
+
+        drop(permit);
+        let permit = queued.await.unwrap();
+        drop(permit);
+
+        // The queue is empty again
+        let permit = limiter.acquire().await.unwrap();
+        let mut queued = Box::pin(limiter.acquire());
+        assert!(queued.as_mut().now_or_never().is_none());
+        drop(queued);
+        // Waits instead of being rejected
+        assert!(limiter.acquire().now_or_never().is_none());
+        drop(permit);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::errors::ApiError;
+
+/// The most common passwords, one per line and in lowercase.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// The requirements the new passwords of the users must meet.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    /// Whether the policy is applied, disabling it accepts any password
+    pub enabled: bool,
+    /// The minimum amount of characters
+    pub min_length: usize,
+    pub require_mixed_case: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Rejects the passwords of the embedded common password list, compared
+    /// case-insensitively
+    pub block_common: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_length: 8,
+            require_mixed_case: false,
+            require_digit: false,
+            require_symbol: false,
+            block_common: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Checks the password against the rules of the policy, failing with
+    /// [`ApiError::ValidationFailed`] describing the first rule that is not
+    /// met.
+    pub fn validate(&self, password: &str) -> Result<(), ApiError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let fail = |reason: &str| Err(ApiError::ValidationFailed(reason.into()));
+
+        if password.chars().count() < self.min_length {
+            return Err(ApiError::ValidationFailed(format!(
+                "the password must be at least {} characters long",
+                self.min_length
+            )));
+        }
+        if self.require_mixed_case
+            && !(password.chars().any(char::is_lowercase)
+                && password.chars().any(char::is_uppercase))
+        {
+            return fail("the password must contain both lowercase and uppercase letters");
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            return fail("the password must contain a digit");
+        }
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            return fail("the password must contain a symbol");
+        }
+        if self.block_common && is_common_password(password) {
+            return fail("the password is too common");
+        }
+
+        Ok(())
+    }
+}
+
+fn is_common_password(password: &str) -> bool {
+    let password = password.to_lowercase();
+    COMMON_PASSWORDS.lines().any(|p| p == password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordPolicy;
+    use crate::errors::ApiError;
+
+    #[test]
+    fn test_validate() {
+        let policy = PasswordPolicy {
+            require_mixed_case: true,
+            require_digit: true,
+            require_symbol: true,
+            ..Default::default()
+        };
+
+        let rule = |password: &str| match policy.validate(password) {
+            Ok(()) => None,
+            Err(ApiError::ValidationFailed(reason)) => Some(reason),
+            Err(e) => panic!("unexpected error {e:?}"),
+        };
+
+        assert!(rule("Sh0rt!").unwrap().contains("at least 8 characters"));
+        assert!(rule("lowercase-only1").unwrap().contains("uppercase"));
+        assert!(rule("No-Digits-Here").unwrap().contains("digit"));
+        assert!(rule("NoSymbols123").unwrap().contains("symbol"));
+        assert_eq!(rule("Tr0ub4dor&3"), None);
+
+        let policy = PasswordPolicy::default();
+        assert!(policy.validate("PassWord123").is_err());
+        assert!(policy.validate("").is_err());
+        assert!(policy.validate("tr0ub4dor&3").is_ok());
+
+        let policy = PasswordPolicy {
+            enabled: false,
+            ..Default::default()
+        };
+        assert!(policy.validate("").is_ok());
+    }
+}
@@ -1,4 +1,6 @@
-use super::models::{InvalidationReason, UserAuthPayload, UserInvalidationPayload};
+use super::models::{
+    GatewayConnectionPayload, InvalidationReason, UserAuthPayload, UserInvalidationPayload,
+};
 use crate::errors::ApiError;
 use async_trait::async_trait;
 use uuid::Uuid;
@@ -16,8 +18,18 @@ pub trait AuthRepository: Sync + Send {
         password: String,
     ) -> Result<String, ApiError>;
 
+    /// Runs the same password verification work as [`AuthRepository::login_user`]
+    /// against a dummy hash and always fails with [`ApiError::AuthFailed`], so
+    /// unknown emails can't be told apart by the response time.
+    async fn login_unknown_user(&self, password: String) -> Result<String, ApiError>;
+
     async fn get_refresh_token(&self, user_id: Uuid) -> Result<String, ApiError>;
 
+    /// Deletes the refresh token of the user, so the next one returned by
+    /// [`AuthRepository::get_refresh_token`] is a new one.
+    async fn revoke_refresh_token(&self, user_id: Uuid) -> Result<(), ApiError>;
+
+    #[allow(dead_code)]
     async fn parse_refresh_token(&self, token: String) -> Result<Uuid, ApiError>;
 
     async fn generate_token(
@@ -37,4 +49,62 @@ pub trait AuthRepository: Sync + Send {
         user_id: Uuid,
         reason: InvalidationReason,
     ) -> Result<(), ApiError>;
+
+    /// Fails with [`ApiError::AuthUserInvalidated`] if the token was issued at
+    /// or before the last invalidation of its user.
+    async fn check_invalidation(&self, payload: &UserAuthPayload) -> Result<(), ApiError>;
+
+    /// Registers a login attempt for the email, returning
+    /// [`ApiError::AccountLocked`] if it goes past the attempts allowed within
+    /// the lockout window. Must be called before the credentials are checked,
+    /// so the concurrent attempts can not get past the limit.
+    async fn add_login_attempt(&self, email: &str) -> Result<(), ApiError>;
+
+    /// Clears the login attempts of the email, once the user is authenticated.
+    async fn clear_login_attempts(&self, email: &str) -> Result<(), ApiError>;
+
+    /// Records the activity of the user, failing with
+    /// [`ApiError::SessionIdleExpired`] if neither the token was issued nor
+    /// the user was seen within the idle timeout.
+    async fn touch_session(&self, payload: &UserAuthPayload) -> Result<(), ApiError>;
+
+    /// Creates a short-lived token that must be exchanged, along with a
+    /// two-factor code, for an auth token.
+    async fn create_2fa_challenge(&self, user_id: Uuid) -> Result<String, ApiError>;
+
+    /// Returns the id of the user that the challenge token was issued to,
+    /// keeping the token valid until the code is checked.
+    async fn get_2fa_challenge(&self, token: &str) -> Result<Uuid, ApiError>;
+
+    /// Returns the id of the user that the challenge token was issued to,
+    /// invalidating the token.
+    async fn consume_2fa_challenge(&self, token: String) -> Result<Uuid, ApiError>;
+
+    async fn create_email_verification(&self, user_id: Uuid) -> Result<String, ApiError>;
+
+    /// Returns the id of the user that the verification token was issued to,
+    /// invalidating the token.
+    async fn consume_email_verification(&self, token: String) -> Result<Uuid, ApiError>;
+
+    async fn create_password_reset(&self, user_id: Uuid) -> Result<String, ApiError>;
+
+    /// Returns the id of the user that the password reset token was issued to,
+    /// invalidating the token.
+    async fn consume_password_reset(&self, token: String) -> Result<Uuid, ApiError>;
+
+    /// Registers an open gateway connection of the user. The registration
+    /// expires if it is not set again within a minute, so connections of a
+    /// crashed server are eventually cleaned up.
+    async fn set_connection(
+        &self,
+        user_id: Uuid,
+        conn: &GatewayConnectionPayload,
+    ) -> Result<(), ApiError>;
+
+    async fn remove_connection(&self, user_id: Uuid, conn_id: Uuid) -> Result<(), ApiError>;
+
+    async fn get_connections(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<GatewayConnectionPayload>, ApiError>;
 }
@@ -0,0 +1,212 @@
+use crate::errors::ApiError;
+use aes_gcm::{
+    aead::{Aead, OsRng},
+    AeadCore, Aes256Gcm, KeyInit, Nonce,
+};
+use base64::{engine::general_purpose, Engine};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, TOTP};
+
+const TOTP_DIGITS: usize = 6;
+/// The amount of steps before and after the current one that are accepted.
+const TOTP_SKEW: u8 = 1;
+const TOTP_STEP: u64 = 30;
+const TOTP_SECRET_LEN: usize = 20;
+const NONCE_LEN: usize = 12;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+pub struct TotpEnrollment {
+    /// The encrypted secret that must be stored
+    pub encrypted_secret: String,
+    /// The base32 encoded secret that must be handed to the user
+    pub secret: String,
+    pub otpauth_url: String,
+}
+
+#[derive(Clone)]
+pub struct TotpManager {
+    cipher: Aes256Gcm,
+    issuer: String,
+}
+
+impl TotpManager {
+    /// Creates a new manager that encrypts the stored secrets using a key
+    /// derived from the sha256 digest of `key`.
+    pub fn new(key: &[u8], issuer: String) -> Self {
+        let key = Sha256::digest(key);
+
+        Self {
+            cipher: Aes256Gcm::new(&key),
+            issuer,
+        }
+    }
+
+    pub fn generate(&self, account_name: String) -> Result<TotpEnrollment, ApiError> {
+        let mut secret = vec![0_u8; TOTP_SECRET_LEN];
+        rand::thread_rng().fill(&mut secret[..]);
+
+        let encrypted_secret = self.encrypt(&secret)?;
+        let totp = self.totp(secret, account_name)?;
+
+        Ok(TotpEnrollment {
+            encrypted_secret,
+            secret: totp.get_secret_base32(),
+            otpauth_url: totp.get_url(),
+        })
+    }
+
+    /// Returns the time step the code was generated for, or `None` if it is
+    /// not valid for the current step nor the ones within the skew. The step
+    /// must be recorded so the code can not be used twice, see
+    /// [`UserRepository::use_totp_step`](crate::user::repository::UserRepository::use_totp_step).
+    pub fn verify(&self, encrypted_secret: &str, code: &str) -> Result<Option<u64>, ApiError> {
+        let secret = self.decrypt(encrypted_secret)?;
+        let totp = self.totp(secret, String::new())?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| {
+                tracing::error!(error = e.to_string(), "Failed to get the system time");
+                ApiError::TwoFactorCryptoFailed
+            })?
+            .as_secs();
+        let current = now / TOTP_STEP;
+        let skew = u64::from(TOTP_SKEW);
+
+        Ok((current.saturating_sub(skew)..=current + skew)
+            .find(|step| totp.check(code, step * TOTP_STEP)))
+    }
+
+    fn totp(&self, secret: Vec<u8>, account_name: String) -> Result<TOTP, ApiError> {
+        // The skew is applied by `verify`, which needs to know the step that
+        // matched
+        TOTP::new(
+            Algorithm::SHA1,
+            TOTP_DIGITS,
+            0,
+            TOTP_STEP,
+            secret,
+            Some(self.issuer.clone()),
+            account_name,
+        )
+        .map_err(|e| {
+            tracing::error!(error = e.to_string(), "Failed to create totp");
+            ApiError::TwoFactorCryptoFailed
+        })
+    }
+
+    fn encrypt(&self, secret: &[u8]) -> Result<String, ApiError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let mut buf = nonce.to_vec();
+        let ciphertext = self.cipher.encrypt(&nonce, secret).map_err(|e| {
+            tracing::error!(error = e.to_string(), "Failed to encrypt totp secret");
+            ApiError::TwoFactorCryptoFailed
+        })?;
+        buf.extend(ciphertext);
+
+        Ok(general_purpose::STANDARD.encode(buf))
+    }
+
+    fn decrypt(&self, encrypted_secret: &str) -> Result<Vec<u8>, ApiError> {
+        let buf = general_purpose::STANDARD
+            .decode(encrypted_secret)
+            .map_err(|e| {
+                tracing::error!(error = e.to_string(), "Failed to decode totp secret");
+                ApiError::TwoFactorCryptoFailed
+            })?;
+
+        if buf.len() <= NONCE_LEN {
+            tracing::error!("Failed to decrypt totp secret: invalid length");
+            return Err(ApiError::TwoFactorCryptoFailed);
+        }
+        let (nonce, ciphertext) = buf.split_at(NONCE_LEN);
+
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| {
+                tracing::error!(error = e.to_string(), "Failed to decrypt totp secret");
+                ApiError::TwoFactorCryptoFailed
+            })
+    }
+}
+
+/// Generates a set of one-time recovery codes, returning the plain codes that
+/// must be handed to the user and their hashes that must be stored.
+pub fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let mut t_rng = rand::thread_rng();
+
+    let codes = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let n: u64 = t_rng.gen_range(0..10_u64.pow(10));
+            let s = format!("{n:010}");
+            format!("{}-{}", &s[..5], &s[5..])
+        })
+        .collect::<Vec<_>>();
+
+    let hashes = codes.iter().map(|c| hash_recovery_code(c)).collect();
+
+    (codes, hashes)
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    let code = code.replace('-', "");
+    let hash = Sha256::digest(code.trim().as_bytes());
+
+    general_purpose::STANDARD.encode(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_recovery_codes, hash_recovery_code, TotpManager, TOTP_STEP};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use totp_rs::TOTP;
+
+    #[test]
+    fn test_totp_verify() {
+        let manager = TotpManager::new(b"random key", "messaging-app".into());
+
+        let enrollment = manager.generate("izanrodrigues".into()).unwrap();
+        let totp = TOTP::from_url(enrollment.otpauth_url).unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let code = totp.generate(now);
+        let step = manager.verify(&enrollment.encrypted_secret, &code).unwrap();
+        assert_eq!(step, Some(now / TOTP_STEP));
+        // The codes of the next step are accepted too, for the clock skew
+        let code = totp.generate(now + TOTP_STEP);
+        let step = manager.verify(&enrollment.encrypted_secret, &code).unwrap();
+        assert_eq!(step, Some(now / TOTP_STEP + 1));
+
+        let stale = totp.generate(now - 10 * TOTP_STEP);
+        assert!(manager
+            .verify(&enrollment.encrypted_secret, &stale)
+            .unwrap()
+            .is_none());
+        assert!(manager
+            .verify(&enrollment.encrypted_secret, "000000x")
+            .unwrap()
+            .is_none());
+
+        let other = TotpManager::new(b"other key", "messaging-app".into());
+        other
+            .verify(&enrollment.encrypted_secret, &code)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let (codes, hashes) = generate_recovery_codes();
+
+        for (code, hash) in codes.iter().zip(hashes.iter()) {
+            assert_eq!(&hash_recovery_code(code), hash);
+            assert_eq!(&hash_recovery_code(&code.replace('-', "")), hash);
+        }
+    }
+}
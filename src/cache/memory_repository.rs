@@ -1,68 +1,241 @@
 use super::repository::CacheRepository;
 use crate::errors::ApiError;
 use async_trait::async_trait;
+use chrono::Utc;
 use std::{
-    collections::HashMap,
-    sync::Arc,
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Weak},
     time::{Duration, Instant},
 };
 use tokio::sync::Mutex;
 
+/// The default interval between the sweeps of the expired keys.
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(2);
+
+struct CacheEntry {
+    value: String,
+    expires_at: Option<Instant>,
+    /// The position of the entry in [`CacheState::lru`]
+    tick: u64,
+}
+
+impl CacheEntry {
+    #[inline]
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| now > at)
+    }
+}
+
+/// The members of a set, along with the unix timestamp in milliseconds each
+/// one expires at and its value.
+type Members = HashMap<String, (i64, String)>;
+
+fn decode_members(s: &str) -> Result<Members, ApiError> {
+    serde_json::from_str(s).map_err(|e| {
+        tracing::error!(e = e.to_string(), "Failed to deserialize cache set");
+        ApiError::CacheDeserializationFailed
+    })
+}
+
+fn encode_members(members: &Members) -> Result<String, ApiError> {
+    serde_json::to_string(members).map_err(|e| {
+        tracing::error!(e = e.to_string(), "Failed to serialize cache set");
+        ApiError::CacheSerializationFailed
+    })
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    /// The keys ordered by their last use, the least recently used first
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl CacheState {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Returns the entry if it did not expire, marking it as the most
+    /// recently used. The expired entry is removed right away, so the keys
+    /// expire on time even between the sweeps.
+    fn get(&mut self, key: &str) -> Option<&mut CacheEntry> {
+        let entry = self.entries.get(key)?;
+        if entry.is_expired(Instant::now()) {
+            self.remove(key);
+            return None;
+        }
+
+        let tick = self.next_tick();
+        let entry = self.entries.get_mut(key)?;
+        self.lru.remove(&entry.tick);
+        self.lru.insert(tick, key.to_owned());
+        entry.tick = tick;
+
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: String, value: String, expires_at: Option<Instant>) {
+        let tick = self.next_tick();
+        self.lru.insert(tick, key.clone());
+
+        let entry = CacheEntry {
+            value,
+            expires_at,
+            tick,
+        };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.lru.remove(&old.tick);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.tick);
+        }
+    }
+
+    /// Makes room for a new entry when there are already `max`, removing the
+    /// expired entries first and then the least recently used ones whose key
+    /// starts with one of the `evictable` prefixes. Returns `false` if there
+    /// is still no room.
+    fn make_room(&mut self, max: usize, evictable: &[String]) -> bool {
+        if self.entries.len() < max {
+            return true;
+        }
+
+        let now = Instant::now();
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.is_expired(now))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for key in expired {
+            self.remove(&key);
+        }
+
+        while self.entries.len() >= max {
+            let key = self
+                .lru
+                .values()
+                .find(|k| evictable.iter().any(|p| k.starts_with(p.as_str())))
+                .cloned();
+            let Some(key) = key else {
+                return false;
+            };
+            self.remove(&key);
+        }
+
+        true
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct InMemoryCacheRepository {
-    cache: Arc<Mutex<HashMap<String, String>>>,
-    expiry: Arc<Mutex<HashMap<String, Instant>>>,
+    state: Arc<Mutex<CacheState>>,
+    max_entries: Option<usize>,
+    evictable_prefixes: Vec<String>,
 }
 
 impl InMemoryCacheRepository {
-    async fn background(self) {
-        const INTERVAL: Duration = Duration::from_secs(2);
-
-        let mut exclusion = Vec::new();
-        loop {
+    /// Sweeps the expired keys every `interval`. Only holds a weak reference
+    /// to the state, so the task stops once all the clones of the repository
+    /// are dropped.
+    async fn background(weak: Weak<Mutex<CacheState>>, interval: Duration) {
+        while let Some(arc) = weak.upgrade() {
             let now = Instant::now();
 
-            let mut expiry = self.expiry.lock().await;
+            let mut state = arc.lock().await;
+            let expired = state
+                .entries
+                .iter()
+                .filter(|(_, e)| e.is_expired(now))
+                .map(|(k, _)| k.clone())
+                .collect::<Vec<_>>();
 
-            for (k, v) in expiry.iter() {
-                if now > *v {
-                    exclusion.push(k.clone());
-                }
+            for key in expired {
+                state.remove(&key);
             }
+            // Must not keep the state alive while sleeping
+            drop(state);
+            drop(arc);
 
-            if exclusion.len() != 0 {
-                let mut cache = self.cache.lock().await;
-                for e in exclusion.iter() {
-                    cache.remove(e);
-                    expiry.remove(e);
-                }
-                drop(cache);
-            }
-            drop(expiry);
-
-            exclusion.clear();
-
-            tokio::time::sleep(INTERVAL).await;
+            tokio::time::sleep(interval).await;
         }
     }
 
+    /// Creates the cache, sweeping the expired keys every
+    /// [`DEFAULT_SWEEP_INTERVAL`].
+    #[inline]
     pub fn new() -> InMemoryCacheRepository {
+        Self::with_sweep_interval(DEFAULT_SWEEP_INTERVAL)
+    }
+
+    /// Creates the cache, sweeping the expired keys every `interval`. The keys
+    /// are also expired when read, so the interval only bounds how long the
+    /// expired keys that are not read again take memory.
+    pub fn with_sweep_interval(interval: Duration) -> InMemoryCacheRepository {
         let cache = InMemoryCacheRepository::default();
-        tokio::spawn(cache.clone().background());
+        tokio::spawn(Self::background(Arc::downgrade(&cache.state), interval));
 
         cache
     }
+
+    /// Caps the amount of keys. A new key past the cap takes the place of an
+    /// expired key, or else of the least recently used one among the
+    /// [evictable](Self::with_evictable_prefixes) keys. It is refused with
+    /// [`ApiError::CacheSetFailed`] if there is none, since the other keys may
+    /// hold security state, like invalidations and lockouts, that must not be
+    /// dropped before they expire.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Lets the keys starting with any of `prefixes` be evicted before they
+    /// expire once the cap of [`Self::with_max_entries`] is reached.
+    pub fn with_evictable_prefixes(mut self, prefixes: &[&str]) -> Self {
+        self.evictable_prefixes = prefixes.iter().map(|&p| p.to_owned()).collect();
+        self
+    }
+
+    /// Fails if the key is new and there is no room left for it.
+    fn check_room(&self, state: &mut CacheState, key: &str) -> Result<(), ApiError> {
+        let Some(max) = self.max_entries else {
+            return Ok(());
+        };
+
+        if !state.entries.contains_key(key) && !state.make_room(max, &self.evictable_prefixes) {
+            tracing::warn!(max_entries = max, "The cache is full, refusing new key");
+            return Err(ApiError::CacheSetFailed);
+        }
+
+        Ok(())
+    }
+
+    async fn insert(
+        &self,
+        key: String,
+        value: String,
+        expires_at: Option<Instant>,
+    ) -> Result<(), ApiError> {
+        let mut state = self.state.lock().await;
+        self.check_room(&mut state, &key)?;
+        state.insert(key, value, expires_at);
+
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl CacheRepository for InMemoryCacheRepository {
     async fn get<K: ToString + Send>(&self, key: K) -> Result<Option<String>, ApiError> {
-        let lock = self.cache.lock().await;
+        let mut state = self.state.lock().await;
 
-        Ok(match lock.get(&key.to_string()) {
-            Some(v) => Some(v.clone()),
-            None => None,
-        })
+        Ok(state.get(&key.to_string()).map(|e| e.value.clone()))
     }
 
     async fn get_ttl<K: ToString + Send>(
@@ -70,51 +243,284 @@ impl CacheRepository for InMemoryCacheRepository {
         key: K,
         ttl: u64,
     ) -> Result<Option<String>, ApiError> {
+        let mut state = self.state.lock().await;
+
+        Ok(state.get(&key.to_string()).map(|e| {
+            e.expires_at = Some(Instant::now() + Duration::from_secs(ttl));
+            e.value.clone()
+        }))
+    }
+
+    async fn set<K: ToString + Send>(&self, key: K, value: String) -> Result<(), ApiError> {
+        self.insert(key.to_string(), value, None).await
+    }
+
+    async fn set_ttl<K: ToString + Send>(
+        &self,
+        key: K,
+        value: String,
+        ttl: u64,
+    ) -> Result<(), ApiError> {
+        let expires_at = Instant::now() + Duration::from_secs(ttl);
+        self.insert(key.to_string(), value, Some(expires_at)).await
+    }
+
+    async fn delete<K: ToString + Send>(&self, key: K) -> Result<(), ApiError> {
+        let mut state = self.state.lock().await;
+        state.remove(&key.to_string());
+
+        Ok(())
+    }
+
+    async fn get_delete<K: ToString + Send>(&self, key: K) -> Result<Option<String>, ApiError> {
         let key = key.to_string();
+        let mut state = self.state.lock().await;
 
-        match self.get(key.clone()).await? {
-            Some(v) => {
-                let mut lock = self.expiry.lock().await;
-                lock.insert(key, Instant::now() + Duration::from_secs(ttl));
-                drop(lock);
+        let value = state.get(&key).map(|e| e.value.clone());
+        state.remove(&key);
 
-                Ok(Some(v))
-            }
-            None => Ok(None),
+        Ok(value)
+    }
+
+    async fn incr_ttl<K: ToString + Send>(&self, key: K, ttl: u64) -> Result<u64, ApiError> {
+        let key = key.to_string();
+        let mut state = self.state.lock().await;
+
+        if let Some(entry) = state.get(&key) {
+            let count = entry
+                .value
+                .parse::<u64>()
+                .map_err(|_| ApiError::CacheDeserializationFailed)?
+                + 1;
+            entry.value = count.to_string();
+
+            return Ok(count);
         }
+
+        self.check_room(&mut state, &key)?;
+        let expires_at = Instant::now() + Duration::from_secs(ttl);
+        state.insert(key, "1".into(), Some(expires_at));
+
+        Ok(1)
     }
 
-    async fn set<K: ToString + Send>(&self, key: K, value: String) -> Result<(), ApiError> {
-        let mut lock = self.cache.lock().await;
-        lock.insert(key.to_string(), value);
+    async fn ttl<K: ToString + Send>(&self, key: K) -> Result<Option<u64>, ApiError> {
+        let mut state = self.state.lock().await;
 
-        Ok(())
+        Ok(state
+            .get(&key.to_string())
+            .and_then(|e| e.expires_at)
+            .map(|at| at.saturating_duration_since(Instant::now()).as_secs()))
     }
 
-    async fn set_ttl<K: ToString + Send>(
+    async fn set_member_ttl<K: ToString + Send>(
         &self,
         key: K,
+        member: String,
         value: String,
         ttl: u64,
     ) -> Result<(), ApiError> {
         let key = key.to_string();
+        let now = Utc::now().timestamp_millis();
+        let mut state = self.state.lock().await;
 
-        let mut lock = self.cache.lock().await;
-        lock.insert(key.clone(), value);
-        drop(lock);
+        let mut members = match state.get(&key) {
+            Some(entry) => decode_members(&entry.value)?,
+            None => {
+                self.check_room(&mut state, &key)?;
+                Members::new()
+            }
+        };
+        members.retain(|_, (expires_at, _)| *expires_at > now);
 
-        let mut lock = self.expiry.lock().await;
-        lock.insert(key, Instant::now() + Duration::from_secs(ttl));
-        drop(lock);
+        let ttl_millis = i64::try_from(ttl.saturating_mul(1000)).unwrap_or(i64::MAX);
+        members.insert(member, (now.saturating_add(ttl_millis), value));
+
+        let expires_at = Instant::now() + Duration::from_secs(ttl);
+        state.insert(key, encode_members(&members)?, Some(expires_at));
 
         Ok(())
     }
 
-    async fn delete<K: ToString + Send>(&self, key: K) -> Result<(), ApiError> {
-        let mut lock = self.expiry.lock().await;
-        lock.remove(&key.to_string());
-        drop(lock);
+    async fn remove_member<K: ToString + Send>(
+        &self,
+        key: K,
+        member: String,
+    ) -> Result<(), ApiError> {
+        let mut state = self.state.lock().await;
+
+        if let Some(entry) = state.get(&key.to_string()) {
+            let mut members = decode_members(&entry.value)?;
+            members.remove(&member);
+            entry.value = encode_members(&members)?;
+        }
 
         Ok(())
     }
+
+    async fn get_members<K: ToString + Send>(&self, key: K) -> Result<Vec<String>, ApiError> {
+        let now = Utc::now().timestamp_millis();
+        let mut state = self.state.lock().await;
+
+        let Some(entry) = state.get(&key.to_string()) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(decode_members(&entry.value)?
+            .into_values()
+            .filter(|(expires_at, _)| *expires_at > now)
+            .map(|(_, value)| value)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryCacheRepository;
+    use crate::cache::repository::CacheRepository;
+    use std::{sync::Arc, time::Duration};
+
+    #[tokio::test]
+    async fn test_max_entries() {
+        let cache = InMemoryCacheRepository::new()
+            .with_max_entries(2)
+            .with_evictable_prefixes(&["volatile/"]);
+
+        cache.set("volatile/a", "1".into()).await.unwrap();
+        cache.set("volatile/b", "2".into()).await.unwrap();
+        // Makes "b" the least recently used key
+        assert_eq!(cache.get("volatile/a").await.unwrap().as_deref(), Some("1"));
+
+        cache.set("volatile/c", "3".into()).await.unwrap();
+        assert_eq!(cache.get("volatile/b").await.unwrap(), None);
+        assert_eq!(cache.get("volatile/a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.get("volatile/c").await.unwrap().as_deref(), Some("3"));
+
+        // Replacing a key does not evict anything
+        cache.set("volatile/c", "4".into()).await.unwrap();
+        assert_eq!(cache.get("volatile/a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.get("volatile/c").await.unwrap().as_deref(), Some("4"));
+    }
+
+    #[tokio::test]
+    async fn test_max_entries_keeps_protected_keys() {
+        // The sweep never runs during the test
+        let cache = InMemoryCacheRepository::with_sweep_interval(Duration::from_secs(3600))
+            .with_max_entries(2)
+            .with_evictable_prefixes(&["volatile/"]);
+
+        cache.set("protected/a", "1".into()).await.unwrap();
+        cache.set_ttl("protected/b", "2".into(), 1).await.unwrap();
+        // Makes "a" the least recently used key
+        cache.get("protected/b").await.unwrap();
+
+        assert!(cache.set("volatile/c", "3".into()).await.is_err());
+        assert!(cache.incr_ttl("protected/c", 60).await.is_err());
+        assert_eq!(
+            cache.get("protected/a").await.unwrap().as_deref(),
+            Some("1")
+        );
+
+        // The expired keys make room for the new ones
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(cache.incr_ttl("protected/c", 60).await.unwrap(), 1);
+        assert_eq!(
+            cache.get("protected/a").await.unwrap().as_deref(),
+            Some("1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_incr_ttl() {
+        let cache = InMemoryCacheRepository::new();
+
+        assert_eq!(cache.ttl("a").await.unwrap(), None);
+        assert_eq!(cache.incr_ttl("a", 60).await.unwrap(), 1);
+        assert_eq!(cache.incr_ttl("a", 1).await.unwrap(), 2);
+        assert_eq!(cache.get("a").await.unwrap().as_deref(), Some("2"));
+        // The later increments keep the first expiry
+        assert!(cache.ttl("a").await.unwrap().is_some_and(|ttl| ttl > 1));
+
+        cache.set("b", "value".into()).await.unwrap();
+        assert!(cache.incr_ttl("b", 60).await.is_err());
+        assert_eq!(cache.ttl("b").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_delete() {
+        let cache = InMemoryCacheRepository::new();
+        cache.set("a", "1".into()).await.unwrap();
+
+        let (first, second) = tokio::join!(cache.get_delete("a"), cache.get_delete("a"));
+        let mut values = [first.unwrap(), second.unwrap()];
+        values.sort();
+        assert_eq!(values, [None, Some("1".to_owned())]);
+        assert_eq!(cache.get("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_background_stops_on_drop() {
+        const INTERVAL: Duration = Duration::from_millis(10);
+
+        let cache = InMemoryCacheRepository::default();
+        let task = tokio::spawn(InMemoryCacheRepository::background(
+            Arc::downgrade(&cache.state),
+            INTERVAL,
+        ));
+        let clone = cache.clone();
+
+        drop(cache);
+        tokio::time::sleep(INTERVAL * 3).await;
+        assert!(!task.is_finished());
+
+        drop(clone);
+        tokio::time::timeout(INTERVAL * 10, task)
+            .await
+            .expect("the sweeper did not stop")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expiry_on_read() {
+        // The sweep never runs during the test
+        let cache = InMemoryCacheRepository::with_sweep_interval(Duration::from_secs(3600));
+
+        cache.set_ttl("a", "1".into(), 1).await.unwrap();
+        cache.set("b", "2".into()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        assert_eq!(cache.get("a").await.unwrap(), None);
+        assert_eq!(cache.get("b").await.unwrap().as_deref(), Some("2"));
+    }
+
+    #[tokio::test]
+    async fn test_members() {
+        let cache = InMemoryCacheRepository::new();
+        assert!(cache.get_members("set").await.unwrap().is_empty());
+
+        cache
+            .set_member_ttl("set", "a".into(), "1".into(), 60)
+            .await
+            .unwrap();
+        cache
+            .set_member_ttl("set", "b".into(), "2".into(), 1)
+            .await
+            .unwrap();
+        cache
+            .set_member_ttl("set", "c".into(), "3".into(), 60)
+            .await
+            .unwrap();
+        cache.remove_member("set", "c".into()).await.unwrap();
+
+        let mut values = cache.get_members("set").await.unwrap();
+        values.sort();
+        assert_eq!(values, vec!["1".to_owned(), "2".to_owned()]);
+
+        // Each member expires on its own
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(
+            cache.get_members("set").await.unwrap(),
+            vec!["1".to_owned()]
+        );
+    }
 }
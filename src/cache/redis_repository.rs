@@ -1,10 +1,12 @@
 use super::repository::CacheRepository;
 use crate::errors::ApiError;
 use async_trait::async_trait;
+use chrono::Utc;
 use deadpool_redis::{
-    redis::{AsyncCommands, Expiry},
+    redis::{self, AsyncCommands, Expiry},
     Connection, Pool,
 };
+use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct RedisCacheRepository {
@@ -29,6 +31,7 @@ impl RedisCacheRepository {
 
 #[async_trait]
 impl CacheRepository for RedisCacheRepository {
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
     async fn get<K: ToString + Send>(&self, key: K) -> Result<Option<String>, ApiError> {
         let mut conn = self.acquire_conn().await?;
         let key = key.to_string();
@@ -39,6 +42,7 @@ impl CacheRepository for RedisCacheRepository {
         }
     }
 
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
     async fn get_ttl<K: ToString + Send>(
         &self,
         key: K,
@@ -53,6 +57,7 @@ impl CacheRepository for RedisCacheRepository {
         }
     }
 
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
     async fn set<K: ToString + Send>(&self, key: K, value: String) -> Result<(), ApiError> {
         let mut conn = self.acquire_conn().await?;
         let key = key.to_string();
@@ -63,6 +68,7 @@ impl CacheRepository for RedisCacheRepository {
         })
     }
 
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
     async fn set_ttl<K: ToString + Send>(
         &self,
         key: K,
@@ -78,6 +84,7 @@ impl CacheRepository for RedisCacheRepository {
         })
     }
 
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
     async fn delete<K: ToString + Send>(&self, key: K) -> Result<(), ApiError> {
         let mut conn = self.acquire_conn().await?;
         let key = key.to_string();
@@ -87,4 +94,144 @@ impl CacheRepository for RedisCacheRepository {
             ApiError::RedisError
         })
     }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
+    async fn get_delete<K: ToString + Send>(&self, key: K) -> Result<Option<String>, ApiError> {
+        let mut conn = self.acquire_conn().await?;
+        let key = key.to_string();
+
+        redis::cmd("GETDEL")
+            .arg(key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = e.to_string(), operation = "GETDEL", "Redis error");
+                ApiError::RedisError
+            })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
+    async fn incr_ttl<K: ToString + Send>(&self, key: K, ttl: u64) -> Result<u64, ApiError> {
+        let mut conn = self.acquire_conn().await?;
+        let key = key.to_string();
+
+        // Creating the key with its expiry and incrementing it in the same
+        // transaction never leaves a counter without expiry
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("EX")
+            .arg(ttl)
+            .arg("NX")
+            .ignore()
+            .incr(&key, 1)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = e.to_string(), operation = "INCR", "Redis error");
+                ApiError::RedisError
+            })?;
+
+        Ok(count)
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
+    async fn ttl<K: ToString + Send>(&self, key: K) -> Result<Option<u64>, ApiError> {
+        let mut conn = self.acquire_conn().await?;
+        let key = key.to_string();
+
+        // Negative when the key does not exist or never expires
+        let ttl: i64 = conn.ttl(key).await.map_err(|e| {
+            tracing::error!(error = e.to_string(), operation = "TTL", "Redis error");
+            ApiError::RedisError
+        })?;
+
+        Ok(u64::try_from(ttl).ok())
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
+    async fn set_member_ttl<K: ToString + Send>(
+        &self,
+        key: K,
+        member: String,
+        value: String,
+        ttl: u64,
+    ) -> Result<(), ApiError> {
+        let mut conn = self.acquire_conn().await?;
+        let key = key.to_string();
+
+        let ttl_millis = i64::try_from(ttl.saturating_mul(1000)).unwrap_or(i64::MAX);
+        let expires_at = Utc::now().timestamp_millis().saturating_add(ttl_millis);
+
+        // The members are the fields of a hash, whose values are prefixed by
+        // their own expiry
+        redis::pipe()
+            .atomic()
+            .cmd("HSET")
+            .arg(&key)
+            .arg(member)
+            .arg(format!("{expires_at}:{value}"))
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(&key)
+            .arg(ttl)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = e.to_string(), operation = "HSET", "Redis error");
+                ApiError::RedisError
+            })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
+    async fn remove_member<K: ToString + Send>(
+        &self,
+        key: K,
+        member: String,
+    ) -> Result<(), ApiError> {
+        let mut conn = self.acquire_conn().await?;
+        let key = key.to_string();
+
+        conn.hdel(key, member).await.map_err(|e| {
+            tracing::error!(error = e.to_string(), operation = "HDEL", "Redis error");
+            ApiError::RedisError
+        })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "redis")))]
+    async fn get_members<K: ToString + Send>(&self, key: K) -> Result<Vec<String>, ApiError> {
+        let mut conn = self.acquire_conn().await?;
+        let key = key.to_string();
+
+        let members: HashMap<String, String> = conn.hgetall(&key).await.map_err(|e| {
+            tracing::error!(error = e.to_string(), operation = "HGETALL", "Redis error");
+            ApiError::RedisError
+        })?;
+
+        let now = Utc::now().timestamp_millis();
+        let mut values = Vec::with_capacity(members.len());
+        let mut expired = Vec::new();
+        for (member, v) in members {
+            let entry = v
+                .split_once(':')
+                .and_then(|(at, value)| Some((at.parse::<i64>().ok()?, value)));
+
+            match entry {
+                Some((expires_at, value)) if expires_at > now => values.push(value.to_owned()),
+                _ => expired.push(member),
+            }
+        }
+
+        if !expired.is_empty() {
+            // Only cleans the set up, the expired members are already left out
+            if let Err(e) = conn.hdel::<_, _, ()>(&key, expired).await {
+                tracing::warn!(error = e.to_string(), operation = "HDEL", "Redis error");
+            }
+        }
+
+        Ok(values)
+    }
 }
@@ -23,6 +23,41 @@ pub trait CacheRepository: Sync + Send {
 
     async fn delete<K: ToString + Send>(&self, key: K) -> Result<(), ApiError>;
 
+    /// Atomically returns the value of the key and deletes it, so only one of
+    /// the concurrent callers gets it.
+    async fn get_delete<K: ToString + Send>(&self, key: K) -> Result<Option<String>, ApiError>;
+
+    /// Atomically increments the counter stored in the key and returns its new
+    /// value. A missing key starts at one and expires in `ttl` seconds, which
+    /// the later increments do not extend.
+    async fn incr_ttl<K: ToString + Send>(&self, key: K, ttl: u64) -> Result<u64, ApiError>;
+
+    /// Returns the amount of seconds until the key expires, or `None` if it
+    /// does not exist or never expires.
+    async fn ttl<K: ToString + Send>(&self, key: K) -> Result<Option<u64>, ApiError>;
+
+    /// Adds the member to the set stored in the key along with its value,
+    /// replacing it if it was already there. Each member expires `ttl`
+    /// seconds after it is set, and the whole set `ttl` seconds after the
+    /// last one is.
+    async fn set_member_ttl<K: ToString + Send>(
+        &self,
+        key: K,
+        member: String,
+        value: String,
+        ttl: u64,
+    ) -> Result<(), ApiError>;
+
+    async fn remove_member<K: ToString + Send>(
+        &self,
+        key: K,
+        member: String,
+    ) -> Result<(), ApiError>;
+
+    /// Returns the values of the members of the set that did not expire. Only
+    /// the set is read, so the cost does not depend on the amount of keys.
+    async fn get_members<K: ToString + Send>(&self, key: K) -> Result<Vec<String>, ApiError>;
+
     async fn de_get<T: DeserializeOwned>(&self, key: String) -> Result<Option<T>, ApiError> {
         let s = match self.get(key).await? {
             Some(v) => v,
@@ -55,6 +90,22 @@ pub trait CacheRepository: Sync + Send {
         Ok(Some(t))
     }
 
+    async fn de_get_members<T: DeserializeOwned, K: ToString + Send>(
+        &self,
+        key: K,
+    ) -> Result<Vec<T>, ApiError> {
+        self.get_members(key)
+            .await?
+            .iter()
+            .map(|s| {
+                serde_json::from_str(s).map_err(|e| {
+                    tracing::error!(e = e.to_string(), "Failed to deserialize cache");
+                    ApiError::CacheDeserializationFailed
+                })
+            })
+            .collect()
+    }
+
     async fn ser_set<T: Serialize + Sync, K: ToString + Send>(
         &self,
         key: K,
@@ -89,4 +140,23 @@ pub trait CacheRepository: Sync + Send {
 
         self.set_ttl(key, v, ttl).await
     }
+
+    async fn ser_set_member_ttl<T: Serialize + Sync, K: ToString + Send>(
+        &self,
+        key: K,
+        member: String,
+        value: &T,
+        ttl: u64,
+    ) -> Result<(), ApiError> {
+        let v = match serde_json::to_string(value) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!(e = e.to_string(), "Failed to serialize cache");
+
+                return Err(ApiError::CacheSerializationFailed);
+            }
+        };
+
+        self.set_member_ttl(key, member, v, ttl).await
+    }
 }
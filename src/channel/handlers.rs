@@ -1,15 +1,28 @@
 use super::{
-    models::{Channel, ChannelCreateData, ChannelUpdateData, UserPermission, UserPermissionEntry},
+    models::{
+        Channel, ChannelCreateData, ChannelInvite, ChannelInviteCreateData, ChannelInvitePreview,
+        ChannelUpdateData, NotificationPrefEntry, NotificationPrefRequestBody, UserPermission,
+        UserPermissionEntry, CHANNEL_INVITE_PERMISSION, CHANNEL_MAX_INVITES,
+    },
     repository::ChannelRepository,
 };
 use crate::{
+    audit::{
+        models::{AuditAction, AuditLogCreateData},
+        repository::AuditRepository,
+    },
     auth::models::UserAuthPayload,
     errors::ApiError,
     event::{models::AppEvent, repository::EventRepository},
-    http::DataResponse,
+    http::{ApiResponder, DataResponse, NoContent},
+    message::{
+        models::{MessageCreateData, SYSTEM_AUTHOR},
+        repository::MessageRepository,
+    },
 };
 use axum::http::StatusCode;
-use serde::Deserialize;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -18,6 +31,19 @@ pub struct ChannelIdPathParams {
     pub channel_id: Uuid,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InviteTokenPathParams {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelIdInviteIdPathParams {
+    pub channel_id: Uuid,
+    pub invite_id: Uuid,
+}
+
 #[inline(always)]
 fn default_limit() -> u64 {
     100
@@ -36,6 +62,15 @@ pub struct GetManyQueryParams {
     pub offset: u64,
 }
 
+/// The maximum amount of channels fetched by a single batch request.
+pub const CHANNEL_BATCH_MAX_LEN: usize = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchChannelsRequestBody {
+    pub ids: Vec<Uuid>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", deny_unknown_fields)]
 pub enum AddPermissionVariant {
@@ -45,9 +80,9 @@ pub enum AddPermissionVariant {
     None,
 }
 
-impl Into<UserPermission> for AddPermissionVariant {
-    fn into(self) -> UserPermission {
-        match self {
+impl From<AddPermissionVariant> for UserPermission {
+    fn from(value: AddPermissionVariant) -> Self {
+        match value {
             AddPermissionVariant::Admin => UserPermission::Admin,
             AddPermissionVariant::Interact => UserPermission::Interact,
             AddPermissionVariant::Read => UserPermission::Read,
@@ -63,19 +98,83 @@ pub struct AddPermissionRequestBody {
     permission: AddPermissionVariant,
 }
 
-pub struct ChannelHandlers<C: ChannelRepository, E: EventRepository> {
+#[derive(Debug, Serialize)]
+pub struct SelfPermissionResponseBody {
+    permission: UserPermission,
+}
+
+impl ApiResponder for SelfPermissionResponseBody {
+    #[inline]
+    fn unit() -> &'static str {
+        "channel permission"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+pub struct ChannelHandlers<C, M, E, L>
+where
+    C: ChannelRepository,
+    M: MessageRepository,
+    E: EventRepository,
+    L: AuditRepository,
+{
     channel_repo: C,
+    message_repo: M,
     event_repo: E,
+    audit_repo: L,
+    max_invites: usize,
+    system_messages: bool,
 }
 
-impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
-    pub fn new(channel_repo: C, event_repo: E) -> Self {
+impl<C, M, E, L> ChannelHandlers<C, M, E, L>
+where
+    C: ChannelRepository,
+    M: MessageRepository,
+    E: EventRepository,
+    L: AuditRepository,
+{
+    pub fn new(channel_repo: C, message_repo: M, event_repo: E, audit_repo: L) -> Self {
         Self {
             channel_repo,
+            message_repo,
             event_repo,
+            audit_repo,
+            max_invites: CHANNEL_MAX_INVITES,
+            system_messages: false,
         }
     }
 
+    /// Posts a system message in the channel when a user joins it through an
+    /// invite.
+    #[inline]
+    pub fn with_system_messages(mut self, enabled: bool) -> Self {
+        self.system_messages = enabled;
+        self
+    }
+
+    async fn post_system_message(&self, channel_id: Uuid, content: String) -> Result<(), ApiError> {
+        let msg = self
+            .message_repo
+            .create(
+                SYSTEM_AUTHOR,
+                channel_id,
+                MessageCreateData::system(content),
+            )
+            .await?;
+
+        self.event_repo.publish(AppEvent::MessageCreated(msg)).await
+    }
+
+    /// Sets the maximum amount of active invites of a channel.
+    #[inline]
+    pub fn with_max_invites(mut self, max_invites: usize) -> Self {
+        self.max_invites = max_invites;
+        self
+    }
+
     pub async fn handle_get_one(
         &self,
         auth: UserAuthPayload,
@@ -99,6 +198,85 @@ impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
         Ok(chan.into())
     }
 
+    /// Returns the permission of the user in the channel, which is
+    /// [`UserPermission::None`] if the user has no access to it.
+    pub async fn handle_get_self_permission(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+    ) -> Result<DataResponse<SelfPermissionResponseBody>, ApiError> {
+        let permission = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        Ok(SelfPermissionResponseBody { permission }.into())
+    }
+
+    pub async fn handle_get_notification_pref(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+    ) -> Result<DataResponse<NotificationPrefEntry>, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_read_msg() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        let pref = self
+            .channel_repo
+            .get_notification_prefs(auth.sub, &[path.channel_id])
+            .await?
+            .remove(&path.channel_id)
+            .unwrap_or_default();
+
+        Ok(NotificationPrefEntry {
+            channel_id: path.channel_id,
+            pref,
+        }
+        .into())
+    }
+
+    /// Sets which messages of the channel are forwarded to the gateway
+    /// connections of the user.
+    pub async fn handle_set_notification_pref(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+        body: NotificationPrefRequestBody,
+    ) -> Result<DataResponse<NotificationPrefEntry>, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_read_msg() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        self.channel_repo
+            .set_notification_pref(path.channel_id, auth.sub, body.pref)
+            .await?;
+
+        self.event_repo
+            .publish(AppEvent::NotificationPrefChanged {
+                channel_id: path.channel_id,
+                user_id: auth.sub,
+                pref: body.pref,
+            })
+            .await?;
+
+        Ok(NotificationPrefEntry {
+            channel_id: path.channel_id,
+            pref: body.pref,
+        }
+        .into())
+    }
+
     pub async fn handle_get_many_self(
         &self,
         auth: UserAuthPayload,
@@ -112,24 +290,71 @@ impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
         Ok(chans.into())
     }
 
+    /// Returns the channels among the ids that the user can read. The missing
+    /// channels and the ones the user has no access to are omitted.
+    pub async fn handle_get_batch(
+        &self,
+        auth: UserAuthPayload,
+        mut body: BatchChannelsRequestBody,
+    ) -> Result<DataResponse<Vec<Channel>>, ApiError> {
+        body.ids.sort_unstable();
+        body.ids.dedup();
+
+        if body.ids.len() > CHANNEL_BATCH_MAX_LEN {
+            return Err(ApiError::ValidationFailed(format!(
+                "at most {CHANNEL_BATCH_MAX_LEN} channels can be fetched at once"
+            )));
+        }
+
+        let mut chans = Vec::new();
+        for chan in self.channel_repo.get_many(&body.ids).await? {
+            let perm = self
+                .channel_repo
+                .get_user_permission(auth.sub, chan.id)
+                .await?;
+
+            if perm.can_read_msg() {
+                chans.push(chan);
+            }
+        }
+
+        Ok(chans.into())
+    }
+
     pub async fn handle_create(
         &self,
         auth: UserAuthPayload,
         body: ChannelCreateData,
     ) -> Result<DataResponse<Channel>, ApiError> {
-        let chan = self.channel_repo.create(auth.sub, body.clone()).await?;
+        body.validate()?;
 
-        if let Some(users) = body.init_users {
-            for user_id in users {
-                self.event_repo
-                    .publish(AppEvent::ChannelUserAddedIn {
-                        id: chan.id,
-                        user_id,
-                    })
-                    .await?;
-            }
+        let init_users = body.init_users.clone().unwrap_or_default();
+        let init_permission = body.init_permission.clone();
+
+        let chan = self.channel_repo.create(auth.sub, body).await?;
+
+        // The handler is the single source of truth for the initial members:
+        // they are added here and the events are only published for the ones
+        // that were actually added
+        let added = self
+            .channel_repo
+            .add_members(chan.id, &init_users, init_permission.clone())
+            .await?;
+
+        for user_id in added {
+            self.event_repo
+                .publish(AppEvent::ChannelUserAddedIn {
+                    id: chan.id,
+                    user_id,
+                    permission: init_permission.clone(),
+                })
+                .await?;
         }
 
+        self.event_repo
+            .publish(AppEvent::ChannelCreated(chan.clone()))
+            .await?;
+
         Ok(chan.into())
     }
 
@@ -147,26 +372,48 @@ impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
         if !perm.can_update_chan() {
             return Err(ApiError::ChannelPermissionDenied);
         }
-        if body.permission == AddPermissionVariant::Admin && perm != UserPermission::Owner {
+
+        let new_perm: UserPermission = body.permission.into();
+        // Only the owner can grant the admin permission
+        if new_perm >= UserPermission::Admin && perm < UserPermission::Owner {
             return Err(ApiError::ChannelPermissionDenied);
         }
 
-        let perm: UserPermission = body.permission.into();
         let before_permission = self
             .channel_repo
             .get_user_permission(body.user_id, path.channel_id)
             .await?;
 
+        // Users can only manage the ones below them, so admins can not
+        // modify the owner or each other
+        if before_permission >= perm {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        let perm = new_perm;
+
         if before_permission != perm {
             self.channel_repo
                 .set_user_permission(path.channel_id, body.user_id, perm.clone())
                 .await?;
 
+            self.audit_repo.record(AuditLogCreateData::new(
+                Some(auth.sub),
+                AuditAction::ChannelPermissionChanged,
+                Some(path.channel_id),
+                serde_json::json!({
+                    "user_id": body.user_id,
+                    "before": before_permission,
+                    "after": perm,
+                }),
+            ));
+
             if before_permission == UserPermission::None && perm != UserPermission::None {
                 self.event_repo
                     .publish(AppEvent::ChannelUserAddedIn {
                         id: path.channel_id,
                         user_id: body.user_id,
+                        permission: perm.clone(),
                     })
                     .await?;
             } else if before_permission != UserPermission::None && perm == UserPermission::None {
@@ -176,6 +423,14 @@ impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
                         user_id: body.user_id,
                     })
                     .await?;
+            } else {
+                self.event_repo
+                    .publish(AppEvent::ChannelPermissionChanged {
+                        id: path.channel_id,
+                        user_id: body.user_id,
+                        permission: perm.clone(),
+                    })
+                    .await?;
             }
         }
 
@@ -187,6 +442,179 @@ impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
         .into())
     }
 
+    pub async fn handle_create_invite(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+        body: ChannelInviteCreateData,
+    ) -> Result<DataResponse<ChannelInvite>, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_update_chan() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+        body.validate()?;
+
+        let active = self
+            .channel_repo
+            .get_active_invites(path.channel_id)
+            .await?;
+        if active.len() >= self.max_invites {
+            return Err(ApiError::ChannelInviteLimitReached(self.max_invites));
+        }
+
+        let invite = self
+            .channel_repo
+            .create_invite(path.channel_id, auth.sub, body)
+            .await?;
+
+        Ok(invite.into())
+    }
+
+    pub async fn handle_get_invites(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+    ) -> Result<DataResponse<Vec<ChannelInvite>>, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_update_chan() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        let invites = self
+            .channel_repo
+            .get_active_invites(path.channel_id)
+            .await?;
+
+        Ok(invites.into())
+    }
+
+    /// Revokes the invite, so it can no longer be accepted.
+    pub async fn handle_delete_invite(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdInviteIdPathParams,
+    ) -> Result<NoContent, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_update_chan() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        self.channel_repo
+            .delete_invite(path.channel_id, path.invite_id)
+            .await?;
+
+        Ok(NoContent)
+    }
+
+    /// Shows the channel of an invite to any user, so they can decide whether
+    /// to accept it.
+    pub async fn handle_get_invite(
+        &self,
+        path: InviteTokenPathParams,
+    ) -> Result<DataResponse<ChannelInvitePreview>, ApiError> {
+        let invite = self
+            .channel_repo
+            .get_invite(&path.token)
+            .await?
+            .ok_or(ApiError::ChannelInviteNotFound)?;
+
+        if invite.is_exhausted(Utc::now()) {
+            return Err(ApiError::ChannelInviteExhausted);
+        }
+
+        let chan = self
+            .channel_repo
+            .get_by_id(invite.channel_id)
+            .await?
+            .ok_or(ApiError::ChannelInviteNotFound)?;
+
+        Ok(ChannelInvitePreview {
+            channel_id: chan.id,
+            name: chan.name,
+            description: chan.description,
+            topic: chan.topic,
+            expires_at: invite.expires_at,
+        }
+        .into())
+    }
+
+    /// Joins the user to the channel of the invite with
+    /// [`CHANNEL_INVITE_PERMISSION`]. The members keep their permission and do
+    /// not use up the invite.
+    pub async fn handle_accept_invite(
+        &self,
+        auth: UserAuthPayload,
+        path: InviteTokenPathParams,
+    ) -> Result<DataResponse<UserPermissionEntry>, ApiError> {
+        let invite = self
+            .channel_repo
+            .get_invite(&path.token)
+            .await?
+            .ok_or(ApiError::ChannelInviteNotFound)?;
+
+        let permission = self
+            .channel_repo
+            .get_user_permission(auth.sub, invite.channel_id)
+            .await?;
+
+        if permission != UserPermission::None {
+            return Ok(UserPermissionEntry {
+                channel_id: invite.channel_id,
+                user_id: auth.sub,
+                permission,
+            }
+            .into());
+        }
+
+        let invite = self.channel_repo.use_invite(&path.token).await?;
+
+        let added = self
+            .channel_repo
+            .add_members(invite.channel_id, &[auth.sub], CHANNEL_INVITE_PERMISSION)
+            .await?;
+
+        for user_id in added {
+            self.event_repo
+                .publish(AppEvent::ChannelUserAddedIn {
+                    id: invite.channel_id,
+                    user_id,
+                    permission: CHANNEL_INVITE_PERMISSION,
+                })
+                .await?;
+
+            if self.system_messages {
+                let content = format!("{} joined the channel", auth.username);
+                self.post_system_message(invite.channel_id, content).await?;
+            }
+        }
+
+        // The user may have been added by a concurrent request, with some
+        // other permission
+        let permission = self
+            .channel_repo
+            .get_user_permission(auth.sub, invite.channel_id)
+            .await?;
+
+        Ok(UserPermissionEntry {
+            channel_id: invite.channel_id,
+            user_id: auth.sub,
+            permission,
+        }
+        .into())
+    }
+
     pub async fn handle_update(
         &self,
         auth: UserAuthPayload,
@@ -201,14 +629,19 @@ impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
         if !perm.can_update_chan() {
             return Err(ApiError::ChannelPermissionDenied);
         }
+        body.validate()?;
+
         let chan = self
             .channel_repo
             .update(path.channel_id, body.clone())
             .await?;
 
-        self.event_repo
-            .publish(AppEvent::ChannelUpdated(chan.id, body))
-            .await?;
+        // Nothing was updated, so there is nothing to notify the members about
+        if !body.is_empty() {
+            self.event_repo
+                .publish(AppEvent::ChannelUpdated(chan.id, body))
+                .await?;
+        }
 
         Ok(chan.into())
     }
@@ -229,6 +662,13 @@ impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
 
         self.channel_repo.delete(path.channel_id).await?;
 
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(auth.sub),
+            AuditAction::ChannelDeleted,
+            Some(path.channel_id),
+            serde_json::Value::Null,
+        ));
+
         _ = self
             .event_repo
             .publish(AppEvent::ChannelDeleted(path.channel_id))
@@ -248,3 +688,639 @@ impl<C: ChannelRepository, E: EventRepository> ChannelHandlers<C, E> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AddPermissionRequestBody, AddPermissionVariant, BatchChannelsRequestBody, ChannelHandlers,
+        ChannelIdInviteIdPathParams, ChannelIdPathParams, InviteTokenPathParams,
+        CHANNEL_BATCH_MAX_LEN,
+    };
+    use crate::{
+        audit::memory_repository::InMemoryAuditRepository,
+        auth::models::UserAuthPayload,
+        channel::{
+            memory_repository::InMemoryChannelRepository,
+            models::{
+                ChannelCreateData, ChannelInviteCreateData, NotificationPref,
+                NotificationPrefRequestBody, UserPermission, CHANNEL_INVITE_PERMISSION,
+            },
+            repository::ChannelRepository,
+        },
+        errors::ApiError,
+        event::{
+            memory_repository::InMemoryEventRepository,
+            models::AppEvent,
+            repository::{EventConnection, EventRepository},
+        },
+        message::{
+            memory_repository::InMemoryMessageRepository,
+            models::{MessageKind, SYSTEM_AUTHOR},
+            repository::MessageRepository,
+        },
+    };
+    use std::{sync::Arc, time::Duration};
+    use tokio::task::JoinSet;
+    use uuid::Uuid;
+
+    type Handlers = ChannelHandlers<
+        InMemoryChannelRepository,
+        InMemoryMessageRepository,
+        InMemoryEventRepository,
+        InMemoryAuditRepository,
+    >;
+
+    struct Setup {
+        handlers: Handlers,
+        channel_repo: InMemoryChannelRepository,
+        message_repo: InMemoryMessageRepository,
+        channel_id: Uuid,
+        owner: Uuid,
+        admins: [Uuid; 2],
+    }
+
+    async fn setup() -> Setup {
+        let channel_repo = InMemoryChannelRepository::new();
+        let message_repo = InMemoryMessageRepository::new();
+        let owner = Uuid::new_v4();
+        let admins = [Uuid::new_v4(), Uuid::new_v4()];
+
+        let chan = channel_repo
+            .create(
+                owner,
+                ChannelCreateData {
+                    name: "general".into(),
+                    description: None,
+                    topic: None,
+                    init_users: None,
+                    init_permission: UserPermission::Interact,
+                },
+            )
+            .await
+            .unwrap();
+
+        for admin in admins {
+            channel_repo
+                .set_user_permission(chan.id, admin, UserPermission::Admin)
+                .await
+                .unwrap();
+        }
+
+        Setup {
+            handlers: ChannelHandlers::new(
+                channel_repo.clone(),
+                message_repo.clone(),
+                InMemoryEventRepository::new(),
+                InMemoryAuditRepository::new(),
+            )
+            .with_system_messages(true),
+            channel_repo,
+            message_repo,
+            channel_id: chan.id,
+            owner,
+            admins,
+        }
+    }
+
+    async fn edit_permission(
+        setup: &Setup,
+        actor: Uuid,
+        user_id: Uuid,
+        permission: AddPermissionVariant,
+    ) -> Result<(), ApiError> {
+        setup
+            .handlers
+            .handle_edit_user_permission(
+                UserAuthPayload::new(actor, "actor".into(), "actor@example.com".into(), 60),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                AddPermissionRequestBody {
+                    user_id,
+                    permission,
+                },
+            )
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn test_create_init_users() {
+        let setup = setup().await;
+        let mut conn = setup.handlers.event_repo.get_conn().await.unwrap();
+        let users = vec![Uuid::new_v4(), Uuid::new_v4()];
+
+        let chan = setup
+            .handlers
+            .handle_create(
+                UserAuthPayload::new(setup.owner, "owner".into(), "owner@example.com".into(), 60),
+                ChannelCreateData {
+                    name: "random".into(),
+                    description: None,
+                    topic: None,
+                    init_users: Some(users.clone()),
+                    init_permission: UserPermission::Read,
+                },
+            )
+            .await
+            .unwrap()
+            .data;
+
+        for &user_id in &users {
+            let perm = setup
+                .channel_repo
+                .get_user_permission(user_id, chan.id)
+                .await
+                .unwrap();
+            assert_eq!(perm, UserPermission::Read);
+
+            let event = conn.recv().await.unwrap();
+            assert!(matches!(
+                event,
+                AppEvent::ChannelUserAddedIn { id, user_id: u, .. } if id == chan.id && u == user_id
+            ));
+        }
+
+        let event = conn.recv().await.unwrap();
+        assert!(matches!(event, AppEvent::ChannelCreated(c) if c.id == chan.id));
+    }
+
+    #[tokio::test]
+    async fn test_get_batch() {
+        let setup = setup().await;
+        let outsider = Uuid::new_v4();
+
+        let other = setup
+            .channel_repo
+            .create(
+                outsider,
+                ChannelCreateData {
+                    name: "private".into(),
+                    description: None,
+                    topic: None,
+                    init_users: None,
+                    init_permission: UserPermission::Interact,
+                },
+            )
+            .await
+            .unwrap();
+
+        let batch = |ids: Vec<Uuid>| {
+            setup.handlers.handle_get_batch(
+                UserAuthPayload::new(setup.owner, "owner".into(), "owner@example.com".into(), 60),
+                BatchChannelsRequestBody { ids },
+            )
+        };
+
+        let chans = batch(vec![
+            setup.channel_id,
+            other.id,
+            Uuid::new_v4(),
+            setup.channel_id,
+        ])
+        .await
+        .unwrap()
+        .data;
+        assert_eq!(chans.len(), 1);
+        assert_eq!(chans[0].id, setup.channel_id);
+
+        let ids = (0..=CHANNEL_BATCH_MAX_LEN)
+            .map(|_| Uuid::new_v4())
+            .collect();
+        assert!(matches!(
+            batch(ids).await,
+            Err(ApiError::ValidationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_admin_demotes_admin() {
+        let setup = setup().await;
+        let [admin, other_admin] = setup.admins;
+
+        let res = edit_permission(&setup, admin, other_admin, AddPermissionVariant::Read).await;
+        assert!(matches!(res, Err(ApiError::ChannelPermissionDenied)));
+
+        let perm = setup
+            .channel_repo
+            .get_user_permission(other_admin, setup.channel_id)
+            .await
+            .unwrap();
+        assert_eq!(perm, UserPermission::Admin);
+
+        // Keeps an event receiver alive, so the published events are accepted
+        let mut conn = setup.handlers.event_repo.get_conn().await.unwrap();
+
+        edit_permission(&setup, setup.owner, other_admin, AddPermissionVariant::Read)
+            .await
+            .unwrap();
+
+        let perm = setup
+            .channel_repo
+            .get_user_permission(other_admin, setup.channel_id)
+            .await
+            .unwrap();
+        assert_eq!(perm, UserPermission::Read);
+
+        // Still a member, so neither added nor removed
+        let event = conn.recv().await.unwrap();
+        assert!(matches!(
+            event,
+            AppEvent::ChannelPermissionChanged {
+                id,
+                user_id,
+                permission: UserPermission::Read,
+            } if id == setup.channel_id && user_id == other_admin
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_admin_modifies_owner() {
+        let setup = setup().await;
+        let [admin, _] = setup.admins;
+
+        for permission in [AddPermissionVariant::Read, AddPermissionVariant::None] {
+            let res = edit_permission(&setup, admin, setup.owner, permission).await;
+            assert!(matches!(res, Err(ApiError::ChannelPermissionDenied)));
+        }
+
+        let perm = setup
+            .channel_repo
+            .get_user_permission(setup.owner, setup.channel_id)
+            .await
+            .unwrap();
+        assert_eq!(perm, UserPermission::Owner);
+    }
+
+    #[tokio::test]
+    async fn test_invites() {
+        let setup = setup().await;
+        let mut conn = setup.handlers.event_repo.get_conn().await.unwrap();
+        let [admin, _] = setup.admins;
+        let [first, second] = [Uuid::new_v4(), Uuid::new_v4()];
+
+        let auth =
+            |user_id| UserAuthPayload::new(user_id, "user".into(), "user@example.com".into(), 60);
+        let create = |user_id, max_uses| {
+            setup.handlers.handle_create_invite(
+                auth(user_id),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                ChannelInviteCreateData {
+                    expires_at: None,
+                    max_uses,
+                },
+            )
+        };
+        let path = |token: &str| InviteTokenPathParams {
+            token: token.into(),
+        };
+
+        let res = create(first, None).await;
+        assert!(matches!(res, Err(ApiError::ChannelPermissionDenied)));
+        let res = create(admin, Some(0)).await;
+        assert!(matches!(res, Err(ApiError::ValidationFailed(_))));
+
+        let invite = create(admin, Some(1)).await.unwrap().data;
+
+        let preview = setup
+            .handlers
+            .handle_get_invite(path(&invite.token))
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(preview.channel_id, setup.channel_id);
+        assert_eq!(preview.name, "general");
+
+        // The members do not use up the invite
+        let entry = setup
+            .handlers
+            .handle_accept_invite(auth(admin), path(&invite.token))
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(entry.permission, UserPermission::Admin);
+
+        let entry = setup
+            .handlers
+            .handle_accept_invite(auth(first), path(&invite.token))
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(entry.permission, CHANNEL_INVITE_PERMISSION);
+        let perm = setup
+            .channel_repo
+            .get_user_permission(first, setup.channel_id)
+            .await
+            .unwrap();
+        assert_eq!(perm, CHANNEL_INVITE_PERMISSION);
+
+        let event = conn.recv().await.unwrap();
+        assert!(matches!(
+            event,
+            AppEvent::ChannelUserAddedIn { id, user_id, .. } if id == setup.channel_id && user_id == first
+        ));
+
+        // The join is announced with a system message
+        let event = conn.recv().await.unwrap();
+        let AppEvent::MessageCreated(msg) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(msg.kind, MessageKind::System);
+        assert_eq!(msg.user_id, SYSTEM_AUTHOR);
+        assert_eq!(msg.content.as_deref(), Some("user joined the channel"));
+        let msgs = setup
+            .message_repo
+            .get_many(setup.channel_id, 0, 100)
+            .await
+            .unwrap();
+        assert_eq!(msgs.len(), 1);
+
+        let res = setup
+            .handlers
+            .handle_accept_invite(auth(second), path(&invite.token))
+            .await;
+        assert!(matches!(res, Err(ApiError::ChannelInviteExhausted)));
+        let res = setup.handlers.handle_get_invite(path(&invite.token)).await;
+        assert!(matches!(res, Err(ApiError::ChannelInviteExhausted)));
+
+        let res = setup
+            .handlers
+            .handle_accept_invite(auth(second), path("unknown"))
+            .await;
+        assert!(matches!(res, Err(ApiError::ChannelInviteNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_invite_removed_user() {
+        let setup = setup().await;
+        let mut conn = setup.handlers.event_repo.get_conn().await.unwrap();
+        let [admin, _] = setup.admins;
+        let member = Uuid::new_v4();
+
+        setup
+            .channel_repo
+            .set_user_permission(setup.channel_id, member, UserPermission::Interact)
+            .await
+            .unwrap();
+        edit_permission(&setup, admin, member, AddPermissionVariant::None)
+            .await
+            .unwrap();
+
+        let invite = setup
+            .channel_repo
+            .create_invite(setup.channel_id, admin, Default::default())
+            .await
+            .unwrap();
+
+        // The removed user joins again through the invite
+        let entry = setup
+            .handlers
+            .handle_accept_invite(
+                UserAuthPayload::new(member, "user".into(), "user@example.com".into(), 60),
+                InviteTokenPathParams {
+                    token: invite.token.clone(),
+                },
+            )
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(entry.permission, CHANNEL_INVITE_PERMISSION);
+        let perm = setup
+            .channel_repo
+            .get_user_permission(member, setup.channel_id)
+            .await
+            .unwrap();
+        assert_eq!(perm, CHANNEL_INVITE_PERMISSION);
+
+        let added = async {
+            loop {
+                if let AppEvent::ChannelUserAddedIn { user_id, .. } = conn.recv().await.unwrap() {
+                    break user_id;
+                }
+            }
+        };
+        let user_id = tokio::time::timeout(Duration::from_secs(1), added)
+            .await
+            .expect("the user was not added");
+        assert_eq!(user_id, member);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_invite() {
+        let setup = setup().await;
+        let [admin, _] = setup.admins;
+        let member = Uuid::new_v4();
+        setup
+            .channel_repo
+            .set_user_permission(setup.channel_id, member, UserPermission::Interact)
+            .await
+            .unwrap();
+
+        let auth =
+            |user_id| UserAuthPayload::new(user_id, "user".into(), "user@example.com".into(), 60);
+        let channel_path = || ChannelIdPathParams {
+            channel_id: setup.channel_id,
+        };
+        let handlers = ChannelHandlers::new(
+            setup.channel_repo.clone(),
+            setup.message_repo.clone(),
+            InMemoryEventRepository::new(),
+            InMemoryAuditRepository::new(),
+        )
+        .with_max_invites(2);
+
+        let mut invites = Vec::new();
+        for _ in 0..2 {
+            let invite = handlers
+                .handle_create_invite(auth(admin), channel_path(), Default::default())
+                .await
+                .unwrap()
+                .data;
+            invites.push(invite);
+        }
+        let res = handlers
+            .handle_create_invite(auth(admin), channel_path(), Default::default())
+            .await;
+        assert!(matches!(res, Err(ApiError::ChannelInviteLimitReached(2))));
+
+        let res = handlers
+            .handle_get_invites(auth(member), channel_path())
+            .await;
+        assert!(matches!(res, Err(ApiError::ChannelPermissionDenied)));
+        let listed = handlers
+            .handle_get_invites(auth(admin), channel_path())
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(listed.len(), 2);
+
+        let revoke = |user_id, invite_id| {
+            handlers.handle_delete_invite(
+                auth(user_id),
+                ChannelIdInviteIdPathParams {
+                    channel_id: setup.channel_id,
+                    invite_id,
+                },
+            )
+        };
+        let res = revoke(member, invites[0].id).await;
+        assert!(matches!(res, Err(ApiError::ChannelPermissionDenied)));
+        revoke(admin, invites[0].id).await.unwrap();
+        let res = revoke(admin, invites[0].id).await;
+        assert!(matches!(res, Err(ApiError::ChannelInviteNotFound)));
+
+        let res = handlers
+            .handle_accept_invite(
+                auth(Uuid::new_v4()),
+                InviteTokenPathParams {
+                    token: invites[0].token.clone(),
+                },
+            )
+            .await;
+        assert!(matches!(res, Err(ApiError::ChannelInviteNotFound)));
+
+        // The revoked invite no longer counts towards the limit
+        handlers
+            .handle_create_invite(auth(admin), channel_path(), Default::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invite_concurrent_accepts() {
+        const MAX_USES: u32 = 3;
+
+        let setup = Arc::new(setup().await);
+        let [admin, _] = setup.admins;
+        // Keeps the published events from failing
+        let _conn = setup.handlers.event_repo.get_conn().await.unwrap();
+
+        let invite = setup
+            .channel_repo
+            .create_invite(
+                setup.channel_id,
+                admin,
+                ChannelInviteCreateData {
+                    expires_at: None,
+                    max_uses: Some(MAX_USES),
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut tasks = JoinSet::new();
+        for _ in 0..10 {
+            let setup = setup.clone();
+            let token = invite.token.clone();
+
+            tasks.spawn(async move {
+                let user_id = Uuid::new_v4();
+                setup
+                    .handlers
+                    .handle_accept_invite(
+                        UserAuthPayload::new(user_id, "user".into(), "user@example.com".into(), 60),
+                        InviteTokenPathParams { token },
+                    )
+                    .await
+            });
+        }
+
+        let mut accepted = 0;
+        while let Some(res) = tasks.join_next().await {
+            match res.unwrap() {
+                Ok(_) => accepted += 1,
+                Err(ApiError::ChannelInviteExhausted) => {}
+                Err(e) => panic!("unexpected error {e:?}"),
+            }
+        }
+        assert_eq!(accepted, MAX_USES);
+
+        let invite = setup
+            .channel_repo
+            .get_invite(&invite.token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(invite.uses, MAX_USES);
+    }
+
+    #[tokio::test]
+    async fn test_notification_pref() {
+        let setup = setup().await;
+        let [admin, _] = setup.admins;
+        let mut conn = setup.handlers.event_repo.get_conn().await.unwrap();
+
+        let auth =
+            |user_id| UserAuthPayload::new(user_id, "user".into(), "user@example.com".into(), 60);
+        let channel_path = || ChannelIdPathParams {
+            channel_id: setup.channel_id,
+        };
+
+        let entry = setup
+            .handlers
+            .handle_get_notification_pref(auth(admin), channel_path())
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(entry.pref, NotificationPref::All);
+
+        let entry = setup
+            .handlers
+            .handle_set_notification_pref(
+                auth(admin),
+                channel_path(),
+                NotificationPrefRequestBody {
+                    pref: NotificationPref::Mentions,
+                },
+            )
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(entry.pref, NotificationPref::Mentions);
+
+        let event = conn.recv().await.unwrap();
+        assert!(matches!(
+            event,
+            AppEvent::NotificationPrefChanged { channel_id, user_id, pref: NotificationPref::Mentions }
+                if channel_id == setup.channel_id && user_id == admin
+        ));
+
+        let entry = setup
+            .handlers
+            .handle_get_notification_pref(auth(admin), channel_path())
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(entry.pref, NotificationPref::Mentions);
+        // The preferences are personal
+        let entry = setup
+            .handlers
+            .handle_get_notification_pref(auth(setup.owner), channel_path())
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(entry.pref, NotificationPref::All);
+
+        let res = setup
+            .handlers
+            .handle_set_notification_pref(
+                auth(Uuid::new_v4()),
+                channel_path(),
+                NotificationPrefRequestBody {
+                    pref: NotificationPref::None,
+                },
+            )
+            .await;
+        assert!(matches!(res, Err(ApiError::ChannelPermissionDenied)));
+
+        setup.channel_repo.delete(setup.channel_id).await.unwrap();
+        let prefs = setup
+            .channel_repo
+            .get_notification_prefs(admin, &[setup.channel_id])
+            .await
+            .unwrap();
+        assert!(prefs.is_empty());
+    }
+}
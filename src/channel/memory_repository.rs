@@ -1,11 +1,19 @@
 use super::{
-    models::{Channel, ChannelCreateData, ChannelUpdateData, UserPermission, UserPermissionEntry},
+    models::{
+        Channel, ChannelCreateData, ChannelInvite, ChannelInviteCreateData, ChannelUpdateData,
+        NotificationPref, UserPermission, UserPermissionEntry,
+    },
     repository::ChannelRepository,
 };
 use crate::errors::ApiError;
 use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine};
 use chrono::Utc;
-use std::{collections::HashMap, sync::Arc};
+use rand::Rng;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
@@ -13,6 +21,10 @@ use uuid::Uuid;
 pub struct InMemoryChannelRepository {
     channel_map: Arc<Mutex<HashMap<Uuid, Channel>>>,
     perm_map: Arc<Mutex<Vec<UserPermissionEntry>>>,
+    /// The invites indexed by their token
+    invite_map: Arc<Mutex<HashMap<String, ChannelInvite>>>,
+    /// The notification preferences indexed by `(channel_id, user_id)`
+    notification_map: Arc<Mutex<HashMap<(Uuid, Uuid), NotificationPref>>>,
 }
 
 impl InMemoryChannelRepository {
@@ -21,6 +33,8 @@ impl InMemoryChannelRepository {
         Self {
             channel_map: Arc::new(Mutex::new(HashMap::new())),
             perm_map: Arc::new(Mutex::new(Vec::new())),
+            invite_map: Arc::new(Mutex::new(HashMap::new())),
+            notification_map: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -35,54 +49,49 @@ impl ChannelRepository for InMemoryChannelRepository {
         }
     }
 
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Channel>, ApiError> {
+        let lock = self.channel_map.lock().await;
+
+        Ok(ids.iter().filter_map(|id| lock.get(id)).cloned().collect())
+    }
+
     async fn get_by_user(
         &self,
         user_id: Uuid,
-        mut offset: u64,
+        offset: u64,
         limit: u64,
     ) -> Result<Vec<Channel>, ApiError> {
         let lock = self.perm_map.lock().await;
-        let mut channel_id_vec = Vec::new();
-
-        let mut i = 0;
-        for perm in lock.iter() {
-            if offset > 0 {
-                offset -= 1;
-                continue;
-            }
-            if i > limit {
-                break;
-            }
-
-            if perm.user_id == user_id {
-                channel_id_vec.push(perm.channel_id);
-                i += 1;
-            }
-        }
+        let mut channel_ids = lock
+            .iter()
+            .filter(|perm| perm.user_id == user_id)
+            .map(|perm| perm.channel_id)
+            .collect::<Vec<_>>();
         drop(lock);
 
         let lock = self.channel_map.lock().await;
-        for (id, chan) in lock.iter() {
-            if chan.user_id == user_id {
-                channel_id_vec.push(id.clone());
-            }
-        }
-        drop(lock);
+        channel_ids.extend(
+            lock.values()
+                .filter(|chan| chan.user_id == user_id)
+                .map(|chan| chan.id),
+        );
 
-        let mut channel_vec = Vec::with_capacity(channel_id_vec.len());
+        // The ids are sorted so the pages are stable, and since they are v7
+        // the channels are in creation order
+        channel_ids.sort_unstable();
+        channel_ids.dedup();
 
-        let lock = self.channel_map.lock().await;
-        for (id, chan) in lock.iter() {
-            if channel_id_vec.contains(id) {
-                channel_vec.push(chan.clone());
-            }
-        }
-
-        Ok(channel_vec)
+        Ok(channel_ids
+            .iter()
+            .filter_map(|id| lock.get(id))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
     }
 
     async fn create(&self, user_id: Uuid, data: ChannelCreateData) -> Result<Channel, ApiError> {
-        let id = Uuid::new_v4();
+        let id = Uuid::now_v7();
         let now = Utc::now();
         let channel = Channel {
             id,
@@ -90,25 +99,53 @@ impl ChannelRepository for InMemoryChannelRepository {
             updated_at: now,
             user_id,
             name: data.name,
+            description: data.description.filter(|v| !v.is_empty()),
+            topic: data.topic.filter(|v| !v.is_empty()),
         };
 
         let mut lock = self.channel_map.lock().await;
         lock.insert(id, channel.clone());
-        drop(lock);
 
-        if let Some(users) = data.init_users {
-            let mut lock = self.perm_map.lock().await;
-            for u in users {
-                lock.push(UserPermissionEntry {
-                    channel_id: channel.id,
-                    user_id: u,
-                    permission: UserPermission::Interact,
-                });
+        Ok(channel)
+    }
+
+    async fn add_members(
+        &self,
+        channel_id: Uuid,
+        user_ids: &[Uuid],
+        perm: UserPermission,
+    ) -> Result<Vec<Uuid>, ApiError> {
+        let chan_lock = self.channel_map.lock().await;
+        let owner_id = chan_lock
+            .get(&channel_id)
+            .ok_or(ApiError::ChannelNotFound)?
+            .user_id;
+
+        let mut lock = self.perm_map.lock().await;
+        let mut added = Vec::new();
+        for &user_id in user_ids {
+            if user_id == owner_id || added.contains(&user_id) {
+                continue;
+            }
+
+            // The removed users are left with a `None` entry, which is
+            // replaced instead of counting as a membership
+            let entry = lock
+                .iter_mut()
+                .find(|p| p.channel_id == channel_id && p.user_id == user_id);
+            match entry {
+                Some(p) if p.permission != UserPermission::None => continue,
+                Some(p) => p.permission = perm.clone(),
+                None => lock.push(UserPermissionEntry {
+                    channel_id,
+                    user_id,
+                    permission: perm.clone(),
+                }),
             }
-            drop(lock);
+            added.push(user_id);
         }
 
-        Ok(channel)
+        Ok(added)
     }
 
     async fn set_user_permission(
@@ -167,6 +204,64 @@ impl ChannelRepository for InMemoryChannelRepository {
         Ok(perm)
     }
 
+    async fn get_user_permissions(
+        &self,
+        user_id: Uuid,
+        channel_ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, UserPermission>, ApiError> {
+        let ids = channel_ids.iter().collect::<HashSet<_>>();
+        let mut perms = HashMap::with_capacity(ids.len());
+
+        let lock = self.channel_map.lock().await;
+        for id in ids.iter().copied() {
+            if lock.get(id).is_some_and(|chan| chan.user_id == user_id) {
+                perms.insert(*id, UserPermission::Owner);
+            }
+        }
+        drop(lock);
+
+        let lock = self.perm_map.lock().await;
+        for p in lock.iter() {
+            if p.user_id == user_id
+                && p.permission != UserPermission::None
+                && ids.contains(&p.channel_id)
+            {
+                perms.entry(p.channel_id).or_insert(p.permission.clone());
+            }
+        }
+
+        Ok(perms)
+    }
+
+    async fn set_notification_pref(
+        &self,
+        channel_id: Uuid,
+        user_id: Uuid,
+        pref: NotificationPref,
+    ) -> Result<(), ApiError> {
+        let mut lock = self.notification_map.lock().await;
+        if pref == NotificationPref::default() {
+            lock.remove(&(channel_id, user_id));
+        } else {
+            lock.insert((channel_id, user_id), pref);
+        }
+
+        Ok(())
+    }
+
+    async fn get_notification_prefs(
+        &self,
+        user_id: Uuid,
+        channel_ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, NotificationPref>, ApiError> {
+        let lock = self.notification_map.lock().await;
+
+        Ok(channel_ids
+            .iter()
+            .filter_map(|id| lock.get(&(*id, user_id)).map(|pref| (*id, *pref)))
+            .collect())
+    }
+
     async fn update(&self, id: Uuid, data: ChannelUpdateData) -> Result<Channel, ApiError> {
         let mut lock = self.channel_map.lock().await;
         let mut chan = match lock.get(&id) {
@@ -175,7 +270,20 @@ impl ChannelRepository for InMemoryChannelRepository {
         }
         .clone();
 
-        chan.name = data.name;
+        if data.is_empty() {
+            return Ok(chan);
+        }
+
+        if let Some(name) = data.name {
+            chan.name = name;
+        }
+        if let Some(description) = data.description {
+            chan.description = (!description.is_empty()).then_some(description);
+        }
+        if let Some(topic) = data.topic {
+            chan.topic = (!topic.is_empty()).then_some(topic);
+        }
+        chan.updated_at = Utc::now();
         lock.insert(id, chan.clone());
 
         Ok(chan)
@@ -196,7 +304,243 @@ impl ChannelRepository for InMemoryChannelRepository {
             }
         }
         *lock = new_vec;
+        drop(lock);
+
+        let mut lock = self.invite_map.lock().await;
+        lock.retain(|_, invite| invite.channel_id != id);
+        drop(lock);
+
+        let mut lock = self.notification_map.lock().await;
+        lock.retain(|(channel_id, _), _| *channel_id != id);
 
         Ok(())
     }
+
+    async fn count(&self) -> Result<u64, ApiError> {
+        let lock = self.channel_map.lock().await;
+
+        Ok(lock.len() as u64)
+    }
+
+    async fn create_invite(
+        &self,
+        channel_id: Uuid,
+        user_id: Uuid,
+        data: ChannelInviteCreateData,
+    ) -> Result<ChannelInvite, ApiError> {
+        let mut buf: [u8; 24] = [0; 24];
+        rand::thread_rng().fill(&mut buf);
+
+        let invite = ChannelInvite {
+            id: Uuid::now_v7(),
+            token: general_purpose::URL_SAFE_NO_PAD.encode(buf),
+            channel_id,
+            user_id,
+            created_at: Utc::now(),
+            expires_at: data.expires_at,
+            max_uses: data.max_uses,
+            uses: 0,
+        };
+
+        let mut lock = self.invite_map.lock().await;
+        lock.insert(invite.token.clone(), invite.clone());
+
+        Ok(invite)
+    }
+
+    async fn get_invite(&self, token: &str) -> Result<Option<ChannelInvite>, ApiError> {
+        let lock = self.invite_map.lock().await;
+
+        Ok(lock.get(token).cloned())
+    }
+
+    async fn get_active_invites(&self, channel_id: Uuid) -> Result<Vec<ChannelInvite>, ApiError> {
+        let now = Utc::now();
+        let lock = self.invite_map.lock().await;
+
+        let mut invites = lock
+            .values()
+            .filter(|i| i.channel_id == channel_id && !i.is_exhausted(now))
+            .cloned()
+            .collect::<Vec<_>>();
+        invites.sort_by_key(|i| i.created_at);
+
+        Ok(invites)
+    }
+
+    async fn delete_invite(&self, channel_id: Uuid, id: Uuid) -> Result<(), ApiError> {
+        let mut lock = self.invite_map.lock().await;
+        let len = lock.len();
+        lock.retain(|_, i| !(i.id == id && i.channel_id == channel_id));
+
+        if lock.len() < len {
+            Ok(())
+        } else {
+            Err(ApiError::ChannelInviteNotFound)
+        }
+    }
+
+    async fn use_invite(&self, token: &str) -> Result<ChannelInvite, ApiError> {
+        let mut lock = self.invite_map.lock().await;
+        let invite = lock.get_mut(token).ok_or(ApiError::ChannelInviteNotFound)?;
+
+        if invite.is_exhausted(Utc::now()) {
+            return Err(ApiError::ChannelInviteExhausted);
+        }
+        invite.uses += 1;
+
+        Ok(invite.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryChannelRepository;
+    use crate::channel::{
+        models::{ChannelCreateData, ChannelUpdateData, UserPermission},
+        repository::ChannelRepository,
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_partial_update() {
+        let repo = InMemoryChannelRepository::new();
+
+        let chan = repo
+            .create(
+                Uuid::new_v4(),
+                ChannelCreateData {
+                    name: "general".into(),
+                    description: Some("Anything goes".into()),
+                    topic: None,
+                    init_users: None,
+                    init_permission: UserPermission::Interact,
+                },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let updated = repo
+            .update(
+                chan.id,
+                ChannelUpdateData {
+                    name: None,
+                    description: None,
+                    topic: Some("Release planning".into()),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(updated.name, "general");
+        assert_eq!(updated.description.as_deref(), Some("Anything goes"));
+        assert_eq!(updated.topic.as_deref(), Some("Release planning"));
+        assert!(updated.updated_at > chan.updated_at);
+
+        let unchanged = repo
+            .update(
+                chan.id,
+                ChannelUpdateData {
+                    name: None,
+                    description: None,
+                    topic: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(unchanged.topic, updated.topic);
+        assert_eq!(unchanged.updated_at, updated.updated_at);
+    }
+
+    #[tokio::test]
+    async fn test_add_members() {
+        let repo = InMemoryChannelRepository::new();
+        let owner = Uuid::new_v4();
+        let users = vec![Uuid::new_v4(), Uuid::new_v4()];
+
+        let chan = repo
+            .create(
+                owner,
+                ChannelCreateData {
+                    name: "announcements".into(),
+                    description: None,
+                    topic: None,
+                    init_users: Some(users.clone()),
+                    init_permission: UserPermission::Read,
+                },
+            )
+            .await
+            .unwrap();
+
+        for &user_id in &users {
+            let perm = repo.get_user_permission(user_id, chan.id).await.unwrap();
+            assert_eq!(perm, UserPermission::None);
+        }
+
+        let mut with_owner = users.clone();
+        with_owner.extend([owner, users[0]]);
+        let added = repo
+            .add_members(chan.id, &with_owner, UserPermission::Read)
+            .await
+            .unwrap();
+        assert_eq!(added, users);
+
+        for user_id in users {
+            let perm = repo.get_user_permission(user_id, chan.id).await.unwrap();
+
+            assert_eq!(perm, UserPermission::Read);
+            assert!(perm.can_read_msg());
+            assert!(!perm.can_send_msg());
+        }
+
+        let perm = repo.get_user_permission(owner, chan.id).await.unwrap();
+        assert!(perm.can_send_msg());
+    }
+
+    #[tokio::test]
+    async fn test_get_user_permissions() {
+        let repo = InMemoryChannelRepository::new();
+        let (owner, user) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let mut ids = Vec::new();
+        for name in ["general", "random", "off-topic"] {
+            let chan = repo
+                .create(
+                    owner,
+                    ChannelCreateData {
+                        name: name.into(),
+                        description: None,
+                        topic: None,
+                        init_users: None,
+                        init_permission: UserPermission::Read,
+                    },
+                )
+                .await
+                .unwrap();
+            ids.push(chan.id);
+        }
+
+        repo.add_members(ids[0], &[user], UserPermission::Read)
+            .await
+            .unwrap();
+        repo.add_members(ids[1], &[user], UserPermission::Admin)
+            .await
+            .unwrap();
+        repo.set_user_permission(ids[1], user, UserPermission::Interact)
+            .await
+            .unwrap();
+
+        let perms = repo.get_user_permissions(user, &ids).await.unwrap();
+        assert_eq!(perms.len(), 2);
+        assert_eq!(perms[&ids[0]], UserPermission::Read);
+        assert_eq!(perms[&ids[1]], UserPermission::Interact);
+
+        let perms = repo.get_user_permissions(owner, &ids[1..]).await.unwrap();
+        assert_eq!(perms.len(), 2);
+        assert!(perms.values().all(|p| *p == UserPermission::Owner));
+    }
 }
@@ -1,6 +1,7 @@
-use crate::http::ApiResponder;
+use crate::{errors::ApiError, http::ApiResponder};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +12,24 @@ pub struct Channel {
     pub updated_at: DateTime<Utc>,
     pub user_id: Uuid,
     pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+pub const CHANNEL_DESCRIPTION_MAX_LEN: usize = 1024;
+pub const CHANNEL_TOPIC_MAX_LEN: usize = 256;
+
+fn validate_metadata(description: Option<&str>, topic: Option<&str>) -> Result<(), ApiError> {
+    if description.is_some_and(|d| d.chars().count() > CHANNEL_DESCRIPTION_MAX_LEN) {
+        return Err(ApiError::ChannelDescriptionTooLong);
+    }
+    if topic.is_some_and(|t| t.chars().count() > CHANNEL_TOPIC_MAX_LEN) {
+        return Err(ApiError::ChannelTopicTooLong);
+    }
+
+    Ok(())
 }
 
 impl ApiResponder for Channel {
@@ -28,13 +47,59 @@ impl ApiResponder for Channel {
 #[serde(deny_unknown_fields)]
 pub struct ChannelCreateData {
     pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
     pub init_users: Option<Vec<Uuid>>,
+    /// The permission `init_users` are added with
+    #[serde(default = "default_init_permission")]
+    pub init_permission: UserPermission,
+}
+
+#[inline(always)]
+fn default_init_permission() -> UserPermission {
+    UserPermission::Interact
 }
 
+impl ChannelCreateData {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        // The creator is the only owner of the channel, and adding users with
+        // no permission is meaningless
+        if matches!(
+            self.init_permission,
+            UserPermission::Owner | UserPermission::None
+        ) {
+            return Err(ApiError::ChannelInitPermissionInvalid);
+        }
+
+        validate_metadata(self.description.as_deref(), self.topic.as_deref())
+    }
+}
+
+/// A partial update of a channel, where the fields that are not provided are
+/// left unchanged. An empty `description` or `topic` clears it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ChannelUpdateData {
-    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
+
+impl ChannelUpdateData {
+    #[inline]
+    pub fn validate(&self) -> Result<(), ApiError> {
+        validate_metadata(self.description.as_deref(), self.topic.as_deref())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.description.is_none() && self.topic.is_none()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -48,44 +113,58 @@ pub enum UserPermission {
 }
 
 impl UserPermission {
+    /// The strength of the permission, used to order them
     #[inline]
-    pub fn can_delete_chan(&self) -> bool {
+    fn level(&self) -> u8 {
         match self {
-            Self::Owner => true,
-            _ => false,
+            Self::Owner => 4,
+            Self::Admin => 3,
+            Self::Interact => 2,
+            Self::Read => 1,
+            Self::None => 0,
         }
     }
 
+    #[inline]
+    pub fn can_delete_chan(&self) -> bool {
+        *self >= Self::Owner
+    }
+
     #[inline]
     pub fn can_update_chan(&self) -> bool {
-        match self {
-            Self::Owner | Self::Admin => true,
-            _ => false,
-        }
+        *self >= Self::Admin
     }
 
     #[inline]
+    #[allow(dead_code)]
     pub fn can_delete_msg(&self) -> bool {
-        match self {
-            Self::Owner | Self::Admin => true,
-            _ => false,
-        }
+        *self >= Self::Admin
     }
 
     #[inline]
     pub fn can_send_msg(&self) -> bool {
-        match self {
-            Self::Owner | Self::Admin | Self::Interact => true,
-            _ => false,
-        }
+        *self >= Self::Interact
     }
 
     #[inline]
     pub fn can_read_msg(&self) -> bool {
-        match self {
-            Self::None => false,
-            _ => true,
-        }
+        *self >= Self::Read
+    }
+}
+
+/// Permissions are ordered by strength, so `Owner > Admin > Interact > Read >
+/// None`.
+impl Ord for UserPermission {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.level().cmp(&other.level())
+    }
+}
+
+impl PartialOrd for UserPermission {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -107,3 +186,133 @@ impl ApiResponder for UserPermissionEntry {
         "An"
     }
 }
+
+/// Which of the messages of a channel are forwarded to the gateway
+/// connections of a user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum NotificationPref {
+    #[default]
+    All,
+    /// Only the messages that mention the user
+    Mentions,
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationPrefRequestBody {
+    pub pref: NotificationPref,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationPrefEntry {
+    pub channel_id: Uuid,
+    pub pref: NotificationPref,
+}
+
+impl ApiResponder for NotificationPrefEntry {
+    #[inline]
+    fn unit() -> &'static str {
+        "notification preference"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+/// The permission the users that accept an invite join the channel with.
+pub const CHANNEL_INVITE_PERMISSION: UserPermission = UserPermission::Interact;
+
+/// The default of the maximum amount of active invites of a channel.
+pub const CHANNEL_MAX_INVITES: usize = 100;
+
+/// A token that lets any user join the channel, until it expires or is used
+/// `max_uses` times.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelInvite {
+    pub id: Uuid,
+    pub token: String,
+    pub channel_id: Uuid,
+    /// The user that created the invite
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+impl ChannelInvite {
+    /// Whether the invite can no longer be used, either because it expired
+    /// or because it was used `max_uses` times.
+    #[inline]
+    pub fn is_exhausted(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+            || self.max_uses.is_some_and(|max| self.uses >= max)
+    }
+}
+
+impl ApiResponder for ChannelInvite {
+    #[inline]
+    fn unit() -> &'static str {
+        "channel invite"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelInviteCreateData {
+    /// The date the invite stops being accepted on, never if not provided
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The amount of times the invite can be accepted, unlimited if not
+    /// provided
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+}
+
+impl ChannelInviteCreateData {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.expires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(ApiError::ValidationFailed(
+                "the expiry date of the invite must be in the future".into(),
+            ));
+        }
+        if self.max_uses == Some(0) {
+            return Err(ApiError::ValidationFailed(
+                "the invite must be usable at least once".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// What the users see of the channel before accepting an invite.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelInvitePreview {
+    pub channel_id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub topic: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ApiResponder for ChannelInvitePreview {
+    #[inline]
+    fn unit() -> &'static str {
+        "channel invite preview"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
+}
@@ -1,12 +1,19 @@
-use super::models::{Channel, ChannelCreateData, ChannelUpdateData, UserPermission};
+use super::models::{
+    Channel, ChannelCreateData, ChannelInvite, ChannelInviteCreateData, ChannelUpdateData,
+    NotificationPref, UserPermission,
+};
 use crate::errors::ApiError;
 use async_trait::async_trait;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 #[async_trait]
 pub trait ChannelRepository: Sync + Send {
     async fn get_by_id(&self, id: Uuid) -> Result<Option<Channel>, ApiError>;
 
+    /// Returns the channels that exist among the ids, in no particular order.
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Channel>, ApiError>;
+
     async fn get_by_user(
         &self,
         user_id: Uuid,
@@ -14,8 +21,22 @@ pub trait ChannelRepository: Sync + Send {
         limit: u64,
     ) -> Result<Vec<Channel>, ApiError>;
 
+    /// Creates the channel owned by the user. The `init_users` are not added
+    /// by the repository, [`ChannelRepository::add_members`] is the single
+    /// place where members are inserted.
     async fn create(&self, user_id: Uuid, data: ChannelCreateData) -> Result<Channel, ApiError>;
 
+    /// Adds all the users to the channel with the permission at once, so a
+    /// failure never leaves only part of them added. The users that already
+    /// have some permission other than [`UserPermission::None`] in the channel
+    /// are left untouched, and the ones that were actually added are returned.
+    async fn add_members(
+        &self,
+        channel_id: Uuid,
+        user_ids: &[Uuid],
+        perm: UserPermission,
+    ) -> Result<Vec<Uuid>, ApiError>;
+
     async fn set_user_permission(
         &self,
         channel_id: Uuid,
@@ -29,7 +50,60 @@ pub trait ChannelRepository: Sync + Send {
         channel_id: Uuid,
     ) -> Result<UserPermission, ApiError>;
 
+    /// Returns the permissions of the user in all the channels at once. The
+    /// channels where the user has no permission are left out.
+    async fn get_user_permissions(
+        &self,
+        user_id: Uuid,
+        channel_ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, UserPermission>, ApiError>;
+
+    /// Sets which messages of the channel reach the user, the preferences
+    /// are deleted along with the channel.
+    async fn set_notification_pref(
+        &self,
+        channel_id: Uuid,
+        user_id: Uuid,
+        pref: NotificationPref,
+    ) -> Result<(), ApiError>;
+
+    /// Returns the preferences of the user in all the channels at once. The
+    /// channels where the user kept the default are left out.
+    async fn get_notification_prefs(
+        &self,
+        user_id: Uuid,
+        channel_ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, NotificationPref>, ApiError>;
+
     async fn update(&self, id: Uuid, data: ChannelUpdateData) -> Result<Channel, ApiError>;
 
     async fn delete(&self, id: Uuid) -> Result<(), ApiError>;
+
+    /// Returns the total amount of channels.
+    async fn count(&self) -> Result<u64, ApiError>;
+
+    /// Creates an invite to the channel with a random token. The invites are
+    /// deleted along with the channel.
+    async fn create_invite(
+        &self,
+        channel_id: Uuid,
+        user_id: Uuid,
+        data: ChannelInviteCreateData,
+    ) -> Result<ChannelInvite, ApiError>;
+
+    async fn get_invite(&self, token: &str) -> Result<Option<ChannelInvite>, ApiError>;
+
+    /// Returns the invites of the channel that can still be used, the oldest
+    /// first.
+    async fn get_active_invites(&self, channel_id: Uuid) -> Result<Vec<ChannelInvite>, ApiError>;
+
+    /// Deletes the invite, failing with [`ApiError::ChannelInviteNotFound`] if
+    /// it is not an invite of the channel.
+    async fn delete_invite(&self, channel_id: Uuid, id: Uuid) -> Result<(), ApiError>;
+
+    /// Counts a use of the invite, failing with
+    /// [`ApiError::ChannelInviteExhausted`] if it can no longer be used. The
+    /// check and the count are atomic, so an invite is never used more than
+    /// `max_uses` times.
+    async fn use_invite(&self, token: &str) -> Result<ChannelInvite, ApiError>;
 }
@@ -1,4 +1,4 @@
-use crate::ENCODING_FAILED_BODY;
+use crate::{http::to_json_vec, ENCODING_FAILED_BODY};
 use axum::{
     body::Body,
     http::{header, HeaderValue, Response, StatusCode},
@@ -14,6 +14,10 @@ pub enum ApiError {
     #[cfg(feature = "sqlx")]
     #[error("Something went wrong while fetching the data")]
     SqlxError,
+    #[cfg(feature = "sqlx")]
+    #[error("The database is overloaded, try again in {retry_after} seconds")]
+    /// The amount of seconds the client should wait before retrying
+    DatabaseOverloaded { retry_after: u64 },
 
     #[cfg(feature = "redis")]
     #[error("Something went wrong")]
@@ -24,9 +28,37 @@ pub enum ApiError {
     GatewayTimeout(u64),
     #[error("The received message does not contain valid utf8 characters")]
     GatewayMessageNonUTF8,
+    #[error("Gateway messages can be at most {0} bytes long")]
+    /// The maximum size of a message in bytes
+    GatewayMessageTooLarge(usize),
     #[error("The received message could not be deserialized: {0}")]
     /// The serde deserialization error string
     GatewayDeserializationFailed(String),
+    #[error("The gateway protocol version {0} is not supported")]
+    /// The requested version
+    GatewayVersionUnsupported(u32),
+    #[error("Too many gateway connections were opened from this address")]
+    GatewayTooManyConnections,
+    #[error("The gateway is overloaded, try again in {retry_after} seconds")]
+    /// The amount of seconds the client should wait before reconnecting
+    GatewayOverloaded { retry_after: u64 },
+
+    #[error("The server took too long to process the request")]
+    RequestTimedOut,
+    #[error("The server is overloaded, try again in {retry_after} seconds")]
+    /// The amount of seconds the client should wait before retrying
+    ServerOverloaded { retry_after: u64 },
+    #[error("Failed to encode the response body")]
+    ResponseEncodingFailed,
+    #[error("The request body is too large")]
+    RequestBodyTooLarge,
+    #[error("The requested route does not exist")]
+    RouteNotFound,
+    #[error("The method is not allowed for the requested route")]
+    MethodNotAllowed,
+    #[error("The request is invalid: {0}")]
+    /// The description of the rule that was not met
+    ValidationFailed(String),
 
     #[error("Something went wrong")]
     CacheGetFailed,
@@ -54,10 +86,26 @@ pub enum ApiError {
 
     #[error("The message could not be found")]
     MessageNotFound,
+    #[error("The message belongs to another channel")]
+    MessageChannelMismatch,
     #[error("Failed to fetch the message")]
     MessageFetchFailed,
     #[error("You cannot edit a message you didn't send")]
     MessageEditDenied,
+    #[error("The message can no longer be edited")]
+    MessageEditWindowExpired,
+    #[error("At most 1000 messages can be imported at once")]
+    MessageImportTooLarge,
+    #[error("The author of an imported message is not a member of the channel")]
+    MessageImportAuthorInvalid,
+    #[error("The imported messages can not be older than the latest message of the channel")]
+    MessageImportOutOfOrder,
+    #[error("The message content can be at most {0} characters long")]
+    /// The maximum amount of characters
+    MessageTooLong(usize),
+    #[error("The message was rejected by the content filter: {0}")]
+    /// The category of the matched content
+    MessageRejectedByFilter(String),
     #[error("You cannot delete a message if you don't own it or if you are not an admin")]
     MessageDeleteDenied,
 
@@ -67,6 +115,8 @@ pub enum ApiError {
     UserFetchFailed,
     #[error("The user already exists")]
     UserAlreadyExists,
+    #[error("The username is already taken")]
+    UsernameAlreadyTaken,
 
     #[error("Authorization is required but the 'Authorization' header was not provided")]
     AuthHeaderMissing,
@@ -86,6 +136,53 @@ pub enum ApiError {
     AuthBcryptHashFailed,
     #[error("The user is under invalidation, please login again later")]
     AuthUserInvalidated,
+    #[error("The session expired due to inactivity, please login again")]
+    SessionIdleExpired,
+    #[error("Too many failed login attempts, try again in {retry_after} seconds")]
+    /// The amount of seconds until the lockout window expires
+    AccountLocked { retry_after: u64 },
+    #[error("Too many password checks are pending, try again in {retry_after} seconds")]
+    /// The amount of seconds the client should wait before retrying
+    AuthOverloaded { retry_after: u64 },
+
+    #[error("Two-factor authentication is not enrolled for this user")]
+    TwoFactorNotEnrolled,
+    #[error("Two-factor authentication is already enabled for this user")]
+    TwoFactorAlreadyEnabled,
+    #[error("The provided two-factor authentication code is invalid")]
+    TwoFactorCodeInvalid,
+    #[error("The provided two-factor challenge token is invalid or expired")]
+    TwoFactorChallengeInvalid,
+    #[error("Something went wrong")]
+    TwoFactorCryptoFailed,
+
+    #[error("The email address of the user must be verified first")]
+    EmailNotVerified,
+    #[error("The provided email verification token is invalid or expired")]
+    EmailVerificationTokenInvalid,
+    #[error("The email address of the user is already verified")]
+    EmailAlreadyVerified,
+
+    #[error("The provided password reset token is invalid or expired")]
+    PasswordResetTokenInvalid,
+
+    #[error("This action requires administrator privileges")]
+    AdminPermissionRequired,
+
+    #[error("Something went wrong")]
+    MailSendFailed,
+
+    #[error("The attachment could not be found")]
+    AttachmentNotFound,
+    #[error("The attachment is invalid: {0}")]
+    /// The reason the attachment was rejected
+    AttachmentInvalid(String),
+    #[error("You cannot delete an attachment you didn't upload")]
+    AttachmentDeleteDenied,
+    #[error("Something went wrong")]
+    AttachmentStoreFailed,
+    #[error("The requested range is not satisfiable")]
+    AttachmentRangeNotSatisfiable,
 
     #[error("The channel could not be found")]
     ChannelNotFound,
@@ -93,21 +190,55 @@ pub enum ApiError {
     ChannelFetchFailed,
     #[error("You don't have permission to do this action in the channel")]
     ChannelPermissionDenied,
+    #[error("The channel description must be at most 1024 characters long")]
+    ChannelDescriptionTooLong,
+    #[error("The channel topic must be at most 256 characters long")]
+    ChannelTopicTooLong,
+    #[error("Channel members can only be added as ADMIN, INTERACT or READ")]
+    ChannelInitPermissionInvalid,
+    #[error("The channel invite could not be found")]
+    ChannelInviteNotFound,
+    #[error("The channel invite expired or was used too many times")]
+    ChannelInviteExhausted,
+    #[error("The channel can have at most {0} active invites")]
+    /// The maximum amount of active invites
+    ChannelInviteLimitReached(usize),
+}
+
+/// The amount of seconds the clients are told to wait when no database
+/// connection could be acquired in time.
+#[cfg(feature = "sqlx")]
+const DATABASE_OVERLOAD_RETRY_AFTER: u64 = 2;
+
+#[cfg(feature = "sqlx")]
+impl ApiError {
+    /// Maps a failed query, telling an exhausted connection pool apart from
+    /// the database being unreachable or the query failing.
+    pub fn from_sqlx(err: &sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::PoolTimedOut => ApiError::DatabaseOverloaded {
+                retry_after: DATABASE_OVERLOAD_RETRY_AFTER,
+            },
+            _ => ApiError::SqlxError,
+        }
+    }
 }
 
 impl Serialize for ApiError {
     #[inline]
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        Into::<ErrorResponse>::into(self).serialize(serializer)
+        ErrorResponse::from(self).serialize(serializer)
     }
 }
 
-impl Into<StatusCode> for &ApiError {
+impl From<&ApiError> for StatusCode {
     #[inline]
-    fn into(self) -> StatusCode {
-        match self {
+    fn from(value: &ApiError) -> Self {
+        match value {
             #[cfg(feature = "sqlx")]
             ApiError::SqlxError => StatusCode::INTERNAL_SERVER_ERROR,
+            #[cfg(feature = "sqlx")]
+            ApiError::DatabaseOverloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
             #[cfg(feature = "redis")]
             ApiError::RedisError => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::ServicePanicked(_)
@@ -126,12 +257,38 @@ impl Into<StatusCode> for &ApiError {
             | ApiError::MessagingSubscribeFailed
             | ApiError::MessagingUnsubscribeFailed
             | ApiError::AuthBcryptHashFailed
+            | ApiError::TwoFactorCryptoFailed
+            | ApiError::MailSendFailed
+            | ApiError::ResponseEncodingFailed
+            | ApiError::AttachmentStoreFailed
             | ApiError::ChannelFetchFailed => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::GatewayTimeout(_) => StatusCode::REQUEST_TIMEOUT,
-            ApiError::GatewayDeserializationFailed(_) | ApiError::GatewayMessageNonUTF8 => {
-                StatusCode::BAD_REQUEST
+            ApiError::GatewayOverloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::RequestTimedOut => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::ServerOverloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::RequestBodyTooLarge | ApiError::GatewayMessageTooLarge(_) => {
+                StatusCode::PAYLOAD_TOO_LARGE
             }
-            ApiError::UserAlreadyExists => StatusCode::CONFLICT,
+            ApiError::AttachmentRangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            ApiError::GatewayDeserializationFailed(_)
+            | ApiError::GatewayMessageNonUTF8
+            | ApiError::GatewayVersionUnsupported(_)
+            | ApiError::ValidationFailed(_)
+            | ApiError::TwoFactorNotEnrolled
+            | ApiError::ChannelDescriptionTooLong
+            | ApiError::ChannelTopicTooLong
+            | ApiError::ChannelInitPermissionInvalid
+            | ApiError::AttachmentInvalid(_)
+            | ApiError::MessageImportTooLarge
+            | ApiError::MessageImportAuthorInvalid
+            | ApiError::MessageTooLong(_)
+            | ApiError::MessageRejectedByFilter(_) => StatusCode::BAD_REQUEST,
+            ApiError::UserAlreadyExists
+            | ApiError::UsernameAlreadyTaken
+            | ApiError::TwoFactorAlreadyEnabled
+            | ApiError::EmailAlreadyVerified
+            | ApiError::MessageImportOutOfOrder
+            | ApiError::ChannelInviteLimitReached(_) => StatusCode::CONFLICT,
             ApiError::AuthHeaderMissing
             | ApiError::AuthHeaderInvalid
             | ApiError::AuthFailed
@@ -140,21 +297,41 @@ impl Into<StatusCode> for &ApiError {
             | ApiError::AuthTokenExpired
             | ApiError::AuthRefreshTokenInvalid
             | ApiError::AuthUserInvalidated
+            | ApiError::SessionIdleExpired
+            | ApiError::TwoFactorCodeInvalid
+            | ApiError::TwoFactorChallengeInvalid
+            | ApiError::EmailVerificationTokenInvalid
+            | ApiError::PasswordResetTokenInvalid
             | ApiError::ChannelNotFound => StatusCode::UNAUTHORIZED,
-            ApiError::MessageNotFound => StatusCode::NOT_FOUND,
+            ApiError::MessageNotFound
+            | ApiError::MessageChannelMismatch
+            | ApiError::AttachmentNotFound
+            | ApiError::ChannelInviteNotFound
+            | ApiError::RouteNotFound => StatusCode::NOT_FOUND,
+            ApiError::ChannelInviteExhausted => StatusCode::GONE,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::AccountLocked { .. }
+            | ApiError::AuthOverloaded { .. }
+            | ApiError::GatewayTooManyConnections => StatusCode::TOO_MANY_REQUESTS,
             ApiError::MessageEditDenied
+            | ApiError::MessageEditWindowExpired
             | ApiError::MessageDeleteDenied
-            | ApiError::ChannelPermissionDenied => StatusCode::FORBIDDEN,
+            | ApiError::ChannelPermissionDenied
+            | ApiError::EmailNotVerified
+            | ApiError::AdminPermissionRequired
+            | ApiError::AttachmentDeleteDenied => StatusCode::FORBIDDEN,
         }
     }
 }
 
-impl Into<u32> for &ApiError {
+impl From<&ApiError> for u32 {
     #[inline]
-    fn into(self) -> u32 {
-        match self {
+    fn from(value: &ApiError) -> Self {
+        match value {
             #[cfg(feature = "sqlx")]
             ApiError::SqlxError => 50000,
+            #[cfg(feature = "sqlx")]
+            ApiError::DatabaseOverloaded { .. } => 50302,
             #[cfg(feature = "redis")]
             ApiError::RedisError => 50000,
             ApiError::CacheGetFailed
@@ -172,14 +349,33 @@ impl Into<u32> for &ApiError {
             ApiError::ServicePanicked(_) => 50001,
             ApiError::GatewayTimeout(_) => 40801,
             ApiError::GatewayMessageNonUTF8 => 40001,
+            ApiError::GatewayMessageTooLarge(_) => 41302,
             ApiError::GatewayDeserializationFailed(_) => 40002,
+            ApiError::GatewayVersionUnsupported(_) => 40011,
+            ApiError::GatewayTooManyConnections => 42902,
+            ApiError::GatewayOverloaded { .. } => 50301,
+            ApiError::RequestTimedOut => 50401,
+            ApiError::ServerOverloaded { .. } => 50303,
+            ApiError::ResponseEncodingFailed => 50008,
+            ApiError::RequestBodyTooLarge => 41301,
+            ApiError::RouteNotFound => 40405,
+            ApiError::MessageChannelMismatch => 40406,
+            ApiError::MethodNotAllowed => 40501,
+            ApiError::ValidationFailed(_) => 40012,
             ApiError::MessageNotFound => 40401,
             ApiError::MessageFetchFailed => 50002,
             ApiError::MessageEditDenied => 40301,
             ApiError::MessageDeleteDenied => 40302,
+            ApiError::MessageEditWindowExpired => 40307,
+            ApiError::MessageImportTooLarge => 40008,
+            ApiError::MessageImportAuthorInvalid => 40009,
+            ApiError::MessageImportOutOfOrder => 40906,
+            ApiError::MessageTooLong(_) => 40010,
+            ApiError::MessageRejectedByFilter(_) => 40013,
             ApiError::UserNotFound => 40402,
             ApiError::UserFetchFailed => 50003,
             ApiError::UserAlreadyExists => 40901,
+            ApiError::UsernameAlreadyTaken => 40905,
             ApiError::AuthHeaderMissing => 40101,
             ApiError::AuthHeaderInvalid => 40102,
             ApiError::AuthFailed => 40103,
@@ -187,10 +383,35 @@ impl Into<u32> for &ApiError {
             ApiError::AuthTokenExpired => 40105,
             ApiError::AuthRefreshTokenInvalid => 40106,
             ApiError::AuthUserInvalidated => 40107,
+            ApiError::SessionIdleExpired => 40112,
             ApiError::AuthTokenGenerationFailed => 50004,
+            ApiError::AccountLocked { .. } => 42901,
+            ApiError::AuthOverloaded { .. } => 42903,
+            ApiError::TwoFactorNotEnrolled => 40003,
+            ApiError::TwoFactorAlreadyEnabled => 40902,
+            ApiError::TwoFactorCodeInvalid => 40108,
+            ApiError::TwoFactorChallengeInvalid => 40109,
+            ApiError::TwoFactorCryptoFailed => 50006,
+            ApiError::EmailNotVerified => 40304,
+            ApiError::EmailVerificationTokenInvalid => 40110,
+            ApiError::EmailAlreadyVerified => 40903,
+            ApiError::ChannelInviteLimitReached(_) => 40904,
+            ApiError::MailSendFailed => 50007,
+            ApiError::PasswordResetTokenInvalid => 40111,
+            ApiError::AdminPermissionRequired => 40305,
             ApiError::ChannelNotFound => 40403,
+            ApiError::ChannelInviteNotFound => 40407,
+            ApiError::ChannelInviteExhausted => 41001,
+            ApiError::AttachmentNotFound => 40404,
+            ApiError::AttachmentInvalid(_) => 40007,
+            ApiError::AttachmentDeleteDenied => 40306,
+            ApiError::AttachmentStoreFailed => 50009,
+            ApiError::AttachmentRangeNotSatisfiable => 41601,
             ApiError::ChannelFetchFailed => 50005,
             ApiError::ChannelPermissionDenied => 40303,
+            ApiError::ChannelDescriptionTooLong => 40004,
+            ApiError::ChannelTopicTooLong => 40005,
+            ApiError::ChannelInitPermissionInvalid => 40006,
         }
     }
 }
@@ -201,22 +422,29 @@ pub struct ErrorResponse {
     pub error_code: u32,
     #[serde(skip_serializing)]
     pub status_code: StatusCode,
+    /// The value of the `Retry-After` header, in seconds
+    #[serde(skip_serializing)]
+    pub retry_after: Option<u64>,
 }
 
 impl ErrorResponse {
     #[inline]
+    #[allow(dead_code)]
     pub fn new(message: String, error_code: u32, status_code: StatusCode) -> Self {
         Self {
             message,
             error_code,
             status_code,
+            retry_after: None,
         }
     }
 }
 
 impl IntoResponse for ErrorResponse {
     fn into_response(self) -> axum::response::Response {
-        let tuple = match serde_json::to_vec(&self) {
+        let retry_after = self.retry_after.map(HeaderValue::from);
+
+        let tuple = match to_json_vec(&self) {
             Ok(buf) => (
                 self.status_code,
                 [(
@@ -225,27 +453,48 @@ impl IntoResponse for ErrorResponse {
                 )],
                 buf,
             ),
-            Err(_) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                [(
-                    header::CONTENT_TYPE,
-                    HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
-                )],
-                ENCODING_FAILED_BODY.to_vec(),
-            ),
+            Err(e) => {
+                tracing::error!(
+                    error = e.to_string(),
+                    "Failed to encode error response body"
+                );
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    [(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
+                    )],
+                    ENCODING_FAILED_BODY.to_vec(),
+                )
+            }
         };
 
-        tuple.into_response()
+        let mut res = tuple.into_response();
+        if let Some(retry_after) = retry_after {
+            res.headers_mut().insert(header::RETRY_AFTER, retry_after);
+        }
+        res
     }
 }
 
 impl From<&ApiError> for ErrorResponse {
     #[inline]
     fn from(value: &ApiError) -> Self {
+        let retry_after = match value {
+            ApiError::AccountLocked { retry_after } => Some(*retry_after),
+            ApiError::AuthOverloaded { retry_after } => Some(*retry_after),
+            ApiError::GatewayOverloaded { retry_after } => Some(*retry_after),
+            ApiError::ServerOverloaded { retry_after } => Some(*retry_after),
+            #[cfg(feature = "sqlx")]
+            ApiError::DatabaseOverloaded { retry_after } => Some(*retry_after),
+            _ => None,
+        };
+
         ErrorResponse {
             error_code: value.into(),
             status_code: value.into(),
             message: value.to_string(),
+            retry_after,
         }
     }
 }
@@ -260,6 +509,6 @@ impl From<ApiError> for ErrorResponse {
 impl IntoResponse for ApiError {
     #[inline]
     fn into_response(self) -> Response<Body> {
-        ErrorResponse::new(self.to_string(), (&self).into(), (&self).into()).into_response()
+        ErrorResponse::from(&self).into_response()
     }
 }
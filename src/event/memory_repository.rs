@@ -1,5 +1,5 @@
 use super::{
-    models::AppEvent,
+    models::{AppEvent, EventEnvelope},
     repository::{EventConnection, EventRepository},
 };
 use crate::errors::ApiError;
@@ -7,12 +7,12 @@ use async_trait::async_trait;
 use tokio::sync::broadcast::{Receiver, Sender};
 
 pub struct InMemoryEventConnection {
-    receiver: Receiver<AppEvent>,
+    receiver: Receiver<EventEnvelope>,
 }
 
 #[async_trait]
 impl EventConnection for InMemoryEventConnection {
-    async fn recv(&mut self) -> Result<AppEvent, ApiError> {
+    async fn recv_envelope(&mut self) -> Result<EventEnvelope, ApiError> {
         match self.receiver.recv().await {
             Ok(v) => Ok(v),
             Err(e) => {
@@ -25,7 +25,7 @@ impl EventConnection for InMemoryEventConnection {
 
 #[derive(Clone)]
 pub struct InMemoryEventRepository {
-    sender: Sender<AppEvent>,
+    sender: Sender<EventEnvelope>,
 }
 
 impl InMemoryEventRepository {
@@ -47,7 +47,7 @@ impl EventRepository for InMemoryEventRepository {
     }
 
     async fn publish(&self, event: AppEvent) -> Result<(), ApiError> {
-        match self.sender.send(event) {
+        match self.sender.send(EventEnvelope::new(event)) {
             Ok(_) => Ok(()),
             Err(e) => {
                 tracing::error!(error = e.to_string(), "Failed to publish event");
@@ -1,7 +1,10 @@
 use crate::{
-    auth::models::InvalidationReason, channel::models::ChannelUpdateData, message::models::Message,
+    auth::models::InvalidationReason,
+    channel::models::{Channel, ChannelUpdateData, NotificationPref, UserPermission},
+    message::models::Message,
 };
 use serde::{Deserialize, Serialize};
+use tracing::Span;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,10 +17,128 @@ use uuid::Uuid;
 pub enum AppEvent {
     MessageCreated(Message),
     MessageUpdated(Message),
-    MessageDeleted { id: Uuid, channel_id: Uuid },
+    MessageDeleted {
+        id: Uuid,
+        channel_id: Uuid,
+    },
+    MessageRead {
+        channel_id: Uuid,
+        user_id: Uuid,
+        up_to_message_id: Uuid,
+    },
+    UserTyping {
+        channel_id: Uuid,
+        user_id: Uuid,
+    },
+    /// Published after the `ChannelUserAddedIn` events of the initial
+    /// members, so the channel is already known by their connections
+    ChannelCreated(Channel),
     ChannelDeleted(Uuid),
-    ChannelUserAddedIn { id: Uuid, user_id: Uuid },
-    ChannelUserRemovedFrom { id: Uuid, user_id: Uuid },
+    /// A batch of historical messages was imported, which is published once
+    /// instead of one `MessageCreated` for each message
+    ChannelImported {
+        id: Uuid,
+        count: u64,
+    },
+    ChannelUserAddedIn {
+        id: Uuid,
+        user_id: Uuid,
+        permission: UserPermission,
+    },
+    ChannelUserRemovedFrom {
+        id: Uuid,
+        user_id: Uuid,
+    },
+    /// The permission of a user that stays a member of the channel changed,
+    /// the ones that add or remove the user are published as
+    /// `ChannelUserAddedIn` and `ChannelUserRemovedFrom`
+    ChannelPermissionChanged {
+        id: Uuid,
+        user_id: Uuid,
+        permission: UserPermission,
+    },
     ChannelUpdated(Uuid, ChannelUpdateData),
+    NotificationPrefChanged {
+        channel_id: Uuid,
+        user_id: Uuid,
+        pref: NotificationPref,
+    },
     UserInvalidated(Uuid, InvalidationReason),
+    /// An administrator asked for a single gateway connection to be closed
+    ConnectionClosed {
+        connection_id: Uuid,
+    },
+}
+
+/// An [`AppEvent`] as carried from the publisher to the connections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    #[serde(flatten)]
+    pub event: AppEvent,
+    /// The W3C trace context of the span that published the event, only set
+    /// with the `otel` feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traceparent: Option<String>,
+}
+
+impl EventEnvelope {
+    /// Wraps the event with the trace context of the current span.
+    pub fn new(event: AppEvent) -> Self {
+        #[cfg(feature = "otel")]
+        let traceparent = crate::telemetry::current_traceparent();
+        #[cfg(not(feature = "otel"))]
+        let traceparent = None;
+
+        Self { event, traceparent }
+    }
+
+    /// Creates the span of the delivery of the event to a connection, linked
+    /// to the trace of the request that published it.
+    pub fn delivery_span(&self) -> Span {
+        let span = tracing::info_span!("event_delivery");
+
+        #[cfg(feature = "otel")]
+        if let Some(traceparent) = &self.traceparent {
+            crate::telemetry::link_traceparent(&span, traceparent);
+        }
+
+        span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppEvent, EventEnvelope};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_envelope() {
+        let event = AppEvent::ChannelDeleted(Uuid::nil());
+        let envelope = EventEnvelope {
+            event: event.clone(),
+            traceparent: None,
+        };
+
+        // Without a trace context the envelope is a plain event
+        assert_eq!(
+            serde_json::to_value(&envelope).unwrap(),
+            serde_json::to_value(&event).unwrap()
+        );
+        let envelope: EventEnvelope =
+            serde_json::from_value(serde_json::to_value(&event).unwrap()).unwrap();
+        assert!(envelope.traceparent.is_none());
+
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let envelope = EventEnvelope {
+            event,
+            traceparent: Some(traceparent.into()),
+        };
+        let json = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(json["traceparent"], traceparent);
+        assert_eq!(json["type"], "CHANNEL_DELETED");
+
+        let envelope: EventEnvelope = serde_json::from_value(json).unwrap();
+        assert_eq!(envelope.traceparent.as_deref(), Some(traceparent));
+        assert!(matches!(envelope.event, AppEvent::ChannelDeleted(id) if id.is_nil()));
+    }
 }
@@ -1,25 +1,135 @@
 use super::{
-    models::AppEvent,
+    models::{AppEvent, EventEnvelope},
     repository::{EventConnection, EventRepository},
 };
-use crate::errors::ApiError;
+use crate::{errors::ApiError, BoxedError};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use deadpool_redis::{
-    redis::{aio::PubSub, AsyncCommands, RedisError},
-    Connection,
+    redis::{self, aio::PubSub, AsyncCommands, RedisError},
+    Connection, Pool, PoolError,
 };
+use serde::Serialize;
+use std::collections::VecDeque;
 use tokio::sync::broadcast::{error::RecvError, Receiver, Sender};
 use tokio_stream::StreamExt;
 
-const REDIS_CHANNEL: &'static str = "app_event";
+const REDIS_CHANNEL: &str = "app_event";
+/// The list the events that failed to be published are pushed to, newest
+/// first, so they can be inspected and replayed by an operator
+const REDIS_DEAD_LETTER_LIST: &str = "app_event:dlq";
+/// The amount of entries kept in the dead-letter list, the oldest ones are
+/// dropped past it
+const DEAD_LETTER_MAX_LEN: isize = 1000;
+/// The amount of dead letters kept in memory while redis can not be reached,
+/// the oldest ones are lost past it
+const DEAD_LETTER_BUFFER_LEN: usize = 100;
+
+/// An event that could not be published, along with the reason.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    /// The event encoded as json, or its debug representation if the
+    /// encoding is what failed
+    pub event: String,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    fn new(event: String, error: impl ToString) -> Self {
+        Self {
+            event,
+            error: error.to_string(),
+            failed_at: Utc::now(),
+        }
+    }
+}
+
+/// Pushes the letter to the dead-letter list, trimming it to
+/// [`DEAD_LETTER_MAX_LEN`].
+async fn push_dead_letter(conn: &mut Connection, letter: &DeadLetter) -> Result<(), RedisError> {
+    let payload = serde_json::to_string(letter).unwrap_or_default();
+    redis::pipe()
+        .lpush(REDIS_DEAD_LETTER_LIST, payload)
+        .ignore()
+        .ltrim(REDIS_DEAD_LETTER_LIST, 0, DEAD_LETTER_MAX_LEN - 1)
+        .ignore()
+        .query_async::<_, ()>(conn)
+        .await
+}
+
+/// Returns the publishing connection, or a fresh one from the pool if the last
+/// one failed and was dropped.
+async fn take_conn(pool: &Pool, conn: &mut Option<Connection>) -> Result<Connection, PoolError> {
+    match conn.take() {
+        Some(v) => Ok(v),
+        None => pool.get().await,
+    }
+}
+
+/// Publishes the payload. A connection that fails is detached from the pool
+/// and dropped, so neither the dead-letter push nor the next event reuse it.
+async fn publish(
+    pool: &Pool,
+    conn: &mut Option<Connection>,
+    payload: &str,
+) -> Result<(), BoxedError> {
+    let mut send_conn = take_conn(pool, conn).await?;
+
+    match send_conn.publish::<_, _, ()>(REDIS_CHANNEL, payload).await {
+        Ok(()) => {
+            *conn = Some(send_conn);
+            Ok(())
+        }
+        Err(e) => {
+            drop(Connection::take(send_conn));
+            Err(e.into())
+        }
+    }
+}
+
+/// Pushes the pending letters to the dead-letter list, oldest first. Stops at
+/// the first failure, leaving the rest to be retried with the next event.
+async fn flush_dead_letters(
+    pool: &Pool,
+    conn: &mut Option<Connection>,
+    pending: &mut VecDeque<DeadLetter>,
+) {
+    while let Some(letter) = pending.front() {
+        let mut send_conn = match take_conn(pool, conn).await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!(
+                    error = e.to_string(),
+                    pending = pending.len(),
+                    "Failed to acquire redis connection for the dead-letter list"
+                );
+                return;
+            }
+        };
+
+        if let Err(e) = push_dead_letter(&mut send_conn, letter).await {
+            tracing::error!(
+                error = e.to_string(),
+                pending = pending.len(),
+                "Failed to push event to the dead-letter list, retrying later"
+            );
+            drop(Connection::take(send_conn));
+            return;
+        }
+
+        *conn = Some(send_conn);
+        pending.pop_front();
+    }
+}
 
 pub struct RedisEventConnection {
-    sub_recv: Receiver<AppEvent>,
+    sub_recv: Receiver<EventEnvelope>,
 }
 
 #[async_trait]
 impl EventConnection for RedisEventConnection {
-    async fn recv(&mut self) -> Result<AppEvent, ApiError> {
+    async fn recv_envelope(&mut self) -> Result<EventEnvelope, ApiError> {
         match self.sub_recv.recv().await {
             Ok(v) => Ok(v),
             Err(e) => {
@@ -32,14 +142,17 @@ impl EventConnection for RedisEventConnection {
 
 #[derive(Clone)]
 pub struct RedisEventRepository {
-    sub_sender: Sender<AppEvent>,
-    pub_sender: Sender<AppEvent>,
+    sub_sender: Sender<EventEnvelope>,
+    pub_sender: Sender<EventEnvelope>,
 }
 
 impl RedisEventRepository {
+    /// Publishes the events through connections of `pool`. A connection that
+    /// fails is replaced, and the events that could not be published are
+    /// pushed to the dead-letter list with a fresh one.
     pub async fn new(
         mut recv_conn: PubSub,
-        mut send_conn: Connection,
+        pool: Pool,
     ) -> Result<RedisEventRepository, RedisError> {
         match recv_conn.subscribe(REDIS_CHANNEL).await {
             Ok(v) => v,
@@ -100,6 +213,12 @@ impl RedisEventRepository {
 
         let mut pub_recv = pub_sender.subscribe();
         tokio::spawn(async move {
+            let mut send_conn: Option<Connection> = None;
+            // The dead letters that could not be pushed yet
+            let mut pending: VecDeque<DeadLetter> = VecDeque::new();
+            // The amount of events that failed to be published so far
+            let mut dead_letters: u64 = 0;
+
             loop {
                 let event = match pub_recv.recv().await {
                     Ok(v) => v,
@@ -115,20 +234,45 @@ impl RedisEventRepository {
                     }
                 };
 
-                let event = match serde_json::to_string(&event) {
-                    Ok(v) => v,
+                let letter = match serde_json::to_string(&event) {
+                    Ok(payload) => match publish(&pool, &mut send_conn, &payload).await {
+                        Ok(()) => None,
+                        Err(e) => {
+                            tracing::error!(
+                                error = e.to_string(),
+                                "Failed to publish queued event"
+                            );
+                            Some(DeadLetter::new(payload, e))
+                        }
+                    },
                     Err(e) => {
                         tracing::error!(error = e.to_string(), "Failed to serialize queued event");
-                        continue;
+                        Some(DeadLetter::new(format!("{event:?}"), e))
                     }
                 };
 
-                match send_conn.publish(REDIS_CHANNEL, event).await {
-                    Ok(v) => v,
-                    Err(e) => {
-                        tracing::error!(error = e.to_string(), "Failed to publish queued event");
+                if let Some(letter) = letter {
+                    dead_letters += 1;
+                    tracing::warn!(
+                        error = letter.error.as_str(),
+                        dead_letters,
+                        "Event moved to the dead-letter list"
+                    );
+
+                    if pending.len() >= DEAD_LETTER_BUFFER_LEN {
+                        if let Some(lost) = pending.pop_front() {
+                            tracing::error!(
+                                event = lost.event.as_str(),
+                                "The dead-letter buffer is full, the oldest event is lost"
+                            );
+                        }
                     }
-                };
+                    pending.push_back(letter);
+                }
+
+                if !pending.is_empty() {
+                    flush_dead_letters(&pool, &mut send_conn, &mut pending).await;
+                }
             }
         });
 
@@ -150,7 +294,7 @@ impl EventRepository for RedisEventRepository {
     }
 
     async fn publish(&self, event: AppEvent) -> Result<(), ApiError> {
-        match self.pub_sender.send(event) {
+        match self.pub_sender.send(EventEnvelope::new(event)) {
             Ok(_) => Ok(()),
             Err(e) => {
                 tracing::error!(error = e.to_string(), "Failed to publish event");
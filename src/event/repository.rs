@@ -1,10 +1,14 @@
-use super::models::AppEvent;
+use super::models::{AppEvent, EventEnvelope};
 use crate::errors::ApiError;
 use async_trait::async_trait;
 
 #[async_trait]
-pub trait EventConnection {
-    async fn recv(&mut self) -> Result<AppEvent, ApiError>;
+pub trait EventConnection: Send {
+    async fn recv_envelope(&mut self) -> Result<EventEnvelope, ApiError>;
+
+    async fn recv(&mut self) -> Result<AppEvent, ApiError> {
+        self.recv_envelope().await.map(|envelope| envelope.event)
+    }
 }
 
 #[async_trait]
@@ -13,5 +17,6 @@ pub trait EventRepository: Sync + Send {
 
     async fn get_conn(&self) -> Result<Self::Connection, ApiError>;
 
+    /// Publishes the event along with the trace context of the current span.
     async fn publish(&self, event: AppEvent) -> Result<(), ApiError>;
 }
@@ -0,0 +1,567 @@
+use super::models::GatewayEvent;
+use crate::{
+    channel::models::{NotificationPref, UserPermission},
+    errors::ApiError,
+    event::models::AppEvent,
+    message::models::Message,
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The permission of the user in each channel they are a member of, cached by
+/// a connection so its actions do not need to query the repository.
+pub type ChannelPermissions = HashMap<Uuid, UserPermission>;
+
+/// The notification preferences of the user that differ from the default,
+/// cached by a connection along with the permissions.
+pub type NotificationPrefs = HashMap<Uuid, NotificationPref>;
+
+/// Whether the created message must reach the user, according to their
+/// preference in its channel.
+fn is_notified(prefs: &NotificationPrefs, msg: &Message, user_id: Uuid) -> bool {
+    match prefs.get(&msg.channel_id).copied().unwrap_or_default() {
+        NotificationPref::All => true,
+        NotificationPref::Mentions => msg.mentions(user_id),
+        NotificationPref::None => false,
+    }
+}
+
+/// Resolves the event that must be forwarded to a connection of `user_id`,
+/// keeping `channels`, the permissions of the user in the channels they are a
+/// member of, and `prefs` up to date. The created messages are only forwarded
+/// as allowed by the notification preferences.
+///
+/// An [`ApiError::AuthUserInvalidated`] error event means that the user was
+/// invalidated and that the connection must be closed after sending it. The
+/// [`AppEvent::ConnectionClosed`] events target a single connection rather
+/// than a user, so they are left to the caller.
+pub fn filter_event(
+    event: &AppEvent,
+    user_id: Uuid,
+    channels: &mut ChannelPermissions,
+    prefs: &mut NotificationPrefs,
+) -> Option<GatewayEvent> {
+    match event {
+        AppEvent::MessageCreated(msg) => (channels.contains_key(&msg.channel_id)
+            && is_notified(prefs, msg, user_id))
+        .then(|| GatewayEvent::MessageCreated(msg.clone())),
+        AppEvent::MessageUpdated(msg) => channels
+            .contains_key(&msg.channel_id)
+            .then(|| GatewayEvent::MessageUpdated(msg.clone())),
+        AppEvent::MessageDeleted { id, channel_id } => {
+            channels
+                .contains_key(channel_id)
+                .then_some(GatewayEvent::MessageDeleted {
+                    id: *id,
+                    channel_id: *channel_id,
+                })
+        }
+        AppEvent::MessageRead {
+            channel_id,
+            user_id,
+            up_to_message_id,
+        } => channels
+            .contains_key(channel_id)
+            .then_some(GatewayEvent::MessageRead {
+                channel_id: *channel_id,
+                user_id: *user_id,
+                up_to_message_id: *up_to_message_id,
+            }),
+        AppEvent::UserTyping {
+            channel_id,
+            user_id,
+        } => channels
+            .contains_key(channel_id)
+            .then_some(GatewayEvent::UserTyping {
+                channel_id: *channel_id,
+                user_id: *user_id,
+            }),
+        AppEvent::ChannelCreated(chan) => {
+            if chan.user_id == user_id {
+                channels.insert(chan.id, UserPermission::Owner);
+            }
+            channels
+                .contains_key(&chan.id)
+                .then(|| GatewayEvent::ChannelCreated(chan.clone()))
+        }
+        AppEvent::ChannelDeleted(id) => channels
+            .contains_key(id)
+            .then_some(GatewayEvent::ChannelDeleted { id: *id }),
+        AppEvent::ChannelImported { id, count } => {
+            channels
+                .contains_key(id)
+                .then_some(GatewayEvent::ChannelImported {
+                    id: *id,
+                    count: *count,
+                })
+        }
+        AppEvent::ChannelUserAddedIn {
+            id,
+            user_id: added,
+            permission,
+        } => {
+            if *added != user_id {
+                return None;
+            }
+            channels.insert(*id, permission.clone());
+            Some(GatewayEvent::ChannelUserAddedIn { id: *id })
+        }
+        AppEvent::ChannelUserRemovedFrom {
+            id,
+            user_id: removed,
+        } => {
+            if *removed != user_id {
+                return None;
+            }
+            channels.remove(id);
+            Some(GatewayEvent::ChannelUserRemovedFrom { id: *id })
+        }
+        AppEvent::ChannelPermissionChanged {
+            id,
+            user_id: changed,
+            permission,
+        } => {
+            if *changed != user_id {
+                return None;
+            }
+            if let Some(cached) = channels.get_mut(id) {
+                *cached = permission.clone();
+            }
+            Some(GatewayEvent::ChannelPermissionChanged {
+                id: *id,
+                permission: permission.clone(),
+            })
+        }
+        AppEvent::ChannelUpdated(id, data) => {
+            channels
+                .contains_key(id)
+                .then(|| GatewayEvent::ChannelUpdated {
+                    id: *id,
+                    data: data.clone(),
+                })
+        }
+        AppEvent::NotificationPrefChanged {
+            channel_id,
+            user_id: changed,
+            pref,
+        } => {
+            if *changed != user_id {
+                return None;
+            }
+            if *pref == NotificationPref::default() {
+                prefs.remove(channel_id);
+            } else {
+                prefs.insert(*channel_id, *pref);
+            }
+            Some(GatewayEvent::NotificationPrefChanged {
+                channel_id: *channel_id,
+                pref: *pref,
+            })
+        }
+        AppEvent::UserInvalidated(id, _) => {
+            (*id == user_id).then_some(GatewayEvent::Error(ApiError::AuthUserInvalidated))
+        }
+        AppEvent::ConnectionClosed { .. } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filter_event, ChannelPermissions, NotificationPrefs};
+    use crate::{
+        auth::models::InvalidationReason,
+        channel::models::{Channel, ChannelUpdateData, NotificationPref, UserPermission},
+        errors::ApiError,
+        event::models::AppEvent,
+        gateway::models::GatewayEvent,
+        message::models::{Message, MessageKind},
+    };
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn message(channel_id: Uuid) -> Message {
+        Message {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            channel_id,
+            seq: 1,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            content: Some("Hello".into()),
+            image: None,
+            expires_at: None,
+            flagged: false,
+            kind: MessageKind::User,
+        }
+    }
+
+    /// Returns the events of each channel scoped variant, for the member
+    /// channel and for another one.
+    fn channel_events(channel_id: Uuid) -> Vec<AppEvent> {
+        vec![
+            AppEvent::MessageCreated(message(channel_id)),
+            AppEvent::MessageUpdated(message(channel_id)),
+            AppEvent::MessageDeleted {
+                id: Uuid::new_v4(),
+                channel_id,
+            },
+            AppEvent::MessageRead {
+                channel_id,
+                user_id: Uuid::new_v4(),
+                up_to_message_id: Uuid::new_v4(),
+            },
+            AppEvent::UserTyping {
+                channel_id,
+                user_id: Uuid::new_v4(),
+            },
+            AppEvent::ChannelDeleted(channel_id),
+            AppEvent::ChannelImported {
+                id: channel_id,
+                count: 3,
+            },
+            AppEvent::ChannelUpdated(
+                channel_id,
+                ChannelUpdateData {
+                    name: Some("general".into()),
+                    description: None,
+                    topic: None,
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_channel_events() {
+        let user_id = Uuid::new_v4();
+        let member_of = Uuid::new_v4();
+        let mut channels = ChannelPermissions::from([(member_of, UserPermission::Interact)]);
+
+        for event in channel_events(member_of) {
+            let res = filter_event(
+                &event,
+                user_id,
+                &mut channels,
+                &mut NotificationPrefs::new(),
+            );
+            let expected = match (&event, res) {
+                (AppEvent::MessageCreated(a), Some(GatewayEvent::MessageCreated(b)))
+                | (AppEvent::MessageUpdated(a), Some(GatewayEvent::MessageUpdated(b))) => {
+                    a.id == b.id
+                }
+                (
+                    AppEvent::MessageDeleted { id, channel_id },
+                    Some(GatewayEvent::MessageDeleted {
+                        id: id2,
+                        channel_id: channel_id2,
+                    }),
+                ) => *id == id2 && *channel_id == channel_id2,
+                (
+                    AppEvent::MessageRead {
+                        up_to_message_id, ..
+                    },
+                    Some(GatewayEvent::MessageRead {
+                        up_to_message_id: up_to2,
+                        ..
+                    }),
+                ) => *up_to_message_id == up_to2,
+                (
+                    AppEvent::UserTyping { user_id, .. },
+                    Some(GatewayEvent::UserTyping {
+                        user_id: user_id2, ..
+                    }),
+                ) => *user_id == user_id2,
+                (AppEvent::ChannelDeleted(id), Some(GatewayEvent::ChannelDeleted { id: id2 })) => {
+                    *id == id2
+                }
+                (
+                    AppEvent::ChannelImported { count, .. },
+                    Some(GatewayEvent::ChannelImported { count: count2, .. }),
+                ) => *count == count2,
+                (
+                    AppEvent::ChannelUpdated(id, _),
+                    Some(GatewayEvent::ChannelUpdated { id: id2, data }),
+                ) => *id == id2 && data.name.as_deref() == Some("general"),
+                _ => false,
+            };
+            assert!(expected, "{event:?} was not forwarded as expected");
+        }
+
+        for event in channel_events(Uuid::new_v4()) {
+            let res = filter_event(
+                &event,
+                user_id,
+                &mut channels,
+                &mut NotificationPrefs::new(),
+            );
+            assert!(res.is_none(), "{event:?} of another channel was forwarded");
+        }
+
+        assert_eq!(
+            channels,
+            ChannelPermissions::from([(member_of, UserPermission::Interact)])
+        );
+    }
+
+    #[test]
+    fn test_membership_changes() {
+        let user_id = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+        let mut channels = ChannelPermissions::new();
+
+        let other_added = AppEvent::ChannelUserAddedIn {
+            id: channel_id,
+            user_id: Uuid::new_v4(),
+            permission: UserPermission::Interact,
+        };
+        assert!(filter_event(
+            &other_added,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new()
+        )
+        .is_none());
+        assert!(channels.is_empty());
+
+        let added = AppEvent::ChannelUserAddedIn {
+            id: channel_id,
+            user_id,
+            permission: UserPermission::Read,
+        };
+        let res = filter_event(
+            &added,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new(),
+        );
+        assert!(matches!(res, Some(GatewayEvent::ChannelUserAddedIn { id }) if id == channel_id));
+        assert_eq!(channels[&channel_id], UserPermission::Read);
+
+        let created = AppEvent::MessageCreated(message(channel_id));
+        assert!(filter_event(
+            &created,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new()
+        )
+        .is_some());
+
+        let other_removed = AppEvent::ChannelUserRemovedFrom {
+            id: channel_id,
+            user_id: Uuid::new_v4(),
+        };
+        assert!(filter_event(
+            &other_removed,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new()
+        )
+        .is_none());
+        assert!(channels.contains_key(&channel_id));
+
+        let removed = AppEvent::ChannelUserRemovedFrom {
+            id: channel_id,
+            user_id,
+        };
+        let res = filter_event(
+            &removed,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new(),
+        );
+        assert!(
+            matches!(res, Some(GatewayEvent::ChannelUserRemovedFrom { id }) if id == channel_id)
+        );
+        assert!(channels.is_empty());
+
+        assert!(filter_event(
+            &created,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_permission_changed() {
+        let user_id = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+        let mut channels = ChannelPermissions::from([(channel_id, UserPermission::Interact)]);
+
+        let other = AppEvent::ChannelPermissionChanged {
+            id: channel_id,
+            user_id: Uuid::new_v4(),
+            permission: UserPermission::Read,
+        };
+        assert!(filter_event(
+            &other,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new()
+        )
+        .is_none());
+        assert_eq!(channels[&channel_id], UserPermission::Interact);
+
+        let own = AppEvent::ChannelPermissionChanged {
+            id: channel_id,
+            user_id,
+            permission: UserPermission::Read,
+        };
+        let res = filter_event(&own, user_id, &mut channels, &mut NotificationPrefs::new());
+        assert!(matches!(
+            res,
+            Some(GatewayEvent::ChannelPermissionChanged {
+                id,
+                permission: UserPermission::Read,
+            }) if id == channel_id
+        ));
+        // The cached permission follows the change
+        assert_eq!(
+            channels,
+            ChannelPermissions::from([(channel_id, UserPermission::Read)])
+        );
+    }
+
+    #[test]
+    fn test_invalidation() {
+        let user_id = Uuid::new_v4();
+        let mut channels = ChannelPermissions::new();
+
+        let other = AppEvent::UserInvalidated(Uuid::new_v4(), InvalidationReason::Requested);
+        assert!(filter_event(
+            &other,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new()
+        )
+        .is_none());
+
+        let own = AppEvent::UserInvalidated(user_id, InvalidationReason::PasswordChanged);
+        let res = filter_event(&own, user_id, &mut channels, &mut NotificationPrefs::new());
+        assert!(matches!(
+            res,
+            Some(GatewayEvent::Error(ApiError::AuthUserInvalidated))
+        ));
+
+        let closed = AppEvent::ConnectionClosed {
+            connection_id: Uuid::new_v4(),
+        };
+        assert!(filter_event(
+            &closed,
+            user_id,
+            &mut channels,
+            &mut NotificationPrefs::new()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_channel_created() {
+        let owner = Uuid::new_v4();
+        let member = Uuid::new_v4();
+        let chan = Channel {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            user_id: owner,
+            name: "general".into(),
+            description: None,
+            topic: None,
+        };
+        let created = AppEvent::ChannelCreated(chan.clone());
+
+        // Two connections of the owner, such as two open tabs
+        for _ in 0..2 {
+            let mut channels = ChannelPermissions::new();
+            let res = filter_event(
+                &created,
+                owner,
+                &mut channels,
+                &mut NotificationPrefs::new(),
+            );
+            assert!(matches!(res, Some(GatewayEvent::ChannelCreated(c)) if c.id == chan.id));
+            assert_eq!(channels[&chan.id], UserPermission::Owner);
+
+            let msg = AppEvent::MessageCreated(message(chan.id));
+            assert!(
+                filter_event(&msg, owner, &mut channels, &mut NotificationPrefs::new()).is_some()
+            );
+        }
+
+        // An initial member already knows the channel from `ChannelUserAddedIn`
+        let mut channels = ChannelPermissions::from([(chan.id, UserPermission::Interact)]);
+        let res = filter_event(
+            &created,
+            member,
+            &mut channels,
+            &mut NotificationPrefs::new(),
+        );
+        assert!(matches!(res, Some(GatewayEvent::ChannelCreated(_))));
+
+        let mut channels = ChannelPermissions::new();
+        assert!(filter_event(
+            &created,
+            Uuid::new_v4(),
+            &mut channels,
+            &mut NotificationPrefs::new()
+        )
+        .is_none());
+        assert!(channels.is_empty());
+    }
+
+    #[test]
+    fn test_notification_prefs() {
+        let user_id = Uuid::new_v4();
+        let channel_id = Uuid::new_v4();
+        let mut channels = ChannelPermissions::from([(channel_id, UserPermission::Interact)]);
+        let mut prefs = NotificationPrefs::new();
+
+        let plain = AppEvent::MessageCreated(message(channel_id));
+        let mention = AppEvent::MessageCreated(Message {
+            content: Some(format!("Hello <@{user_id}>")),
+            ..message(channel_id)
+        });
+        let other_mention = AppEvent::MessageCreated(Message {
+            content: Some(format!("Hello <@{}>", Uuid::new_v4())),
+            ..message(channel_id)
+        });
+
+        let mut forwarded = |prefs: &mut NotificationPrefs| {
+            [&plain, &mention, &other_mention]
+                .map(|e| filter_event(e, user_id, &mut channels, prefs).is_some())
+        };
+
+        assert_eq!(forwarded(&mut prefs), [true, true, true]);
+
+        for (pref, expected) in [
+            (NotificationPref::Mentions, [false, true, false]),
+            (NotificationPref::None, [false, false, false]),
+            (NotificationPref::All, [true, true, true]),
+        ] {
+            let other = AppEvent::NotificationPrefChanged {
+                channel_id,
+                user_id: Uuid::new_v4(),
+                pref: NotificationPref::None,
+            };
+            assert!(
+                filter_event(&other, user_id, &mut ChannelPermissions::new(), &mut prefs).is_none()
+            );
+
+            let own = AppEvent::NotificationPrefChanged {
+                channel_id,
+                user_id,
+                pref,
+            };
+            let res = filter_event(&own, user_id, &mut ChannelPermissions::new(), &mut prefs);
+            assert!(matches!(
+                res,
+                Some(GatewayEvent::NotificationPrefChanged { channel_id: id, pref: p })
+                    if id == channel_id && p == pref
+            ));
+
+            assert_eq!(forwarded(&mut prefs), expected, "{pref:?}");
+        }
+        // The default is not kept
+        assert!(prefs.is_empty());
+    }
+}
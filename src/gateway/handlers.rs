@@ -1,53 +1,185 @@
 use crate::{
-    auth::{http::AuthExtractor, models::UserAuthPayload, repository::AuthRepository},
-    channel::repository::ChannelRepository,
+    auth::{
+        http::AuthExtractor,
+        models::{GatewayClientInfo, GatewayConnectionPayload, UserAuthPayload},
+        repository::AuthRepository,
+    },
+    channel::{models::UserPermission, repository::ChannelRepository},
     errors::ApiError,
     event::{
-        models::AppEvent,
+        models::{AppEvent, EventEnvelope},
         repository::{EventConnection, EventRepository},
     },
-    gateway::models::{GatewayEvent, IncommingMessage},
-    http::{marshal_json_string, AppData},
+    gateway::{
+        filter::{filter_event, ChannelPermissions, NotificationPrefs},
+        limiter::{ConnectionLimiter, ErrorBudget},
+        models::{GatewayCloseCode, GatewayEvent, GatewayVersion, IncommingMessage},
+    },
+    http::{marshal_json_string, AppData, ClientIp},
+    message::repository::MessageRepository,
 };
+use async_trait::async_trait;
 use axum::{
     extract::{
-        ws::{Message as WsMessage, WebSocket},
-        ConnectInfo, WebSocketUpgrade,
+        ws::{CloseFrame, Message as WsMessage, WebSocket},
+        FromRequestParts, Query, WebSocketUpgrade,
     },
-    response::Response,
-    Error,
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    Error, Extension,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
-    net::SocketAddr,
+    net::IpAddr,
     sync::Arc,
     time::{Duration, Instant},
 };
 use tokio::time::sleep;
+use tokio_tungstenite::tungstenite;
+use tracing::Instrument;
 use uuid::Uuid;
 
-pub async fn ws_upgrader<E, A, C>(
+/// The caps of the messages sent by the gateway clients, which are only small
+/// control payloads.
+#[derive(Debug, Clone, Copy)]
+pub struct GatewayLimits {
+    /// The maximum size of a message, and of each of its frames, in bytes
+    pub max_message_size: usize,
+    /// The amount of consecutive invalid messages within `error_window` after
+    /// which the connection is closed, zero disables it
+    pub max_errors: u32,
+    pub error_window: Duration,
+    /// The maximum amount of channels of the user whose events are received
+    pub max_channels: usize,
+}
+
+impl Default for GatewayLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: 64 * 1024,
+            max_errors: 10,
+            error_window: Duration::from_secs(60),
+            max_channels: 10000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GatewayQueryParams {
+    /// The [`GatewayVersion`] the client speaks
+    pub v: Option<u32>,
+    /// The name of the client, see [`GatewayClientInfo`]
+    pub client: Option<String>,
+    pub client_version: Option<String>,
+}
+
+impl GatewayQueryParams {
+    /// The client the connection identified itself as with the query
+    /// parameters, if any.
+    pub fn client_info(self) -> Result<Option<GatewayClientInfo>, ApiError> {
+        let Some(client) = self.client else {
+            return Ok(None);
+        };
+
+        let info = GatewayClientInfo {
+            client,
+            version: self.client_version,
+        };
+        info.validate()?;
+
+        Ok(Some(info))
+    }
+}
+
+/// Resolves the version requested by the client with the `v` query
+/// parameter, falling back to the latest one.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for GatewayVersion {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<GatewayQueryParams>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        match query.v {
+            Some(v) => GatewayVersion::try_from(v).map_err(IntoResponse::into_response),
+            None => Ok(GatewayVersion::LATEST),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn ws_upgrader<E, A, C, M>(
     AuthExtractor(auth_payload, _): AuthExtractor<A>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ClientIp(addr): ClientIp,
+    version: GatewayVersion,
+    Query(query): Query<GatewayQueryParams>,
     AppData(event_repo): AppData<E>,
     AppData(channel_repo): AppData<C>,
+    AppData(message_repo): AppData<M>,
+    AppData(limiter): AppData<ConnectionLimiter>,
+    AppData(limits): AppData<GatewayLimits>,
+    Extension(auth_repo): Extension<A>,
     ws: WebSocketUpgrade,
 ) -> Result<Response, ApiError>
 where
     E: EventRepository + 'static,
-    A: AuthRepository + 'static,
+    A: AuthRepository + Clone + 'static,
     C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
 {
+    let client = query.client_info()?;
+    let guard = limiter.acquire(addr).inspect_err(|_| {
+        tracing::warn!(addr = addr.to_string(), "Gateway connection limit reached");
+    })?;
     let conn = event_repo.get_conn().await?;
 
-    Ok(ws.on_upgrade(move |socket| ws_handler(socket, addr, conn, auth_payload, channel_repo)))
+    let ws = ws
+        .max_message_size(limits.max_message_size)
+        .max_frame_size(limits.max_message_size);
+
+    // Each connection is its own trace, outliving the upgrade request
+    let span = tracing::info_span!(
+        parent: None,
+        "gateway_connection",
+        user_id = %auth_payload.sub,
+        addr = %addr,
+    );
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        // The connection is released from the limiter once it is closed
+        let _guard = guard;
+        ws_handler(
+            socket,
+            addr,
+            version,
+            client,
+            conn,
+            auth_payload,
+            auth_repo,
+            event_repo,
+            channel_repo,
+            message_repo,
+            *limits,
+        )
+        .instrument(span)
+        .await
+    }))
 }
 
-async fn send_message<T: Serialize>(ws: &mut WebSocket, value: &T) -> Result<(), Error> {
+pub(super) async fn send_message<T: Serialize>(ws: &mut WebSocket, value: &T) -> Result<(), Error> {
     ws.send(WsMessage::Text(marshal_json_string(value))).await
 }
 
+pub(super) async fn send_close(ws: &mut WebSocket, code: GatewayCloseCode) -> Result<(), Error> {
+    ws.send(WsMessage::Close(Some(CloseFrame {
+        code: code.code(),
+        reason: code.reason().into(),
+    })))
+    .await
+}
+
 async fn send_event(ws: &mut WebSocket, value: &GatewayEvent) {
     _ = ws
         .send(WsMessage::Text(marshal_json_string(value)))
@@ -55,140 +187,283 @@ async fn send_event(ws: &mut WebSocket, value: &GatewayEvent) {
         .map_err(|e| tracing::error!(error = e.to_string(), "Failed to send message on websocket"));
 }
 
-pub async fn ws_handler<EC: EventConnection, C: ChannelRepository>(
+/// The amount of channels fetched at once when loading the channels of a
+/// gateway connection.
+const CHANNEL_LOAD_PAGE_SIZE: u64 = 1000;
+
+/// Loads the permissions and the notification preferences of the user in
+/// their channels page by page, up to `max` channels. Also returns whether the
+/// user has more channels than that.
+pub(super) async fn load_channels<C: ChannelRepository>(
+    channel_repo: &C,
+    user_id: Uuid,
+    max: usize,
+) -> Result<(ChannelPermissions, NotificationPrefs, bool), ApiError> {
+    let mut channels = ChannelPermissions::new();
+    let mut prefs = NotificationPrefs::new();
+    let mut offset = 0;
+
+    while channels.len() < max {
+        let limit = CHANNEL_LOAD_PAGE_SIZE.min((max - channels.len()) as u64);
+        let page = channel_repo.get_by_user(user_id, offset, limit).await?;
+        offset += page.len() as u64;
+
+        let ids = page.iter().map(|chan| chan.id).collect::<Vec<_>>();
+        channels.extend(channel_repo.get_user_permissions(user_id, &ids).await?);
+        prefs.extend(channel_repo.get_notification_prefs(user_id, &ids).await?);
+
+        if (page.len() as u64) < limit {
+            return Ok((channels, prefs, false));
+        }
+    }
+
+    let truncated = !channel_repo
+        .get_by_user(user_id, offset, 1)
+        .await?
+        .is_empty();
+    Ok((channels, prefs, truncated))
+}
+
+/// Lists the user as typing in the channel and notifies the members.
+async fn start_typing<M: MessageRepository, E: EventRepository>(
+    message_repo: &M,
+    event_repo: &E,
+    channel_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), ApiError> {
+    message_repo.set_typing(channel_id, user_id).await?;
+    event_repo
+        .publish(AppEvent::UserTyping {
+            channel_id,
+            user_id,
+        })
+        .await
+}
+
+/// Whether the websocket error was caused by a message or frame larger than
+/// the configured limits.
+fn is_message_too_large(e: &Error) -> bool {
+    use std::error::Error as _;
+
+    e.source()
+        .and_then(|e| e.downcast_ref::<tungstenite::Error>())
+        .is_some_and(|e| matches!(e, tungstenite::Error::Capacity(_)))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn ws_handler<E, EC, A, C, M>(
     mut socket: WebSocket,
-    addr: SocketAddr,
+    addr: IpAddr,
+    version: GatewayVersion,
+    client: Option<GatewayClientInfo>,
     mut conn: EC,
     auth_payload: UserAuthPayload,
+    auth_repo: A,
+    event_repo: Arc<E>,
     channel_repo: Arc<C>,
-) {
+    message_repo: Arc<M>,
+    limits: GatewayLimits,
+) where
+    E: EventRepository,
+    EC: EventConnection,
+    A: AuthRepository,
+    C: ChannelRepository,
+    M: MessageRepository,
+{
     const SOCKET_TIMEOUT: Duration = Duration::from_secs(30);
     const SOCKET_TICK_CHECK: Duration = Duration::from_secs(5);
 
     tracing::info!(addr = addr.to_string(), "Incomming gateway connection");
 
     let mut last_ping = Instant::now();
+    let mut error_budget = ErrorBudget::new(limits.max_errors, limits.error_window);
 
-    let mut channels = match channel_repo.get_by_user(auth_payload.sub, 0, 1000).await {
-        Ok(v) => v.iter().map(|msg| msg.id).collect::<HashSet<Uuid>>(),
+    let (mut channels, mut prefs, channels_truncated) =
+        match load_channels(&*channel_repo, auth_payload.sub, limits.max_channels).await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!(error = e.to_string(), "Failed to get user permissions");
+
+                _ = send_event(&mut socket, &GatewayEvent::Error(e)).await;
+                _ = send_close(&mut socket, GatewayCloseCode::InternalError).await;
+                return;
+            }
+        };
+
+    if channels_truncated {
+        tracing::warn!(
+            user_id = auth_payload.sub.to_string(),
+            max_channels = limits.max_channels,
+            "Gateway connection only receives the events of part of the user channels"
+        );
+    }
+
+    let channel_ids = channels.keys().copied().collect::<Vec<_>>();
+    let latest_messages = match message_repo.get_latest(&channel_ids).await {
+        Ok(v) => v,
         Err(e) => {
-            tracing::error!(error = e.to_string(), "Failed to get user permissions");
+            tracing::error!(error = e.to_string(), "Failed to get latest messages");
 
             _ = send_event(&mut socket, &GatewayEvent::Error(e)).await;
+            _ = send_close(&mut socket, GatewayCloseCode::InternalError).await;
             return;
         }
     };
 
+    let mut conn_info = GatewayConnectionPayload::new(addr, client);
+    if let Err(e) = auth_repo.set_connection(auth_payload.sub, &conn_info).await {
+        tracing::error!(
+            error = e.to_string(),
+            "Failed to register gateway connection"
+        );
+    }
+
+    let ready = GatewayEvent::Ready {
+        v: version,
+        connection_id: conn_info.id,
+        channels_truncated,
+        latest_messages,
+    };
+    send_event(&mut socket, &ready).await;
+
+    // Resolves to the close code that must be sent, or `None` if the
+    // connection was closed by the client
     let res = loop {
         tokio::select! {
             recv = socket.recv() => {
                 if let Some(result) = recv {
                     match result {
+                        Ok(WsMessage::Close(_)) => break Ok(None),
                         Ok(message) => {
-                            let s = match message.to_text() {
-                                Ok(s) => s,
-                                Err(_) => match send_message(
-                                    &mut socket,
-                                    &GatewayEvent::Error(ApiError::GatewayMessageNonUTF8),
-                                )
-                                .await
-                                {
-                                    Ok(_) => continue,
-                                    Err(e) => break Err(e),
-                                },
-                            };
+                            let data = message
+                                .to_text()
+                                .map_err(|_| ApiError::GatewayMessageNonUTF8)
+                                .and_then(|s| {
+                                    serde_json::from_str(s).map_err(|e| {
+                                        ApiError::GatewayDeserializationFailed(e.to_string())
+                                    })
+                                });
 
-                            let data = match serde_json::from_str(s) {
-                                Ok(v) => v,
-                                Err(e) => match send_message(
-                                    &mut socket,
-                                    &GatewayEvent::Error(ApiError::GatewayDeserializationFailed(e.to_string())),
-                                )
-                                .await
-                                {
-                                    Ok(_) => continue,
-                                    Err(e) => break Err(e),
-                                },
+                            let data = match data {
+                                Ok(v) => {
+                                    error_budget.reset();
+                                    v
+                                }
+                                Err(e) => {
+                                    let exhausted = error_budget.record_error();
+                                    match send_message(&mut socket, &GatewayEvent::Error(e)).await {
+                                        Ok(_) if exhausted => {
+                                            tracing::info!(
+                                                addr = addr.to_string(),
+                                                "Gateway connection sent too many invalid messages"
+                                            );
+                                            break Ok(Some(GatewayCloseCode::PolicyViolation));
+                                        }
+                                        Ok(_) => continue,
+                                        Err(e) => break Err(e),
+                                    }
+                                }
                             };
 
                             match data {
                                 IncommingMessage::Ping => {
                                     last_ping = Instant::now();
+                                    if let Err(e) = auth_repo.set_connection(auth_payload.sub, &conn_info).await {
+                                        tracing::error!(error = e.to_string(), "Failed to refresh gateway connection");
+                                    }
                                     if let Err(e) = send_message(&mut socket, &GatewayEvent::Pong).await {
                                         break Err(e);
                                     }
                                 }
+                                IncommingMessage::Identify(info) => {
+                                    if let Err(e) = info.validate() {
+                                        if let Err(e) = send_message(&mut socket, &GatewayEvent::Error(e)).await {
+                                            break Err(e);
+                                        }
+                                        continue;
+                                    }
+
+                                    conn_info.client = Some(info);
+                                    if let Err(e) = auth_repo.set_connection(auth_payload.sub, &conn_info).await {
+                                        tracing::error!(error = e.to_string(), "Failed to refresh gateway connection");
+                                    }
+                                }
+                                IncommingMessage::Typing { channel_id } => {
+                                    // The channels left out of a truncated
+                                    // connection are not cached
+                                    let perm = match channels.get(&channel_id) {
+                                        Some(perm) => Ok(perm.clone()),
+                                        None if channels_truncated => channel_repo
+                                            .get_user_permission(auth_payload.sub, channel_id)
+                                            .await,
+                                        None => Ok(UserPermission::None),
+                                    };
+
+                                    let res = match perm {
+                                        Ok(perm) if perm.can_send_msg() => start_typing(
+                                            &*message_repo,
+                                            &*event_repo,
+                                            channel_id,
+                                            auth_payload.sub,
+                                        )
+                                        .await,
+                                        Ok(_) => Err(ApiError::ChannelPermissionDenied),
+                                        Err(e) => Err(e),
+                                    };
+
+                                    if let Err(e) = res {
+                                        if let Err(e) = send_message(&mut socket, &GatewayEvent::Error(e)).await {
+                                            break Err(e);
+                                        }
+                                    }
+                                }
                             }
                         },
+                        Err(e) if is_message_too_large(&e) => {
+                            let e = ApiError::GatewayMessageTooLarge(limits.max_message_size);
+                            match send_message(&mut socket, &GatewayEvent::Error(e)).await {
+                                Ok(_) => break Ok(Some(GatewayCloseCode::MessageTooBig)),
+                                Err(e) => break Err(e),
+                            }
+                        }
                         Err(e) => break Err(e),
                     }
+                } else {
+                    break Ok(None);
                 }
             }
-            event = conn.recv() => {
+            event = conn.recv_envelope() => {
                 match event {
-                    Ok(event) => match event {
-                        AppEvent::MessageCreated(msg) => {
-                            if channels.contains(&msg.channel_id) {
-                                send_event(&mut socket, &GatewayEvent::MessageCreated(msg)).await
-                            }
-                        }
-                        AppEvent::MessageUpdated(msg) => {
-                            if channels.contains(&msg.channel_id) {
-                                send_event(&mut socket, &GatewayEvent::MessageUpdated(msg)).await
-                            }
-                        }
-                        AppEvent::MessageDeleted { id, channel_id } => {
-                            if channels.contains(&channel_id) {
-                                send_event(
-                                    &mut socket,
-                                    &GatewayEvent::MessageDeleted { id, channel_id },
-                                )
-                                .await
-                            }
-                        }
-                        AppEvent::ChannelDeleted(id) => {
-                            if channels.contains(&id) {
-                                send_event(&mut socket, &GatewayEvent::ChannelDeleted { id }).await
-                            }
+                    Ok(EventEnvelope {
+                        event: AppEvent::ConnectionClosed { connection_id },
+                        ..
+                    }) => {
+                        if connection_id == conn_info.id {
+                            tracing::info!(
+                                user_id = auth_payload.sub.to_string(),
+                                connection_id = connection_id.to_string(),
+                                "Connection closed by an administrator"
+                            );
+                            break Ok(Some(GatewayCloseCode::Kicked));
                         }
-                        AppEvent::ChannelUserAddedIn { id, user_id } => {
-                            if user_id == auth_payload.sub {
-                                channels.insert(id);
-                                send_event(&mut socket, &GatewayEvent::ChannelUserAddedIn { id })
-                                    .await
-                            }
-                        }
-                        AppEvent::ChannelUserRemovedFrom { id, user_id } => {
-                            if user_id == auth_payload.sub {
-                                channels.remove(&id);
-                                send_event(
-                                    &mut socket,
-                                    &GatewayEvent::ChannelUserRemovedFrom { id },
-                                )
-                                .await
-                            }
-                        }
-                        AppEvent::ChannelUpdated(id, data) => {
-                            if channels.contains(&id) {
-                                send_event(&mut socket, &GatewayEvent::ChannelUpdated { id, data })
-                                    .await
-                            }
+                    }
+                    Ok(envelope) => {
+                        if let Some(e) = filter_event(&envelope.event, auth_payload.sub, &mut channels, &mut prefs) {
+                            send_event(&mut socket, &e)
+                                .instrument(envelope.delivery_span())
+                                .await;
                         }
-                        AppEvent::UserInvalidated(id, reason) => {
+                        if let AppEvent::UserInvalidated(id, reason) = envelope.event {
                             if id == auth_payload.sub {
                                 tracing::info!(
                                     user_id = id.to_string(),
                                     invalidation_reason = reason.to_string(),
                                     "User disconected due to invalidation"
                                 );
-                                send_event(
-                                    &mut socket,
-                                    &GatewayEvent::Error(ApiError::AuthUserInvalidated),
-                                )
-                                .await;
+                                break Ok(Some(GatewayCloseCode::Invalidated));
                             }
                         }
-                    },
+                    }
                     Err(e) => {
                         tracing::error!(
                             error = e.to_string(),
@@ -203,12 +478,18 @@ pub async fn ws_handler<EC: EventConnection, C: ChannelRepository>(
         if Instant::now() - last_ping > SOCKET_TIMEOUT {
             let e = ApiError::GatewayTimeout(SOCKET_TIMEOUT.as_secs());
             match send_message(&mut socket, &GatewayEvent::Error(e)).await {
-                Ok(_) => break Ok(()),
+                Ok(_) => break Ok(Some(GatewayCloseCode::Timeout)),
                 Err(e) => break Err(e),
             }
         }
     };
 
+    let res = match res {
+        Ok(Some(code)) => send_close(&mut socket, code).await,
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+
     if let Err(e) = res {
         tracing::error!(
             error = e.to_string(),
@@ -217,5 +498,62 @@ pub async fn ws_handler<EC: EventConnection, C: ChannelRepository>(
         );
     }
 
+    if let Err(e) = auth_repo
+        .remove_connection(auth_payload.sub, conn_info.id)
+        .await
+    {
+        tracing::error!(error = e.to_string(), "Failed to remove gateway connection");
+    }
+
     tracing::info!(addr = addr.to_string(), "Closed gateway connection");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{load_channels, CHANNEL_LOAD_PAGE_SIZE};
+    use crate::channel::{
+        memory_repository::InMemoryChannelRepository,
+        models::{ChannelCreateData, UserPermission},
+        repository::ChannelRepository,
+    };
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_load_channels() {
+        let repo = InMemoryChannelRepository::new();
+        let user_id = Uuid::new_v4();
+        let count = CHANNEL_LOAD_PAGE_SIZE as usize * 3 / 2;
+
+        for i in 0..count {
+            let chan = repo
+                .create(
+                    Uuid::new_v4(),
+                    ChannelCreateData {
+                        name: format!("channel-{i}"),
+                        description: None,
+                        topic: None,
+                        init_users: None,
+                        init_permission: UserPermission::Interact,
+                    },
+                )
+                .await
+                .unwrap();
+            repo.set_user_permission(chan.id, user_id, UserPermission::Read)
+                .await
+                .unwrap();
+        }
+
+        let (perms, _, truncated) = load_channels(&repo, user_id, 10000).await.unwrap();
+        assert_eq!(perms.len(), count);
+        assert!(perms.values().all(|p| *p == UserPermission::Read));
+        assert!(!truncated);
+
+        let (perms, _, truncated) = load_channels(&repo, user_id, count).await.unwrap();
+        assert_eq!(perms.len(), count);
+        assert!(!truncated);
+
+        let (perms, _, truncated) = load_channels(&repo, user_id, 1200).await.unwrap();
+        assert_eq!(perms.len(), 1200);
+        assert!(truncated);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::errors::ApiError;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The amount of seconds clients are told to wait when the gateway is full.
+const OVERLOAD_RETRY_AFTER: u64 = 5;
+/// The fraction of the total capacity after which a warning is logged.
+const OVERLOAD_WARN_RATIO: f64 = 0.9;
+
+/// Keeps track of the active gateway connections of each client ip and of the
+/// total amount of connections.
+///
+/// Each connection holds its own subscription to the event broadcast, whose
+/// buffer is allocated once and shared by all the subscribers, but every event
+/// is cloned for each one of them when received. The total cap therefore
+/// bounds the memory used per event rather than the buffer size.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimiter {
+    max_per_ip: usize,
+    max_total: usize,
+    conns: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    total: Arc<AtomicUsize>,
+}
+
+impl ConnectionLimiter {
+    #[inline]
+    pub fn new(max_per_ip: usize) -> Self {
+        Self {
+            max_per_ip,
+            max_total: usize::MAX,
+            conns: Arc::new(Mutex::new(HashMap::new())),
+            total: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Caps the total amount of gateway connections the server accepts.
+    pub fn with_max_total(mut self, max_total: usize) -> Self {
+        self.max_total = max_total;
+        self
+    }
+
+    /// Registers a new connection from `addr`, that is released when the
+    /// returned guard is dropped.
+    pub fn acquire(&self, addr: IpAddr) -> Result<ConnectionGuard, ApiError> {
+        let mut lock = self.conns.lock().unwrap_or_else(|e| e.into_inner());
+
+        let count = lock.entry(addr).or_insert(0);
+        if *count >= self.max_per_ip {
+            return Err(ApiError::GatewayTooManyConnections);
+        }
+
+        let total = self
+            .total
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max_total).then_some(n + 1)
+            })
+            .map_err(|_| ApiError::GatewayOverloaded {
+                retry_after: OVERLOAD_RETRY_AFTER,
+            })?
+            + 1;
+        *count += 1;
+
+        if total as f64 >= self.max_total as f64 * OVERLOAD_WARN_RATIO {
+            tracing::warn!(
+                total,
+                max_total = self.max_total,
+                "Gateway is near its connection capacity"
+            );
+        }
+
+        Ok(ConnectionGuard {
+            addr,
+            conns: self.conns.clone(),
+            total: self.total.clone(),
+        })
+    }
+
+    /// The amount of open gateway connections.
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::Acquire)
+    }
+
+    #[cfg(test)]
+    fn count(&self, addr: &IpAddr) -> usize {
+        let lock = self.conns.lock().unwrap();
+        lock.get(addr).copied().unwrap_or(0)
+    }
+}
+
+/// Releases the connection slot when dropped, so that it is freed even if the
+/// connection handler ends abnormally.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    addr: IpAddr,
+    conns: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    total: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.total.fetch_sub(1, Ordering::AcqRel);
+
+        let mut lock = self.conns.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(count) = lock.get_mut(&self.addr) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                lock.remove(&self.addr);
+            }
+        }
+    }
+}
+
+/// Counts the consecutive invalid messages of a gateway connection, so the
+/// clients that keep sending them are disconnected while an occasional one is
+/// tolerated.
+#[derive(Debug)]
+pub struct ErrorBudget {
+    max_errors: u32,
+    window: Duration,
+    count: u32,
+    window_start: Instant,
+}
+
+impl ErrorBudget {
+    /// Allows up to `max_errors - 1` consecutive errors within `window`.
+    /// Setting `max_errors` to zero disables the budget.
+    #[inline]
+    pub fn new(max_errors: u32, window: Duration) -> Self {
+        Self {
+            max_errors,
+            window,
+            count: 0,
+            window_start: Instant::now(),
+        }
+    }
+
+    /// Registers an error, returning whether the budget was exhausted. The
+    /// count starts over once the window since the first error elapsed.
+    pub fn record_error(&mut self) -> bool {
+        let now = Instant::now();
+        if self.count == 0 || now - self.window_start > self.window {
+            self.count = 0;
+            self.window_start = now;
+        }
+        self.count += 1;
+
+        self.max_errors != 0 && self.count >= self.max_errors
+    }
+
+    /// Clears the errors after a valid message was received.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionLimiter, ErrorBudget};
+    use crate::errors::ApiError;
+    use std::{net::IpAddr, thread::sleep, time::Duration};
+
+    #[test]
+    fn test_error_budget() {
+        let mut budget = ErrorBudget::new(3, Duration::from_millis(50));
+
+        assert!(!budget.record_error());
+        assert!(!budget.record_error());
+        budget.reset();
+        assert!(!budget.record_error());
+        assert!(!budget.record_error());
+        assert!(budget.record_error());
+
+        let mut budget = ErrorBudget::new(2, Duration::from_millis(50));
+        assert!(!budget.record_error());
+        sleep(Duration::from_millis(60));
+        assert!(!budget.record_error());
+        assert!(budget.record_error());
+
+        let mut budget = ErrorBudget::new(0, Duration::from_millis(50));
+        assert!((0..100).all(|_| !budget.record_error()));
+    }
+
+    #[test]
+    fn test_connection_limit_per_ip() {
+        const MAX: usize = 4;
+
+        let limiter = ConnectionLimiter::new(MAX);
+        let addr = "203.0.113.7".parse::<IpAddr>().unwrap();
+        let other = "203.0.113.8".parse::<IpAddr>().unwrap();
+
+        let mut guards = (0..MAX)
+            .map(|_| limiter.acquire(addr).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            limiter.acquire(addr).unwrap_err(),
+            ApiError::GatewayTooManyConnections
+        );
+        let other_guard = limiter.acquire(other).unwrap();
+
+        guards.pop();
+        guards.push(limiter.acquire(addr).unwrap());
+        assert_eq!(limiter.count(&addr), MAX);
+
+        drop(guards);
+        drop(other_guard);
+        assert_eq!(limiter.count(&addr), 0);
+        assert_eq!(limiter.count(&other), 0);
+    }
+
+    #[test]
+    fn test_connection_limit_total() {
+        let limiter = ConnectionLimiter::new(2).with_max_total(3);
+        let addrs =
+            ["203.0.113.7", "203.0.113.8", "203.0.113.9"].map(|s| s.parse::<IpAddr>().unwrap());
+
+        let a = limiter.acquire(addrs[0]).unwrap();
+        let _b = limiter.acquire(addrs[0]).unwrap();
+        let _c = limiter.acquire(addrs[1]).unwrap();
+
+        assert!(matches!(
+            limiter.acquire(addrs[2]),
+            Err(ApiError::GatewayOverloaded { .. })
+        ));
+        // A rejected connection must not take a per ip slot
+        assert_eq!(limiter.count(&addrs[2]), 0);
+
+        drop(a);
+        assert_eq!(limiter.total(), 2);
+        let _d = limiter.acquire(addrs[2]).unwrap();
+        assert_eq!(limiter.total(), 3);
+    }
+}
@@ -1,2 +1,6 @@
+pub mod filter;
 pub mod handlers;
+pub mod limiter;
 pub mod models;
+pub mod sse;
+pub mod tail;
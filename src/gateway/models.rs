@@ -1,7 +1,54 @@
-use crate::{channel::models::ChannelUpdateData, errors::ApiError, message::models::Message};
-use serde::{Deserialize, Serialize};
+use crate::{
+    auth::models::GatewayClientInfo,
+    channel::models::{Channel, ChannelUpdateData, NotificationPref, UserPermission},
+    errors::ApiError,
+    message::models::{ChannelLatestMessage, Message},
+};
+use serde::{Deserialize, Serialize, Serializer};
 use uuid::Uuid;
 
+/// The versions of the gateway wire format, that is the shape of the
+/// [`GatewayEvent`]s and [`IncommingMessage`]s and the close codes. The
+/// client picks one with the `v` query parameter when connecting, the latest
+/// one being used if it is omitted, and the server confirms it in the
+/// [`GatewayEvent::Ready`] event.
+///
+/// Adding events, event fields or close codes keeps the version, and clients
+/// must ignore what they do not know. Renaming or removing an event or a
+/// field, or changing its type or meaning, requires a new version, while the
+/// clients that negotiated an older one keep receiving the old shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayVersion {
+    V1 = 1,
+}
+
+impl GatewayVersion {
+    pub const LATEST: Self = Self::V1;
+
+    #[inline]
+    pub fn number(self) -> u32 {
+        self as u32
+    }
+}
+
+impl TryFrom<u32> for GatewayVersion {
+    type Error = ApiError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::V1),
+            _ => Err(ApiError::GatewayVersionUnsupported(value)),
+        }
+    }
+}
+
+impl Serialize for GatewayVersion {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.number())
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 #[serde(
     tag = "type",
@@ -10,13 +57,62 @@ use uuid::Uuid;
     deny_unknown_fields
 )]
 pub enum GatewayEvent {
+    /// The first event of every connection
+    Ready {
+        v: GatewayVersion,
+        connection_id: Uuid,
+        /// Set when the user has more channels than the gateway loads, so the
+        /// events of some of them are not received
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        channels_truncated: bool,
+        /// The latest message of each channel of the user that has messages,
+        /// to be compared with the local state of the client
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        latest_messages: Vec<ChannelLatestMessage>,
+    },
     MessageCreated(Message),
     MessageUpdated(Message),
-    MessageDeleted { id: Uuid, channel_id: Uuid },
-    ChannelDeleted { id: Uuid },
-    ChannelUserAddedIn { id: Uuid },
-    ChannelUserRemovedFrom { id: Uuid },
-    ChannelUpdated { id: Uuid, data: ChannelUpdateData },
+    MessageDeleted {
+        id: Uuid,
+        channel_id: Uuid,
+    },
+    MessageRead {
+        channel_id: Uuid,
+        user_id: Uuid,
+        up_to_message_id: Uuid,
+    },
+    UserTyping {
+        channel_id: Uuid,
+        user_id: Uuid,
+    },
+    ChannelCreated(Channel),
+    ChannelDeleted {
+        id: Uuid,
+    },
+    ChannelImported {
+        id: Uuid,
+        count: u64,
+    },
+    ChannelUserAddedIn {
+        id: Uuid,
+    },
+    ChannelUserRemovedFrom {
+        id: Uuid,
+    },
+    /// Only sent to the user whose permission changed
+    ChannelPermissionChanged {
+        id: Uuid,
+        permission: UserPermission,
+    },
+    ChannelUpdated {
+        id: Uuid,
+        data: ChannelUpdateData,
+    },
+    /// Only sent to the user whose preference changed
+    NotificationPrefChanged {
+        channel_id: Uuid,
+        pref: NotificationPref,
+    },
     Error(ApiError),
     Pong,
 }
@@ -30,4 +126,112 @@ pub enum GatewayEvent {
 )]
 pub enum IncommingMessage {
     Ping,
+    /// Records the client of the connection, replacing the one of the query
+    /// parameters
+    Identify(GatewayClientInfo),
+    /// Lists the user as typing in the channel, like the typing route
+    Typing {
+        channel_id: Uuid,
+    },
+}
+
+/// The close codes sent by the server when it ends a gateway connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayCloseCode {
+    /// The client sent too many invalid messages in a row
+    PolicyViolation = 1008,
+    /// The client sent a message larger than the configured limit
+    MessageTooBig = 1009,
+    /// Something went wrong on the server while setting up the connection
+    InternalError = 1011,
+    /// The client did not send a ping within the timeout
+    Timeout = 4000,
+    /// The user was invalidated and must authenticate again
+    Invalidated = 4001,
+    /// The connection was closed by an administrator
+    Kicked = 4002,
+    /// The admin event tail reached its maximum duration
+    TailExpired = 4003,
+}
+
+impl GatewayCloseCode {
+    #[inline]
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    #[inline]
+    pub fn reason(self) -> &'static str {
+        match self {
+            GatewayCloseCode::PolicyViolation => "too many invalid messages",
+            GatewayCloseCode::MessageTooBig => "message too big",
+            GatewayCloseCode::InternalError => "internal error",
+            GatewayCloseCode::Timeout => "ping timeout",
+            GatewayCloseCode::Invalidated => "user invalidated",
+            GatewayCloseCode::Kicked => "closed by an administrator",
+            GatewayCloseCode::TailExpired => "tail duration exceeded",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GatewayEvent, GatewayVersion, IncommingMessage};
+    use crate::{
+        auth::models::{GatewayClientInfo, GATEWAY_CLIENT_INFO_MAX_LEN},
+        errors::ApiError,
+    };
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_version() {
+        assert_eq!(GatewayVersion::try_from(1), Ok(GatewayVersion::V1));
+        assert_eq!(
+            GatewayVersion::try_from(2),
+            Err(ApiError::GatewayVersionUnsupported(2))
+        );
+
+        let connection_id = Uuid::new_v4();
+        let ready = GatewayEvent::Ready {
+            v: GatewayVersion::LATEST,
+            connection_id,
+            channels_truncated: false,
+            latest_messages: Vec::new(),
+        };
+        assert_eq!(
+            serde_json::to_value(&ready).unwrap(),
+            json!({
+                "type": "READY",
+                "data": { "v": 1, "connection_id": connection_id },
+            })
+        );
+    }
+
+    #[test]
+    fn test_identify() {
+        let msg = serde_json::from_value::<IncommingMessage>(json!({
+            "type": "IDENTIFY",
+            "data": { "client": "iOS app", "version": "1.2.3" },
+        }))
+        .unwrap();
+        let IncommingMessage::Identify(info) = msg else {
+            panic!("unexpected message {msg:?}");
+        };
+        assert_eq!(info.client, "iOS app");
+        assert_eq!(info.version.as_deref(), Some("1.2.3"));
+        assert!(info.validate().is_ok());
+
+        let info = |client: String, version: Option<&str>| GatewayClientInfo {
+            client,
+            version: version.map(Into::into),
+        };
+        let long = "a".repeat(GATEWAY_CLIENT_INFO_MAX_LEN + 1);
+
+        assert!(info("web".into(), None).validate().is_ok());
+        assert!(info("".into(), None).validate().is_err());
+        assert!(info(long.clone(), None).validate().is_err());
+        assert!(info("web".into(), Some(&long)).validate().is_err());
+        assert!(info("web\n".into(), None).validate().is_err());
+    }
 }
@@ -0,0 +1,135 @@
+use super::{
+    filter::{filter_event, ChannelPermissions, NotificationPrefs},
+    handlers::{load_channels, GatewayLimits},
+    limiter::{ConnectionGuard, ConnectionLimiter},
+};
+use crate::{
+    auth::{http::AuthExtractor, repository::AuthRepository},
+    channel::repository::ChannelRepository,
+    errors::ApiError,
+    event::{
+        models::AppEvent,
+        repository::{EventConnection, EventRepository},
+    },
+    http::{marshal_json_string, AppData, ClientIp},
+};
+use axum::response::{
+    sse::{Event, KeepAlive},
+    Sse,
+};
+use std::{convert::Infallible, time::Duration};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+/// The amount of events buffered for a client that is slow to receive them.
+const EVENT_STREAM_BUFFER: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct EventStreamOptions {
+    /// The interval between the keep-alive comments sent on idle streams
+    pub keep_alive: Duration,
+}
+
+impl Default for EventStreamOptions {
+    fn default() -> Self {
+        Self {
+            keep_alive: Duration::from_secs(15),
+        }
+    }
+}
+
+/// A receive-only alternative to the gateway, streaming the same events as
+/// server-sent events.
+pub async fn sse_handler<E, A, C>(
+    AuthExtractor(auth_payload, _): AuthExtractor<A>,
+    ClientIp(addr): ClientIp,
+    AppData(event_repo): AppData<E>,
+    AppData(channel_repo): AppData<C>,
+    AppData(limiter): AppData<ConnectionLimiter>,
+    AppData(options): AppData<EventStreamOptions>,
+    AppData(limits): AppData<GatewayLimits>,
+) -> Result<Sse<ReceiverStream<Result<Event, Infallible>>>, ApiError>
+where
+    E: EventRepository + 'static,
+    A: AuthRepository + 'static,
+    C: ChannelRepository + 'static,
+{
+    let guard = limiter.acquire(addr).inspect_err(|_| {
+        tracing::warn!(
+            addr = addr.to_string(),
+            "Event stream connection limit reached"
+        );
+    })?;
+
+    let (channels, prefs, channels_truncated) =
+        load_channels(&*channel_repo, auth_payload.sub, limits.max_channels).await?;
+    if channels_truncated {
+        tracing::warn!(
+            user_id = auth_payload.sub.to_string(),
+            max_channels = limits.max_channels,
+            "Event stream only receives the events of part of the user channels"
+        );
+    }
+
+    let conn = event_repo.get_conn().await?;
+
+    let (tx, rx) = mpsc::channel(EVENT_STREAM_BUFFER);
+    tokio::spawn(forward_events(
+        conn,
+        tx,
+        auth_payload.sub,
+        channels,
+        prefs,
+        guard,
+    ));
+
+    Ok(Sse::new(ReceiverStream::new(rx)).keep_alive(KeepAlive::new().interval(options.keep_alive)))
+}
+
+async fn forward_events<EC: EventConnection>(
+    mut conn: EC,
+    tx: mpsc::Sender<Result<Event, Infallible>>,
+    user_id: Uuid,
+    mut channels: ChannelPermissions,
+    mut prefs: NotificationPrefs,
+    _guard: ConnectionGuard,
+) {
+    tracing::info!(user_id = user_id.to_string(), "Opened event stream");
+
+    loop {
+        let event = tokio::select! {
+            event = conn.recv() => event,
+            // The client went away, no event needs to arrive to notice it
+            _ = tx.closed() => break,
+        };
+
+        let event = match event {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!(
+                    error = e.to_string(),
+                    "Failed to receive message on tokio channel"
+                );
+                continue;
+            }
+        };
+
+        if let Some(e) = filter_event(&event, user_id, &mut channels, &mut prefs) {
+            let data = Event::default().data(marshal_json_string(&e));
+            if tx.send(Ok(data)).await.is_err() {
+                break;
+            }
+        }
+
+        if matches!(event, AppEvent::UserInvalidated(id, _) if id == user_id) {
+            tracing::info!(
+                user_id = user_id.to_string(),
+                "Event stream closed due to invalidation"
+            );
+            break;
+        }
+    }
+
+    tracing::info!(user_id = user_id.to_string(), "Closed event stream");
+}
@@ -0,0 +1,154 @@
+use super::{
+    handlers::{send_close, send_message},
+    models::GatewayCloseCode,
+};
+use crate::{
+    auth::{http::AuthExtractor, repository::AuthRepository},
+    errors::ApiError,
+    event::repository::{EventConnection, EventRepository},
+    http::AppData,
+    user::{models::UserRole, repository::UserRepository},
+};
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket},
+        WebSocketUpgrade,
+    },
+    response::Response,
+};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use uuid::Uuid;
+
+/// The caps of the admin event tail, which forwards every event of the app
+/// and is therefore far more expensive than a gateway connection.
+#[derive(Debug, Clone, Copy)]
+pub struct EventsTailLimits {
+    /// The connection is closed once it has been open for this long
+    pub max_duration: Duration,
+    /// The amount of events forwarded each second, the exceeding ones are
+    /// dropped and reported with a [`TailNotice::Dropped`]
+    pub max_rate: u32,
+}
+
+impl Default for EventsTailLimits {
+    fn default() -> Self {
+        Self {
+            max_duration: Duration::from_secs(900),
+            max_rate: 100,
+        }
+    }
+}
+
+/// Sent along with the raw events, in the same format.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TailNotice {
+    /// The amount of events dropped in the last second due to the rate cap
+    Dropped { count: u64 },
+}
+
+pub async fn events_tail_upgrader<E, A, U>(
+    AuthExtractor(auth_payload, _): AuthExtractor<A>,
+    AppData(event_repo): AppData<E>,
+    AppData(user_repo): AppData<U>,
+    AppData(limits): AppData<EventsTailLimits>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError>
+where
+    E: EventRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+{
+    let user = user_repo
+        .get_by_id(auth_payload.sub)
+        .await?
+        .ok_or(ApiError::UserNotFound)?;
+
+    if user.role != UserRole::Admin {
+        return Err(ApiError::AdminPermissionRequired);
+    }
+
+    let conn = event_repo.get_conn().await?;
+
+    Ok(ws.on_upgrade(move |socket| events_tail_handler(socket, conn, user.id, *limits)))
+}
+
+async fn events_tail_handler<EC: EventConnection>(
+    mut socket: WebSocket,
+    mut conn: EC,
+    user_id: Uuid,
+    limits: EventsTailLimits,
+) {
+    const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+    tracing::info!(user_id = user_id.to_string(), "Opened admin event tail");
+
+    let deadline = sleep(limits.max_duration);
+    tokio::pin!(deadline);
+
+    let mut window_start = Instant::now();
+    let mut sent = 0;
+    let mut dropped = 0;
+
+    let res = loop {
+        tokio::select! {
+            recv = socket.recv() => match recv {
+                Some(Ok(WsMessage::Close(_))) | None => break Ok(None),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => break Err(e),
+            },
+            event = conn.recv() => {
+                let event = match event {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::error!(
+                            error = e.to_string(),
+                            "Failed to receive message on tokio channel"
+                        );
+                        continue;
+                    }
+                };
+
+                if window_start.elapsed() >= RATE_WINDOW {
+                    if dropped > 0 {
+                        let notice = TailNotice::Dropped { count: dropped };
+                        if let Err(e) = send_message(&mut socket, &notice).await {
+                            break Err(e);
+                        }
+                    }
+                    window_start = Instant::now();
+                    sent = 0;
+                    dropped = 0;
+                }
+
+                if sent >= limits.max_rate {
+                    dropped += 1;
+                    continue;
+                }
+                sent += 1;
+
+                if let Err(e) = send_message(&mut socket, &event).await {
+                    break Err(e);
+                }
+            }
+            _ = &mut deadline => break Ok(Some(GatewayCloseCode::TailExpired)),
+        }
+    };
+
+    let res = match res {
+        Ok(Some(code)) => send_close(&mut socket, code).await,
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = res {
+        tracing::error!(
+            error = e.to_string(),
+            "Admin event tail closed unexpectedly"
+        );
+    }
+
+    tracing::info!(user_id = user_id.to_string(), "Closed admin event tail");
+}
@@ -1,191 +1,654 @@
 use crate::{
+    attachment::{
+        handlers::{AttachmentHandlers, AttachmentIdPathParams},
+        models::Attachment,
+        repository::StorageRepository,
+    },
+    audit::{handlers::AuditHandlers, models::AuditLog, repository::AuditRepository},
     auth::{
-        handlers::{AuthHandlers, InvalidationResponseBody, SignInRequestBody, SignInResponseBody},
+        handlers::{
+            AuthHandlers, ConnectionIdPathParams, ConnectionsResponseBody,
+            ForgotPasswordRequestBody, GetUsersQueryParams, InvalidationResponseBody,
+            ResetPasswordRequestBody, RevokeAllQueryParams, RevokeAllResponseBody,
+            SetRoleRequestBody, SignInRequestBody, SignInResponseBody, TokenValidationResponseBody,
+            TwoFactorChallengeRequestBody, TwoFactorEnrollResponseBody,
+            TwoFactorRecoveryCodesResponseBody, TwoFactorVerifyRequestBody, UserIdPathParams,
+            UsernameAvailabilityResponseBody, UsernamePathParams, UsersResponseBody,
+            VerifyEmailRequestBody,
+        },
         http::AuthExtractor,
         repository::AuthRepository,
     },
     channel::{
-        handlers::{AddPermissionRequestBody, ChannelHandlers},
-        models::{Channel, ChannelCreateData, ChannelUpdateData, UserPermissionEntry},
+        handlers::{
+            AddPermissionRequestBody, BatchChannelsRequestBody, ChannelHandlers,
+            ChannelIdInviteIdPathParams, InviteTokenPathParams, SelfPermissionResponseBody,
+        },
+        models::{
+            Channel, ChannelCreateData, ChannelInvite, ChannelInviteCreateData,
+            ChannelInvitePreview, ChannelUpdateData, NotificationPrefEntry,
+            NotificationPrefRequestBody, UserPermissionEntry,
+        },
         repository::ChannelRepository,
     },
     errors::ApiError,
     event::repository::EventRepository,
-    http::{AppData, DataResponse, Json},
+    http::{AppData, DataResponse, Json, ListResponse, NoContent},
+    mail::repository::Mailer,
     message::{
         handlers::{
-            ChannelIdMessageIdPathParams, ChannelIdPathParams, GetManyQueryParams, MessageHandlers,
+            ChannelIdMessageIdPathParams, ChannelIdPathParams, ExpandQueryParams,
+            ExportQueryParams, GetAroundQueryParams, GetManyQueryParams, MessageHandlers,
+        },
+        models::{
+            Message, MessageCreateData, MessageImportData, MessageImportResponseBody,
+            MessageUpdateData, MessageView, ReadAllResponseBody, ReadMarker, TypingResponseBody,
         },
-        models::{Message, MessageCreateData, MessageUpdateData},
         repository::MessageRepository,
     },
+    stats::handlers::{StatsHandlers, StatsResponseBody},
     user::{
         models::{User, UserCreateData},
         repository::UserRepository,
     },
 };
-use axum::extract::{Path, Query};
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::{header, HeaderMap},
+    response::Response,
+};
 
-pub async fn post_auth_signin<A, U, E>(
-    AppData(data): AppData<AuthHandlers<A, U, E>>,
+pub async fn post_auth_signin<A, U, E, M, L>(
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
     Json(body): Json<SignInRequestBody>,
 ) -> Result<DataResponse<SignInResponseBody>, ApiError>
 where
     A: AuthRepository + 'static,
     U: UserRepository + 'static,
     E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_signin(body).await
 }
 
-pub async fn post_auth_signup<A, U, E>(
-    AppData(data): AppData<AuthHandlers<A, U, E>>,
+pub async fn post_auth_signup<A, U, E, M, L>(
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
     Json(b): Json<UserCreateData>,
 ) -> Result<DataResponse<User>, ApiError>
 where
     A: AuthRepository + 'static,
     U: UserRepository + 'static,
     E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_signup(b).await
 }
 
-pub async fn get_auth_self<A, U, E>(
+pub async fn get_auth_username_availability<A, U, E, M, L>(
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Path(path): Path<UsernamePathParams>,
+) -> Result<DataResponse<UsernameAvailabilityResponseBody>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_username_availability(path).await
+}
+
+pub async fn get_auth_self<A, U, E, M, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<AuthHandlers<A, U, E>>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
 ) -> Result<DataResponse<User>, ApiError>
 where
     A: AuthRepository + 'static,
     U: UserRepository + 'static,
     E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_get_self(auth).await
 }
 
-pub async fn post_auth_self_invalidate<A, U, E>(
+pub async fn get_auth_validate<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+) -> Result<DataResponse<TokenValidationResponseBody>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_validate(auth)
+}
+
+pub async fn get_auth_self_connections<A, U, E, M, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<AuthHandlers<A, U, E>>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+) -> Result<DataResponse<ConnectionsResponseBody>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_connections(auth).await
+}
+
+pub async fn delete_admin_connection_id<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Path(path): Path<ConnectionIdPathParams>,
+) -> Result<NoContent, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_close_connection(auth, path).await
+}
+
+pub async fn get_admin_users<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Query(query): Query<GetUsersQueryParams>,
+) -> Result<DataResponse<UsersResponseBody>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_users(auth, query).await
+}
+
+pub async fn get_admin_users_username<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Path(path): Path<UsernamePathParams>,
+) -> Result<DataResponse<User>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_user_by_username(auth, path).await
+}
+
+pub async fn put_admin_users_id_role<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Path(path): Path<UserIdPathParams>,
+    Json(body): Json<SetRoleRequestBody>,
+) -> Result<DataResponse<User>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_set_role(auth, path, body).await
+}
+
+pub async fn post_auth_self_invalidate<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
 ) -> Result<DataResponse<InvalidationResponseBody>, ApiError>
 where
     A: AuthRepository + 'static,
     U: UserRepository + 'static,
     E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_invalidate(auth).await
 }
 
-pub async fn get_channel_id<C, A, E>(
+pub async fn post_auth_self_revoke_all<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Query(query): Query<RevokeAllQueryParams>,
+) -> Result<DataResponse<RevokeAllResponseBody>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_revoke_all(auth, query).await
+}
+
+pub async fn post_auth_verify_email<A, U, E, M, L>(
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Json(body): Json<VerifyEmailRequestBody>,
+) -> Result<DataResponse<User>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_verify_email(body).await
+}
+
+pub async fn post_auth_self_verify_email<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+) -> Result<DataResponse<()>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_request_email_verification(auth).await
+}
+
+pub async fn post_auth_forgot_password<A, U, E, M, L>(
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Json(body): Json<ForgotPasswordRequestBody>,
+) -> Result<DataResponse<()>, ApiError>
+where
+    A: AuthRepository + Clone + 'static,
+    U: UserRepository + Clone + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + Clone + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_forgot_password(body).await
+}
+
+pub async fn post_auth_reset_password<A, U, E, M, L>(
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Json(body): Json<ResetPasswordRequestBody>,
+) -> Result<DataResponse<()>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_reset_password(body).await
+}
+
+pub async fn post_auth_2fa_enroll<A, U, E, M, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+) -> Result<DataResponse<TwoFactorEnrollResponseBody>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_2fa_enroll(auth).await
+}
+
+pub async fn post_auth_2fa_verify<A, U, E, M, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<ChannelHandlers<C, E>>,
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Json(body): Json<TwoFactorVerifyRequestBody>,
+) -> Result<DataResponse<TwoFactorRecoveryCodesResponseBody>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_2fa_verify(auth, body).await
+}
+
+pub async fn post_auth_2fa_challenge<A, U, E, M, L>(
+    AppData(data): AppData<AuthHandlers<A, U, E, M, L>>,
+    Json(body): Json<TwoFactorChallengeRequestBody>,
+) -> Result<DataResponse<SignInResponseBody>, ApiError>
+where
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    M: Mailer + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_2fa_challenge(body).await
+}
+
+pub async fn get_channel_id<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
     Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
 ) -> Result<DataResponse<Channel>, ApiError>
 where
     C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
     A: AuthRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_get_one(auth, path).await
 }
 
-pub async fn get_channels_self<C, A, E>(
+pub async fn get_channel_id_permission_self<C, M, A, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<ChannelHandlers<C, E>>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
+) -> Result<DataResponse<SelfPermissionResponseBody>, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_self_permission(auth, path).await
+}
+
+pub async fn get_channels_self<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
     Query(query): Query<crate::channel::handlers::GetManyQueryParams>,
 ) -> Result<DataResponse<Vec<Channel>>, ApiError>
 where
     C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
     A: AuthRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_get_many_self(auth, query).await
 }
 
-pub async fn post_channel<C, A, E>(
+pub async fn post_channels_batch<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Json(body): Json<BatchChannelsRequestBody>,
+) -> Result<DataResponse<Vec<Channel>>, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_batch(auth, body).await
+}
+
+pub async fn post_channel<C, M, A, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<ChannelHandlers<C, E>>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
     Json(body): Json<ChannelCreateData>,
 ) -> Result<DataResponse<Channel>, ApiError>
 where
     C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
     A: AuthRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_create(auth, body).await
 }
 
-pub async fn put_channel_id_permission<C, A, E>(
+pub async fn put_channel_id_permission<C, M, A, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<ChannelHandlers<C, E>>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
     Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
     Json(body): Json<AddPermissionRequestBody>,
 ) -> Result<DataResponse<UserPermissionEntry>, ApiError>
 where
     C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
     A: AuthRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_edit_user_permission(auth, path, body).await
 }
 
-pub async fn put_channel_id<C, A, E>(
+pub async fn get_channel_id_notifications<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
+) -> Result<DataResponse<NotificationPrefEntry>, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_notification_pref(auth, path).await
+}
+
+pub async fn put_channel_id_notifications<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
+    Json(body): Json<NotificationPrefRequestBody>,
+) -> Result<DataResponse<NotificationPrefEntry>, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_set_notification_pref(auth, path, body).await
+}
+
+pub async fn post_channel_id_invites<C, M, A, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<ChannelHandlers<C, E>>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
+    Json(body): Json<ChannelInviteCreateData>,
+) -> Result<DataResponse<ChannelInvite>, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_create_invite(auth, path, body).await
+}
+
+pub async fn get_channel_id_invites<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
+) -> Result<DataResponse<Vec<ChannelInvite>>, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_invites(auth, path).await
+}
+
+pub async fn delete_channel_id_invite_id<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Path(path): Path<ChannelIdInviteIdPathParams>,
+) -> Result<NoContent, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_delete_invite(auth, path).await
+}
+
+pub async fn get_invite_token<C, M, A, E, L>(
+    AuthExtractor(_, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Path(path): Path<InviteTokenPathParams>,
+) -> Result<DataResponse<ChannelInvitePreview>, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_invite(path).await
+}
+
+pub async fn post_invite_token_accept<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
+    Path(path): Path<InviteTokenPathParams>,
+) -> Result<DataResponse<UserPermissionEntry>, ApiError>
+where
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_accept_invite(auth, path).await
+}
+
+pub async fn put_channel_id<C, M, A, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
     Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
     Json(body): Json<ChannelUpdateData>,
 ) -> Result<DataResponse<Channel>, ApiError>
 where
     C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
     A: AuthRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_update(auth, path, body).await
 }
 
-pub async fn delete_channel_id<C, A, E>(
+pub async fn delete_channel_id<C, M, A, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<ChannelHandlers<C, E>>,
+    AppData(data): AppData<ChannelHandlers<C, M, E, L>>,
     Path(path): Path<crate::channel::handlers::ChannelIdPathParams>,
 ) -> Result<DataResponse<()>, ApiError>
 where
     C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
     A: AuthRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_delete(auth, path).await
 }
 
-pub async fn get_channel_id_message_id<M, C, A, E>(
+pub async fn get_channel_id_message_id<M, C, A, U, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<MessageHandlers<M, C, E>>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
     Path(path): Path<ChannelIdMessageIdPathParams>,
-) -> Result<DataResponse<Message>, ApiError>
+    Query(query): Query<ExpandQueryParams>,
+) -> Result<DataResponse<MessageView>, ApiError>
 where
     M: MessageRepository + 'static,
     C: ChannelRepository + 'static,
     A: AuthRepository + 'static,
+    U: UserRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
-    data.handle_get_one(auth, path).await
+    data.handle_get_one(auth, path, query).await
 }
 
-pub async fn get_channel_id_messages<M, C, A, E>(
+pub async fn get_channel_id_messages<M, C, A, U, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<MessageHandlers<M, C, E>>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
     Path(path): Path<ChannelIdPathParams>,
     Query(query): Query<GetManyQueryParams>,
-) -> Result<DataResponse<Vec<Message>>, ApiError>
+) -> Result<DataResponse<Vec<MessageView>>, ApiError>
 where
     M: MessageRepository + 'static,
     C: ChannelRepository + 'static,
     A: AuthRepository + 'static,
+    U: UserRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_get_many(auth, path, query).await
 }
 
-pub async fn post_channel_id_message<M, C, A, E>(
+pub async fn get_channel_id_export<M, C, A, U, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
+    Path(path): Path<ChannelIdPathParams>,
+    Query(query): Query<ExportQueryParams>,
+) -> Result<Response, ApiError>
+where
+    M: MessageRepository + Clone + 'static,
+    C: ChannelRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_export(auth, path, query).await
+}
+
+pub async fn post_channel_id_import<M, C, A, U, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
+    Path(path): Path<ChannelIdPathParams>,
+    Json(body): Json<Vec<MessageImportData>>,
+) -> Result<DataResponse<MessageImportResponseBody>, ApiError>
+where
+    M: MessageRepository + 'static,
+    C: ChannelRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_import(auth, path, body).await
+}
+
+pub async fn get_channel_id_messages_around<M, C, A, U, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
+    Path(path): Path<ChannelIdPathParams>,
+    Query(query): Query<GetAroundQueryParams>,
+) -> Result<DataResponse<Vec<MessageView>>, ApiError>
+where
+    M: MessageRepository + 'static,
+    C: ChannelRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_around(auth, path, query).await
+}
+
+pub async fn post_channel_id_message<M, C, A, U, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<MessageHandlers<M, C, E>>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
     Path(path): Path<ChannelIdPathParams>,
     Json(body): Json<MessageCreateData>,
 ) -> Result<DataResponse<Message>, ApiError>
@@ -193,14 +656,16 @@ where
     M: MessageRepository + 'static,
     C: ChannelRepository + 'static,
     A: AuthRepository + 'static,
+    U: UserRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_create(auth, path, body).await
 }
 
-pub async fn put_channel_id_message_id<M, C, A, E>(
+pub async fn put_channel_id_message_id<M, C, A, U, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<MessageHandlers<M, C, E>>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
     Path(path): Path<ChannelIdMessageIdPathParams>,
     Json(body): Json<MessageUpdateData>,
 ) -> Result<DataResponse<Message>, ApiError>
@@ -208,21 +673,186 @@ where
     M: MessageRepository + 'static,
     C: ChannelRepository + 'static,
     A: AuthRepository + 'static,
+    U: UserRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
 {
     data.handle_update(auth, path, body).await
 }
 
-pub async fn delete_channel_id_message_id<M, C, A, E>(
+pub async fn delete_channel_id_message_id<M, C, A, U, E, L>(
     AuthExtractor(auth, _): AuthExtractor<A>,
-    AppData(data): AppData<MessageHandlers<M, C, E>>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
     Path(path): Path<ChannelIdMessageIdPathParams>,
 ) -> Result<DataResponse<()>, ApiError>
 where
     M: MessageRepository + 'static,
     C: ChannelRepository + 'static,
     A: AuthRepository + 'static,
+    U: UserRepository + 'static,
     E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_delete(auth, path).await
+}
+
+pub async fn post_channel_id_message_id_read<M, C, A, U, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
+    Path(path): Path<ChannelIdMessageIdPathParams>,
+) -> Result<NoContent, ApiError>
+where
+    M: MessageRepository + 'static,
+    C: ChannelRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_mark_read(auth, path).await
+}
+
+pub async fn post_channels_self_read_all<M, C, A, U, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
+) -> Result<DataResponse<ReadAllResponseBody>, ApiError>
+where
+    M: MessageRepository + 'static,
+    C: ChannelRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_mark_all_read(auth).await
+}
+
+pub async fn get_channel_id_message_id_receipts<M, C, A, U, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
+    Path(path): Path<ChannelIdMessageIdPathParams>,
+) -> Result<DataResponse<ListResponse<ReadMarker>>, ApiError>
+where
+    M: MessageRepository + 'static,
+    C: ChannelRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_receipts(auth, path).await
+}
+
+pub async fn post_channel_id_typing<M, C, A, U, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
+    Path(path): Path<ChannelIdPathParams>,
+) -> Result<NoContent, ApiError>
+where
+    M: MessageRepository + 'static,
+    C: ChannelRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_typing(auth, path).await
+}
+
+pub async fn get_channel_id_typing<M, C, A, U, E, L>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<MessageHandlers<M, C, U, E, L>>,
+    Path(path): Path<ChannelIdPathParams>,
+) -> Result<DataResponse<TypingResponseBody>, ApiError>
+where
+    M: MessageRepository + 'static,
+    C: ChannelRepository + 'static,
+    A: AuthRepository + 'static,
+    U: UserRepository + 'static,
+    E: EventRepository + 'static,
+    L: AuditRepository + 'static,
+{
+    data.handle_get_typing(auth, path).await
+}
+
+pub async fn get_admin_audit<L, U, A>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AuditHandlers<L, U>>,
+    Query(query): Query<crate::audit::handlers::GetManyQueryParams>,
+) -> Result<DataResponse<Vec<AuditLog>>, ApiError>
+where
+    L: AuditRepository + 'static,
+    U: UserRepository + 'static,
+    A: AuthRepository + 'static,
+{
+    data.handle_get_many(auth, query).await
+}
+
+pub async fn get_stats<U, C, M, A>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<StatsHandlers<U, C, M>>,
+) -> Result<DataResponse<StatsResponseBody>, ApiError>
+where
+    U: UserRepository + 'static,
+    C: ChannelRepository + 'static,
+    M: MessageRepository + 'static,
+    A: AuthRepository + 'static,
+{
+    data.handle_get(auth).await
+}
+
+pub async fn post_attachments<S, A>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AttachmentHandlers<S>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<DataResponse<Attachment>, ApiError>
+where
+    S: StorageRepository + 'static,
+    A: AuthRepository + 'static,
+{
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+
+    data.handle_upload(auth, content_type, body).await
+}
+
+pub async fn get_attachment_id<S, A>(
+    AuthExtractor(_, _): AuthExtractor<A>,
+    AppData(data): AppData<AttachmentHandlers<S>>,
+    Path(path): Path<AttachmentIdPathParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError>
+where
+    S: StorageRepository + 'static,
+    A: AuthRepository + 'static,
+{
+    let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+
+    data.handle_download(path, range).await
+}
+
+pub async fn get_attachment_id_thumbnail<S, A>(
+    AuthExtractor(_, _): AuthExtractor<A>,
+    AppData(data): AppData<AttachmentHandlers<S>>,
+    Path(path): Path<AttachmentIdPathParams>,
+) -> Result<Response, ApiError>
+where
+    S: StorageRepository + 'static,
+    A: AuthRepository + 'static,
+{
+    data.handle_download_thumbnail(path).await
+}
+
+pub async fn delete_attachment_id<S, A>(
+    AuthExtractor(auth, _): AuthExtractor<A>,
+    AppData(data): AppData<AttachmentHandlers<S>>,
+    Path(path): Path<AttachmentIdPathParams>,
+) -> Result<NoContent, ApiError>
+where
+    S: StorageRepository + 'static,
+    A: AuthRepository + 'static,
 {
     data.handle_delete(auth, path).await
 }
@@ -5,13 +5,56 @@ use crate::{
 use async_trait::async_trait;
 use axum::{
     body::Body,
-    extract::{rejection::JsonRejection, FromRequest, FromRequestParts},
-    http::{header, request::Parts, HeaderValue, Request, StatusCode},
-    response::IntoResponse,
+    extract::{rejection::JsonRejection, ConnectInfo, FromRequest, FromRequestParts, State},
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
     Extension,
 };
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
 use serde::Serialize;
-use std::{any::type_name, sync::Arc};
+use std::{
+    any::type_name,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+static JSON_PRETTY: AtomicBool = AtomicBool::new(false);
+
+/// Makes the http responses be encoded as indented json. Meant to be set once
+/// on startup.
+#[inline]
+pub fn set_json_pretty(pretty: bool) {
+    JSON_PRETTY.store(pretty, Ordering::Relaxed);
+}
+
+/// Encodes the value as json, indented if [`set_json_pretty`] was enabled.
+#[inline]
+pub fn to_json_vec<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<Vec<u8>> {
+    if JSON_PRETTY.load(Ordering::Relaxed) {
+        serde_json::to_vec_pretty(value)
+    } else {
+        serde_json::to_vec(value)
+    }
+}
+
+/// Encodes the value as a json string, indented if [`set_json_pretty`] was
+/// enabled.
+#[inline]
+pub fn to_json_string<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<String> {
+    if JSON_PRETTY.load(Ordering::Relaxed) {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+}
 
 pub trait ApiResponder {
     fn http_code(&self) -> StatusCode {
@@ -48,14 +91,51 @@ impl<T: ApiResponder + Serialize> ApiResponder for Vec<T> {
         T::article()
     }
 
+    #[inline]
     fn message(&self) -> String {
-        let unit = Self::unit();
+        list_message(self.len(), Self::unit())
+    }
+}
 
-        match self.len() {
-            0 => format!("No {unit} was returned"),
-            1 => format!("1 {unit} was returned"),
-            n => format!("{n} {unit} were returned"),
-        }
+fn list_message(len: usize, unit: &str) -> String {
+    match len {
+        0 => format!("No {unit} was returned"),
+        1 => format!("1 {unit} was returned"),
+        n => format!("{n} {unit} were returned"),
+    }
+}
+
+/// A list of items described by an explicit unit, for the list endpoints
+/// whose items do not implement [`ApiResponder`] themselves.
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub struct ListResponse<T: Serialize> {
+    items: Vec<T>,
+    #[serde(skip)]
+    unit: &'static str,
+}
+
+impl<T: Serialize> ListResponse<T> {
+    #[inline]
+    pub fn new(items: Vec<T>, unit: &'static str) -> Self {
+        Self { items, unit }
+    }
+}
+
+impl<T: Serialize> ApiResponder for ListResponse<T> {
+    #[inline]
+    fn unit() -> &'static str {
+        "item"
+    }
+
+    #[inline]
+    fn article() -> &'static str {
+        "An"
+    }
+
+    #[inline]
+    fn message(&self) -> String {
+        list_message(self.items.len(), self.unit)
     }
 }
 
@@ -74,6 +154,7 @@ impl<T> AppData<T> {
     }
 
     #[inline]
+    #[allow(dead_code)]
     pub fn extension_arc(data: Arc<T>) -> Extension<Arc<T>> {
         Extension(data)
     }
@@ -116,7 +197,7 @@ impl<T: ApiResponder + Serialize> IntoResponse for DataResponse<T> {
             self.message = Some(self.data.message());
         }
 
-        let tuple = match serde_json::to_vec(&self) {
+        match to_json_vec(&self) {
             Ok(buf) => (
                 self.http_code.unwrap(),
                 [(
@@ -124,21 +205,17 @@ impl<T: ApiResponder + Serialize> IntoResponse for DataResponse<T> {
                     HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
                 )],
                 buf,
-            ),
+            )
+                .into_response(),
             Err(e) => {
-                tracing::error!({ error = e.to_string() }, "Failed to encode response body");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    [(
-                        header::CONTENT_TYPE,
-                        HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
-                    )],
-                    ENCODING_FAILED_BODY.to_vec(),
-                )
+                tracing::error!(
+                    error = e.to_string(),
+                    type_name = type_name::<T>(),
+                    "Failed to encode response body"
+                );
+                ApiError::ResponseEncodingFailed.into_response()
             }
-        };
-
-        tuple.into_response()
+        }
     }
 }
 
@@ -153,6 +230,180 @@ impl<T: ApiResponder + Serialize> From<T> for DataResponse<T> {
     }
 }
 
+/// A `204 No Content` response, for the endpoints that have nothing to return.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoContent;
+
+impl IntoResponse for NoContent {
+    #[inline]
+    fn into_response(self) -> axum::response::Response {
+        StatusCode::NO_CONTENT.into_response()
+    }
+}
+
+/// Fails the requests that take longer than `timeout` with
+/// [`ApiError::RequestTimedOut`].
+pub async fn request_timeout(
+    State(timeout): State<Duration>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    match tokio::time::timeout(timeout, next.run(req)).await {
+        Ok(res) => res,
+        Err(_) => {
+            tracing::warn!(timeout = timeout.as_secs(), "Request timed out");
+            ApiError::RequestTimedOut.into_response()
+        }
+    }
+}
+
+/// The amount of seconds a client should wait before retrying a request that
+/// was shed by [`concurrency_limit`].
+const SERVER_OVERLOAD_RETRY_AFTER: u64 = 1;
+
+/// Fails the requests with [`ApiError::ServerOverloaded`] while all the
+/// permits of `limit` are held by in-flight requests. They are shed rather
+/// than queued so a burst of requests can not pile up waiting on the database
+/// pool.
+pub async fn concurrency_limit(
+    State(limit): State<Arc<Semaphore>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let _permit = match limit.try_acquire() {
+        Ok(v) => v,
+        Err(_) => {
+            tracing::warn!("Request shed due to the concurrency limit");
+            return ApiError::ServerOverloaded {
+                retry_after: SERVER_OVERLOAD_RETRY_AFTER,
+            }
+            .into_response();
+        }
+    };
+
+    next.run(req).await
+}
+
+/// Marks the responses of the unversioned route aliases as deprecated with
+/// the `Deprecation` header, along with the `Sunset` header if the date they
+/// are going to be removed on is known.
+pub async fn deprecated_route(
+    State(sunset): State<Option<DateTime<Utc>>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let mut res = next.run(req).await;
+    let headers = res.headers_mut();
+
+    headers.insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static("true"),
+    );
+    if let Some(sunset) = sunset {
+        let date = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        if let Ok(v) = HeaderValue::from_str(&date) {
+            headers.insert(HeaderName::from_static("sunset"), v);
+        }
+    }
+
+    res
+}
+
+/// The security headers added to the http responses.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    /// Whether the headers are sent at all, they may already be set by a
+    /// reverse proxy
+    pub enabled: bool,
+    pub referrer_policy: HeaderValue,
+    /// The `max-age` of the `Strict-Transport-Security` header, that must
+    /// only be sent when the app is served over TLS
+    pub hsts_max_age: Option<Duration>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            referrer_policy: HeaderValue::from_static("no-referrer"),
+            hsts_max_age: None,
+        }
+    }
+}
+
+/// Adds the [`SecurityHeaders`] to the responses that do not set them
+/// already. The WebSocket upgrades are left untouched, since the headers only
+/// make sense for documents.
+pub async fn security_headers(
+    State(config): State<SecurityHeaders>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let mut res = next.run(req).await;
+    if !config.enabled || res.status() == StatusCode::SWITCHING_PROTOCOLS {
+        return res;
+    }
+    let headers = res.headers_mut();
+
+    headers
+        .entry(header::X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    headers
+        .entry(header::X_FRAME_OPTIONS)
+        .or_insert(HeaderValue::from_static("DENY"));
+    headers
+        .entry(header::REFERRER_POLICY)
+        .or_insert(config.referrer_policy);
+    if let Some(max_age) = config.hsts_max_age {
+        headers
+            .entry(header::STRICT_TRANSPORT_SECURITY)
+            .or_insert_with(|| {
+                HeaderValue::from_str(&format!("max-age={}", max_age.as_secs())).unwrap()
+            });
+    }
+
+    res
+}
+
+/// Replaces the plain text `413 Payload Too Large` responses of the body
+/// limits with [`ApiError::RequestBodyTooLarge`].
+pub async fn json_payload_too_large(res: Response) -> Response {
+    let is_json = res.headers().get(header::CONTENT_TYPE).is_some_and(|v| {
+        v.as_bytes()
+            .starts_with(mime::APPLICATION_JSON.as_ref().as_bytes())
+    });
+
+    if res.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        return ApiError::RequestBodyTooLarge.into_response();
+    }
+
+    res
+}
+
+/// The fallback of the router, so the unmatched routes respond with
+/// [`ApiError::RouteNotFound`] instead of an empty body.
+pub async fn route_not_found() -> ApiError {
+    ApiError::RouteNotFound
+}
+
+/// Replaces the empty `405 Method Not Allowed` responses of the routes with
+/// [`ApiError::MethodNotAllowed`], keeping the `Allow` header.
+pub async fn method_not_allowed(res: Response) -> Response {
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED
+        || res.headers().contains_key(header::CONTENT_TYPE)
+    {
+        return res;
+    }
+
+    let allow = res.headers().get(header::ALLOW).cloned();
+    let mut res = ApiError::MethodNotAllowed.into_response();
+    if let Some(allow) = allow {
+        res.headers_mut().insert(header::ALLOW, allow);
+    }
+
+    res
+}
+
 pub struct Json<T>(pub T);
 
 #[async_trait]
@@ -166,36 +417,382 @@ where
     async fn from_request(req: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
         match axum::Json::from_request(req, state).await {
             Ok(axum::Json(v)) => Ok(Self(v)),
+            // The body limits may reject the body while it is being read
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                Err(ApiError::RequestBodyTooLarge.into())
+            }
             Err(e) => {
                 let status_code = e.status();
                 Err(ErrorResponse {
                     error_code: u32::from(status_code.as_u16()) * 100_u32,
                     status_code,
                     message: e.body_text(),
+                    retry_after: None,
                 })
             }
         }
     }
 }
 
+/// The list of reverse proxies whose forwarding headers are trusted.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+    #[inline]
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(addr))
+    }
+
+    /// Resolves the address of the client that originated the request.
+    ///
+    /// The forwarding headers are only taken into account when `peer` is a
+    /// trusted proxy, otherwise any client could spoof its address.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(&peer) {
+            return peer;
+        }
+
+        let forwarded_for = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().parse::<IpAddr>())
+            .collect::<Result<Vec<_>, _>>();
+
+        if let Ok(forwarded_for) = forwarded_for {
+            // Each proxy appends the address of its peer, so the client is the
+            // rightmost address that was not added by a trusted proxy
+            let mut client = None;
+            for addr in forwarded_for.into_iter().rev() {
+                client = Some(addr);
+                if !self.contains(&addr) {
+                    break;
+                }
+            }
+            if let Some(client) = client {
+                return client;
+            }
+        }
+
+        headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(peer)
+    }
+}
+
+impl FromStr for TrustedProxies {
+    type Err = ipnet::AddrParseError;
+
+    /// Parses a comma separated list of CIDRs or plain ip addresses.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let nets = s
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| match v.parse::<IpAddr>() {
+                Ok(addr) => Ok(IpNet::from(addr)),
+                Err(_) => v.parse::<IpNet>(),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self(nets))
+    }
+}
+
+/// The ip address of the client, resolved through the [`TrustedProxies`].
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .ok_or_else(|| {
+                tracing::error!("Failed to get ConnectInfo request extension");
+
+                ApiError::ServicePanicked(Some(
+                    "Failed to get 'ConnectInfo' request extension".into(),
+                ))
+            })?
+            .ip();
+
+        let addr = match parts.extensions.get::<Arc<TrustedProxies>>() {
+            Some(proxies) => proxies.resolve(peer, &parts.headers),
+            None => peer,
+        };
+
+        Ok(Self(addr))
+    }
+}
+
 pub fn marshal_json_string<T: Serialize>(value: &T) -> String {
-    match serde_json::to_string(value) {
+    match to_json_string(value) {
         Ok(v) => v,
         Err(e) => {
-            tracing::error!(error = e.to_string(), "Failed to encode json");
+            tracing::error!(
+                error = e.to_string(),
+                type_name = type_name::<T>(),
+                "Failed to encode json"
+            );
 
             unsafe { String::from_utf8_unchecked(ENCODING_FAILED_BODY.to_vec()) }
         }
     }
 }
 
+#[allow(dead_code)]
 pub fn marshal_json_vec<T: Serialize, R: From<Vec<u8>>>(value: &T) -> R {
-    match serde_json::to_vec(value) {
+    match to_json_vec(value) {
         Ok(v) => R::from(v),
         Err(e) => {
-            tracing::error!(error = e.to_string(), "Failed to encode json");
+            tracing::error!(
+                error = e.to_string(),
+                type_name = type_name::<T>(),
+                "Failed to encode json"
+            );
 
             R::from(ENCODING_FAILED_BODY.to_vec())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        concurrency_limit, json_payload_too_large, ApiResponder, DataResponse, ListResponse,
+        TrustedProxies,
+    };
+    use crate::errors::ApiError;
+    use axum::{
+        body::{to_bytes, Body, Bytes},
+        extract::DefaultBodyLimit,
+        http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+        middleware, routing, Router,
+    };
+    use flate2::read::GzDecoder;
+    use serde::Serialize;
+    use std::{io::Read, net::IpAddr, sync::Arc};
+    use tokio::sync::Semaphore;
+    use tower::ServiceExt;
+    use tower_http::{compression::CompressionLayer, limit::RequestBodyLimitLayer};
+
+    #[derive(Serialize)]
+    struct Item {
+        content: String,
+    }
+
+    impl ApiResponder for Item {
+        fn unit() -> &'static str {
+            "item"
+        }
+        fn article() -> &'static str {
+            "An"
+        }
+    }
+
+    #[test]
+    fn test_list_response() {
+        #[derive(Serialize)]
+        struct Reaction(&'static str);
+
+        let res = DataResponse::from(ListResponse::new(
+            vec![Reaction("+1"), Reaction("heart")],
+            "reaction",
+        ));
+        assert_eq!(res.message.as_deref(), Some("2 reaction were returned"));
+        assert_eq!(
+            serde_json::to_value(&res.data).unwrap(),
+            serde_json::json!(["+1", "heart"]),
+        );
+
+        let res = DataResponse::from(ListResponse::<Reaction>::new(vec![], "reaction"));
+        assert_eq!(res.message.as_deref(), Some("No reaction was returned"));
+    }
+
+    async fn get_gzip(app: Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let req = Request::get(uri)
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+
+        let status = res.status();
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let buf = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let mut s = String::new();
+        GzDecoder::new(&buf[..]).read_to_string(&mut s).unwrap();
+
+        (status, serde_json::from_str(&s).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let app = Router::new()
+            .route(
+                "/items",
+                routing::get(|| async {
+                    let items = (0..1000)
+                        .map(|i| Item {
+                            content: format!("Message number {i}"),
+                        })
+                        .collect::<Vec<_>>();
+                    DataResponse::from(items)
+                }),
+            )
+            .route(
+                "/error",
+                routing::get(|| async { ApiError::MessageNotFound }),
+            )
+            .layer(CompressionLayer::new());
+
+        let (status, body) = get_gzip(app.clone(), "/items").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"].as_array().unwrap().len(), 1000);
+
+        let (status, body) = get_gzip(app, "/error").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error_code"], 40401);
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let app = Router::new()
+            .route(
+                "/upload",
+                routing::post(|body: Bytes| async move { body.len().to_string() }),
+            )
+            .layer(DefaultBodyLimit::disable())
+            .layer(RequestBodyLimitLayer::new(1024))
+            .layer(middleware::map_response(json_payload_too_large));
+
+        let send = |body: Body| {
+            let app = app.clone();
+            async move {
+                let res = app
+                    .oneshot(Request::post("/upload").body(body).unwrap())
+                    .await
+                    .unwrap();
+                let status = res.status();
+                let buf = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+                (status, buf)
+            }
+        };
+
+        let (status, buf) = send(Body::from(vec![0_u8; 1024])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&buf[..], b"1024");
+
+        // Rejected by the content length
+        let (status, buf) = send(Body::from(vec![0_u8; 1025])).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(body["error_code"], 41301);
+
+        // Rejected while streaming, with no content length
+        let chunks = (0..4).map(|_| Ok::<_, std::io::Error>(Bytes::from(vec![0_u8; 512])));
+        let (status, buf) = send(Body::from_stream(tokio_stream::iter(chunks))).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(body["error_code"], 41301);
+    }
+
+    fn headers(forwarded_for: Option<&'static str>, real_ip: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(v) = forwarded_for {
+            headers.insert("x-forwarded-for", HeaderValue::from_static(v));
+        }
+        if let Some(v) = real_ip {
+            headers.insert("x-real-ip", HeaderValue::from_static(v));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_trusted_proxies_resolve() {
+        let proxies: TrustedProxies = "10.0.0.0/8, 127.0.0.1".parse().unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let h = headers(Some("203.0.113.7, 10.1.1.1"), None);
+        assert_eq!(proxies.resolve(ip("127.0.0.1"), &h), ip("203.0.113.7"));
+
+        let h = headers(Some("198.51.100.1, 203.0.113.7"), None);
+        assert_eq!(proxies.resolve(ip("10.2.3.4"), &h), ip("203.0.113.7"));
+
+        let h = headers(None, Some("203.0.113.7"));
+        assert_eq!(proxies.resolve(ip("10.2.3.4"), &h), ip("203.0.113.7"));
+
+        let h = headers(Some("not an ip"), Some("203.0.113.7"));
+        assert_eq!(proxies.resolve(ip("10.2.3.4"), &h), ip("203.0.113.7"));
+
+        let h = headers(None, None);
+        assert_eq!(proxies.resolve(ip("10.2.3.4"), &h), ip("10.2.3.4"));
+    }
+
+    #[test]
+    fn test_untrusted_peer_headers_ignored() {
+        let proxies: TrustedProxies = "10.0.0.0/8".parse().unwrap();
+        let peer = "198.51.100.1".parse::<IpAddr>().unwrap();
+
+        let h = headers(Some("203.0.113.7"), Some("203.0.113.8"));
+        assert_eq!(proxies.resolve(peer, &h), peer);
+
+        let h = headers(Some("203.0.113.7"), None);
+        assert_eq!(TrustedProxies::default().resolve(peer, &h), peer);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        const MAX: usize = 2;
+
+        // Holds the handlers until permits are added to it
+        let release = Arc::new(Semaphore::new(0));
+        let limit = Arc::new(Semaphore::new(MAX));
+
+        let handler_release = release.clone();
+        let app = Router::new()
+            .route(
+                "/",
+                routing::get(move || async move {
+                    _ = handler_release.acquire().await.unwrap();
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                limit.clone(),
+                concurrency_limit,
+            ));
+
+        let req = || Request::get("/").body(Body::empty()).unwrap();
+
+        let in_flight = (0..MAX)
+            .map(|_| tokio::spawn(app.clone().oneshot(req())))
+            .collect::<Vec<_>>();
+        while limit.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[header::RETRY_AFTER], "1");
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let expected = serde_json::to_vec(&ApiError::ServerOverloaded { retry_after: 1 }).unwrap();
+        assert_eq!(body, Bytes::from(expected));
+
+        release.add_permits(MAX + 1);
+        for handle in in_flight {
+            assert_eq!(handle.await.unwrap().unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(limit.available_permits(), MAX);
+
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
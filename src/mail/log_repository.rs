@@ -0,0 +1,23 @@
+use super::repository::Mailer;
+use crate::errors::ApiError;
+use async_trait::async_trait;
+
+/// A [`Mailer`] that only writes the emails to the logs, never delivering them.
+#[derive(Debug, Default, Clone)]
+pub struct LogMailer;
+
+impl LogMailer {
+    #[inline]
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, to: String, subject: String, body: String) -> Result<(), ApiError> {
+        tracing::info!(to, subject, body, "Mail sent");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+#[cfg(any(test, not(feature = "smtp")))]
+pub mod log_repository;
+pub mod repository;
+#[cfg(feature = "smtp")]
+pub mod smtp_repository;
@@ -0,0 +1,7 @@
+use crate::errors::ApiError;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait Mailer: Sync + Send {
+    async fn send(&self, to: String, subject: String, body: String) -> Result<(), ApiError>;
+}
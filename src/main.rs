@@ -1,22 +1,30 @@
 use crate::{
-    auth::handlers::AuthHandlers,
-    channel::handlers::ChannelHandlers,
-    gateway::handlers::ws_upgrader,
-    http::AppData,
-    message::handlers::MessageHandlers,
-    setup::{env_param, JsonPanicHandler},
+    app::{AppBuilder, AppOptions, AppRepositories},
+    auth::{
+        jwt_repository::{DEFAULT_INVALIDATION_SKEW, DEFAULT_MAX_REFRESH_TOKEN_LEN},
+        password::{init_dummy_hash, set_hash_concurrency, DEFAULT_HASH_MAX_QUEUE},
+        totp::TotpManager,
+    },
+    gateway::{handlers::GatewayLimits, sse::EventStreamOptions, tail::EventsTailLimits},
+    http::set_json_pretty,
+    message::{expiry::run_expiry_job, models::MESSAGE_EXPIRY_SWEEP_INTERVAL},
+    setup::{
+        bootstrap_admin, env_param, setup_attachment_limits, setup_content_filter,
+        setup_jwt_duration, setup_logging, setup_mailer, setup_password_policy,
+        setup_security_headers, setup_totp_key, setup_trusted_proxies, setup_unversioned_sunset,
+    },
 };
-use axum::{routing, Extension, Router};
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
-use std::{error::Error, net::SocketAddr};
+use std::{error::Error, net::SocketAddr, time::Duration};
 use tokio::net::TcpListener;
-use tower_http::{catch_panic::CatchPanicLayer, normalize_path::NormalizePathLayer};
-use tracing_subscriber::EnvFilter;
 
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
+mod app;
+mod attachment;
+mod audit;
 mod auth;
 mod cache;
 mod channel;
@@ -25,8 +33,12 @@ mod event;
 mod gateway;
 mod handlers;
 mod http;
+mod mail;
 mod message;
 mod setup;
+mod stats;
+#[cfg(feature = "otel")]
+mod telemetry;
 mod user;
 
 #[cfg(feature = "postgres")]
@@ -49,113 +61,105 @@ pub type CacheRepo = crate::cache::memory_repository::InMemoryCacheRepository;
 pub type EventRepo = crate::event::redis_repository::RedisEventRepository;
 #[cfg(not(feature = "redis"))]
 pub type EventRepo = crate::event::memory_repository::InMemoryEventRepository;
+#[cfg(feature = "postgres")]
+pub type AuditRepo = crate::audit::postgres_repository::PostgresAuditRepository;
+#[cfg(not(feature = "postgres"))]
+pub type AuditRepo = crate::audit::memory_repository::InMemoryAuditRepository;
+pub type StorageRepo = crate::attachment::memory_repository::InMemoryStorageRepository;
 pub type AuthRepo = crate::auth::jwt_repository::JwtAuthRepository<CacheRepo>;
+#[cfg(feature = "smtp")]
+pub type MailRepo = crate::mail::smtp_repository::SmtpMailer;
+#[cfg(not(feature = "smtp"))]
+pub type MailRepo = crate::mail::log_repository::LogMailer;
 
 pub type BoxedError = Box<dyn Error + Send + Sync>;
 
 pub const ENCODING_FAILED_BODY: &[u8] =
-    br#"{"message":"Failed to encode the response body","error_code":50000}"#;
+    br#"{"message":"Failed to encode the response body","error_code":50008}"#;
 
 async fn body() -> Result<(), BoxedError> {
     #[cfg(feature = "dotenv")]
     dotenvy::dotenv().map_err(|_| crate::setup::VarError::DotenvFileNotFound)?;
 
-    #[cfg(feature = "json-log")]
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .json()
-        .try_init()?;
-
-    #[cfg(not(feature = "json-log"))]
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .try_init()?;
+    setup_logging()?;
 
     let port = env_param("APP_PORT").unwrap_or(8080_u16);
-
-    let mut app = Router::new();
-
-    app = app
-        .route(
-            "/gateway",
-            routing::get(ws_upgrader::<EventRepo, AuthRepo, ChannelRepo>),
-        )
-        .route(
-            "/auth/signin",
-            routing::post(handlers::post_auth_signin::<AuthRepo, UserRepo, EventRepo>),
-        )
-        .route(
-            "/auth/signup",
-            routing::post(handlers::post_auth_signup::<AuthRepo, UserRepo, EventRepo>),
-        )
-        .route(
-            "/auth/self",
-            routing::get(handlers::get_auth_self::<AuthRepo, UserRepo, EventRepo>),
-        )
-        .route(
-            "/auth/self/invalidate",
-            routing::post(handlers::post_auth_self_invalidate::<AuthRepo, UserRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id",
-            routing::get(handlers::get_channel_id::<ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channels/self",
-            routing::get(handlers::get_channels_self::<ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel",
-            routing::post(handlers::post_channel::<ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id/permission",
-            routing::put(handlers::put_channel_id_permission::<ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id",
-            routing::put(handlers::put_channel_id::<ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id",
-            routing::patch(handlers::put_channel_id::<ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id",
-            routing::delete(handlers::delete_channel_id::<ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id/message/:message_id",
-            routing::get(handlers::get_channel_id_message_id::<MessageRepo, ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id/messages",
-            routing::get(handlers::get_channel_id_messages::<MessageRepo, ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id/message",
-            routing::post(handlers::post_channel_id_message::<MessageRepo, ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id/message/:message_id",
-            routing::put(handlers::put_channel_id_message_id::<MessageRepo, ChannelRepo, AuthRepo, EventRepo>),
-        )
-        .route(
-            "/channel/:channel_id/message/:message_id",
-            routing::patch(
-                handlers::put_channel_id_message_id::<MessageRepo, ChannelRepo, AuthRepo, EventRepo>,
+    set_json_pretty(env_param("APP_JSON_PRETTY").unwrap_or(false));
+    set_hash_concurrency(
+        env_param("APP_BCRYPT_CONCURRENCY")
+            .unwrap_or_else(|_| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        env_param("APP_BCRYPT_MAX_QUEUE").unwrap_or(DEFAULT_HASH_MAX_QUEUE),
+    );
+
+    let defaults = AppOptions::default();
+    let message_edit_window = env_param("APP_MESSAGE_EDIT_WINDOW_SECS").unwrap_or(0_u64);
+    let options = AppOptions {
+        base_path: env_param("APP_BASE_PATH").unwrap_or(defaults.base_path),
+        unversioned_routes: env_param("APP_UNVERSIONED_ROUTES")
+            .unwrap_or(defaults.unversioned_routes),
+        unversioned_sunset: setup_unversioned_sunset()?,
+        request_timeout: Duration::from_secs(env_param("APP_REQUEST_TIMEOUT").unwrap_or(30_u64)),
+        max_concurrent_requests: env_param("APP_MAX_CONCURRENT_REQUESTS")
+            .unwrap_or(defaults.max_concurrent_requests),
+        http_compression: env_param("APP_HTTP_COMPRESSION").unwrap_or(defaults.http_compression),
+        trusted_proxies: setup_trusted_proxies()?,
+        security_headers: setup_security_headers()?,
+        max_conns_per_ip: env_param("APP_MAX_CONNS_PER_IP").unwrap_or(defaults.max_conns_per_ip),
+        max_conns: env_param("APP_MAX_CONNS").unwrap_or(defaults.max_conns),
+        gateway_limits: GatewayLimits {
+            max_message_size: env_param("APP_GATEWAY_MAX_FRAME")
+                .unwrap_or(defaults.gateway_limits.max_message_size),
+            max_errors: env_param("APP_GATEWAY_MAX_ERRORS")
+                .unwrap_or(defaults.gateway_limits.max_errors),
+            error_window: Duration::from_secs(
+                env_param("APP_GATEWAY_ERROR_WINDOW")
+                    .unwrap_or(defaults.gateway_limits.error_window.as_secs()),
             ),
-        )
-        .route(
-            "/channel/:channel_id/message/:message_id",
-            routing::delete(
-                handlers::delete_channel_id_message_id::<MessageRepo, ChannelRepo, AuthRepo, EventRepo>,
+            max_channels: env_param("APP_GATEWAY_MAX_CHANNELS")
+                .unwrap_or(defaults.gateway_limits.max_channels),
+        },
+        events_tail_limits: EventsTailLimits {
+            max_duration: Duration::from_secs(
+                env_param("APP_EVENTS_TAIL_MAX_DURATION")
+                    .unwrap_or(defaults.events_tail_limits.max_duration.as_secs()),
             ),
-        );
+            max_rate: env_param("APP_EVENTS_TAIL_MAX_RATE")
+                .unwrap_or(defaults.events_tail_limits.max_rate),
+        },
+        event_stream_options: EventStreamOptions {
+            keep_alive: Duration::from_secs(
+                env_param("APP_EVENTS_KEEP_ALIVE")
+                    .unwrap_or(defaults.event_stream_options.keep_alive.as_secs()),
+            ),
+        },
+        require_email_verification: env_param("APP_REQUIRE_EMAIL_VERIFICATION")
+            .unwrap_or(defaults.require_email_verification),
+        bootstrap_admin_email: env_param("APP_BOOTSTRAP_ADMIN_EMAIL").ok(),
+        password_policy: setup_password_policy(),
+        attachment_limits: setup_attachment_limits()?,
+        thumbnail_size: env_param("APP_ATTACHMENT_THUMBNAIL_SIZE")
+            .unwrap_or(defaults.thumbnail_size),
+        message_max_len: env_param("APP_MESSAGE_MAX_LEN").unwrap_or(defaults.message_max_len),
+        message_edit_window: (message_edit_window > 0)
+            .then(|| Duration::from_secs(message_edit_window)),
+        message_edit_window_bypass: env_param("APP_MESSAGE_EDIT_WINDOW_BYPASS")
+            .unwrap_or(defaults.message_edit_window_bypass),
+        message_max_ttl: env_param("APP_MESSAGE_MAX_TTL")
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.message_max_ttl),
+        message_verbose_channel_mismatch: env_param("APP_MESSAGE_VERBOSE_CHANNEL_MISMATCH")
+            .unwrap_or(defaults.message_verbose_channel_mismatch),
+        channel_max_invites: env_param("APP_CHANNEL_MAX_INVITES")
+            .unwrap_or(defaults.channel_max_invites),
+        channel_system_messages: env_param("APP_CHANNEL_SYSTEM_MESSAGES")
+            .unwrap_or(defaults.channel_system_messages),
+    };
 
     #[cfg(feature = "postgres-redis-repository")]
-    {
+    let app = {
+        use crate::setup::{retry_startup, run_migrations};
         use crate::{
+            audit::postgres_repository::PostgresAuditRepository,
             auth::jwt_repository::JwtAuthRepository, cache::redis_repository::RedisCacheRepository,
             event::redis_repository::RedisEventRepository,
             user::postgres_repository::PostgresUserRepository,
@@ -164,22 +168,44 @@ async fn body() -> Result<(), BoxedError> {
         use sqlx::postgres::PgPoolOptions;
         use std::time::{Duration, Instant};
 
-        let jwt_token_duration = env_param("APP_JWT_DURATION").unwrap_or(3600_u64);
+        let jwt_token_duration = setup_jwt_duration()?;
         let jwt_key = env_param::<String>("APP_JWT_KEY")?;
+        let jwt_leeway = env_param("APP_JWT_LEEWAY").unwrap_or(60_u64);
+        let jwt_validate_exp = env_param("APP_JWT_VALIDATE_EXP").unwrap_or(true);
+        let jwt_issuer = env_param::<String>("APP_JWT_ISSUER").ok();
+        let jwt_audience = env_param::<String>("APP_JWT_AUDIENCE").ok();
         let bcrypt_cost = env_param("APP_BCRYPT_COST").unwrap_or(bcrypt::DEFAULT_COST);
+        init_dummy_hash(bcrypt_cost).await?;
+        let login_max_attempts = env_param("APP_LOGIN_MAX_ATTEMPTS").unwrap_or(5_u32);
+        let login_lockout_window = env_param("APP_LOGIN_LOCKOUT_WINDOW").unwrap_or(900_u64);
+        let totp_key = setup_totp_key(&jwt_key)?;
+        let totp_issuer = env_param("APP_TOTP_ISSUER").unwrap_or_else(|_| "messaging-app".into());
+        let email_verification_ttl = env_param("APP_EMAIL_VERIFICATION_TTL").unwrap_or(86400_u64);
+        let password_reset_ttl = env_param("APP_PASSWORD_RESET_TTL").unwrap_or(900_u64);
+        let max_session_idle = env_param("APP_MAX_SESSION_IDLE_SECS").unwrap_or(0_u64);
+        let invalidation_skew =
+            env_param("APP_JWT_INVALIDATION_SKEW").unwrap_or(DEFAULT_INVALIDATION_SKEW);
+        let max_refresh_token_len =
+            env_param("APP_MAX_REFRESH_TOKEN_LEN").unwrap_or(DEFAULT_MAX_REFRESH_TOKEN_LEN);
         let database_url = env_param::<String>("DATABASE_URL")?;
+        let database_read_url = env_param::<String>("DATABASE_READ_URL").ok();
         let max_open_conns = env_param("DATABASE_MAX_CONNS").unwrap_or(12_u32);
         let min_open_conns = env_param("DATABASE_MIN_CONNS").unwrap_or(5_u32);
         let db_acquire_timeout = env_param("DATABASE_ACQUIRE_TIMEOUT").unwrap_or(8_u64);
         let redis_url = env_param::<String>("REDIS_URL")?;
+        let startup_retries = env_param("APP_STARTUP_RETRIES").unwrap_or(5_u32);
+        let startup_backoff =
+            Duration::from_millis(env_param("APP_STARTUP_BACKOFF").unwrap_or(500_u64));
 
         let redis_start = Instant::now();
 
         let redis_pool = Config::from_url(redis_url).create_pool(Some(Runtime::Tokio1))?;
-        {
+        retry_startup("redis", startup_retries, startup_backoff, || async {
             let mut conn = redis_pool.get().await?;
             cmd("PING").query_async::<_, ()>(&mut conn).await?;
-        }
+            Ok::<_, BoxedError>(())
+        })
+        .await?;
 
         tracing::info!(
             took = format!("{}ms", (Instant::now() - redis_start).as_millis()),
@@ -188,7 +214,7 @@ async fn body() -> Result<(), BoxedError> {
 
         let pg_start = Instant::now();
 
-        let pool = PgPoolOptions::new()
+        let pool_options = PgPoolOptions::new()
             .after_connect(|conn, meta| {
                 Box::pin(async move {
                     let version = conn.server_version_num();
@@ -203,101 +229,218 @@ async fn body() -> Result<(), BoxedError> {
             })
             .max_connections(max_open_conns)
             .min_connections(min_open_conns)
-            .acquire_timeout(Duration::from_secs(db_acquire_timeout))
-            .connect(&database_url)
-            .await?;
+            .acquire_timeout(Duration::from_secs(db_acquire_timeout));
+
+        let pool = retry_startup("postgres", startup_retries, startup_backoff, || async {
+            Ok::<_, BoxedError>(pool_options.clone().connect(&database_url).await?)
+        })
+        .await?;
 
         tracing::info!(
             took = format!("{}ms", (Instant::now() - pg_start).as_millis()),
             "Connected to postgres"
         );
 
-        let user_repo = PostgresUserRepository::new(pool, bcrypt_cost);
+        if env_param("APP_SKIP_MIGRATIONS").unwrap_or(false) {
+            tracing::info!("Skipping database migrations");
+        } else {
+            run_migrations(&pool).await?;
+        }
+
+        let read_pool = match database_read_url {
+            Some(url) => {
+                let pool = retry_startup(
+                    "postgres replica",
+                    startup_retries,
+                    startup_backoff,
+                    || async { Ok::<_, BoxedError>(pool_options.clone().connect(&url).await?) },
+                )
+                .await?;
+                tracing::info!("Connected to postgres replica");
+                pool
+            }
+            None => pool.clone(),
+        };
+
+        let user_repo = PostgresUserRepository::new(pool.clone(), bcrypt_cost)
+            .with_read_pool(read_pool.clone());
+        if let Some(email) = &options.bootstrap_admin_email {
+            bootstrap_admin(&user_repo, email).await?;
+        }
+        let audit_repo = PostgresAuditRepository::new(pool).with_read_pool(read_pool);
         let cache_repo = RedisCacheRepository::new(redis_pool.clone());
-        let auth_repo = JwtAuthRepository::new(
+        let mut auth_repo = JwtAuthRepository::new(
             Algorithm::HS512,
             EncodingKey::from_base64_secret(&jwt_key)?,
             DecodingKey::from_base64_secret(&jwt_key)?,
             jwt_token_duration,
             cache_repo,
-        );
+        )
+        .with_leeway(jwt_leeway)
+        .with_validate_exp(jwt_validate_exp)
+        .with_login_lockout(login_max_attempts, login_lockout_window)
+        .with_email_verification_ttl(email_verification_ttl)
+        .with_password_reset_ttl(password_reset_ttl)
+        .with_max_session_idle(max_session_idle)
+        .with_invalidation_skew(invalidation_skew)
+        .with_max_refresh_token_len(max_refresh_token_len);
+        if let Some(issuer) = jwt_issuer {
+            auth_repo = auth_repo.with_issuer(issuer);
+        }
+        if let Some(audience) = jwt_audience {
+            auth_repo = auth_repo.with_audience(audience);
+        }
         let message_repo = MessageRepo::new();
         let channel_repo = ChannelRepo::new();
+        let storage_repo = StorageRepo::new();
         let event_repo = RedisEventRepository::new(
             Connection::take(redis_pool.get().await?).into_pubsub(),
-            redis_pool.get().await?,
+            redis_pool.clone(),
         )
         .await?;
 
-        let auth_handlers = AuthHandlers::new(auth_repo.clone(), user_repo, event_repo.clone());
-        let message_handlers =
-            MessageHandlers::new(message_repo, channel_repo.clone(), event_repo.clone());
-        let channel_handlers = ChannelHandlers::new(channel_repo.clone(), event_repo.clone());
-
-        app = app
-            .layer(AppData::extension(auth_handlers))
-            .layer(AppData::extension(message_handlers))
-            .layer(AppData::extension(channel_handlers))
-            .layer(AppData::extension(event_repo))
-            .layer(AppData::extension(channel_repo))
-            .layer(Extension(auth_repo));
-    }
+        tokio::spawn(run_expiry_job(
+            message_repo.clone(),
+            event_repo.clone(),
+            MESSAGE_EXPIRY_SWEEP_INTERVAL,
+        ));
+
+        let totp = TotpManager::new(totp_key.as_bytes(), totp_issuer);
+
+        let mailer = setup_mailer()?;
+
+        AppBuilder::new(AppRepositories {
+            auth_repo,
+            user_repo,
+            channel_repo,
+            message_repo,
+            event_repo,
+            audit_repo,
+            storage_repo,
+            mailer,
+            totp,
+        })
+        .with_options(options)
+        .with_content_filter(setup_content_filter()?)
+        .build()
+    };
 
     #[cfg(not(feature = "postgres-redis-repository"))]
-    {
+    let app = {
         use crate::{
-            auth::jwt_repository::JwtAuthRepository,
-            cache::memory_repository::InMemoryCacheRepository,
+            attachment::memory_repository::InMemoryStorageRepository,
+            audit::memory_repository::{InMemoryAuditRepository, DEFAULT_MAX_AUDIT_LOGS},
+            auth::jwt_repository::{JwtAuthRepository, EVICTABLE_CACHE_PREFIXES},
+            cache::memory_repository::{InMemoryCacheRepository, DEFAULT_SWEEP_INTERVAL},
             channel::memory_repository::InMemoryChannelRepository,
             event::memory_repository::InMemoryEventRepository,
             message::memory_repository::InMemoryMessageRepository,
             user::memory_repository::InMemoryUserRepository,
         };
 
-        let jwt_token_duration = env_param("APP_JWT_DURATION").unwrap_or(3600_u64);
+        let jwt_token_duration = setup_jwt_duration()?;
         let jwt_key = env_param::<String>("APP_JWT_KEY")?;
+        let jwt_leeway = env_param("APP_JWT_LEEWAY").unwrap_or(60_u64);
+        let jwt_validate_exp = env_param("APP_JWT_VALIDATE_EXP").unwrap_or(true);
+        let jwt_issuer = env_param::<String>("APP_JWT_ISSUER").ok();
+        let jwt_audience = env_param::<String>("APP_JWT_AUDIENCE").ok();
         let bcrypt_cost = env_param("APP_BCRYPT_COST").unwrap_or(bcrypt::DEFAULT_COST);
+        init_dummy_hash(bcrypt_cost).await?;
+        let login_max_attempts = env_param("APP_LOGIN_MAX_ATTEMPTS").unwrap_or(5_u32);
+        let login_lockout_window = env_param("APP_LOGIN_LOCKOUT_WINDOW").unwrap_or(900_u64);
+        let totp_key = setup_totp_key(&jwt_key)?;
+        let totp_issuer = env_param("APP_TOTP_ISSUER").unwrap_or_else(|_| "messaging-app".into());
+        let email_verification_ttl = env_param("APP_EMAIL_VERIFICATION_TTL").unwrap_or(86400_u64);
+        let password_reset_ttl = env_param("APP_PASSWORD_RESET_TTL").unwrap_or(900_u64);
+        let max_session_idle = env_param("APP_MAX_SESSION_IDLE_SECS").unwrap_or(0_u64);
+        let invalidation_skew =
+            env_param("APP_JWT_INVALIDATION_SKEW").unwrap_or(DEFAULT_INVALIDATION_SKEW);
+        let max_refresh_token_len =
+            env_param("APP_MAX_REFRESH_TOKEN_LEN").unwrap_or(DEFAULT_MAX_REFRESH_TOKEN_LEN);
 
         let user_repo = InMemoryUserRepository::new(bcrypt_cost);
-        let cache_repo = InMemoryCacheRepository::new();
-        let auth_repo = JwtAuthRepository::new(
+        if let Some(email) = &options.bootstrap_admin_email {
+            bootstrap_admin(&user_repo, email).await?;
+        }
+        let audit_max_entries =
+            env_param("APP_AUDIT_MAX_ENTRIES").unwrap_or(DEFAULT_MAX_AUDIT_LOGS);
+        let audit_repo = InMemoryAuditRepository::new().with_max_entries(audit_max_entries);
+        let cache_sweep_interval = env_param("APP_CACHE_SWEEP_INTERVAL")
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SWEEP_INTERVAL);
+        // Past the cap only the expired and the evictable keys make room for
+        // new ones, the rest of the new keys are refused. Each signin attempt
+        // with a new email takes a key, so a low cap lets anyone block the
+        // signins until the lockout window passes.
+        let cache_max_entries = env_param("APP_CACHE_MAX_ENTRIES").unwrap_or(0_usize);
+
+        let mut cache_repo = InMemoryCacheRepository::with_sweep_interval(cache_sweep_interval);
+        if cache_max_entries > 0 {
+            cache_repo = cache_repo
+                .with_max_entries(cache_max_entries)
+                .with_evictable_prefixes(EVICTABLE_CACHE_PREFIXES);
+        }
+        let mut auth_repo = JwtAuthRepository::new(
             Algorithm::HS512,
             EncodingKey::from_base64_secret(&jwt_key)?,
             DecodingKey::from_base64_secret(&jwt_key)?,
             jwt_token_duration,
             cache_repo,
-        );
+        )
+        .with_leeway(jwt_leeway)
+        .with_validate_exp(jwt_validate_exp)
+        .with_login_lockout(login_max_attempts, login_lockout_window)
+        .with_email_verification_ttl(email_verification_ttl)
+        .with_password_reset_ttl(password_reset_ttl)
+        .with_max_session_idle(max_session_idle)
+        .with_invalidation_skew(invalidation_skew)
+        .with_max_refresh_token_len(max_refresh_token_len);
+        if let Some(issuer) = jwt_issuer {
+            auth_repo = auth_repo.with_issuer(issuer);
+        }
+        if let Some(audience) = jwt_audience {
+            auth_repo = auth_repo.with_audience(audience);
+        }
         let message_repo = InMemoryMessageRepository::new();
         let channel_repo = InMemoryChannelRepository::new();
+        let storage_repo = InMemoryStorageRepository::new();
         let event_repo = InMemoryEventRepository::new();
 
-        let auth_handlers = AuthHandlers::new(auth_repo.clone(), user_repo, event_repo.clone());
-        let message_handlers =
-            MessageHandlers::new(message_repo, channel_repo.clone(), event_repo.clone());
-        let channel_handlers = ChannelHandlers::new(channel_repo.clone(), event_repo.clone());
-
-        app = app
-            .layer(AppData::extension(auth_handlers))
-            .layer(AppData::extension(message_handlers))
-            .layer(AppData::extension(channel_handlers))
-            .layer(AppData::extension(event_repo))
-            .layer(AppData::extension(channel_repo))
-            .layer(Extension(auth_repo));
-    }
-
-    app = app
-        .layer(NormalizePathLayer::trim_trailing_slash())
-        .layer(CatchPanicLayer::custom(JsonPanicHandler));
-
-    #[cfg(feature = "http-trace")]
-    {
-        app = app.layer(tower_http::trace::TraceLayer::new_for_http());
-    }
+        tokio::spawn(run_expiry_job(
+            message_repo.clone(),
+            event_repo.clone(),
+            MESSAGE_EXPIRY_SWEEP_INTERVAL,
+        ));
+
+        let totp = TotpManager::new(totp_key.as_bytes(), totp_issuer);
+
+        let mailer = setup_mailer()?;
+
+        AppBuilder::new(AppRepositories {
+            auth_repo,
+            user_repo,
+            channel_repo,
+            message_repo,
+            event_repo,
+            audit_repo,
+            storage_repo,
+            mailer,
+            totp,
+        })
+        .with_options(options)
+        .with_content_filter(setup_content_filter()?)
+        .build()
+    };
+
+    #[cfg(all(feature = "http-trace", not(feature = "otel")))]
+    let app = app.layer(tower_http::trace::TraceLayer::new_for_http());
+    #[cfg(feature = "otel")]
+    let app = app.layer(
+        tower_http::trace::TraceLayer::new_for_http()
+            .make_span_with(crate::telemetry::make_http_span::<axum::body::Body>),
+    );
     #[cfg(feature = "http-cors")]
-    {
-        use crate::setup::setup_app_cors;
-        app = setup_app_cors(app);
-    }
+    let app = crate::setup::setup_app_cors(app);
 
     let listener = TcpListener::bind(&SocketAddr::from(([0, 0, 0, 0], port))).await?;
 
@@ -309,6 +452,9 @@ async fn body() -> Result<(), BoxedError> {
     )
     .await?;
 
+    #[cfg(feature = "otel")]
+    crate::telemetry::shutdown();
+
     Ok(())
 }
 
@@ -0,0 +1,38 @@
+use super::repository::MessageRepository;
+use crate::event::{models::AppEvent, repository::EventRepository};
+use std::time::Duration;
+
+/// Deletes the expired messages every `interval`, publishing a
+/// [`AppEvent::MessageDeleted`] for each one so the clients drop them too.
+/// The failures are logged and retried on the next run.
+pub async fn run_expiry_job<M, E>(message_repo: M, event_repo: E, interval: Duration)
+where
+    M: MessageRepository,
+    E: EventRepository,
+{
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let expired = match message_repo.delete_expired().await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!(error = e.to_string(), "Failed to delete expired messages");
+                continue;
+            }
+        };
+
+        for msg in expired {
+            let event = AppEvent::MessageDeleted {
+                id: msg.id,
+                channel_id: msg.channel_id,
+            };
+            if let Err(e) = event_repo.publish(event).await {
+                tracing::error!(
+                    error = e.to_string(),
+                    message_id = msg.id.to_string(),
+                    "Failed to publish expired message deletion"
+                );
+            }
+        }
+    }
+}
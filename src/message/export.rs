@@ -0,0 +1,210 @@
+use super::{models::Message, repository::MessageRepository};
+use axum::body::Bytes;
+use serde::Deserialize;
+use std::io;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+/// The amount of messages fetched from the repository at a time
+const EXPORT_PAGE_SIZE: u64 = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    #[inline]
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    #[inline]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+        }
+    }
+
+    fn header(self) -> &'static [u8] {
+        match self {
+            Self::Json => b"[",
+            Self::Csv => b"id,user_id,created_at,content\r\n",
+        }
+    }
+
+    fn footer(self) -> &'static [u8] {
+        match self {
+            Self::Json => b"]",
+            Self::Csv => b"",
+        }
+    }
+
+    fn encode(self, msg: &Message, first: bool) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Json => {
+                let mut buf = if first { Vec::new() } else { vec![b','] };
+                serde_json::to_writer(&mut buf, msg)?;
+                Ok(buf)
+            }
+            Self::Csv => Ok(format!(
+                "{},{},{},{}\r\n",
+                msg.id,
+                msg.user_id,
+                msg.created_at.to_rfc3339(),
+                csv_field(msg.content.as_deref().unwrap_or_default()),
+            )
+            .into_bytes()),
+        }
+    }
+}
+
+/// Quotes the field if it contains any character that is special in csv.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Streams all the messages of the channel in chronological order, fetching
+/// them one page at a time so the channel is never loaded in memory at once.
+pub fn export_stream<M>(
+    message_repo: M,
+    channel_id: Uuid,
+    format: ExportFormat,
+) -> ReceiverStream<io::Result<Bytes>>
+where
+    M: MessageRepository + 'static,
+{
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        if tx
+            .send(Ok(Bytes::from_static(format.header())))
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        let mut offset = 0;
+        loop {
+            let msgs = match message_repo
+                .get_many(channel_id, offset, EXPORT_PAGE_SIZE)
+                .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!(error = e.to_string(), "Failed to fetch exported messages");
+                    // Aborts the body, so the client does not take a
+                    // truncated export as a complete one
+                    let _ = tx.send(Err(io::Error::other(e.to_string()))).await;
+                    return;
+                }
+            };
+
+            let mut buf = Vec::new();
+            for msg in &msgs {
+                match format.encode(msg, offset == 0 && buf.is_empty()) {
+                    Ok(v) => buf.extend(v),
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                }
+            }
+            if !buf.is_empty() && tx.send(Ok(buf.into())).await.is_err() {
+                return;
+            }
+
+            if (msgs.len() as u64) < EXPORT_PAGE_SIZE {
+                break;
+            }
+            offset += EXPORT_PAGE_SIZE;
+        }
+
+        let _ = tx.send(Ok(Bytes::from_static(format.footer()))).await;
+    });
+
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_field, export_stream, ExportFormat, EXPORT_PAGE_SIZE};
+    use crate::message::{
+        memory_repository::InMemoryMessageRepository,
+        models::{MessageCreateData, MessageKind},
+        repository::MessageRepository,
+    };
+    use tokio_stream::StreamExt;
+    use uuid::Uuid;
+
+    async fn collect(
+        repo: InMemoryMessageRepository,
+        channel_id: Uuid,
+        format: ExportFormat,
+    ) -> String {
+        let mut stream = export_stream(repo, channel_id, format);
+        let mut buf = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            buf.extend(chunk.unwrap());
+        }
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("hello"), "hello");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\"\n"), "\"say \"\"hi\"\"\n\"");
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let repo = InMemoryMessageRepository::new();
+        let channel_id = Uuid::new_v4();
+
+        let count = EXPORT_PAGE_SIZE + 3;
+        for i in 0..count {
+            repo.create(
+                Uuid::new_v4(),
+                channel_id,
+                MessageCreateData {
+                    content: Some(format!("Message, {i}")),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let json = collect(repo.clone(), channel_id, ExportFormat::Json).await;
+        let msgs: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(msgs.len() as u64, count);
+        assert_eq!(msgs[0]["content"], "Message, 0");
+        assert_eq!(msgs[count as usize - 1]["seq"], count);
+
+        let csv = collect(repo.clone(), channel_id, ExportFormat::Csv).await;
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len() as u64, count + 1);
+        assert_eq!(lines[0], "id,user_id,created_at,content");
+        assert!(lines[1].ends_with(",\"Message, 0\""));
+
+        let empty = collect(repo, Uuid::new_v4(), ExportFormat::Json).await;
+        assert_eq!(empty, "[]");
+    }
+}
@@ -0,0 +1,160 @@
+//! Automated moderation of the message contents.
+//!
+//! The [`MessageHandlers`](super::handlers::MessageHandlers) run the content
+//! of the created and updated messages through a [`ContentFilter`], which
+//! either lets it through, lets it through but flags the message, or rejects
+//! it. No content is filtered by default, see [`NoopContentFilter`].
+
+#[cfg(any(test, feature = "wordlist-filter"))]
+use std::collections::HashMap;
+
+/// The outcome of a [`ContentFilter`].
+#[cfg_attr(not(feature = "wordlist-filter"), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterResult {
+    Allow,
+    /// Allows the message, but flags it for the moderators, with the category
+    /// of the matched content
+    Warn(String),
+    /// Rejects the message with the category of the matched content
+    Block(String),
+}
+
+pub trait ContentFilter: Send + Sync {
+    fn check(&self, content: &str) -> FilterResult;
+}
+
+/// Allows any content.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopContentFilter;
+
+impl ContentFilter for NoopContentFilter {
+    #[inline]
+    fn check(&self, _content: &str) -> FilterResult {
+        FilterResult::Allow
+    }
+}
+
+#[cfg(any(test, feature = "wordlist-filter"))]
+#[derive(Debug, thiserror::Error)]
+pub enum WordlistError {
+    #[error("failed to read the wordlist: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid wordlist entry on line {0}")]
+    InvalidEntry(usize),
+}
+
+#[cfg(any(test, feature = "wordlist-filter"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WordlistAction {
+    Warn,
+    Block,
+}
+
+/// Matches the words of the content against a list of words, compared
+/// case-insensitively.
+///
+/// Each line of the list is an `<action> <category> <word>` entry, where the
+/// action is either `warn` or `block`. Empty lines and lines starting with `#`
+/// are ignored. A blocked word takes precedence over the warned ones.
+#[cfg(any(test, feature = "wordlist-filter"))]
+#[derive(Debug, Clone, Default)]
+pub struct WordlistContentFilter {
+    words: HashMap<String, (WordlistAction, String)>,
+}
+
+#[cfg(any(test, feature = "wordlist-filter"))]
+impl WordlistContentFilter {
+    pub fn parse(list: &str) -> Result<Self, WordlistError> {
+        let mut words = HashMap::new();
+
+        for (i, line) in list.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let (Some(action), Some(category), Some(word), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(WordlistError::InvalidEntry(i + 1));
+            };
+            let action = match action {
+                "warn" => WordlistAction::Warn,
+                "block" => WordlistAction::Block,
+                _ => return Err(WordlistError::InvalidEntry(i + 1)),
+            };
+
+            words.insert(word.to_lowercase(), (action, category.to_owned()));
+        }
+
+        Ok(Self { words })
+    }
+
+    #[cfg(feature = "wordlist-filter")]
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self, WordlistError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(any(test, feature = "wordlist-filter"))]
+impl ContentFilter for WordlistContentFilter {
+    fn check(&self, content: &str) -> FilterResult {
+        let mut warn = None;
+
+        for word in content.split(|c: char| !c.is_alphanumeric()) {
+            if word.is_empty() {
+                continue;
+            }
+
+            match self.words.get(&word.to_lowercase()) {
+                Some((WordlistAction::Block, category)) => {
+                    return FilterResult::Block(category.clone())
+                }
+                Some((WordlistAction::Warn, category)) => {
+                    warn.get_or_insert_with(|| category.clone());
+                }
+                None => {}
+            }
+        }
+
+        match warn {
+            Some(category) => FilterResult::Warn(category),
+            None => FilterResult::Allow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContentFilter, FilterResult, WordlistContentFilter, WordlistError};
+
+    #[test]
+    fn test_wordlist() {
+        let filter =
+            WordlistContentFilter::parse("# comment\n\nwarn rudeness dumb\nblock spam FreeCoins\n")
+                .unwrap();
+
+        assert_eq!(filter.check("Hello there"), FilterResult::Allow);
+        // Only whole words match
+        assert_eq!(filter.check("dumbbell"), FilterResult::Allow);
+        assert_eq!(
+            filter.check("That is DUMB!"),
+            FilterResult::Warn("rudeness".into())
+        );
+        assert_eq!(
+            filter.check("dumb, get your freecoins"),
+            FilterResult::Block("spam".into())
+        );
+
+        assert!(matches!(
+            WordlistContentFilter::parse("warn rudeness\n"),
+            Err(WordlistError::InvalidEntry(1))
+        ));
+        assert!(matches!(
+            WordlistContentFilter::parse("\nallow spam word\n"),
+            Err(WordlistError::InvalidEntry(2))
+        ));
+    }
+}
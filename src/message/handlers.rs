@@ -1,16 +1,37 @@
 use super::{
-    models::{Message, MessageCreateData, MessageUpdateData},
+    export::{export_stream, ExportFormat},
+    filter::{ContentFilter, FilterResult, NoopContentFilter},
+    models::{
+        find_mentions, Message, MessageCreateData, MessageExpand, MessageImportData,
+        MessageImportResponseBody, MessageKind, MessageUpdateData, MessageView,
+        ReadAllResponseBody, ReadMarker, TypingResponseBody, MESSAGE_CONTENT_MAX_LEN,
+        MESSAGE_IMPORT_MAX_BATCH, MESSAGE_MAX_TTL, SYSTEM_IMPORT_AUTHOR,
+    },
     repository::MessageRepository,
 };
 use crate::{
+    audit::{
+        models::{AuditAction, AuditLogCreateData},
+        repository::AuditRepository,
+    },
     auth::models::UserAuthPayload,
     channel::repository::ChannelRepository,
     errors::ApiError,
     event::{models::AppEvent, repository::EventRepository},
-    http::DataResponse,
+    http::{DataResponse, ListResponse, NoContent},
+    user::{models::PublicUser, repository::UserRepository},
 };
-use axum::http::StatusCode;
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 use uuid::Uuid;
 
 #[inline(always)]
@@ -22,6 +43,13 @@ fn default_offset() -> u64 {
     0
 }
 
+/// The amount of channels fetched at once when marking all of them as read.
+const READ_ALL_PAGE_SIZE: u64 = 1000;
+
+/// The most distinct usernames looked up for a message, the mentions past it
+/// are left as written.
+const MAX_RESOLVED_MENTIONS: usize = 20;
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct GetManyQueryParams {
@@ -29,6 +57,32 @@ pub struct GetManyQueryParams {
     pub limit: u64,
     #[serde(default = "default_offset")]
     pub offset: u64,
+    #[serde(default)]
+    pub expand: Option<MessageExpand>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetAroundQueryParams {
+    pub message_id: Uuid,
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+    #[serde(default)]
+    pub expand: Option<MessageExpand>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpandQueryParams {
+    #[serde(default)]
+    pub expand: Option<MessageExpand>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportQueryParams {
+    #[serde(default)]
+    pub format: ExportFormat,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -44,28 +98,191 @@ pub struct ChannelIdPathParams {
     pub channel_id: Uuid,
 }
 
-pub struct MessageHandlers<M, C, E>
+pub struct MessageHandlers<M, C, U, E, L>
 where
     M: MessageRepository,
     C: ChannelRepository,
+    U: UserRepository,
     E: EventRepository,
+    L: AuditRepository,
 {
     message_repo: M,
     channel_repo: C,
+    user_repo: U,
     event_repo: E,
+    audit_repo: L,
+    edit_window: Option<Duration>,
+    edit_window_bypass: bool,
+    max_content_len: usize,
+    max_ttl: Duration,
+    verbose_channel_mismatch: bool,
+    content_filter: Box<dyn ContentFilter>,
 }
 
-impl<M, C, E> MessageHandlers<M, C, E>
+impl<M, C, U, E, L> MessageHandlers<M, C, U, E, L>
 where
     M: MessageRepository,
     C: ChannelRepository,
+    U: UserRepository,
     E: EventRepository,
+    L: AuditRepository,
 {
-    pub fn new(message_repo: M, channel_repo: C, event_repo: E) -> Self {
+    pub fn new(
+        message_repo: M,
+        channel_repo: C,
+        user_repo: U,
+        event_repo: E,
+        audit_repo: L,
+    ) -> Self {
         Self {
             message_repo,
             channel_repo,
+            user_repo,
             event_repo,
+            audit_repo,
+            edit_window: None,
+            edit_window_bypass: false,
+            max_content_len: MESSAGE_CONTENT_MAX_LEN,
+            max_ttl: MESSAGE_MAX_TTL,
+            verbose_channel_mismatch: false,
+            content_filter: Box::new(NoopContentFilter),
+        }
+    }
+
+    /// Sets the maximum amount of characters in the content of a message.
+    #[inline]
+    pub fn with_max_content_len(mut self, max_len: usize) -> Self {
+        self.max_content_len = max_len;
+        self
+    }
+
+    fn validate_content(&self, content: Option<&str>) -> Result<(), ApiError> {
+        if content.is_some_and(|c| c.chars().count() > self.max_content_len) {
+            return Err(ApiError::MessageTooLong(self.max_content_len));
+        }
+
+        Ok(())
+    }
+
+    /// Runs the content of the created and updated messages through the
+    /// filter.
+    #[inline]
+    pub fn with_content_filter(mut self, filter: Box<dyn ContentFilter>) -> Self {
+        self.content_filter = filter;
+        self
+    }
+
+    /// Returns whether the message must be flagged, failing if the content
+    /// is blocked.
+    fn filter_content(&self, content: &str) -> Result<bool, ApiError> {
+        match self.content_filter.check(content) {
+            FilterResult::Allow => Ok(false),
+            FilterResult::Warn(_) => Ok(true),
+            FilterResult::Block(category) => Err(ApiError::MessageRejectedByFilter(category)),
+        }
+    }
+
+    /// Replaces the `@username` mentions of existing users with `<@user_id>`,
+    /// the form [`Message::mentions`] looks for. The usernames are matched
+    /// ignoring the case, and the unknown ones are left as written.
+    async fn resolve_mentions(&self, content: &str) -> Result<String, ApiError> {
+        let mut resolved: HashMap<String, Option<Uuid>> = HashMap::new();
+        let mut out = String::with_capacity(content.len());
+        let mut last = 0;
+
+        for range in find_mentions(content) {
+            let username = content[range.start + 1..range.end].to_lowercase();
+            let user_id = match resolved.get(&username) {
+                Some(user_id) => *user_id,
+                None if resolved.len() < MAX_RESOLVED_MENTIONS => {
+                    let user = self.user_repo.get_by_username(&username).await?;
+                    let user_id = user.map(|u| u.id);
+                    resolved.insert(username, user_id);
+                    user_id
+                }
+                None => None,
+            };
+
+            if let Some(user_id) = user_id {
+                out.push_str(&content[last..range.start]);
+                out.push_str(&format!("<@{user_id}>"));
+                last = range.end;
+            }
+        }
+        out.push_str(&content[last..]);
+
+        Ok(out)
+    }
+
+    /// Resolves the mentions of the content, checking its length again since
+    /// each resolved mention is longer than the username it replaces.
+    async fn resolve_content(&self, content: &str) -> Result<String, ApiError> {
+        let content = self.resolve_mentions(content).await?;
+        self.validate_content(Some(&content))?;
+
+        Ok(content)
+    }
+
+    /// Sets the furthest in the future a message can be set to expire.
+    #[inline]
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    fn validate_expiry(&self, expires_at: Option<DateTime<Utc>>) -> Result<(), ApiError> {
+        let Some(expires_at) = expires_at else {
+            return Ok(());
+        };
+
+        let ttl = (expires_at - Utc::now()).to_std().unwrap_or_default();
+        if ttl.is_zero() {
+            return Err(ApiError::ValidationFailed(
+                "the expiry date of the message must be in the future".into(),
+            ));
+        }
+        if ttl > self.max_ttl {
+            return Err(ApiError::ValidationFailed(format!(
+                "the message can expire in at most {} seconds",
+                self.max_ttl.as_secs()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Makes the messages uneditable once they are older than `window`.
+    #[inline]
+    pub fn with_edit_window(mut self, window: Duration) -> Self {
+        self.edit_window = Some(window);
+        self
+    }
+
+    /// Lets the channel owner and admins edit their messages past the edit
+    /// window.
+    #[inline]
+    pub fn with_edit_window_bypass(mut self, bypass: bool) -> Self {
+        self.edit_window_bypass = bypass;
+        self
+    }
+
+    /// Fails with [`ApiError::MessageChannelMismatch`] instead of
+    /// [`ApiError::MessageNotFound`] when a message is requested through
+    /// another channel. Meant to help client developers, since it tells that
+    /// the message exists, so it should not be enabled in production.
+    #[inline]
+    pub fn with_verbose_channel_mismatch(mut self, verbose: bool) -> Self {
+        self.verbose_channel_mismatch = verbose;
+        self
+    }
+
+    fn check_channel(&self, msg: &Message, channel_id: Uuid) -> Result<(), ApiError> {
+        if msg.channel_id == channel_id {
+            Ok(())
+        } else if self.verbose_channel_mismatch {
+            Err(ApiError::MessageChannelMismatch)
+        } else {
+            Err(ApiError::MessageNotFound)
         }
     }
 
@@ -73,7 +290,53 @@ where
         &self,
         auth: UserAuthPayload,
         path: ChannelIdMessageIdPathParams,
-    ) -> Result<DataResponse<Message>, ApiError> {
+        query: ExpandQueryParams,
+    ) -> Result<DataResponse<MessageView>, ApiError> {
+        let msg = self.get_readable_message(&auth, &path).await?;
+        let mut views = self.expand(vec![msg], query.expand).await?;
+
+        Ok(views.remove(0).into())
+    }
+
+    /// Wraps the messages in views, embedding the data requested with
+    /// `expand`. The authors are fetched in a single batch.
+    async fn expand(
+        &self,
+        msgs: Vec<Message>,
+        expand: Option<MessageExpand>,
+    ) -> Result<Vec<MessageView>, ApiError> {
+        let Some(MessageExpand::Author) = expand else {
+            return Ok(msgs.into_iter().map(MessageView::from).collect());
+        };
+
+        let ids = msgs
+            .iter()
+            .map(|m| m.user_id)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let authors = self
+            .user_repo
+            .get_many(&ids)
+            .await?
+            .into_iter()
+            .map(|u| (u.id, PublicUser::from(u)))
+            .collect::<HashMap<_, _>>();
+
+        Ok(msgs
+            .into_iter()
+            .map(|message| MessageView {
+                author: Some(authors.get(&message.user_id).cloned()),
+                message,
+            })
+            .collect())
+    }
+
+    async fn get_readable_message(
+        &self,
+        auth: &UserAuthPayload,
+        path: &ChannelIdMessageIdPathParams,
+    ) -> Result<Message, ApiError> {
         let perm = self
             .channel_repo
             .get_user_permission(auth.sub, path.channel_id)
@@ -88,11 +351,163 @@ where
             None => return Err(ApiError::MessageNotFound),
         };
 
-        if msg.channel_id != path.channel_id {
-            return Err(ApiError::MessageNotFound);
+        self.check_channel(&msg, path.channel_id)?;
+
+        Ok(msg)
+    }
+
+    /// Moves the read markers of the user up to the latest message of every
+    /// channel the user can still read.
+    pub async fn handle_mark_all_read(
+        &self,
+        auth: UserAuthPayload,
+    ) -> Result<DataResponse<ReadAllResponseBody>, ApiError> {
+        let mut channel_ids = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = self
+                .channel_repo
+                .get_by_user(auth.sub, offset, READ_ALL_PAGE_SIZE)
+                .await?;
+            offset += page.len() as u64;
+
+            let ids = page.iter().map(|chan| chan.id).collect::<Vec<_>>();
+            let perms = self
+                .channel_repo
+                .get_user_permissions(auth.sub, &ids)
+                .await?;
+            channel_ids.extend(
+                ids.into_iter()
+                    .filter(|id| perms.get(id).is_some_and(|p| p.can_read_msg())),
+            );
+
+            if (page.len() as u64) < READ_ALL_PAGE_SIZE {
+                break;
+            }
         }
 
-        Ok(msg.into())
+        let latest = self.message_repo.get_latest(&channel_ids).await?;
+        let markers = self
+            .message_repo
+            .set_read_markers(auth.sub, &latest)
+            .await?;
+
+        for marker in &markers {
+            self.event_repo
+                .publish(AppEvent::MessageRead {
+                    channel_id: marker.channel_id,
+                    user_id: auth.sub,
+                    up_to_message_id: marker.message_id,
+                })
+                .await?;
+        }
+
+        Ok(ReadAllResponseBody {
+            count: markers.len(),
+        }
+        .into())
+    }
+
+    pub async fn handle_mark_read(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdMessageIdPathParams,
+    ) -> Result<NoContent, ApiError> {
+        let msg = self.get_readable_message(&auth, &path).await?;
+
+        let marker = self.message_repo.set_read_marker(auth.sub, &msg).await?;
+
+        if marker.message_id == msg.id {
+            self.event_repo
+                .publish(AppEvent::MessageRead {
+                    channel_id: msg.channel_id,
+                    user_id: auth.sub,
+                    up_to_message_id: msg.id,
+                })
+                .await?;
+        }
+
+        Ok(NoContent)
+    }
+
+    /// Returns the read markers of the channel members that have read up to
+    /// at least the message.
+    pub async fn handle_get_receipts(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdMessageIdPathParams,
+    ) -> Result<DataResponse<ListResponse<ReadMarker>>, ApiError> {
+        let msg = self.get_readable_message(&auth, &path).await?;
+
+        let markers = self.message_repo.get_read_markers(msg.channel_id).await?;
+
+        let mut receipts = Vec::new();
+        for marker in markers {
+            if marker.message_seq < msg.seq {
+                continue;
+            }
+            // Users that left the channel keep their marker but are no
+            // longer members
+            let perm = self
+                .channel_repo
+                .get_user_permission(marker.user_id, msg.channel_id)
+                .await?;
+            if perm.can_read_msg() {
+                receipts.push(marker);
+            }
+        }
+
+        Ok(ListResponse::new(receipts, "read receipt").into())
+    }
+
+    /// Lists the user as typing in the channel and notifies the members.
+    pub async fn handle_typing(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+    ) -> Result<NoContent, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_send_msg() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        self.message_repo
+            .set_typing(path.channel_id, auth.sub)
+            .await?;
+
+        self.event_repo
+            .publish(AppEvent::UserTyping {
+                channel_id: path.channel_id,
+                user_id: auth.sub,
+            })
+            .await?;
+
+        Ok(NoContent)
+    }
+
+    /// Returns the users currently typing in the channel, so a client that
+    /// just connected does not have to wait for their next typing event.
+    pub async fn handle_get_typing(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+    ) -> Result<DataResponse<TypingResponseBody>, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_read_msg() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        let user_ids = self.message_repo.get_typing(path.channel_id).await?;
+
+        Ok(TypingResponseBody { user_ids }.into())
     }
 
     pub async fn handle_get_many(
@@ -100,7 +515,7 @@ where
         auth: UserAuthPayload,
         path: ChannelIdPathParams,
         query: GetManyQueryParams,
-    ) -> Result<DataResponse<Vec<Message>>, ApiError> {
+    ) -> Result<DataResponse<Vec<MessageView>>, ApiError> {
         let perm = self
             .channel_repo
             .get_user_permission(auth.sub, path.channel_id)
@@ -115,14 +530,179 @@ where
             .get_many(path.channel_id, query.offset, query.limit)
             .await?;
 
-        Ok(msgs.into())
+        Ok(self.expand(msgs, query.expand).await?.into())
+    }
+
+    /// Streams all the messages of the channel as a file download. Only the
+    /// channel owner and admins can export it.
+    pub async fn handle_export(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+        query: ExportQueryParams,
+    ) -> Result<Response, ApiError>
+    where
+        M: Clone + 'static,
+    {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_update_chan() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        let format = query.format;
+        let stream = export_stream(self.message_repo.clone(), path.channel_id, format);
+
+        let disposition = format!(
+            "attachment; filename=\"channel-{}.{}\"",
+            path.channel_id,
+            format.extension()
+        );
+
+        Ok((
+            [
+                (header::CONTENT_TYPE, format.content_type().to_owned()),
+                (header::CONTENT_DISPOSITION, disposition),
+            ],
+            Body::from_stream(stream),
+        )
+            .into_response())
+    }
+
+    /// Imports a batch of historical messages keeping their creation dates.
+    /// Only the channel owner can import messages, and a single
+    /// [`AppEvent::ChannelImported`] is published for the whole batch. The
+    /// history must be imported oldest first, since a batch older than the
+    /// latest message of the channel is rejected.
+    pub async fn handle_import(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+        mut body: Vec<MessageImportData>,
+    ) -> Result<DataResponse<MessageImportResponseBody>, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_delete_chan() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+        if body.len() > MESSAGE_IMPORT_MAX_BATCH {
+            return Err(ApiError::MessageImportTooLarge);
+        }
+        let now = Utc::now();
+        for msg in &body {
+            self.validate_content(msg.content.as_deref())?;
+            if msg.created_at > now {
+                return Err(ApiError::ValidationFailed(
+                    "the creation date of an imported message can not be in the future".into(),
+                ));
+            }
+        }
+
+        // The imported messages get the next seqs of the channel, so they
+        // can only be listed in the order of their creation dates if none
+        // of them is older than the existing messages
+        let latest = self.message_repo.get_latest(&[path.channel_id]).await?;
+        let oldest = body.iter().map(|msg| msg.created_at).min();
+        if let (Some(latest), Some(oldest)) = (latest.first(), oldest) {
+            if oldest < latest.created_at {
+                return Err(ApiError::MessageImportOutOfOrder);
+            }
+        }
+
+        // Every author is checked before anything is inserted, so an invalid
+        // batch is rejected as a whole
+        let mut members = HashSet::new();
+        for msg in &body {
+            let Some(user_id) = msg.user_id else {
+                continue;
+            };
+            if members.contains(&user_id) {
+                continue;
+            }
+
+            let perm = self
+                .channel_repo
+                .get_user_permission(user_id, path.channel_id)
+                .await?;
+            if !perm.can_read_msg() {
+                return Err(ApiError::MessageImportAuthorInvalid);
+            }
+            members.insert(user_id);
+        }
+
+        body.sort_by_key(|msg| msg.created_at);
+
+        let count = body.len() as u64;
+        for msg in body {
+            self.message_repo
+                .create_with_timestamp(
+                    msg.user_id.unwrap_or(SYSTEM_IMPORT_AUTHOR),
+                    path.channel_id,
+                    MessageCreateData {
+                        content: msg.content,
+                        image: msg.image,
+                        expires_at: None,
+                        flagged: false,
+                        // Attributed to the system when the author is unknown
+                        kind: match msg.user_id {
+                            Some(_) => MessageKind::User,
+                            None => MessageKind::System,
+                        },
+                    },
+                    msg.created_at,
+                )
+                .await?;
+        }
+
+        if count > 0 {
+            self.event_repo
+                .publish(AppEvent::ChannelImported {
+                    id: path.channel_id,
+                    count,
+                })
+                .await?;
+        }
+
+        Ok(MessageImportResponseBody { count }.into())
+    }
+
+    /// Returns the messages surrounding the target message, used to jump to
+    /// it.
+    pub async fn handle_get_around(
+        &self,
+        auth: UserAuthPayload,
+        path: ChannelIdPathParams,
+        query: GetAroundQueryParams,
+    ) -> Result<DataResponse<Vec<MessageView>>, ApiError> {
+        let perm = self
+            .channel_repo
+            .get_user_permission(auth.sub, path.channel_id)
+            .await?;
+
+        if !perm.can_read_msg() {
+            return Err(ApiError::ChannelPermissionDenied);
+        }
+
+        let msgs = self
+            .message_repo
+            .get_around(path.channel_id, query.message_id, query.limit)
+            .await?
+            .ok_or(ApiError::MessageNotFound)?;
+
+        Ok(self.expand(msgs, query.expand).await?.into())
     }
 
     pub async fn handle_create(
         &self,
         auth: UserAuthPayload,
         path: ChannelIdPathParams,
-        body: MessageCreateData,
+        mut body: MessageCreateData,
     ) -> Result<DataResponse<Message>, ApiError> {
         let perm = self
             .channel_repo
@@ -132,6 +712,12 @@ where
         if !perm.can_send_msg() {
             return Err(ApiError::ChannelPermissionDenied);
         }
+        self.validate_content(body.content.as_deref())?;
+        self.validate_expiry(body.expires_at)?;
+        if let Some(content) = &body.content {
+            body.flagged = self.filter_content(content)?;
+            body.content = Some(self.resolve_content(content).await?);
+        }
 
         let msg = self
             .message_repo
@@ -153,7 +739,7 @@ where
         &self,
         auth: UserAuthPayload,
         path: ChannelIdMessageIdPathParams,
-        body: MessageUpdateData,
+        mut body: MessageUpdateData,
     ) -> Result<DataResponse<Message>, ApiError> {
         let perm = self
             .channel_repo
@@ -163,19 +749,31 @@ where
         if !perm.can_send_msg() {
             return Err(ApiError::ChannelPermissionDenied);
         }
+        self.validate_content(body.content.as_deref())?;
+        if let Some(content) = &body.content {
+            body.flagged = Some(self.filter_content(content)?);
+            body.content = Some(self.resolve_content(content).await?);
+        }
 
         let msg = match self.message_repo.get_by_id(path.message_id).await? {
             Some(v) => v,
             None => return Err(ApiError::MessageNotFound),
         };
 
-        if msg.channel_id != path.channel_id {
-            return Err(ApiError::MessageNotFound);
-        }
+        self.check_channel(&msg, path.channel_id)?;
 
         if msg.user_id != auth.sub {
             return Err(ApiError::MessageEditDenied);
         }
+        if let Some(window) = self.edit_window {
+            let bypass = self.edit_window_bypass && perm.can_update_chan();
+            let age = (Utc::now() - msg.created_at).to_std().unwrap_or_default();
+
+            if !bypass && age > window {
+                return Err(ApiError::MessageEditWindowExpired);
+            }
+        }
+
         let msg = self.message_repo.update(msg.id, body).await?;
 
         self.event_repo
@@ -200,15 +798,23 @@ where
             None => return Err(ApiError::MessageNotFound),
         };
 
-        if msg.channel_id != path.channel_id {
-            return Err(ApiError::MessageNotFound);
-        }
+        self.check_channel(&msg, path.channel_id)?;
         if msg.user_id != auth.sub && !perm.can_update_chan() {
             return Err(ApiError::MessageDeleteDenied);
         }
 
         self.message_repo.delete(path.message_id).await?;
 
+        self.audit_repo.record(AuditLogCreateData::new(
+            Some(auth.sub),
+            AuditAction::MessageDeleted,
+            Some(path.message_id),
+            serde_json::json!({
+                "channel_id": path.channel_id,
+                "author_id": msg.user_id,
+            }),
+        ));
+
         self.event_repo
             .publish(AppEvent::MessageDeleted {
                 id: path.message_id,
@@ -223,3 +829,786 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ChannelIdMessageIdPathParams, ChannelIdPathParams, ExpandQueryParams, GetManyQueryParams,
+        MessageHandlers,
+    };
+    use crate::{
+        audit::memory_repository::InMemoryAuditRepository,
+        auth::models::UserAuthPayload,
+        channel::{
+            memory_repository::InMemoryChannelRepository,
+            models::{ChannelCreateData, UserPermission},
+            repository::ChannelRepository,
+        },
+        errors::ApiError,
+        event::{memory_repository::InMemoryEventRepository, repository::EventRepository},
+        message::{
+            filter::WordlistContentFilter,
+            memory_repository::InMemoryMessageRepository,
+            models::{
+                MessageCreateData, MessageExpand, MessageImportData, MessageKind,
+                MessageUpdateData, SYSTEM_IMPORT_AUTHOR,
+            },
+            repository::MessageRepository,
+        },
+        user::{
+            memory_repository::InMemoryUserRepository,
+            models::{UserCreateData, UserRole},
+            repository::UserRepository,
+        },
+    };
+    use chrono::Utc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    type Handlers = MessageHandlers<
+        InMemoryMessageRepository,
+        InMemoryChannelRepository,
+        InMemoryUserRepository,
+        InMemoryEventRepository,
+        InMemoryAuditRepository,
+    >;
+
+    const EDIT_WINDOW: Duration = Duration::from_millis(50);
+
+    struct Setup {
+        channel_id: Uuid,
+        owner: Uuid,
+        member: Uuid,
+        event_repo: InMemoryEventRepository,
+        _conn: <InMemoryEventRepository as EventRepository>::Connection,
+    }
+
+    async fn setup(channel_repo: &InMemoryChannelRepository) -> Setup {
+        let owner = Uuid::new_v4();
+        let member = Uuid::new_v4();
+
+        let chan = channel_repo
+            .create(
+                owner,
+                ChannelCreateData {
+                    name: "general".into(),
+                    description: None,
+                    topic: None,
+                    init_users: None,
+                    init_permission: UserPermission::Interact,
+                },
+            )
+            .await
+            .unwrap();
+        channel_repo
+            .add_members(chan.id, &[member], UserPermission::Interact)
+            .await
+            .unwrap();
+
+        let event_repo = InMemoryEventRepository::new();
+        // Keeps an event receiver alive, so the published events are accepted
+        let conn = event_repo.get_conn().await.unwrap();
+
+        Setup {
+            channel_id: chan.id,
+            owner,
+            member,
+            event_repo,
+            _conn: conn,
+        }
+    }
+
+    /// Creates another channel of the owner with the member in it.
+    async fn setup_channel(
+        channel_repo: &InMemoryChannelRepository,
+        owner: Uuid,
+        member: Uuid,
+    ) -> Uuid {
+        let chan = channel_repo
+            .create(
+                owner,
+                ChannelCreateData {
+                    name: "random".into(),
+                    description: None,
+                    topic: None,
+                    init_users: None,
+                    init_permission: UserPermission::Interact,
+                },
+            )
+            .await
+            .unwrap();
+        channel_repo
+            .add_members(chan.id, &[member], UserPermission::Interact)
+            .await
+            .unwrap();
+
+        chan.id
+    }
+
+    fn auth(user_id: Uuid) -> UserAuthPayload {
+        UserAuthPayload::new(user_id, "user".into(), "user@example.com".into(), 60)
+    }
+
+    /// Sends a message as the user and edits it after `delay`.
+    async fn send_and_edit(
+        handlers: &Handlers,
+        setup: &Setup,
+        user_id: Uuid,
+        delay: Duration,
+    ) -> Result<(), ApiError> {
+        let msg = handlers
+            .handle_create(
+                auth(user_id),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                MessageCreateData {
+                    content: Some("Hello".into()),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+            .await
+            .unwrap()
+            .data;
+
+        tokio::time::sleep(delay).await;
+
+        handlers
+            .handle_update(
+                auth(user_id),
+                ChannelIdMessageIdPathParams {
+                    channel_id: setup.channel_id,
+                    message_id: msg.id,
+                },
+                MessageUpdateData {
+                    content: Some("Hello, world".into()),
+                    image: None,
+                    flagged: None,
+                },
+            )
+            .await
+            .map(|_| ())
+    }
+
+    fn handlers(channel_repo: &InMemoryChannelRepository, setup: &Setup, bypass: bool) -> Handlers {
+        MessageHandlers::new(
+            InMemoryMessageRepository::new(),
+            channel_repo.clone(),
+            InMemoryUserRepository::new(4),
+            setup.event_repo.clone(),
+            InMemoryAuditRepository::new(),
+        )
+        .with_edit_window(EDIT_WINDOW)
+        .with_edit_window_bypass(bypass)
+    }
+
+    #[tokio::test]
+    async fn test_edit_in_window() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let handlers = handlers(&channel_repo, &setup, false);
+
+        send_and_edit(&handlers, &setup, setup.member, Duration::ZERO)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_edit_out_of_window() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let handlers = handlers(&channel_repo, &setup, false);
+
+        for user_id in [setup.member, setup.owner] {
+            let res = send_and_edit(&handlers, &setup, user_id, EDIT_WINDOW * 2).await;
+            assert!(matches!(res, Err(ApiError::MessageEditWindowExpired)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_edit_window_bypass() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let handlers = handlers(&channel_repo, &setup, true);
+
+        send_and_edit(&handlers, &setup, setup.owner, EDIT_WINDOW * 2)
+            .await
+            .unwrap();
+
+        // Only the owner and admins can bypass the window
+        let res = send_and_edit(&handlers, &setup, setup.member, EDIT_WINDOW * 2).await;
+        assert!(matches!(res, Err(ApiError::MessageEditWindowExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_content_too_long() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let handlers = handlers(&channel_repo, &setup, false).with_max_content_len(5);
+
+        let create = |content: &str| {
+            handlers.handle_create(
+                auth(setup.member),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                MessageCreateData {
+                    content: Some(content.into()),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+        };
+
+        // The limit counts characters, not bytes
+        create("héllo").await.unwrap();
+        let res = create("hello!").await;
+        assert!(matches!(res, Err(ApiError::MessageTooLong(5))));
+    }
+
+    #[tokio::test]
+    async fn test_content_filter() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let filter =
+            WordlistContentFilter::parse("warn rudeness dumb\nblock spam freecoins").unwrap();
+        let handlers = handlers(&channel_repo, &setup, false).with_content_filter(Box::new(filter));
+
+        let create = |content: &str| {
+            handlers.handle_create(
+                auth(setup.member),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                MessageCreateData {
+                    content: Some(content.into()),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+        };
+        let update = |message_id, content: &str| {
+            handlers.handle_update(
+                auth(setup.member),
+                ChannelIdMessageIdPathParams {
+                    channel_id: setup.channel_id,
+                    message_id,
+                },
+                MessageUpdateData {
+                    content: Some(content.into()),
+                    image: None,
+                    flagged: None,
+                },
+            )
+        };
+
+        let res = create("Get your FreeCoins").await;
+        assert!(matches!(res, Err(ApiError::MessageRejectedByFilter(c)) if c == "spam"));
+
+        let msg = create("Hello").await.unwrap().data;
+        assert!(!msg.flagged);
+
+        let msg = update(msg.id, "That is dumb").await.unwrap().data;
+        assert!(msg.flagged);
+        let res = update(msg.id, "freecoins").await;
+        assert!(matches!(res, Err(ApiError::MessageRejectedByFilter(_))));
+        let msg = update(msg.id, "That is fine").await.unwrap().data;
+        assert!(!msg.flagged);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_validation() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let handlers = handlers(&channel_repo, &setup, false).with_max_ttl(Duration::from_secs(60));
+
+        let create = |ttl_secs: i64| {
+            handlers.handle_create(
+                auth(setup.member),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                MessageCreateData {
+                    content: Some("Hello".into()),
+                    image: None,
+                    expires_at: Some(Utc::now() + chrono::Duration::seconds(ttl_secs)),
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+        };
+
+        let msg = create(30).await.unwrap().data;
+        assert!(msg.expires_at.is_some());
+
+        let res = create(-1).await;
+        assert!(matches!(res, Err(ApiError::ValidationFailed(_))));
+        let res = create(120).await;
+        assert!(matches!(res, Err(ApiError::ValidationFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_mark_all_read() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let other = Setup {
+            channel_id: setup_channel(&channel_repo, setup.owner, setup.member).await,
+            ..setup
+        };
+        let revoked = setup_channel(&channel_repo, setup.owner, setup.member).await;
+        let handlers = handlers(&channel_repo, &other, false);
+
+        for channel_id in [other.channel_id, revoked] {
+            for _ in 0..3 {
+                handlers
+                    .handle_create(
+                        auth(other.owner),
+                        ChannelIdPathParams { channel_id },
+                        MessageCreateData {
+                            content: Some("Hello".into()),
+                            image: None,
+                            expires_at: None,
+                            flagged: false,
+                            kind: MessageKind::User,
+                        },
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+        channel_repo
+            .set_user_permission(revoked, other.member, UserPermission::None)
+            .await
+            .unwrap();
+
+        let (message_repo, member) = (&handlers.message_repo, other.member);
+        let unread = |channel_id| async move {
+            let markers = message_repo.get_read_markers(channel_id).await.unwrap();
+            let read_at = markers
+                .iter()
+                .find(|m| m.user_id == member)
+                .map(|m| m.message_seq);
+            let msgs = message_repo.get_many(channel_id, 0, 100).await.unwrap();
+
+            msgs.iter()
+                .filter(|m| read_at.is_none_or(|seq| m.seq > seq))
+                .count()
+        };
+        assert_eq!(unread(other.channel_id).await, 3);
+
+        let res = handlers
+            .handle_mark_all_read(auth(other.member))
+            .await
+            .unwrap()
+            .data;
+        // The channel without messages and the revoked one are skipped
+        assert_eq!(res.count, 1);
+        assert_eq!(unread(other.channel_id).await, 0);
+        assert_eq!(unread(revoked).await, 3);
+
+        let res = handlers
+            .handle_mark_all_read(auth(other.member))
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(res.count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_read_markers_same_instant() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let handlers = handlers(&channel_repo, &setup, false);
+
+        let created_at = Utc::now();
+        let mut msgs = Vec::new();
+        for _ in 0..2 {
+            let data = MessageCreateData {
+                content: Some("Hello".into()),
+                image: None,
+                expires_at: None,
+                flagged: false,
+                kind: MessageKind::User,
+            };
+            let msg = handlers
+                .message_repo
+                .create_with_timestamp(setup.owner, setup.channel_id, data, created_at)
+                .await
+                .unwrap();
+            msgs.push(msg);
+        }
+
+        let path = |message_id| ChannelIdMessageIdPathParams {
+            channel_id: setup.channel_id,
+            message_id,
+        };
+        let (handlers, owner) = (&handlers, setup.owner);
+        let receipts = |message_id| async move {
+            let res = handlers
+                .handle_get_receipts(auth(owner), path(message_id))
+                .await
+                .unwrap();
+            serde_json::to_value(res.data).unwrap()
+        };
+
+        handlers
+            .handle_mark_read(auth(setup.member), path(msgs[0].id))
+            .await
+            .unwrap();
+        assert_eq!(receipts(msgs[1].id).await, serde_json::json!([]));
+
+        // The message created in the same instant still moves the marker
+        handlers
+            .handle_mark_read(auth(setup.member), path(msgs[1].id))
+            .await
+            .unwrap();
+        let markers = receipts(msgs[1].id).await;
+        assert_eq!(markers.as_array().unwrap().len(), 1, "{markers}");
+        assert_eq!(markers[0]["message_id"], msgs[1].id.to_string());
+        assert_eq!(markers[0]["message_seq"], msgs[1].seq);
+    }
+
+    #[tokio::test]
+    async fn test_channel_mismatch() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let other = setup(&channel_repo).await;
+        let setup = setup(&channel_repo).await;
+        let handlers = handlers(&channel_repo, &setup, false);
+
+        let msg = handlers
+            .handle_create(
+                auth(setup.member),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                MessageCreateData {
+                    content: Some("Hello".into()),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+            .await
+            .unwrap()
+            .data;
+
+        // The message is requested through a channel the user can read
+        let path = || ChannelIdMessageIdPathParams {
+            channel_id: other.channel_id,
+            message_id: msg.id,
+        };
+
+        let query = || ExpandQueryParams { expand: None };
+
+        let res = handlers
+            .handle_get_one(auth(other.owner), path(), query())
+            .await;
+        assert!(matches!(res, Err(ApiError::MessageNotFound)));
+
+        let handlers = handlers.with_verbose_channel_mismatch(true);
+        let res = handlers
+            .handle_get_one(auth(other.owner), path(), query())
+            .await;
+        assert!(matches!(res, Err(ApiError::MessageChannelMismatch)));
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let message_repo = InMemoryMessageRepository::new();
+        let handlers = MessageHandlers::new(
+            message_repo.clone(),
+            channel_repo.clone(),
+            InMemoryUserRepository::new(4),
+            setup.event_repo.clone(),
+            InMemoryAuditRepository::new(),
+        );
+
+        let path = || ChannelIdPathParams {
+            channel_id: setup.channel_id,
+        };
+        let import = |user_id, days_ago| MessageImportData {
+            user_id,
+            created_at: Utc::now() - chrono::Duration::days(days_ago),
+            content: Some("Imported".into()),
+            image: None,
+        };
+
+        let res = handlers
+            .handle_import(auth(setup.member), path(), vec![import(None, 1)])
+            .await;
+        assert!(matches!(res, Err(ApiError::ChannelPermissionDenied)));
+
+        let res = handlers
+            .handle_import(
+                auth(setup.owner),
+                path(),
+                vec![
+                    import(Some(setup.member), 2),
+                    import(Some(Uuid::new_v4()), 1),
+                ],
+            )
+            .await;
+        assert!(matches!(res, Err(ApiError::MessageImportAuthorInvalid)));
+        assert!(message_repo
+            .get_many(setup.channel_id, 0, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let batch = vec![import(None, 1), import(Some(setup.member), 3)];
+        let res = handlers
+            .handle_import(auth(setup.owner), path(), batch.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.data.count, 2);
+
+        let msgs = message_repo
+            .get_many(setup.channel_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(msgs[0].user_id, setup.member);
+        assert_eq!(msgs[0].created_at, batch[1].created_at);
+        assert_eq!(msgs[1].user_id, SYSTEM_IMPORT_AUTHOR);
+        assert_eq!(msgs[1].kind, MessageKind::System);
+        assert_eq!(msgs[1].created_at, batch[0].created_at);
+
+        let res = handlers
+            .handle_import(auth(setup.owner), path(), vec![import(None, -1)])
+            .await;
+        assert!(matches!(res, Err(ApiError::ValidationFailed(_))));
+
+        // Mixing live and imported messages must keep them in chronological
+        // order
+        handlers
+            .handle_create(
+                auth(setup.member),
+                path(),
+                MessageCreateData {
+                    content: Some("Live".into()),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+            .await
+            .unwrap();
+        let res = handlers
+            .handle_import(auth(setup.owner), path(), vec![import(None, 2)])
+            .await;
+        assert!(matches!(res, Err(ApiError::MessageImportOutOfOrder)));
+
+        handlers
+            .handle_import(auth(setup.owner), path(), vec![import(None, 0)])
+            .await
+            .unwrap();
+
+        let msgs = message_repo
+            .get_many(setup.channel_id, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(msgs.len(), 4);
+        assert!(msgs.windows(2).all(|w| w[0].created_at <= w[1].created_at));
+        assert_eq!(msgs[3].content.as_deref(), Some("Imported"));
+    }
+
+    #[tokio::test]
+    async fn test_expand_author() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let user_repo = InMemoryUserRepository::new(4);
+        let handlers = MessageHandlers::new(
+            InMemoryMessageRepository::new(),
+            channel_repo.clone(),
+            user_repo.clone(),
+            setup.event_repo.clone(),
+            InMemoryAuditRepository::new(),
+        );
+
+        let author = user_repo
+            .create(
+                UserRole::Common,
+                UserCreateData {
+                    email: "author@example.com".into(),
+                    username: "author".into(),
+                    password: "password".into(),
+                },
+            )
+            .await
+            .unwrap();
+        channel_repo
+            .add_members(setup.channel_id, &[author.id], UserPermission::Interact)
+            .await
+            .unwrap();
+
+        let path = || ChannelIdPathParams {
+            channel_id: setup.channel_id,
+        };
+        // The member has no user, like a deleted account
+        for user_id in [author.id, setup.member, author.id] {
+            handlers
+                .handle_create(
+                    auth(user_id),
+                    path(),
+                    MessageCreateData {
+                        content: Some("Hello".into()),
+                        image: None,
+                        expires_at: None,
+                        flagged: false,
+                        kind: MessageKind::User,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        let query = |expand| GetManyQueryParams {
+            limit: 100,
+            offset: 0,
+            expand,
+        };
+
+        let msgs = handlers
+            .handle_get_many(auth(setup.owner), path(), query(None))
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(msgs.len(), 3);
+        assert!(msgs.iter().all(|m| m.author.is_none()));
+        let json = serde_json::to_value(&msgs[0]).unwrap();
+        assert!(json.get("author").is_none());
+
+        let msgs = handlers
+            .handle_get_many(
+                auth(setup.owner),
+                path(),
+                query(Some(MessageExpand::Author)),
+            )
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(msgs.len(), 3);
+        for msg in &msgs {
+            let expected = (msg.message.user_id == author.id).then(|| author.clone().into());
+            assert_eq!(msg.author, Some(expected));
+        }
+        let json = serde_json::to_value(&msgs).unwrap();
+        let missing = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["user_id"] == setup.member.to_string())
+            .unwrap();
+        assert!(missing["author"].is_null());
+        let found = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["user_id"] == author.id.to_string())
+            .unwrap();
+        assert_eq!(found["author"]["username"], "author");
+        assert!(found["author"].get("email").is_none());
+
+        let msg = handlers
+            .handle_get_one(
+                auth(setup.owner),
+                ChannelIdMessageIdPathParams {
+                    channel_id: setup.channel_id,
+                    message_id: found["id"].as_str().unwrap().parse().unwrap(),
+                },
+                ExpandQueryParams {
+                    expand: Some(MessageExpand::Author),
+                },
+            )
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(msg.author, Some(Some(author.into())));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_mentions() {
+        let channel_repo = InMemoryChannelRepository::new();
+        let setup = setup(&channel_repo).await;
+        let user_repo = InMemoryUserRepository::new(4);
+        let handlers = MessageHandlers::new(
+            InMemoryMessageRepository::new(),
+            channel_repo.clone(),
+            user_repo.clone(),
+            setup.event_repo.clone(),
+            InMemoryAuditRepository::new(),
+        );
+
+        let alice = user_repo
+            .create(
+                UserRole::Common,
+                UserCreateData {
+                    email: "alice@example.com".into(),
+                    username: "Alice".into(),
+                    password: "password".into(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let msg = handlers
+            .handle_create(
+                auth(setup.member),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                MessageCreateData {
+                    content: Some("@alice, ask @nobody or mail alice@example.com".into()),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+            .await
+            .unwrap()
+            .data;
+
+        assert_eq!(
+            msg.content.as_deref(),
+            Some(format!("<@{}>, ask @nobody or mail alice@example.com", alice.id).as_str())
+        );
+        assert!(msg.mentions(alice.id));
+
+        // The content is too long once the mention is resolved
+        let handlers = MessageHandlers::new(
+            InMemoryMessageRepository::new(),
+            channel_repo,
+            user_repo,
+            setup.event_repo.clone(),
+            InMemoryAuditRepository::new(),
+        )
+        .with_max_content_len(20);
+        let res = handlers
+            .handle_create(
+                auth(setup.member),
+                ChannelIdPathParams {
+                    channel_id: setup.channel_id,
+                },
+                MessageCreateData {
+                    content: Some("Hello @alice".into()),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+            .await;
+        assert!(matches!(res, Err(ApiError::MessageTooLong(20))));
+    }
+}
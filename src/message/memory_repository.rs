@@ -1,32 +1,73 @@
 use super::{
-    models::{Message, MessageCreateData, MessageUpdateData},
+    models::{
+        ChannelLatestMessage, Message, MessageCreateData, MessageUpdateData, ReadMarker, TYPING_TTL,
+    },
     repository::MessageRepository,
 };
 use crate::errors::ApiError;
 use async_trait::async_trait;
-use chrono::Utc;
-use std::{collections::HashMap, sync::Arc};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Instant,
+};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
 #[derive(Default, Clone)]
-pub struct InMemoryMessageRepository(Arc<Mutex<HashMap<Uuid, Message>>>);
+pub struct InMemoryMessageRepository {
+    message_map: Arc<Mutex<HashMap<Uuid, Message>>>,
+    /// The last `seq` assigned in each channel
+    seq_map: Arc<Mutex<HashMap<Uuid, u64>>>,
+    /// Read markers indexed by `(user_id, channel_id)`
+    marker_map: Arc<Mutex<HashMap<(Uuid, Uuid), ReadMarker>>>,
+    /// The expiry of the typing users indexed by `(channel_id, user_id)`
+    typing_map: Arc<Mutex<HashMap<(Uuid, Uuid), Instant>>>,
+}
 
 impl InMemoryMessageRepository {
     #[inline]
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(HashMap::new())))
+        Self {
+            message_map: Arc::new(Mutex::new(HashMap::new())),
+            seq_map: Arc::new(Mutex::new(HashMap::new())),
+            marker_map: Arc::new(Mutex::new(HashMap::new())),
+            typing_map: Arc::new(Mutex::new(HashMap::new())),
+        }
     }
 }
 
+/// Moves the read marker of the user up to the message, returning it if it
+/// moved.
+fn advance_marker(
+    markers: &mut HashMap<(Uuid, Uuid), ReadMarker>,
+    user_id: Uuid,
+    msg: &ChannelLatestMessage,
+) -> Option<ReadMarker> {
+    let key = (user_id, msg.channel_id);
+    if markers.get(&key).is_some_and(|m| m.message_seq >= msg.seq) {
+        return None;
+    }
+
+    let marker = ReadMarker {
+        user_id,
+        channel_id: msg.channel_id,
+        message_id: msg.message_id,
+        message_seq: msg.seq,
+        message_created_at: msg.created_at,
+        read_at: Utc::now(),
+    };
+    markers.insert(key, marker.clone());
+
+    Some(marker)
+}
+
 #[async_trait]
 impl MessageRepository for InMemoryMessageRepository {
     async fn get_by_id(&self, id: Uuid) -> Result<Option<Message>, ApiError> {
-        let lock = self.0.lock().await;
-        let msg = match lock.get(&id) {
-            Some(v) => Some(v.clone()),
-            None => None,
-        };
+        let lock = self.message_map.lock().await;
+        let msg = lock.get(&id).filter(|m| !m.is_expired(Utc::now())).cloned();
         drop(lock);
 
         Ok(msg)
@@ -35,30 +76,79 @@ impl MessageRepository for InMemoryMessageRepository {
     async fn get_many(
         &self,
         channel_id: Uuid,
-        mut offset: u64,
+        offset: u64,
         limit: u64,
     ) -> Result<Vec<Message>, ApiError> {
-        let lock = self.0.lock().await;
-        let mut arr = Vec::new();
-
-        let mut i = 0u64;
-        for (_, v) in lock.iter() {
-            if offset > 0 {
-                offset -= 1;
-                continue;
-            }
-            if i > limit {
-                break;
-            }
+        let now = Utc::now();
+        let lock = self.message_map.lock().await;
+        let mut msgs: Vec<&Message> = lock
+            .values()
+            .filter(|m| m.channel_id == channel_id && !m.is_expired(now))
+            .collect();
+        msgs.sort_by_key(|m| m.seq);
+
+        Ok(msgs
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
 
-            if v.channel_id == channel_id {
-                arr.push(v.clone());
-                i += 1;
+    async fn count(&self) -> Result<u64, ApiError> {
+        let lock = self.message_map.lock().await;
+
+        Ok(lock.len() as u64)
+    }
+
+    async fn get_latest(
+        &self,
+        channel_ids: &[Uuid],
+    ) -> Result<Vec<ChannelLatestMessage>, ApiError> {
+        let channel_ids = channel_ids.iter().collect::<HashSet<_>>();
+        let now = Utc::now();
+        let lock = self.message_map.lock().await;
+
+        let mut latest: HashMap<Uuid, &Message> = HashMap::new();
+        for msg in lock
+            .values()
+            .filter(|m| channel_ids.contains(&m.channel_id) && !m.is_expired(now))
+        {
+            let entry = latest.entry(msg.channel_id).or_insert(msg);
+            if msg.seq > entry.seq {
+                *entry = msg;
             }
         }
-        drop(lock);
 
-        Ok(arr)
+        Ok(latest
+            .into_values()
+            .map(ChannelLatestMessage::from)
+            .collect())
+    }
+
+    async fn get_around(
+        &self,
+        channel_id: Uuid,
+        message_id: Uuid,
+        limit: u64,
+    ) -> Result<Option<Vec<Message>>, ApiError> {
+        let now = Utc::now();
+        let lock = self.message_map.lock().await;
+        let mut msgs: Vec<&Message> = lock
+            .values()
+            .filter(|m| m.channel_id == channel_id && !m.is_expired(now))
+            .collect();
+        msgs.sort_by_key(|m| m.seq);
+
+        let Some(index) = msgs.iter().position(|m| m.id == message_id) else {
+            return Ok(None);
+        };
+
+        let limit = limit.max(1) as usize;
+        let start = index.saturating_sub(limit / 2);
+        let end = (start + limit).min(msgs.len());
+
+        Ok(Some(msgs[start..end].iter().map(|&m| m.clone()).collect()))
     }
 
     async fn create(
@@ -67,19 +157,41 @@ impl MessageRepository for InMemoryMessageRepository {
         channel_id: Uuid,
         data: MessageCreateData,
     ) -> Result<Message, ApiError> {
-        let now = Utc::now();
+        self.create_with_timestamp(user_id, channel_id, data, Utc::now())
+            .await
+    }
+
+    async fn create_with_timestamp(
+        &self,
+        user_id: Uuid,
+        channel_id: Uuid,
+        data: MessageCreateData,
+        created_at: DateTime<Utc>,
+    ) -> Result<Message, ApiError> {
+        let mut lock = self.message_map.lock().await;
+
+        // The seq is assigned while the messages are locked, so it is in the
+        // same order as the insertions
+        let mut seq_lock = self.seq_map.lock().await;
+        let seq = seq_lock.entry(channel_id).or_default();
+        *seq += 1;
+        let seq = *seq;
+        drop(seq_lock);
 
         let msg = Message {
-            id: Uuid::new_v4(),
+            id: Uuid::now_v7(),
             user_id,
             channel_id,
+            seq,
             content: data.content,
-            created_at: now,
-            updated_at: now,
+            created_at,
+            updated_at: created_at,
             image: data.image,
+            expires_at: data.expires_at,
+            flagged: data.flagged,
+            kind: data.kind,
         };
 
-        let mut lock = self.0.lock().await;
         lock.insert(msg.id, msg.clone());
         drop(lock);
 
@@ -87,7 +199,7 @@ impl MessageRepository for InMemoryMessageRepository {
     }
 
     async fn update(&self, id: Uuid, data: MessageUpdateData) -> Result<Message, ApiError> {
-        let mut lock = self.0.lock().await;
+        let mut lock = self.message_map.lock().await;
         let msg = lock.get(&id);
 
         if let Some(v) = msg {
@@ -99,6 +211,10 @@ impl MessageRepository for InMemoryMessageRepository {
             if let Some(content) = data.content {
                 v.content = Some(content);
             }
+            if let Some(flagged) = data.flagged {
+                v.flagged = flagged;
+            }
+            v.updated_at = Utc::now();
             lock.insert(id, v.clone());
 
             Ok(v)
@@ -108,7 +224,7 @@ impl MessageRepository for InMemoryMessageRepository {
     }
 
     async fn delete(&self, id: Uuid) -> Result<(), ApiError> {
-        let mut lock = self.0.lock().await;
+        let mut lock = self.message_map.lock().await;
         let msg = lock.remove(&id);
         drop(lock);
 
@@ -118,4 +234,300 @@ impl MessageRepository for InMemoryMessageRepository {
             Err(ApiError::MessageNotFound)
         }
     }
+
+    async fn delete_expired(&self) -> Result<Vec<Message>, ApiError> {
+        let now = Utc::now();
+        let mut lock = self.message_map.lock().await;
+
+        let expired = lock
+            .values()
+            .filter(|m| m.is_expired(now))
+            .map(|m| m.id)
+            .collect::<Vec<_>>();
+
+        Ok(expired
+            .into_iter()
+            .filter_map(|id| lock.remove(&id))
+            .collect())
+    }
+
+    async fn set_read_marker(&self, user_id: Uuid, msg: &Message) -> Result<ReadMarker, ApiError> {
+        let mut lock = self.marker_map.lock().await;
+
+        let marker = advance_marker(&mut lock, user_id, &msg.into())
+            .unwrap_or_else(|| lock[&(user_id, msg.channel_id)].clone());
+
+        Ok(marker)
+    }
+
+    async fn set_read_markers(
+        &self,
+        user_id: Uuid,
+        latest: &[ChannelLatestMessage],
+    ) -> Result<Vec<ReadMarker>, ApiError> {
+        let mut lock = self.marker_map.lock().await;
+
+        Ok(latest
+            .iter()
+            .filter_map(|msg| advance_marker(&mut lock, user_id, msg))
+            .collect())
+    }
+
+    async fn get_read_markers(&self, channel_id: Uuid) -> Result<Vec<ReadMarker>, ApiError> {
+        let lock = self.marker_map.lock().await;
+
+        Ok(lock
+            .values()
+            .filter(|m| m.channel_id == channel_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn set_typing(&self, channel_id: Uuid, user_id: Uuid) -> Result<(), ApiError> {
+        let mut lock = self.typing_map.lock().await;
+        lock.insert((channel_id, user_id), Instant::now() + TYPING_TTL);
+
+        Ok(())
+    }
+
+    async fn get_typing(&self, channel_id: Uuid) -> Result<Vec<Uuid>, ApiError> {
+        let mut lock = self.typing_map.lock().await;
+
+        let now = Instant::now();
+        lock.retain(|_, expiry| *expiry > now);
+
+        Ok(lock
+            .keys()
+            .filter(|(id, _)| *id == channel_id)
+            .map(|(_, user_id)| *user_id)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryMessageRepository;
+    use crate::message::{
+        models::{MessageCreateData, MessageKind, MessageUpdateData},
+        repository::MessageRepository,
+    };
+    use chrono::Utc;
+    use std::time::{Duration, Instant};
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_update_touches_updated_at() {
+        let repo = InMemoryMessageRepository::new();
+
+        let msg = repo
+            .create(
+                Uuid::new_v4(),
+                Uuid::new_v4(),
+                MessageCreateData {
+                    content: Some("Hello".into()),
+                    image: None,
+                    expires_at: None,
+                    flagged: false,
+                    kind: MessageKind::User,
+                },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let updated = repo
+            .update(
+                msg.id,
+                MessageUpdateData {
+                    content: Some("Hello, world".into()),
+                    image: None,
+                    flagged: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(updated.created_at, msg.created_at);
+        assert!(updated.updated_at > msg.updated_at);
+    }
+
+    #[tokio::test]
+    async fn test_get_around() {
+        let repo = InMemoryMessageRepository::new();
+        let channel_id = Uuid::new_v4();
+
+        let mut ids = Vec::new();
+        for i in 0..10 {
+            let msg = repo
+                .create(
+                    Uuid::new_v4(),
+                    channel_id,
+                    MessageCreateData {
+                        content: Some(format!("Message {i}")),
+                        image: None,
+                        expires_at: None,
+                        flagged: false,
+                        kind: MessageKind::User,
+                    },
+                )
+                .await
+                .unwrap();
+            ids.push(msg.id);
+        }
+
+        let around = |id, limit| {
+            let repo = repo.clone();
+            async move {
+                repo.get_around(channel_id, id, limit)
+                    .await
+                    .unwrap()
+                    .map(|msgs| msgs.into_iter().map(|m| m.id).collect::<Vec<_>>())
+            }
+        };
+
+        assert_eq!(around(ids[5], 4).await.unwrap(), ids[3..7]);
+        assert_eq!(around(ids[1], 6).await.unwrap(), ids[0..6]);
+        assert_eq!(around(ids[9], 4).await.unwrap(), ids[7..10]);
+
+        assert!(around(Uuid::new_v4(), 4).await.is_none());
+        let other = repo.get_around(Uuid::new_v4(), ids[5], 4).await.unwrap();
+        assert!(other.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_create_order() {
+        let repo = InMemoryMessageRepository::new();
+        let channel_id = Uuid::new_v4();
+
+        let tasks = (0..50)
+            .map(|_| {
+                let repo = repo.clone();
+                tokio::spawn(async move {
+                    repo.create(
+                        Uuid::new_v4(),
+                        channel_id,
+                        MessageCreateData {
+                            content: Some("Hello".into()),
+                            image: None,
+                            expires_at: None,
+                            flagged: false,
+                            kind: MessageKind::User,
+                        },
+                    )
+                    .await
+                    .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let msgs = repo.get_many(channel_id, 0, 100).await.unwrap();
+        let seqs = msgs.iter().map(|m| m.seq).collect::<Vec<_>>();
+        assert_eq!(seqs, (1..=50).collect::<Vec<_>>());
+
+        let page = repo.get_many(channel_id, 10, 5).await.unwrap();
+        let seqs = page.iter().map(|m| m.seq).collect::<Vec<_>>();
+        assert_eq!(seqs, (11..=15).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_get_latest() {
+        let repo = InMemoryMessageRepository::new();
+        let channels = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+
+        let mut last = Vec::new();
+        for channel_id in &channels[..2] {
+            let mut msg = None;
+            for i in 0..5 {
+                msg = Some(
+                    repo.create(
+                        Uuid::new_v4(),
+                        *channel_id,
+                        MessageCreateData {
+                            content: Some(format!("Message {i}")),
+                            image: None,
+                            expires_at: None,
+                            flagged: false,
+                            kind: MessageKind::User,
+                        },
+                    )
+                    .await
+                    .unwrap(),
+                );
+            }
+            last.push(msg.unwrap());
+        }
+
+        let mut latest = repo.get_latest(&channels[1..]).await.unwrap();
+        assert_eq!(latest.len(), 1);
+        let latest = latest.pop().unwrap();
+        assert_eq!(latest.channel_id, channels[1]);
+        assert_eq!(latest.message_id, last[1].id);
+        assert_eq!(latest.seq, 5);
+
+        assert_eq!(repo.get_latest(&channels).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_messages() {
+        let repo = InMemoryMessageRepository::new();
+        let channel_id = Uuid::new_v4();
+
+        let data = |expires_at| MessageCreateData {
+            content: Some("Hello".into()),
+            image: None,
+            expires_at,
+            flagged: false,
+            kind: MessageKind::User,
+        };
+        let expires_at = Utc::now() + chrono::Duration::milliseconds(50);
+
+        let kept = repo
+            .create(Uuid::new_v4(), channel_id, data(None))
+            .await
+            .unwrap();
+        let ephemeral = repo
+            .create(Uuid::new_v4(), channel_id, data(Some(expires_at)))
+            .await
+            .unwrap();
+        assert_eq!(ephemeral.expires_at, Some(expires_at));
+        assert!(repo.get_by_id(ephemeral.id).await.unwrap().is_some());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(repo.get_by_id(ephemeral.id).await.unwrap().is_none());
+        let msgs = repo.get_many(channel_id, 0, 100).await.unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].id, kept.id);
+
+        let deleted = repo.delete_expired().await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].id, ephemeral.id);
+        assert!(repo.delete_expired().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_typing() {
+        let repo = InMemoryMessageRepository::new();
+        let channel_id = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+
+        repo.set_typing(channel_id, user_id).await.unwrap();
+        repo.set_typing(channel_id, user_id).await.unwrap();
+        repo.set_typing(Uuid::new_v4(), Uuid::new_v4())
+            .await
+            .unwrap();
+
+        // An entry whose ttl already elapsed
+        repo.typing_map
+            .lock()
+            .await
+            .insert((channel_id, Uuid::new_v4()), Instant::now());
+
+        assert_eq!(repo.get_typing(channel_id).await.unwrap(), vec![user_id]);
+        assert!(repo.get_typing(Uuid::new_v4()).await.unwrap().is_empty());
+    }
 }
@@ -1,3 +1,6 @@
+pub mod expiry;
+pub mod export;
+pub mod filter;
 pub mod handlers;
 pub mod memory_repository;
 pub mod models;
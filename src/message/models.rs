@@ -1,18 +1,97 @@
-use crate::http::ApiResponder;
+use crate::{http::ApiResponder, user::models::PublicUser};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::{ops::Range, time::Duration};
 use uuid::Uuid;
 
+/// A message sent in a channel.
+///
+/// Messages are ordered by their `seq`, not by `created_at` or `id`: two
+/// messages can be created in the same instant, but each one gets a distinct,
+/// increasing sequence number in its channel. Both the listing endpoints and
+/// the gateway events carry it, so every client renders the same order.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Message {
     pub id: Uuid,
     pub user_id: Uuid,
     pub channel_id: Uuid,
+    /// The position of the message in the channel, assigned on creation
+    #[serde(default)]
+    pub seq: u64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub content: Option<String>,
     pub image: Option<Uuid>,
+    /// The date the message disappears on, hidden from then on and deleted
+    /// by [`run_expiry_job`](super::expiry::run_expiry_job)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether the content filter warned about the content, see
+    /// [`FilterResult::Warn`](super::filter::FilterResult::Warn)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub flagged: bool,
+    #[serde(default)]
+    pub kind: MessageKind,
+}
+
+/// Who a message was sent by. The system messages are posted by the server,
+/// like the join notices, and their `user_id` is [`SYSTEM_AUTHOR`], so the
+/// clients that do not know about the kind still get a valid id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MessageKind {
+    #[default]
+    User,
+    System,
+}
+
+/// The author of the messages that are not sent by a user.
+pub const SYSTEM_AUTHOR: Uuid = Uuid::nil();
+
+impl Message {
+    #[inline]
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Whether the content mentions the user, written as `<@user_id>`.
+    pub fn mentions(&self, user_id: Uuid) -> bool {
+        self.content
+            .as_deref()
+            .is_some_and(|c| c.contains(&format!("<@{user_id}>")))
+    }
+}
+
+/// Returns the byte ranges of the `@username` mentions of the content, the
+/// `@` included. A mention must start the content or follow a whitespace, so
+/// the emails are skipped, and the username is made of letters, digits, `_`
+/// and `-`.
+pub fn find_mentions(content: &str) -> Vec<Range<usize>> {
+    let is_username_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+
+    let mut mentions = Vec::new();
+    let mut prev = ' ';
+    let mut chars = content.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let at_boundary = prev.is_whitespace();
+        prev = c;
+        if c != '@' || !at_boundary {
+            continue;
+        }
+
+        let mut end = start + 1;
+        while let Some((i, c)) = chars.next_if(|&(_, c)| is_username_char(c)) {
+            end = i + c.len_utf8();
+            prev = c;
+        }
+        if end > start + 1 {
+            mentions.push(start..end);
+        }
+    }
+
+    mentions
 }
 
 impl ApiResponder for Message {
@@ -24,11 +103,199 @@ impl ApiResponder for Message {
     }
 }
 
+/// The related data a message can be returned with, requested with the
+/// `expand` query parameter of the fetching endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageExpand {
+    Author,
+}
+
+/// A message as returned by the fetching endpoints.
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageView {
+    #[serde(flatten)]
+    pub message: Message,
+    /// Only present when expanded with [`MessageExpand::Author`], `null` if
+    /// the author does not exist, like for the system messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<Option<PublicUser>>,
+}
+
+impl From<Message> for MessageView {
+    #[inline]
+    fn from(value: Message) -> Self {
+        Self {
+            message: value,
+            author: None,
+        }
+    }
+}
+
+impl ApiResponder for MessageView {
+    fn unit() -> &'static str {
+        "message"
+    }
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+/// The latest message of a channel that a user has read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReadMarker {
+    pub user_id: Uuid,
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+    /// The `seq` of the read message, used to order the markers
+    pub message_seq: u64,
+    pub message_created_at: DateTime<Utc>,
+    pub read_at: DateTime<Utc>,
+}
+
+/// The latest message of a channel, that lets the clients tell whether they
+/// missed messages while disconnected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelLatestMessage {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+    pub seq: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&Message> for ChannelLatestMessage {
+    #[inline]
+    fn from(value: &Message) -> Self {
+        Self {
+            channel_id: value.channel_id,
+            message_id: value.id,
+            seq: value.seq,
+            created_at: value.created_at,
+        }
+    }
+}
+
+/// How long a user is listed as typing in a channel after the last typing
+/// notification they sent.
+pub const TYPING_TTL: Duration = Duration::from_secs(5);
+
+/// The amount of channels whose read marker moved.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadAllResponseBody {
+    pub count: usize,
+}
+
+impl ApiResponder for ReadAllResponseBody {
+    #[inline]
+    fn unit() -> &'static str {
+        "read channel count"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TypingResponseBody {
+    pub user_ids: Vec<Uuid>,
+}
+
+impl ApiResponder for TypingResponseBody {
+    #[inline]
+    fn unit() -> &'static str {
+        "typing user list"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+/// The default maximum amount of characters in the content of a message.
+pub const MESSAGE_CONTENT_MAX_LEN: usize = 4000;
+
+/// The largest request body that can carry a message with `max_len`
+/// characters of content.
+///
+/// Oversized messages are rejected twice: the body limit of the message
+/// routes drops the request before it is buffered and deserialized, while the
+/// handlers check the exact amount of characters. The body limit is only an
+/// upper bound, as a character takes up to 6 bytes once escaped in json.
+pub fn message_body_limit(max_len: usize) -> usize {
+    max_len.saturating_mul(6).saturating_add(1024)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct MessageCreateData {
     pub content: Option<String>,
     pub image: Option<Uuid>,
+    /// Makes the message disappear on the date, which must be in the future
+    /// and within the maximum ttl of the messages
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Set by the handlers when the content filter warns about the content
+    #[serde(skip)]
+    pub flagged: bool,
+    /// Only set by the server, the users can not post system messages
+    #[serde(skip)]
+    pub kind: MessageKind,
+}
+
+impl MessageCreateData {
+    /// A message posted by the server, see [`MessageKind::System`].
+    pub fn system(content: String) -> Self {
+        Self {
+            content: Some(content),
+            image: None,
+            expires_at: None,
+            flagged: false,
+            kind: MessageKind::System,
+        }
+    }
+}
+
+/// The default of the furthest a message can be set to expire in.
+pub const MESSAGE_MAX_TTL: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// How often the expired messages are deleted.
+pub const MESSAGE_EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The author of the imported messages that have no author in the channel.
+pub const SYSTEM_IMPORT_AUTHOR: Uuid = SYSTEM_AUTHOR;
+
+pub const MESSAGE_IMPORT_MAX_BATCH: usize = 1000;
+
+/// A historical message imported from another platform.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageImportData {
+    /// The author of the message, which must be a member of the channel. The
+    /// message is attributed to [`SYSTEM_IMPORT_AUTHOR`] if not provided
+    #[serde(default)]
+    pub user_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub content: Option<String>,
+    pub image: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageImportResponseBody {
+    pub count: u64,
+}
+
+impl ApiResponder for MessageImportResponseBody {
+    #[inline]
+    fn unit() -> &'static str {
+        "message import"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,8 +303,13 @@ pub struct MessageCreateData {
 pub struct MessageUpdateData {
     pub content: Option<String>,
     pub image: Option<Uuid>,
+    /// Set by the handlers when the content is replaced, depending on whether
+    /// the content filter warns about the new one
+    #[serde(skip)]
+    pub flagged: Option<bool>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub(super) enum MessageUpdateVariant {
     Content(String),
@@ -46,16 +318,13 @@ pub(super) enum MessageUpdateVariant {
     None,
 }
 
-impl Into<MessageUpdateVariant> for MessageUpdateData {
-    fn into(self) -> MessageUpdateVariant {
-        if self.content.is_some() && self.image.is_some() {
-            MessageUpdateVariant::ContentAndImage(self.image.unwrap(), self.content.unwrap())
-        } else if let Some(content) = self.content {
-            MessageUpdateVariant::Content(content)
-        } else if let Some(image) = self.image {
-            MessageUpdateVariant::Image(image)
-        } else {
-            MessageUpdateVariant::None
+impl From<MessageUpdateData> for MessageUpdateVariant {
+    fn from(value: MessageUpdateData) -> Self {
+        match (value.content, value.image) {
+            (Some(content), Some(image)) => Self::ContentAndImage(image, content),
+            (Some(content), None) => Self::Content(content),
+            (None, Some(image)) => Self::Image(image),
+            (None, None) => Self::None,
         }
     }
 }
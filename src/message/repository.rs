@@ -1,12 +1,18 @@
-use super::models::{Message, MessageCreateData, MessageUpdateData};
+use super::models::{
+    ChannelLatestMessage, Message, MessageCreateData, MessageUpdateData, ReadMarker,
+};
 use crate::errors::ApiError;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
 #[async_trait]
 pub trait MessageRepository: Sync + Send {
+    /// The expired messages are left out by all the lookups, even before they
+    /// are deleted by [`MessageRepository::delete_expired`].
     async fn get_by_id(&self, id: Uuid) -> Result<Option<Message>, ApiError>;
 
+    /// Returns the messages of the channel ordered by their `seq`.
     async fn get_many(
         &self,
         channel_id: Uuid,
@@ -14,6 +20,26 @@ pub trait MessageRepository: Sync + Send {
         limit: u64,
     ) -> Result<Vec<Message>, ApiError>;
 
+    /// Returns the total amount of messages of all the channels.
+    async fn count(&self) -> Result<u64, ApiError>;
+
+    /// Returns the latest message of each of the channels, leaving out the
+    /// channels without messages.
+    async fn get_latest(&self, channel_ids: &[Uuid])
+        -> Result<Vec<ChannelLatestMessage>, ApiError>;
+
+    /// Returns about `limit / 2` messages before and after the target message,
+    /// including it, in chronological order. Returns `None` if the target
+    /// message is not in the channel.
+    async fn get_around(
+        &self,
+        channel_id: Uuid,
+        message_id: Uuid,
+        limit: u64,
+    ) -> Result<Option<Vec<Message>>, ApiError>;
+
+    /// Creates the message with the next `seq` of the channel, so the
+    /// concurrently created messages never share it.
     async fn create(
         &self,
         user_id: Uuid,
@@ -21,7 +47,42 @@ pub trait MessageRepository: Sync + Send {
         data: MessageCreateData,
     ) -> Result<Message, ApiError>;
 
+    /// Creates the message with the provided creation date instead of the
+    /// current one, used to import historical messages.
+    async fn create_with_timestamp(
+        &self,
+        user_id: Uuid,
+        channel_id: Uuid,
+        data: MessageCreateData,
+        created_at: DateTime<Utc>,
+    ) -> Result<Message, ApiError>;
+
     async fn update(&self, id: Uuid, data: MessageUpdateData) -> Result<Message, ApiError>;
 
     async fn delete(&self, id: Uuid) -> Result<(), ApiError>;
+
+    /// Deletes the messages whose expiry date passed, returning them.
+    async fn delete_expired(&self) -> Result<Vec<Message>, ApiError>;
+
+    /// Moves the read marker of the user in the message channel up to the
+    /// message. Markers never move backwards, so the current marker is
+    /// returned if it already points to a newer message.
+    async fn set_read_marker(&self, user_id: Uuid, msg: &Message) -> Result<ReadMarker, ApiError>;
+
+    /// Moves the read markers of the user up to each of the messages at once,
+    /// returning only the markers that moved.
+    async fn set_read_markers(
+        &self,
+        user_id: Uuid,
+        latest: &[ChannelLatestMessage],
+    ) -> Result<Vec<ReadMarker>, ApiError>;
+
+    async fn get_read_markers(&self, channel_id: Uuid) -> Result<Vec<ReadMarker>, ApiError>;
+
+    /// Lists the user as typing in the channel for [`TYPING_TTL`](super::models::TYPING_TTL), which is
+    /// extended each time it is set again.
+    async fn set_typing(&self, channel_id: Uuid, user_id: Uuid) -> Result<(), ApiError>;
+
+    /// Returns the ids of the users that are currently typing in the channel.
+    async fn get_typing(&self, channel_id: Uuid) -> Result<Vec<Uuid>, ApiError>;
 }
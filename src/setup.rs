@@ -1,9 +1,24 @@
-use crate::errors::ApiError;
-use axum::{body::Body, http::Response, response::IntoResponse};
+use crate::{
+    attachment::validation::AttachmentLimits,
+    auth::password_policy::PasswordPolicy,
+    errors::ApiError,
+    http::{SecurityHeaders, TrustedProxies},
+    message::filter::{ContentFilter, NoopContentFilter},
+    user::{models::UserRole, repository::UserRepository},
+    BoxedError, MailRepo,
+};
+use axum::{
+    body::Body,
+    http::{HeaderValue, Response},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
 use std::{
     env,
     fmt::{Debug, Display},
+    ops::RangeInclusive,
     str::FromStr,
+    time::Duration,
 };
 use tower_http::catch_panic::ResponseForPanic;
 
@@ -36,6 +51,354 @@ impl ResponseForPanic for JsonPanicHandler {
     }
 }
 
+pub fn setup_mailer() -> Result<MailRepo, BoxedError> {
+    #[cfg(feature = "smtp")]
+    {
+        let smtp_url = env_param::<String>("APP_SMTP_URL")?;
+        let smtp_from = env_param::<String>("APP_SMTP_FROM")?;
+
+        MailRepo::new(&smtp_url, &smtp_from)
+    }
+
+    #[cfg(not(feature = "smtp"))]
+    Ok(MailRepo::new())
+}
+
+/// The formatter of the logs, selected with `APP_LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Only available with the `json-log` feature
+    #[cfg(feature = "json-log")]
+    Json,
+    Pretty,
+    Compact,
+}
+
+impl Default for LogFormat {
+    #[inline]
+    fn default() -> Self {
+        #[cfg(feature = "json-log")]
+        return Self::Json;
+        #[cfg(not(feature = "json-log"))]
+        return Self::Pretty;
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            #[cfg(feature = "json-log")]
+            "json" => Ok(Self::Json),
+            "pretty" => Ok(Self::Pretty),
+            "compact" => Ok(Self::Compact),
+            _ => Err(format!("invalid value {s:?} for enum LogFormat")),
+        }
+    }
+}
+
+/// Installs the global tracing subscriber, filtered by `RUST_LOG` and
+/// formatted according to `APP_LOG_FORMAT`, which defaults to `json` when the
+/// `json-log` feature is enabled and to `pretty` otherwise. The spans are also
+/// exported over OTLP with the `otel` feature.
+pub fn setup_logging() -> Result<(), BoxedError> {
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+    let format = match env_param("APP_LOG_FORMAT") {
+        Ok(v) => v,
+        Err(VarError::NotProvided(_)) => LogFormat::default(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let fmt = tracing_subscriber::fmt::layer();
+    let fmt = match format {
+        #[cfg(feature = "json-log")]
+        LogFormat::Json => fmt.json().boxed(),
+        LogFormat::Pretty => fmt.boxed(),
+        LogFormat::Compact => fmt.compact().boxed(),
+    };
+
+    let registry = tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt);
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(crate::telemetry::otel_layer()?);
+
+    registry.try_init()?;
+
+    Ok(())
+}
+
+/// Loads the wordlist of the content filter from the file at
+/// `APP_CONTENT_FILTER_WORDLIST`, filtering no content if it is not set or if
+/// the `wordlist-filter` feature is disabled.
+pub fn setup_content_filter() -> Result<Box<dyn ContentFilter>, BoxedError> {
+    #[cfg(feature = "wordlist-filter")]
+    {
+        use crate::message::filter::WordlistContentFilter;
+
+        match env_param::<String>("APP_CONTENT_FILTER_WORDLIST") {
+            Ok(path) => return Ok(Box::new(WordlistContentFilter::from_file(path)?)),
+            Err(VarError::NotProvided(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(Box::new(NoopContentFilter))
+}
+
+/// Reads the `APP_TRUSTED_PROXIES` list, trusting no proxy if it is not set.
+pub fn setup_trusted_proxies() -> Result<TrustedProxies, VarError> {
+    match env_param("APP_TRUSTED_PROXIES") {
+        Ok(v) => Ok(v),
+        Err(VarError::NotProvided(_)) => Ok(TrustedProxies::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The accepted lifetimes of the auth tokens, in seconds. Shorter ones would
+/// expire before being used, longer ones make leaked tokens useful for too
+/// long.
+pub const JWT_DURATION_RANGE: RangeInclusive<u64> = 60..=604800;
+
+/// Reads the `APP_JWT_DURATION` lifetime of the auth tokens in seconds,
+/// defaulting to an hour, and rejects the values out of
+/// [`JWT_DURATION_RANGE`]. The user invalidations are cached for about as
+/// long, see [`crate::auth::jwt_repository::JwtAuthRepository::new`].
+pub fn setup_jwt_duration() -> Result<u64, VarError> {
+    const KEY: &str = "APP_JWT_DURATION";
+
+    let duration = match env_param(KEY) {
+        Ok(v) => v,
+        Err(VarError::NotProvided(_)) => 3600,
+        Err(e) => return Err(e),
+    };
+
+    if !JWT_DURATION_RANGE.contains(&duration) {
+        return Err(VarError::OutOfRange(
+            KEY,
+            *JWT_DURATION_RANGE.start(),
+            *JWT_DURATION_RANGE.end(),
+        ));
+    }
+
+    Ok(duration)
+}
+
+/// Reads the `APP_TOTP_KEY` the totp secrets are encrypted with. It falls back
+/// to the JWT key, with a warning: sharing it means a leaked JWT key also
+/// exposes the totp secrets, and rotating it makes them unreadable.
+pub fn setup_totp_key(jwt_key: &str) -> Result<String, VarError> {
+    match env_param::<String>("APP_TOTP_KEY") {
+        Ok(v) if v == jwt_key => {
+            tracing::warn!("APP_TOTP_KEY is the same as APP_JWT_KEY, use a separate key");
+            Ok(v)
+        }
+        Ok(v) => Ok(v),
+        Err(VarError::NotProvided(_)) => {
+            tracing::warn!(
+                "APP_TOTP_KEY is not set, the totp secrets are encrypted with APP_JWT_KEY"
+            );
+            Ok(jwt_key.to_owned())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the `APP_UNVERSIONED_SUNSET` date, in the RFC 3339 format, the
+/// unversioned route aliases are going to be removed on.
+pub fn setup_unversioned_sunset() -> Result<Option<DateTime<Utc>>, VarError> {
+    match env_param("APP_UNVERSIONED_SUNSET") {
+        Ok(v) => Ok(Some(v)),
+        Err(VarError::NotProvided(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the security headers settings, falling back to the defaults of
+/// [`SecurityHeaders`] for the ones that are not set. An
+/// `APP_HSTS_MAX_AGE` of zero disables the `Strict-Transport-Security`
+/// header.
+pub fn setup_security_headers() -> Result<SecurityHeaders, VarError> {
+    let default = SecurityHeaders::default();
+
+    let referrer_policy = match env_param::<String>("APP_REFERRER_POLICY") {
+        Ok(v) => HeaderValue::from_str(&v).map_err(|_| VarError::Invalid("APP_REFERRER_POLICY"))?,
+        Err(VarError::NotProvided(_)) => default.referrer_policy,
+        Err(e) => return Err(e),
+    };
+
+    let hsts_max_age = match env_param::<u64>("APP_HSTS_MAX_AGE") {
+        Ok(0) => None,
+        Ok(v) => Some(Duration::from_secs(v)),
+        Err(VarError::NotProvided(_)) => default.hsts_max_age,
+        Err(e) => return Err(e),
+    };
+
+    Ok(SecurityHeaders {
+        enabled: env_param("APP_SECURITY_HEADERS").unwrap_or(default.enabled),
+        referrer_policy,
+        hsts_max_age,
+    })
+}
+
+/// Grants [`UserRole::Admin`] to the user with the email if they already
+/// signed up, the ones signing up later are handled by
+/// [`crate::auth::handlers::AuthHandlers::with_bootstrap_admin_email`].
+pub async fn bootstrap_admin<U: UserRepository>(
+    user_repo: &U,
+    email: &str,
+) -> Result<(), ApiError> {
+    let Some(user) = user_repo.get_by_email(email.to_owned()).await? else {
+        return Ok(());
+    };
+
+    if user.role != UserRole::Admin {
+        user_repo.set_role(user.id, UserRole::Admin).await?;
+        tracing::info!(
+            user_id = user.id.to_string(),
+            "Granted admin role to bootstrap admin"
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads the attachment upload restrictions, falling back to the defaults of
+/// [`AttachmentLimits`] for the ones that are not set.
+pub fn setup_attachment_limits() -> Result<AttachmentLimits, VarError> {
+    let default = AttachmentLimits::default();
+
+    let allowed_types = match env_param::<String>("APP_ATTACHMENT_ALLOWED_TYPES") {
+        Ok(v) => v
+            .split(',')
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect(),
+        Err(VarError::NotProvided(_)) => default.allowed_types,
+        Err(e) => return Err(e),
+    };
+
+    Ok(AttachmentLimits {
+        allowed_types,
+        max_size: env_param("APP_MAX_ATTACHMENT_BYTES").unwrap_or(default.max_size),
+        max_dimension: env_param("APP_ATTACHMENT_MAX_DIMENSION").unwrap_or(default.max_dimension),
+    })
+}
+
+/// Reads the password policy, falling back to the defaults of
+/// [`PasswordPolicy`] for the rules that are not set. `APP_PASSWORD_POLICY`
+/// can be set to `false` to accept any password in test and dev environments.
+pub fn setup_password_policy() -> PasswordPolicy {
+    let default = PasswordPolicy::default();
+
+    PasswordPolicy {
+        enabled: env_param("APP_PASSWORD_POLICY").unwrap_or(default.enabled),
+        min_length: env_param("APP_PASSWORD_MIN_LENGTH").unwrap_or(default.min_length),
+        require_mixed_case: env_param("APP_PASSWORD_REQUIRE_MIXED_CASE")
+            .unwrap_or(default.require_mixed_case),
+        require_digit: env_param("APP_PASSWORD_REQUIRE_DIGIT").unwrap_or(default.require_digit),
+        require_symbol: env_param("APP_PASSWORD_REQUIRE_SYMBOL").unwrap_or(default.require_symbol),
+        block_common: env_param("APP_PASSWORD_BLOCK_COMMON").unwrap_or(default.block_common),
+    }
+}
+
+#[cfg(feature = "postgres-redis-repository")]
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to connect to {name} after {attempts} attempts: {source}")]
+pub struct StartupError {
+    name: &'static str,
+    attempts: u32,
+    source: BoxedError,
+}
+
+/// Runs `connect` until it succeeds, up to `retries` more times, doubling the
+/// `backoff` between the attempts. Meant for the dependencies that may not be
+/// ready yet when the app starts.
+#[cfg(feature = "postgres-redis-repository")]
+pub async fn retry_startup<T, F, Fut>(
+    name: &'static str,
+    retries: u32,
+    mut backoff: std::time::Duration,
+    mut connect: F,
+) -> Result<T, StartupError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, BoxedError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match connect().await {
+            Ok(v) => return Ok(v),
+            Err(e) if attempt > retries => {
+                return Err(StartupError {
+                    name,
+                    attempts: attempt,
+                    source: e,
+                })
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error = e.to_string(),
+                    attempt,
+                    retry_in = format!("{}ms", backoff.as_millis()),
+                    "Failed to connect to {name}"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_STARTUP_BACKOFF);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "postgres-redis-repository")]
+const MAX_STARTUP_BACKOFF: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Applies the migrations embedded from the `migrations` directory that were
+/// not applied yet, logging each one of them.
+#[cfg(feature = "postgres-redis-repository")]
+pub async fn run_migrations(pool: &sqlx::PgPool) -> Result<(), sqlx::migrate::MigrateError> {
+    use sqlx::migrate::{Migrate, Migrator};
+    use std::collections::HashSet;
+
+    static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+    let applied = {
+        let mut conn = pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+
+        conn.list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|m| m.version)
+            .collect::<HashSet<_>>()
+    };
+
+    MIGRATOR.run(pool).await?;
+
+    let mut count = 0;
+    for m in MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+    {
+        tracing::info!(
+            version = m.version,
+            description = m.description.as_ref(),
+            "Applied migration"
+        );
+        count += 1;
+    }
+
+    if count == 0 {
+        tracing::info!("Database schema is up to date");
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "http-cors")]
 use axum::routing::Router;
 
@@ -70,6 +433,8 @@ pub enum VarError {
     NotProvided(&'static str),
     #[error("The environment variable \"{0}\" could not be parsed")]
     Invalid(&'static str),
+    #[error("The environment variable \"{0}\" must be between {1} and {2}")]
+    OutOfRange(&'static str, u64, u64),
 }
 
 impl Debug for VarError {
@@ -78,16 +443,16 @@ impl Debug for VarError {
     }
 }
 
-pub fn env_param<T: FromStr>(key: &'static str) -> Result<T, VarError> {
-    impl VarError {
-        fn from_std(err: env::VarError, key: &'static str) -> Self {
-            match err {
-                env::VarError::NotPresent => Self::NotProvided(key),
-                env::VarError::NotUnicode(_) => Self::Invalid(key),
-            }
+impl VarError {
+    fn from_std(err: env::VarError, key: &'static str) -> Self {
+        match err {
+            env::VarError::NotPresent => Self::NotProvided(key),
+            env::VarError::NotUnicode(_) => Self::Invalid(key),
         }
     }
+}
 
+pub fn env_param<T: FromStr>(key: &'static str) -> Result<T, VarError> {
     match env::var(key) {
         Ok(v) => T::from_str(&v).map_err(|_| VarError::Invalid(key)),
         Err(err) => Err(VarError::from_std(err, key)),
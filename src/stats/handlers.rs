@@ -0,0 +1,122 @@
+use crate::{
+    auth::models::UserAuthPayload,
+    channel::repository::ChannelRepository,
+    errors::ApiError,
+    gateway::limiter::ConnectionLimiter,
+    http::{ApiResponder, DataResponse},
+    message::repository::MessageRepository,
+    user::{
+        models::{UserFilter, UserRole},
+        repository::UserRepository,
+    },
+};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// How long the counts of the repositories are reused before being queried
+/// again.
+pub const STATS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+struct RepositoryCounts {
+    users: u64,
+    channels: u64,
+    messages: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsResponseBody {
+    users: u64,
+    channels: u64,
+    messages: u64,
+    /// The open gateway connections, always up to date unlike the other counts
+    connections: usize,
+}
+
+impl ApiResponder for StatsResponseBody {
+    #[inline]
+    fn unit() -> &'static str {
+        "stats payload"
+    }
+    #[inline]
+    fn article() -> &'static str {
+        "A"
+    }
+}
+
+pub struct StatsHandlers<U, C, M>
+where
+    U: UserRepository,
+    C: ChannelRepository,
+    M: MessageRepository,
+{
+    user_repo: U,
+    channel_repo: C,
+    message_repo: M,
+    limiter: ConnectionLimiter,
+    cached: Mutex<Option<(Instant, RepositoryCounts)>>,
+}
+
+impl<U, C, M> StatsHandlers<U, C, M>
+where
+    U: UserRepository,
+    C: ChannelRepository,
+    M: MessageRepository,
+{
+    pub fn new(user_repo: U, channel_repo: C, message_repo: M, limiter: ConnectionLimiter) -> Self {
+        Self {
+            user_repo,
+            channel_repo,
+            message_repo,
+            limiter,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Returns the counts cached within [`STATS_CACHE_TTL`], or queries them.
+    /// The lock is held while querying so concurrent requests wait for the
+    /// same counts instead of querying the database again.
+    async fn counts(&self) -> Result<RepositoryCounts, ApiError> {
+        let mut cached = self.cached.lock().await;
+        if let Some((at, counts)) = *cached {
+            if at.elapsed() < STATS_CACHE_TTL {
+                return Ok(counts);
+            }
+        }
+
+        let counts = RepositoryCounts {
+            users: self.user_repo.count(&UserFilter::default()).await?,
+            channels: self.channel_repo.count().await?,
+            messages: self.message_repo.count().await?,
+        };
+        *cached = Some((Instant::now(), counts));
+
+        Ok(counts)
+    }
+
+    pub async fn handle_get(
+        &self,
+        auth: UserAuthPayload,
+    ) -> Result<DataResponse<StatsResponseBody>, ApiError> {
+        let user = self
+            .user_repo
+            .get_by_id(auth.sub)
+            .await?
+            .ok_or(ApiError::UserNotFound)?;
+
+        if user.role != UserRole::Admin {
+            return Err(ApiError::AdminPermissionRequired);
+        }
+
+        let counts = self.counts().await?;
+
+        Ok(StatsResponseBody {
+            users: counts.users,
+            channels: counts.channels,
+            messages: counts.messages,
+            connections: self.limiter.total(),
+        }
+        .into())
+    }
+}
@@ -0,0 +1,101 @@
+//! Export of the traces to an OpenTelemetry collector over OTLP.
+//!
+//! Nothing is exported unless `OTEL_EXPORTER_OTLP_ENDPOINT` is set, the
+//! exporter also reads the other standard `OTEL_*` environment variables.
+
+use crate::{
+    setup::{env_param, VarError},
+    BoxedError,
+};
+use axum::http::Request;
+use opentelemetry::{
+    global,
+    trace::{TraceContextExt, TracerProvider},
+};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use std::{collections::HashMap, sync::OnceLock};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Builds the tracing layer exporting the spans, if an endpoint was provided.
+pub fn otel_layer<S>() -> Result<Option<OpenTelemetryLayer<S, SdkTracer>>, BoxedError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    match env_param::<String>("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(_) => {}
+        Err(VarError::NotProvided(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let exporter = SpanExporter::builder().with_http().build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+    _ = PROVIDER.set(provider);
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Flushes the pending spans, must be called before the process exits.
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            tracing::error!(error = e.to_string(), "Failed to flush the traces");
+        }
+    }
+}
+
+/// Creates the span of an http request, continuing the trace of the
+/// `traceparent` header if the request has one.
+pub fn make_http_span<B>(req: &Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        version = ?req.version(),
+    );
+
+    let cx = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(req.headers())));
+    _ = span.set_parent(cx);
+
+    span
+}
+
+/// The W3C `traceparent` of the current span, if it is part of a trace.
+pub fn current_traceparent() -> Option<String> {
+    let cx = Span::current().context();
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|p| p.inject_context(&cx, &mut carrier));
+
+    carrier.remove("traceparent")
+}
+
+/// Links the span to the trace of the W3C `traceparent`.
+pub fn link_traceparent(span: &Span, traceparent: &str) {
+    let carrier = HashMap::from([("traceparent".to_owned(), traceparent.to_owned())]);
+    let cx = global::get_text_map_propagator(|p| p.extract(&carrier));
+
+    let span_context = cx.span().span_context().clone();
+    if span_context.is_valid() {
+        span.add_link(span_context);
+    }
+}
@@ -1,17 +1,25 @@
 use super::{
-    models::{User, UserCreateData, UserRole, UserUpdateData},
+    models::{
+        normalize_email, User, UserCreateData, UserFilter, UserRole, UserTotp, UserUpdateData,
+    },
     repository::UserRepository,
 };
-use crate::errors::ApiError;
+use crate::{
+    auth::password::{hash_password, PasswordHashError, HASH_OVERLOAD_RETRY_AFTER},
+    errors::ApiError,
+};
 use async_trait::async_trait;
 use chrono::Utc;
 use std::{collections::HashMap, sync::Arc};
-use tokio::{sync::Mutex, task::spawn_blocking};
+use tokio::sync::Mutex;
 use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct InMemoryUserRepository {
     map: Arc<Mutex<HashMap<Uuid, User>>>,
+    totp_map: Arc<Mutex<HashMap<Uuid, UserTotp>>>,
+    /// The last totp step used by each user, see [`UserRepository::use_totp_step`]
+    totp_steps: Arc<Mutex<HashMap<Uuid, u64>>>,
     bcrypt_cost: u32,
 }
 
@@ -19,6 +27,8 @@ impl Default for InMemoryUserRepository {
     fn default() -> Self {
         Self {
             map: Default::default(),
+            totp_map: Default::default(),
+            totp_steps: Default::default(),
             bcrypt_cost: bcrypt::DEFAULT_COST,
         }
     }
@@ -29,6 +39,8 @@ impl InMemoryUserRepository {
     pub fn new(bcrypt_cost: u32) -> Self {
         Self {
             map: Arc::new(Mutex::new(HashMap::new())),
+            totp_map: Arc::new(Mutex::new(HashMap::new())),
+            totp_steps: Arc::new(Mutex::new(HashMap::new())),
             bcrypt_cost,
         }
     }
@@ -48,7 +60,14 @@ impl UserRepository for InMemoryUserRepository {
         }
     }
 
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<User>, ApiError> {
+        let lock = self.map.lock().await;
+
+        Ok(ids.iter().filter_map(|id| lock.get(id).cloned()).collect())
+    }
+
     async fn get_by_email(&self, email: String) -> Result<Option<User>, ApiError> {
+        let email = normalize_email(&email);
         let lock = self.map.lock().await;
 
         for (_, u) in lock.iter() {
@@ -61,8 +80,18 @@ impl UserRepository for InMemoryUserRepository {
         Ok(None)
     }
 
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, ApiError> {
+        let username = username.to_lowercase();
+        let lock = self.map.lock().await;
+
+        Ok(lock
+            .values()
+            .find(|u| u.username.to_lowercase() == username)
+            .cloned())
+    }
+
     async fn create(&self, role: UserRole, data: UserCreateData) -> Result<User, ApiError> {
-        let id = Uuid::new_v4();
+        let id = Uuid::now_v7();
 
         let lock = self.map.lock().await;
         if lock.get(&id).is_some() {
@@ -73,33 +102,39 @@ impl UserRepository for InMemoryUserRepository {
         if self.get_by_email(data.email.clone()).await?.is_some() {
             return Err(ApiError::UserAlreadyExists);
         }
+        if self.get_by_username(&data.username).await?.is_some() {
+            return Err(ApiError::UsernameAlreadyTaken);
+        }
 
         let now = Utc::now();
         let bcrypt_cost = self.bcrypt_cost;
 
-        let password = spawn_blocking(move || bcrypt::hash(data.password, bcrypt_cost))
+        let password = hash_password(data.password, bcrypt_cost)
             .await
-            .map_err(|e| {
-                tracing::error!(error = e.to_string(), "Failed to spawn blocking");
-                ApiError::AuthBcryptHashFailed
-            })?
-            .map_err(|e| {
-                tracing::error!(
-                    user_id = id.to_string(),
-                    error = e.to_string(),
-                    "Failed to hash password while creating user"
-                );
-                ApiError::AuthBcryptHashFailed
+            .map_err(|e| match e {
+                PasswordHashError::Overloaded => ApiError::AuthOverloaded {
+                    retry_after: HASH_OVERLOAD_RETRY_AFTER,
+                },
+                e => {
+                    tracing::error!(
+                        user_id = id.to_string(),
+                        error = e.to_string(),
+                        "Failed to hash password while creating user"
+                    );
+                    ApiError::AuthBcryptHashFailed
+                }
             })?;
 
         let user = User {
             id,
             created_at: now,
             updated_at: now,
-            email: data.email,
+            email: normalize_email(&data.email),
+            email_verified: false,
             password,
             username: data.username,
             role,
+            last_login_at: None,
         };
 
         let mut lock = self.map.lock().await;
@@ -118,6 +153,12 @@ impl UserRepository for InMemoryUserRepository {
         };
 
         if let Some(username) = data.username {
+            let taken = lock
+                .values()
+                .any(|u| u.id != id && u.username.to_lowercase() == username.to_lowercase());
+            if taken {
+                return Err(ApiError::UsernameAlreadyTaken);
+            }
             user.username = username;
         }
 
@@ -134,6 +175,239 @@ impl UserRepository for InMemoryUserRepository {
         }
         drop(lock);
 
+        let mut lock = self.totp_map.lock().await;
+        lock.remove(&id);
+        drop(lock);
+
+        let mut lock = self.totp_steps.lock().await;
+        lock.remove(&id);
+        drop(lock);
+
+        Ok(())
+    }
+
+    async fn update_password(&self, id: Uuid, password: String) -> Result<User, ApiError> {
+        if self.get_by_id(id).await?.is_none() {
+            return Err(ApiError::UserNotFound);
+        }
+
+        let bcrypt_cost = self.bcrypt_cost;
+
+        let password = hash_password(password, bcrypt_cost)
+            .await
+            .map_err(|e| match e {
+                PasswordHashError::Overloaded => ApiError::AuthOverloaded {
+                    retry_after: HASH_OVERLOAD_RETRY_AFTER,
+                },
+                e => {
+                    tracing::error!(
+                        user_id = id.to_string(),
+                        error = e.to_string(),
+                        "Failed to hash password while updating user"
+                    );
+                    ApiError::AuthBcryptHashFailed
+                }
+            })?;
+
+        let mut lock = self.map.lock().await;
+
+        let user = lock.get_mut(&id).ok_or(ApiError::UserNotFound)?;
+        user.password = password;
+        user.updated_at = Utc::now();
+
+        Ok(user.clone())
+    }
+
+    async fn set_email_verified(&self, id: Uuid, verified: bool) -> Result<User, ApiError> {
+        let mut lock = self.map.lock().await;
+
+        let user = lock.get_mut(&id).ok_or(ApiError::UserNotFound)?;
+        user.email_verified = verified;
+        user.updated_at = Utc::now();
+
+        Ok(user.clone())
+    }
+
+    async fn set_role(&self, id: Uuid, role: UserRole) -> Result<User, ApiError> {
+        let mut lock = self.map.lock().await;
+
+        let user = lock.get_mut(&id).ok_or(ApiError::UserNotFound)?;
+        user.role = role;
+        user.updated_at = Utc::now();
+
+        Ok(user.clone())
+    }
+
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), ApiError> {
+        let mut lock = self.map.lock().await;
+
+        let user = lock.get_mut(&id).ok_or(ApiError::UserNotFound)?;
+        user.last_login_at = Some(Utc::now());
+
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<User>, ApiError> {
+        let lock = self.map.lock().await;
+
+        let mut users: Vec<&User> = lock.values().filter(|u| filter.matches(u)).collect();
+        users.sort_by_key(|u| (u.created_at, u.id));
+
+        Ok(users
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn count(&self, filter: &UserFilter) -> Result<u64, ApiError> {
+        let lock = self.map.lock().await;
+
+        Ok(lock.values().filter(|u| filter.matches(u)).count() as u64)
+    }
+
+    async fn get_totp(&self, id: Uuid) -> Result<Option<UserTotp>, ApiError> {
+        let lock = self.totp_map.lock().await;
+
+        Ok(lock.get(&id).cloned())
+    }
+
+    async fn set_totp(&self, id: Uuid, totp: Option<UserTotp>) -> Result<(), ApiError> {
+        if self.get_by_id(id).await?.is_none() {
+            return Err(ApiError::UserNotFound);
+        }
+
+        let mut lock = self.totp_map.lock().await;
+        match totp {
+            Some(totp) => lock.insert(id, totp),
+            None => lock.remove(&id),
+        };
+
         Ok(())
     }
+
+    async fn use_totp_step(&self, id: Uuid, step: u64) -> Result<bool, ApiError> {
+        let totp_lock = self.totp_map.lock().await;
+        if !totp_lock.contains_key(&id) {
+            return Ok(false);
+        }
+
+        let mut lock = self.totp_steps.lock().await;
+        if lock.get(&id).is_some_and(|last| *last >= step) {
+            return Ok(false);
+        }
+        lock.insert(id, step);
+
+        Ok(true)
+    }
+
+    async fn use_recovery_code(&self, id: Uuid, code_hash: &str) -> Result<bool, ApiError> {
+        let mut lock = self.totp_map.lock().await;
+        let Some(totp) = lock.get_mut(&id) else {
+            return Ok(false);
+        };
+
+        let len = totp.recovery_codes.len();
+        totp.recovery_codes.retain(|c| c != code_hash);
+
+        Ok(totp.recovery_codes.len() != len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryUserRepository;
+    use crate::{
+        errors::ApiError,
+        user::{
+            models::{UserCreateData, UserRole, UserTotp, UserUpdateData},
+            repository::UserRepository,
+        },
+    };
+
+    fn create_data(email: &str, username: &str) -> UserCreateData {
+        UserCreateData {
+            email: email.into(),
+            username: username.into(),
+            password: "password".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_by_username() {
+        let repo = InMemoryUserRepository::new(4);
+
+        let user = repo
+            .create(UserRole::Common, create_data("alice@example.com", "Alice"))
+            .await
+            .unwrap();
+
+        for username in ["Alice", "alice", "ALICE"] {
+            let found = repo.get_by_username(username).await.unwrap().unwrap();
+            assert_eq!(found.id, user.id, "{username}");
+        }
+        assert!(repo.get_by_username("bob").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_username_taken() {
+        let repo = InMemoryUserRepository::new(4);
+
+        let alice = repo
+            .create(UserRole::Common, create_data("alice@example.com", "alice"))
+            .await
+            .unwrap();
+        let bob = repo
+            .create(UserRole::Common, create_data("bob@example.com", "bob"))
+            .await
+            .unwrap();
+
+        let res = repo
+            .create(UserRole::Common, create_data("other@example.com", "ALICE"))
+            .await;
+        assert!(matches!(res, Err(ApiError::UsernameAlreadyTaken)));
+
+        let rename = |username: &str| UserUpdateData {
+            username: Some(username.into()),
+        };
+        let res = repo.update(bob.id, rename("Alice")).await;
+        assert!(matches!(res, Err(ApiError::UsernameAlreadyTaken)));
+
+        // Only the case of their own username changes
+        let alice = repo.update(alice.id, rename("Alice")).await.unwrap();
+        assert_eq!(alice.username, "Alice");
+    }
+    #[tokio::test]
+    async fn test_use_recovery_code() {
+        let repo = InMemoryUserRepository::new(4);
+
+        let user = repo
+            .create(UserRole::Common, create_data("alice@example.com", "alice"))
+            .await
+            .unwrap();
+        assert!(!repo.use_recovery_code(user.id, "a").await.unwrap());
+
+        let totp = UserTotp {
+            secret: "secret".into(),
+            enabled: true,
+            recovery_codes: vec!["a".into(), "b".into()],
+        };
+        repo.set_totp(user.id, Some(totp)).await.unwrap();
+
+        // Only one of the concurrent redemptions succeeds
+        let (first, second) = tokio::join!(
+            repo.use_recovery_code(user.id, "a"),
+            repo.use_recovery_code(user.id, "a"),
+        );
+        assert!(first.unwrap() ^ second.unwrap());
+
+        let totp = repo.get_totp(user.id).await.unwrap().unwrap();
+        assert_eq!(totp.recovery_codes, vec!["b".to_owned()]);
+    }
 }
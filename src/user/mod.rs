@@ -1,4 +1,4 @@
-#[cfg(any(test, not(feature = "postgres")))]
+#[cfg(any(test, not(feature = "postgres-redis-repository")))]
 pub mod memory_repository;
 pub mod models;
 #[cfg(feature = "postgres")]
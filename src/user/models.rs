@@ -12,6 +12,7 @@ pub enum UserRole {
 
 impl UserRole {
     #[inline]
+    #[cfg_attr(not(feature = "sqlx"), allow(dead_code))]
     pub(super) fn to_upper_enum(&self) -> &'static str {
         match self {
             UserRole::Admin => "ADMIN",
@@ -27,8 +28,13 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub email: String,
+    #[serde(default)]
+    pub email_verified: bool,
     pub username: String,
     pub role: UserRole,
+    /// When the user last completed a signin, only shown to the user itself
+    #[serde(default)]
+    pub last_login_at: Option<DateTime<Utc>>,
     #[serde(skip_serializing)]
     pub password: String,
 }
@@ -42,6 +48,35 @@ impl ApiResponder for User {
     }
 }
 
+/// The part of a [`User`] any other user can see.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PublicUser {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub username: String,
+}
+
+impl From<User> for PublicUser {
+    fn from(value: User) -> Self {
+        Self {
+            id: value.id,
+            created_at: value.created_at,
+            username: value.username,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserTotp {
+    /// The encrypted totp secret, see [`crate::auth::totp::TotpManager`]
+    pub secret: String,
+    pub enabled: bool,
+    /// The hashes of the recovery codes that were not used yet
+    pub recovery_codes: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct UserCreateData {
@@ -50,21 +85,50 @@ pub struct UserCreateData {
     pub password: String,
 }
 
+/// The form the emails are stored and looked up in, so they are compared
+/// case-insensitively.
+#[inline]
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct UserUpdateData {
     pub username: Option<String>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserFilter {
+    pub role: Option<UserRole>,
+    /// Matches the users whose email contains it, ignoring the case
+    pub email: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+}
+
+impl UserFilter {
+    pub fn matches(&self, user: &User) -> bool {
+        self.role.as_ref().is_none_or(|v| user.role == *v)
+            && self
+                .email
+                .as_ref()
+                .is_none_or(|v| user.email.to_lowercase().contains(&v.to_lowercase()))
+            && self.created_after.is_none_or(|v| user.created_at >= v)
+    }
+}
+
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
 #[derive(Debug, Clone)]
 pub(super) enum UserUpdateVariant {
     Username(String),
     None,
 }
 
-impl Into<UserUpdateVariant> for UserUpdateData {
-    fn into(self) -> UserUpdateVariant {
-        if let Some(username) = self.username {
+impl From<UserUpdateData> for UserUpdateVariant {
+    fn from(value: UserUpdateData) -> Self {
+        if let Some(username) = value.username {
             UserUpdateVariant::Username(username)
         } else {
             UserUpdateVariant::None
@@ -105,6 +169,7 @@ mod sqlx {
         Uuid: Decode<'de, R::Database> + Type<R::Database>,
         DateTime<Utc>: Decode<'de, R::Database> + Type<R::Database>,
         String: Decode<'de, R::Database> + Type<R::Database>,
+        bool: Decode<'de, R::Database> + Type<R::Database>,
         UserRole: Decode<'de, R::Database> + Type<R::Database>,
     {
         fn from_row(row: &'de R) -> Result<Self, sqlx::Error> {
@@ -113,8 +178,10 @@ mod sqlx {
                 created_at: row.try_get("created_at")?,
                 updated_at: row.try_get("updated_at")?,
                 email: row.try_get("email")?,
+                email_verified: row.try_get("email_verified")?,
                 username: row.try_get("username")?,
                 role: row.try_get("role")?,
+                last_login_at: row.try_get("last_login_at")?,
                 password: row.try_get("password")?,
             };
 
@@ -1,11 +1,16 @@
 use super::{
-    models::{User, UserCreateData, UserRole, UserUpdateData, UserUpdateVariant},
+    models::{
+        normalize_email, User, UserCreateData, UserFilter, UserRole, UserTotp, UserUpdateData,
+        UserUpdateVariant,
+    },
     repository::UserRepository,
 };
-use crate::errors::ApiError;
+use crate::{
+    auth::password::{hash_password, PasswordHashError, HASH_OVERLOAD_RETRY_AFTER},
+    errors::ApiError,
+};
 use async_trait::async_trait;
 use sqlx::{postgres::PgTypeInfo, Pool, Postgres, Type};
-use tokio::task::spawn_blocking;
 use uuid::Uuid;
 
 impl Type<Postgres> for UserRole {
@@ -14,24 +19,60 @@ impl Type<Postgres> for UserRole {
     }
 }
 
+/// Escapes the wildcards of a `LIKE` pattern, so the value is matched as is.
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// The unique index on the lowercase usernames.
+const USERNAME_INDEX: &str = "users_username_lower_key";
+
+/// Whether the error comes from another user having the username.
+fn is_username_taken(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(e) if e.constraint() == Some(USERNAME_INDEX))
+}
+
+#[derive(Clone)]
 pub struct PostgresUserRepository {
     pool: Pool<Postgres>,
+    read_pool: Pool<Postgres>,
     bcrypt_cost: u32,
 }
 
 impl PostgresUserRepository {
     pub fn new(pool: Pool<Postgres>, bcrypt_cost: u32) -> Self {
-        Self { pool, bcrypt_cost }
+        Self {
+            read_pool: pool.clone(),
+            pool,
+            bcrypt_cost,
+        }
+    }
+
+    /// Serves the listings and the lookups by username from `read_pool`,
+    /// usually a replica, that may lag behind the primary. The lookups by id
+    /// and email back the authentication, so they always hit the primary.
+    pub fn with_read_pool(mut self, read_pool: Pool<Postgres>) -> Self {
+        self.read_pool = read_pool;
+        self
     }
 }
 
 #[async_trait]
 impl UserRepository for PostgresUserRepository {
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
     async fn get_by_id(&self, id: Uuid) -> Result<Option<User>, ApiError> {
-        let res = sqlx::query_as(r#"SELECT * FROM "users" where "id" = $1"#)
-            .bind(id)
-            .fetch_one(&self.pool)
-            .await;
+        let res = sqlx::query_as!(
+            User,
+            r#"SELECT "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password"
+            FROM "users" WHERE "id" = $1"#,
+            id,
+        )
+        .fetch_one(&self.pool)
+        .await;
 
         match res {
             Ok(v) => Ok(Some(v)),
@@ -45,17 +86,45 @@ impl UserRepository for PostgresUserRepository {
                         "PostgresUserRepository sqlx error"
                     );
 
-                    Err(ApiError::SqlxError)
+                    Err(ApiError::from_sqlx(&e))
                 }
             }
         }
     }
 
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<User>, ApiError> {
+        sqlx::query_as!(
+            User,
+            r#"SELECT "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password"
+            FROM "users" WHERE "id" = ANY($1)"#,
+            ids,
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                method = "get_many",
+                "PostgresUserRepository sqlx error"
+            );
+
+            ApiError::from_sqlx(&e)
+        })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
     async fn get_by_email(&self, email: String) -> Result<Option<User>, ApiError> {
-        let res = sqlx::query_as(r#"SELECT * FROM "users" where "email" = $1"#)
-            .bind(email)
-            .fetch_one(&self.pool)
-            .await;
+        let res = sqlx::query_as!(
+            User,
+            r#"SELECT "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password"
+            FROM "users" WHERE "email" = $1"#,
+            normalize_email(&email),
+        )
+        .fetch_one(&self.pool)
+        .await;
 
         match res {
             Ok(v) => Ok(Some(v)),
@@ -69,47 +138,74 @@ impl UserRepository for PostgresUserRepository {
                         "PostgresUserRepository sqlx error"
                     );
 
-                    Err(ApiError::SqlxError)
+                    Err(ApiError::from_sqlx(&e))
                 }
             }
         }
     }
 
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, ApiError> {
+        sqlx::query_as!(
+            User,
+            r#"SELECT "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password"
+            FROM "users" WHERE lower("username") = lower($1)"#,
+            username,
+        )
+        .fetch_optional(&self.read_pool)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                method = "get_by_username",
+                "PostgresUserRepository sqlx error"
+            );
+
+            ApiError::from_sqlx(&e)
+        })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
     async fn create(&self, role: UserRole, data: UserCreateData) -> Result<User, ApiError> {
-        let id = Uuid::new_v4();
+        let id = Uuid::now_v7();
 
         let cost = self.bcrypt_cost;
-        let passwd = spawn_blocking(move || {
-            bcrypt::hash(data.password, cost).map_err(|e| {
-                tracing::error!(
-                    user_id = id.to_string(),
-                    error = e.to_string(),
-                    "Failed to hash password while creating user"
-                );
-                ApiError::AuthBcryptHashFailed
-            })
-        })
-        .await
-        .map_err(|e| {
-            tracing::error!(error = e.to_string(), "Failed to spawn blocking");
-            ApiError::AuthBcryptHashFailed
-        })??;
+        let passwd = hash_password(data.password, cost)
+            .await
+            .map_err(|e| match e {
+                PasswordHashError::Overloaded => ApiError::AuthOverloaded {
+                    retry_after: HASH_OVERLOAD_RETRY_AFTER,
+                },
+                e => {
+                    tracing::error!(
+                        user_id = id.to_string(),
+                        error = e.to_string(),
+                        "Failed to hash password while creating user"
+                    );
+                    ApiError::AuthBcryptHashFailed
+                }
+            })?;
 
-        sqlx::query_as(
+        sqlx::query_as!(
+            User,
             r#"INSERT INTO "users"
             ("id", "email", "username", "role", "password")
             VALUES ($1, $2, $3, $4, $5)
-            RETURNING *"#,
+            RETURNING "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password""#,
+            id,
+            normalize_email(&data.email),
+            data.username,
+            role as _,
+            passwd,
         )
-        .bind(id)
-        .bind(data.email)
-        .bind(data.username)
-        .bind(role)
-        .bind(passwd)
         .fetch_one(&self.pool)
         .await
         .map_err(|e| {
-            if let sqlx::Error::Database(_) = e {
+            if is_username_taken(&e) {
+                ApiError::UsernameAlreadyTaken
+            } else if let sqlx::Error::Database(_) = e {
                 ApiError::UserAlreadyExists
             } else {
                 tracing::error!(
@@ -118,26 +214,35 @@ impl UserRepository for PostgresUserRepository {
                     "PostgresUserRepository sqlx error"
                 );
 
-                ApiError::SqlxError
+                ApiError::from_sqlx(&e)
             }
         })
     }
 
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
     async fn update(&self, id: Uuid, data: UserUpdateData) -> Result<User, ApiError> {
-        let query_as = match data.into() {
+        let username = match data.into() {
             UserUpdateVariant::None => {
                 return self.get_by_id(id).await?.ok_or(ApiError::UserNotFound);
             }
-            UserUpdateVariant::Username(u) => {
-                sqlx::query_as(r#"UPDATE "users" SET "username" = $1 WHERE "id" = $1 RETURNING *"#)
-                    .bind(u)
-            }
-        }
-        .bind(id);
+            UserUpdateVariant::Username(u) => u,
+        };
 
-        query_as.fetch_one(&self.pool).await.map_err(|e| {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE "users" SET "username" = $1, "updated_at" = now()
+            WHERE "id" = $2 RETURNING "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password""#,
+            username,
+            id,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| {
             if matches!(e, sqlx::Error::RowNotFound) {
                 ApiError::UserNotFound
+            } else if is_username_taken(&e) {
+                ApiError::UsernameAlreadyTaken
             } else {
                 tracing::error!(
                     error = e.to_string(),
@@ -145,14 +250,14 @@ impl UserRepository for PostgresUserRepository {
                     "PostgresUserRepository sqlx error"
                 );
 
-                ApiError::SqlxError
+                ApiError::from_sqlx(&e)
             }
         })
     }
 
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
     async fn delete(&self, id: Uuid) -> Result<(), ApiError> {
-        let res = sqlx::query(r#"DELETE FROM "users" WHERE id = $1"#)
-            .bind(id)
+        let res = sqlx::query!(r#"DELETE FROM "users" WHERE "id" = $1"#, id)
             .execute(&self.pool)
             .await;
 
@@ -174,9 +279,311 @@ impl UserRepository for PostgresUserRepository {
                         "PostgresUserRepository sqlx error"
                     );
 
-                    Err(ApiError::SqlxError)
+                    Err(ApiError::from_sqlx(&e))
+                }
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn update_password(&self, id: Uuid, password: String) -> Result<User, ApiError> {
+        let cost = self.bcrypt_cost;
+        let passwd = hash_password(password, cost).await.map_err(|e| match e {
+            PasswordHashError::Overloaded => ApiError::AuthOverloaded {
+                retry_after: HASH_OVERLOAD_RETRY_AFTER,
+            },
+            e => {
+                tracing::error!(
+                    user_id = id.to_string(),
+                    error = e.to_string(),
+                    "Failed to hash password while updating user"
+                );
+                ApiError::AuthBcryptHashFailed
+            }
+        })?;
+
+        sqlx::query_as!(
+            User,
+            r#"UPDATE "users" SET "password" = $1, "updated_at" = now()
+            WHERE "id" = $2 RETURNING "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password""#,
+            passwd,
+            id,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| {
+            if matches!(e, sqlx::Error::RowNotFound) {
+                ApiError::UserNotFound
+            } else {
+                tracing::error!(
+                    error = e.to_string(),
+                    method = "update_password",
+                    "PostgresUserRepository sqlx error"
+                );
+
+                ApiError::from_sqlx(&e)
+            }
+        })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn set_email_verified(&self, id: Uuid, verified: bool) -> Result<User, ApiError> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE "users" SET "email_verified" = $1, "updated_at" = now()
+            WHERE "id" = $2 RETURNING "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password""#,
+            verified,
+            id,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| {
+            if matches!(e, sqlx::Error::RowNotFound) {
+                ApiError::UserNotFound
+            } else {
+                tracing::error!(
+                    error = e.to_string(),
+                    method = "set_email_verified",
+                    "PostgresUserRepository sqlx error"
+                );
+
+                ApiError::from_sqlx(&e)
+            }
+        })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn set_role(&self, id: Uuid, role: UserRole) -> Result<User, ApiError> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE "users" SET "role" = $1, "updated_at" = now()
+            WHERE "id" = $2 RETURNING "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password""#,
+            role as _,
+            id,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| {
+            if matches!(e, sqlx::Error::RowNotFound) {
+                ApiError::UserNotFound
+            } else {
+                tracing::error!(
+                    error = e.to_string(),
+                    method = "set_role",
+                    "PostgresUserRepository sqlx error"
+                );
+
+                ApiError::from_sqlx(&e)
+            }
+        })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), ApiError> {
+        let res = sqlx::query!(
+            r#"UPDATE "users" SET "last_login_at" = now() WHERE "id" = $1"#,
+            id,
+        )
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    Err(ApiError::UserNotFound)
+                } else {
+                    Ok(())
                 }
             }
+            Err(e) => {
+                tracing::error!(
+                    error = e.to_string(),
+                    method = "touch_last_login",
+                    "PostgresUserRepository sqlx error"
+                );
+
+                Err(ApiError::from_sqlx(&e))
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<User>, ApiError> {
+        sqlx::query_as!(
+            User,
+            r#"SELECT "id", "created_at", "updated_at", "email", "email_verified", "username",
+            "role" AS "role: UserRole", "last_login_at", "password"
+            FROM "users" WHERE ($1::userrole IS NULL OR "role" = $1)
+            AND ($2::varchar IS NULL OR "email" ILIKE '%' || $2 || '%')
+            AND ($3::timestamptz IS NULL OR "created_at" >= $3)
+            ORDER BY "created_at", "id"
+            LIMIT $4 OFFSET $5"#,
+            filter.role.clone() as _,
+            filter.email.as_deref().map(escape_like),
+            filter.created_after,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.read_pool)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                method = "list",
+                "PostgresUserRepository sqlx error"
+            );
+
+            ApiError::from_sqlx(&e)
+        })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn count(&self, filter: &UserFilter) -> Result<u64, ApiError> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!" FROM "users"
+            WHERE ($1::userrole IS NULL OR "role" = $1)
+            AND ($2::varchar IS NULL OR "email" ILIKE '%' || $2 || '%')
+            AND ($3::timestamptz IS NULL OR "created_at" >= $3)"#,
+            filter.role.clone() as _,
+            filter.email.as_deref().map(escape_like),
+            filter.created_after,
+        )
+        .fetch_one(&self.read_pool)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                error = e.to_string(),
+                method = "count",
+                "PostgresUserRepository sqlx error"
+            );
+
+            ApiError::from_sqlx(&e)
+        })?;
+
+        Ok(count as u64)
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn get_totp(&self, id: Uuid) -> Result<Option<UserTotp>, ApiError> {
+        let res = sqlx::query_as!(
+            UserTotp,
+            r#"SELECT "secret", "enabled", "recovery_codes" FROM "user_totps" WHERE "user_id" = $1"#,
+            id,
+        )
+        .fetch_optional(&self.pool)
+        .await;
+
+        match res {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                tracing::error!(
+                    error = e.to_string(),
+                    method = "get_totp",
+                    "PostgresUserRepository sqlx error"
+                );
+
+                Err(ApiError::from_sqlx(&e))
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn set_totp(&self, id: Uuid, totp: Option<UserTotp>) -> Result<(), ApiError> {
+        let res = match totp {
+            Some(totp) => {
+                sqlx::query!(
+                    r#"INSERT INTO "user_totps"
+                    ("user_id", "secret", "enabled", "recovery_codes")
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT ("user_id") DO UPDATE SET
+                    "secret" = EXCLUDED."secret",
+                    "enabled" = EXCLUDED."enabled",
+                    "recovery_codes" = EXCLUDED."recovery_codes""#,
+                    id,
+                    totp.secret,
+                    totp.enabled,
+                    &totp.recovery_codes[..],
+                )
+                .execute(&self.pool)
+                .await
+            }
+            None => {
+                sqlx::query!(r#"DELETE FROM "user_totps" WHERE "user_id" = $1"#, id)
+                    .execute(&self.pool)
+                    .await
+            }
+        };
+
+        res.map(|_| ()).map_err(|e| {
+            if let sqlx::Error::Database(_) = e {
+                ApiError::UserNotFound
+            } else {
+                tracing::error!(
+                    error = e.to_string(),
+                    method = "set_totp",
+                    "PostgresUserRepository sqlx error"
+                );
+
+                ApiError::from_sqlx(&e)
+            }
+        })
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn use_totp_step(&self, id: Uuid, step: u64) -> Result<bool, ApiError> {
+        let res = sqlx::query!(
+            r#"UPDATE "user_totps" SET "last_used_step" = $2
+            WHERE "user_id" = $1 AND ("last_used_step" IS NULL OR "last_used_step" < $2)"#,
+            id,
+            step as i64,
+        )
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Ok(r) => Ok(r.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!(
+                    error = e.to_string(),
+                    method = "use_totp_step",
+                    "PostgresUserRepository sqlx error"
+                );
+
+                Err(ApiError::from_sqlx(&e))
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(db.system = "postgresql")))]
+    async fn use_recovery_code(&self, id: Uuid, code_hash: &str) -> Result<bool, ApiError> {
+        let res = sqlx::query!(
+            r#"UPDATE "user_totps" SET "recovery_codes" = array_remove("recovery_codes", $2)
+            WHERE "user_id" = $1 AND $2 = ANY("recovery_codes")"#,
+            id,
+            code_hash,
+        )
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Ok(r) => Ok(r.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!(
+                    error = e.to_string(),
+                    method = "use_recovery_code",
+                    "PostgresUserRepository sqlx error"
+                );
+
+                Err(ApiError::from_sqlx(&e))
+            }
         }
     }
 }
@@ -1,4 +1,4 @@
-use super::models::{User, UserCreateData, UserRole, UserUpdateData};
+use super::models::{User, UserCreateData, UserFilter, UserRole, UserTotp, UserUpdateData};
 use crate::errors::ApiError;
 use async_trait::async_trait;
 use uuid::Uuid;
@@ -7,7 +7,54 @@ use uuid::Uuid;
 pub trait UserRepository: Sync + Send {
     async fn get_by_id(&self, id: Uuid) -> Result<Option<User>, ApiError>;
     async fn get_by_email(&self, email: String) -> Result<Option<User>, ApiError>;
+
+    /// Returns the users with the ids, in no particular order. The ids of the
+    /// users that do not exist are skipped.
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<User>, ApiError>;
+
+    /// Returns the user with the username. The usernames are unique and
+    /// compared case-insensitively, so `Alice` and `alice` are the same user.
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, ApiError>;
+
+    /// Fails with [`ApiError::UsernameAlreadyTaken`] if another user has the
+    /// username.
     async fn create(&self, role: UserRole, data: UserCreateData) -> Result<User, ApiError>;
+    #[allow(dead_code)]
     async fn update(&self, id: Uuid, data: UserUpdateData) -> Result<User, ApiError>;
+    #[allow(dead_code)]
     async fn delete(&self, id: Uuid) -> Result<(), ApiError>;
+
+    /// Hashes and replaces the password of the user.
+    async fn update_password(&self, id: Uuid, password: String) -> Result<User, ApiError>;
+
+    async fn set_email_verified(&self, id: Uuid, verified: bool) -> Result<User, ApiError>;
+
+    async fn set_role(&self, id: Uuid, role: UserRole) -> Result<User, ApiError>;
+
+    /// Sets the last login time of the user to now.
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), ApiError>;
+
+    /// Returns the matching users, oldest first.
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<User>, ApiError>;
+
+    /// Returns the amount of matching users.
+    async fn count(&self, filter: &UserFilter) -> Result<u64, ApiError>;
+
+    async fn get_totp(&self, id: Uuid) -> Result<Option<UserTotp>, ApiError>;
+    async fn set_totp(&self, id: Uuid, totp: Option<UserTotp>) -> Result<(), ApiError>;
+
+    /// Records the totp time step a code of the user was accepted for. Returns
+    /// `false`, recording nothing, if the user has no totp or a code of the
+    /// same or a later step was accepted before, so each code is used once.
+    async fn use_totp_step(&self, id: Uuid, step: u64) -> Result<bool, ApiError>;
+
+    /// Removes the recovery code hash from the totp of the user. Returns
+    /// `false` if the user has no such code, so concurrent requests can't
+    /// redeem the same one twice.
+    async fn use_recovery_code(&self, id: Uuid, code_hash: &str) -> Result<bool, ApiError>;
 }